@@ -0,0 +1,58 @@
+//! 基准测试共用的工作簿构造和计时函数
+
+use std::io::{Cursor, Write};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use xlsx_handlebars::render_template;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// 构造 xlsx 文件, sheets 中每一项是一个 sheet 的 sheetData 内容, 共享字符串写入 sharedStrings.xml
+pub fn build_workbook(shared_strings: &[String], sheets: &[String]) -> Vec<u8> {
+  let sheet_count = sheets.len();
+  let sheet_list: String = (1..=sheet_count).map(|n| format!(r#"<sheet name="Sheet{n}" sheetId="{n}" r:id="rId{n}"/>"#)).collect();
+  let rels: String = (1..=sheet_count).map(|n| format!(r#"<Relationship Id="rId{n}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{n}.xml"/>"#)).collect();
+  let overrides: String = (1..=sheet_count).map(|n| format!(r#"<Override PartName="/xl/worksheets/sheet{n}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#)).collect();
+  
+  let mut entries = vec![
+    ("[Content_Types].xml".to_string(), format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>{overrides}<Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/></Types>"#)),
+    ("_rels/.rels".to_string(), r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string()),
+    ("xl/workbook.xml".to_string(), format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{sheet_list}</sheets></workbook>"#)),
+    ("xl/_rels/workbook.xml.rels".to_string(), format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{rels}<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/></Relationships>"#, sheet_count + 1)),
+    ("xl/sharedStrings.xml".to_string(), format!(
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="{0}" uniqueCount="{0}">{1}</sst>"#,
+      shared_strings.len(),
+      shared_strings.iter().map(|s| format!("<si><t>{s}</t></si>")).collect::<String>(),
+    )),
+  ];
+  for (index, sheet_data) in sheets.iter().enumerate() {
+    entries.push((format!("xl/worksheets/sheet{}.xml", index + 1), format!(
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{sheet_data}</sheetData></worksheet>"#
+    )));
+  }
+  
+  let mut output = Vec::new();
+  let mut zip_writer = ZipWriter::new(Cursor::new(&mut output));
+  for (name, contents) in &entries {
+    zip_writer.start_file(name.as_str(), SimpleFileOptions::default()).unwrap();
+    zip_writer.write_all(contents.as_bytes()).unwrap();
+  }
+  zip_writer.finish().unwrap();
+  output
+}
+
+/// 预热一次后渲染 iterations 次, 输出平均每次渲染的耗时
+pub fn bench_render(label: &str, template: &[u8], data: &Value, iterations: u32) {
+  // 预热一次, 同时确认渲染成功
+  render_template(template.to_vec(), data).unwrap();
+  
+  let mut total = Duration::ZERO;
+  for _ in 0..iterations {
+    let template = template.to_vec();
+    let start = Instant::now();
+    let result = render_template(template, data).unwrap();
+    total += start.elapsed();
+    std::hint::black_box(result);
+  }
+  println!("{label}: {:.2?} per render ({iterations} iterations)", total / iterations);
+}
@@ -0,0 +1,29 @@
+/*
+ * 相同工作表渲染基准测试: 12 个内容相同的月份模板 sheet, 共用编译后的模板
+ * 
+ * 运行命令: cargo bench --bench identical_sheets
+ */
+
+mod common;
+
+use serde_json::json;
+
+const SHEETS: usize = 12;
+const ROWS_PER_SHEET: usize = 200;
+
+fn main() {
+  // 每行 3 个带 handlebars 表达式的单元格, 每个 sheet 的内容相同
+  let shared_strings = vec!["{{title}} {{upper name}}".to_string(), "{{num (add amount 1)}}".to_string(), "{{#if flag}}Y{{else}}N{{/if}}".to_string()];
+  let rows: String = (1..=ROWS_PER_SHEET)
+    .map(|row| format!(r#"<row r="{row}"><c r="A{row}" t="s"><v>0</v></c><c r="B{row}" t="s"><v>1</v></c><c r="C{row}" t="s"><v>2</v></c></row>"#))
+    .collect();
+  let sheets = vec![rows; SHEETS];
+  
+  let template = common::build_workbook(&shared_strings, &sheets);
+  common::bench_render(
+    &format!("identical_sheets: {SHEETS} identical sheets x {ROWS_PER_SHEET} rows"),
+    &template,
+    &json!({ "title": "Report", "name": "alice", "amount": 41, "flag": true }),
+    10,
+  );
+}
@@ -0,0 +1,31 @@
+/*
+ * 静态工作表渲染基准测试: 一个模板 sheet 加多个不包含 handlebars 语法的参考数据 sheet
+ * 
+ * 运行命令: cargo bench --bench static_sheets
+ */
+
+mod common;
+
+use serde_json::json;
+
+const STATIC_SHEETS: usize = 20;
+const ROWS_PER_SHEET: usize = 500;
+
+fn main() {
+  // 第一个 sheet 使用模板, 其余 sheet 每行 3 个单元格, 文本使用共享字符串
+  let mut shared_strings = vec!["{{title}}".to_string()];
+  shared_strings.extend((0..ROWS_PER_SHEET).map(|row| format!("Item {row}")));
+  let static_rows: String = (1..=ROWS_PER_SHEET)
+    .map(|row| format!(r#"<row r="{row}"><c r="A{row}" t="s"><v>{row}</v></c><c r="B{row}"><v>{row}</v></c><c r="C{row}"><f>B{row}*2</f><v>{}</v></c></row>"#, row * 2))
+    .collect();
+  let mut sheets = vec![r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#.to_string()];
+  sheets.extend(std::iter::repeat_n(static_rows, STATIC_SHEETS));
+  
+  let template = common::build_workbook(&shared_strings, &sheets);
+  common::bench_render(
+    &format!("static_sheets: {STATIC_SHEETS} static sheets x {ROWS_PER_SHEET} rows"),
+    &template,
+    &json!({ "title": "Report" }),
+    20,
+  );
+}
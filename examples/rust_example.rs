@@ -1,5 +1,5 @@
 use base64::{engine::general_purpose, Engine};
-/**
+/*
  * Rust native example for xlsx-handlebars
  * 
  * 运行命令: cargo run --example rust_example
@@ -60,7 +60,7 @@ async function utilsExample() {
         
         // 4. Excel 序列号转日期
         console.log('🔄 Excel 序列号转日期示例：');
-        const excelNum1 = 45294.0;
+        const excelNum1 = 45292.0;
         const convertedTimestamp1 = wasm_excel_date_to_timestamp(excelNum1);
         if (convertedTimestamp1 !== null && convertedTimestamp1 !== undefined) {
             const convertedDate1 = new Date(Number(convertedTimestamp1));
@@ -70,7 +70,7 @@ async function utilsExample() {
         }
         console.log();
         
-        const excelNum2 = 25571.0; // 1970-01-01
+        const excelNum2 = 25569.0; // 1970-01-01
         const convertedTimestamp2 = wasm_excel_date_to_timestamp(excelNum2);
         if (convertedTimestamp2 !== null && convertedTimestamp2 !== undefined) {
             const convertedDate2 = new Date(Number(convertedTimestamp2));
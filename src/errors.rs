@@ -9,4 +9,38 @@ pub enum XlsxError {
     InvalidZipFormat,
     #[error("{0}")]
     TemplateRenderError(String),
+    #[error("XML Processing Error: {0}")]
+    XmlProcessingError(String),
+    /// `{{deleteCurrentSheet}}` 要删除工作簿中的所有工作表
+    /// 
+    /// 设置 `RenderOptions::keep_last_sheet` 后不再返回此错误, 而是保留最后一个工作表
+    #[error("Cannot delete all worksheets: {} of {total} worksheets were marked for deletion ({}), leaving none. Excel workbook must contain at least one worksheet; set RenderOptions::keep_last_sheet to keep the last one.", requested.len(), requested.join(", "))]
+    CannotDeleteLastSheet {
+        /// 工作簿中的工作表总数
+        total: usize,
+        /// 被标记删除的工作表名称, 按标记顺序去重
+        requested: Vec<String>,
+    },
+    /// `{{hideCurrentSheet}}` 要隐藏工作簿中的所有工作表
+    #[error("Cannot hide all worksheets: {} of {total} worksheets were marked as hidden ({}), leaving none visible. Excel workbook must have at least one visible worksheet.", requested.len(), requested.join(", "))]
+    CannotHideAllSheets {
+        /// 工作簿中的工作表总数
+        total: usize,
+        /// 被标记隐藏的工作表名称
+        requested: Vec<String>,
+    },
+    /// `{{img}}` 的图片数据无法解码 (base64 格式错误或无法识别的图片格式)
+    /// 
+    /// 设置 `RenderOptions::skip_bad_images` 后不再返回此错误, 而是跳过该图片
+    #[error("Failed to decode image at {sheet}!{cell}: {message}")]
+    ImageDecode {
+        /// 工作表路径, 如 `xl/worksheets/sheet1.xml`
+        sheet: String,
+        /// 图片锚点单元格, 如 `B3`
+        cell: String,
+        message: String,
+    },
+    /// 设置 `RenderOptions::validate_output` 后, 生成的文件中发现了不一致, 例如缺少内容类型或关系
+    #[error("Invalid output package: {0}")]
+    InvalidOutput(String),
 }
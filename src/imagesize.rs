@@ -1,27 +1,78 @@
 
 /// 获取 PNG 图片的宽高
 fn get_png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
-    if data.len() < 24 || &data[0..8] != b"\x89PNG\r\n\x1a\n" {
+    if data.len() < 8 || &data[0..8] != b"\x89PNG\r\n\x1a\n" {
         return None;
     }
-    // IHDR chunk: starts at byte 8, width at 16~19, height at 20~23
-    let width = u32::from_be_bytes([data[16], data[17], data[18], data[19]]);
-    let height = u32::from_be_bytes([data[20], data[21], data[22], data[23]]);
-    Some((width, height))
+    // 按 chunk 头扫描 IHDR, 而不是假定 IHDR 紧跟在签名之后
+    // 每个 chunk: 4 字节长度 + 4 字节类型 + 数据 + 4 字节 CRC
+    let mut i = 8;
+    while i + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        let chunk_type = &data[i + 4..i + 8];
+        if chunk_type == b"IHDR" {
+            // IHDR 数据: 宽 4 字节, 高 4 字节, ...
+            if len < 8 || i + 16 > data.len() {
+                return None;
+            }
+            let width = u32::from_be_bytes([data[i + 8], data[i + 9], data[i + 10], data[i + 11]]);
+            let height = u32::from_be_bytes([data[i + 12], data[i + 13], data[i + 14], data[i + 15]]);
+            return Some((width, height));
+        }
+        if chunk_type == b"IDAT" || chunk_type == b"IEND" {
+            // 图像数据之后不会再出现 IHDR
+            return None;
+        }
+        i = i.checked_add(12 + len)?;
+    }
+    None
+}
+
+/// 是否为 SOFn (Start Of Frame) 标记: 0xC0~0xCF 中除去 DHT (0xC4)、JPG (0xC8)、DAC (0xCC)
+/// 包括基线、扩展、渐进、无损以及差分和算术编码的各种帧
+fn is_jpeg_sof_marker(marker: u8) -> bool {
+    matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
 }
 
 /// 获取 JPEG 图片的宽高
 fn get_jpeg_dimensions(data: &[u8]) -> Option<(u16, u16)> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return None;
+    }
     let mut i = 2;
-    while i + 9 < data.len() {
+    while i + 1 < data.len() {
         if data[i] != 0xFF {
-            i += 1;
-            continue;
+            // 段之间只能是标记, 否则说明段长度不一致
+            return None;
         }
         let marker = data[i + 1];
+        match marker {
+            // 填充字节, 标记前可以有多个 0xFF
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // 没有长度字段的独立标记: TEM、RSTn、SOI
+            0x01 | 0xD0..=0xD8 => {
+                i += 2;
+                continue;
+            }
+            // SOS 之后是图像数据, EOI 表示结束, 都不会再出现 SOF
+            0xD9 | 0xDA => return None,
+            _ => {}
+        }
+        if i + 4 > data.len() {
+            return None;
+        }
+        // 段长度包含长度字段本身的 2 字节
         let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
-        if marker == 0xC0 || marker == 0xC2 {
-            // SOF0 or SOF2
+        if len < 2 {
+            return None;
+        }
+        if is_jpeg_sof_marker(marker) {
+            if len < 7 || i + 9 > data.len() {
+                return None;
+            }
             let height = u16::from_be_bytes([data[i + 5], data[i + 6]]);
             let width = u16::from_be_bytes([data[i + 7], data[i + 8]]);
             return Some((width, height));
@@ -165,7 +216,7 @@ fn get_gif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,no_run
 /// use xlsx_handlebars::get_image_dimensions;
 ///
 /// // Read image file
@@ -179,7 +230,7 @@ fn get_gif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
 /// }
 /// ```
 ///
-/// ```rust
+/// ```rust,no_run
 /// use xlsx_handlebars::get_image_dimensions;
 ///
 /// // Validate image size before using in template
@@ -214,3 +265,104 @@ pub fn get_image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造一个 PNG chunk (CRC 不参与尺寸检测, 填 0)
+    fn png_chunk(chunk_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(payload);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn ihdr(width: u32, height: u32) -> Vec<u8> {
+        let mut payload = width.to_be_bytes().to_vec();
+        payload.extend_from_slice(&height.to_be_bytes());
+        payload.extend_from_slice(&[8, 6, 0, 0, 0]);
+        png_chunk(b"IHDR", &payload)
+    }
+
+    #[test]
+    fn test_png_dimensions_scan_chunks() {
+        let signature = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        // IHDR 在标准位置
+        let png = [signature.clone(), ihdr(640, 480), png_chunk(b"IEND", &[])].concat();
+        assert_eq!(get_image_dimensions(&png), Some((640, 480)));
+
+        // IHDR 前有一个辅助 chunk
+        let png = [signature.clone(), png_chunk(b"sRGB", &[0]), ihdr(32, 16), png_chunk(b"IEND", &[])].concat();
+        assert_eq!(get_png_dimensions(&png), Some((32, 16)));
+
+        // 没有 IHDR 或被截断
+        let png = [signature.clone(), png_chunk(b"IEND", &[])].concat();
+        assert_eq!(get_png_dimensions(&png), None);
+        let png = [signature, ihdr(32, 16)[..12].to_vec()].concat();
+        assert_eq!(get_png_dimensions(&png), None);
+    }
+
+    /// 构造一个 JPEG 段: 标记 + 长度 (包含长度字节本身) + 数据
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    /// SOF 段数据: 精度 1 字节, 高 2 字节, 宽 2 字节, 分量数 + 分量信息
+    fn jpeg_sof(marker: u8, width: u16, height: u16) -> Vec<u8> {
+        let mut payload = vec![8];
+        payload.extend_from_slice(&height.to_be_bytes());
+        payload.extend_from_slice(&width.to_be_bytes());
+        payload.extend_from_slice(&[1, 1, 0x11, 0]);
+        jpeg_segment(marker, &payload)
+    }
+
+    #[test]
+    fn test_jpeg_sof_markers() {
+        let soi = vec![0xFF, 0xD8];
+        let app0 = jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        let dht = jpeg_segment(0xC4, &[0; 20]);
+
+        // SOF1 (扩展顺序) 前有 APP0 和 DHT 段
+        let jpeg = [soi.clone(), app0.clone(), dht.clone(), jpeg_sof(0xC1, 320, 200)].concat();
+        assert_eq!(get_image_dimensions(&jpeg), Some((320, 200)));
+
+        // 所有 SOFn 标记都能识别, DHT/JPG/DAC 不是 SOF
+        for marker in [0xC0, 0xC2, 0xC3, 0xC5, 0xC6, 0xC7, 0xC9, 0xCA, 0xCB, 0xCD, 0xCE, 0xCF] {
+            let jpeg = [soi.clone(), app0.clone(), jpeg_sof(marker, 64, 48)].concat();
+            assert_eq!(get_jpeg_dimensions(&jpeg), Some((64, 48)), "marker {marker:#X}");
+        }
+        for marker in [0xC4, 0xC8, 0xCC] {
+            assert!(!is_jpeg_sof_marker(marker));
+        }
+    }
+
+    #[test]
+    fn test_jpeg_standalone_markers_and_truncation() {
+        let soi = vec![0xFF, 0xD8];
+        let app0 = jpeg_segment(0xE0, &[0; 14]);
+
+        // SOF 前有 RSTn、TEM 标记和填充字节
+        let jpeg = [soi.clone(), app0.clone(), vec![0xFF, 0xD0, 0xFF, 0xD7, 0xFF, 0x01, 0xFF, 0xFF], jpeg_sof(0xC0, 100, 50)].concat();
+        assert_eq!(get_jpeg_dimensions(&jpeg), Some((100, 50)));
+
+        // 段长度小于 2 或超出数据范围
+        let jpeg = [soi.clone(), vec![0xFF, 0xE0, 0x00, 0x01], jpeg_sof(0xC0, 100, 50)].concat();
+        assert_eq!(get_jpeg_dimensions(&jpeg), None);
+        let jpeg = [soi.clone(), vec![0xFF, 0xE0, 0xFF, 0xF0], jpeg_sof(0xC0, 100, 50)].concat();
+        assert_eq!(get_jpeg_dimensions(&jpeg), None);
+
+        // SOF 段被截断
+        let jpeg = [soi.clone(), app0.clone(), jpeg_sof(0xC0, 100, 50)[..7].to_vec()].concat();
+        assert_eq!(get_jpeg_dimensions(&jpeg), None);
+
+        // 段长度与内容不一致导致失步
+        let jpeg = [soi, jpeg_segment(0xE0, &[0; 14])[..10].to_vec(), jpeg_sof(0xC0, 100, 50)].concat();
+        assert_eq!(get_jpeg_dimensions(&jpeg), None);
+    }
+}
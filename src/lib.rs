@@ -5,13 +5,14 @@ use wasm_bindgen::JsValue;
 
 pub mod errors;
 pub mod imagesize;
+pub mod lowlevel;
 mod template;
 pub mod utils;
 
 // 重新导出常用的类型和函数
 pub use errors::XlsxError;
 pub use imagesize::get_image_dimensions;
-pub use utils::{to_column_index, to_column_name, timestamp_to_excel_date, excel_date_to_timestamp};
+pub use utils::{to_column_index, to_column_name, timestamp_to_excel_date, excel_date_to_timestamp, merge_json_values};
 
 /// 当 `console_error_panic_hook` 功能启用时，我们可以调用 `set_panic_hook` 函数
 /// 至少一次在初始化过程中，以便在 panic 时获得更好的错误消息。
@@ -38,6 +39,31 @@ pub fn render_template(
     Ok(JsValue::from(result))
 }
 
+// WASM 平台：列出模板中引用的变量名和 helper 名
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn extract_template_variables(zip_bytes: Vec<u8>) -> Result<Vec<String>, JsValue> {
+    template::extract_template_variables(zip_bytes)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+// WASM 平台：列出模板中已嵌入的图片, 返回 [{ path, width, height }]
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn list_images(zip_bytes: Vec<u8>) -> Result<JsValue, JsValue> {
+    let images = template::list_images(zip_bytes)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let array = js_sys::Array::new();
+    for (path, width, height) in images {
+        let obj = js_sys::Object::new();
+        js_sys::Reflect::set(&obj, &"path".into(), &path.into()).unwrap();
+        js_sys::Reflect::set(&obj, &"width".into(), &width.into()).unwrap();
+        js_sys::Reflect::set(&obj, &"height".into(), &height.into()).unwrap();
+        array.push(&obj);
+    }
+    Ok(array.into())
+}
+
 // WASM 平台：导出工具函数
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
@@ -79,4 +105,4 @@ pub fn wasm_excel_date_to_timestamp(excel_date: f64) -> Option<i64> {
 
 // 非 WASM 平台：直接导出原生 Rust 函数
 #[cfg(not(target_arch = "wasm32"))]
-pub use template::render_template;
+pub use template::{render_template, render_template_from_reader, render_template_to_writer, render_template_with_options, render_template_merged, WorkbookRenderer, RenderOptions, PostProcessHook, WarningHook, extract_template_variables, list_images};
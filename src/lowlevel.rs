@@ -0,0 +1,42 @@
+//! 底层 sheet XML 预处理函数
+//! 
+//! `render_template` 在渲染每个 sheet 之前会先替换共享字符串、合并被 XML 标签分割的 handlebars 语法,
+//! 这里把这些步骤单独导出, 方便在自己的流程中处理单个 sheet XML 片段, 或者不构造完整工作簿就测试模板片段。
+//! 
+//! 处理顺序与 `render_template` 一致:
+//! 
+//! ```rust
+//! use xlsx_handlebars::lowlevel::{extract_shared_strings, replace_shared_strings_in_sheet, merge_handlebars_in_xml};
+//! 
+//! let sst = r#"<sst><si><t>{{name}}</t></si></sst>"#;
+//! let sheet = r#"<sheetData><row r="1"><c r="A1" t="s"><v>0</v></c></row></sheetData>"#;
+//! 
+//! let shared_strings = extract_shared_strings(sst);
+//! let sheet = replace_shared_strings_in_sheet(sheet, &shared_strings)?;
+//! let sheet = merge_handlebars_in_xml(&sheet)?;
+//! assert!(sheet.contains("{{name}}"));
+//! # Ok::<(), xlsx_handlebars::XlsxError>(())
+//! ```
+
+use crate::{utils, XlsxError};
+
+/// 把 sharedStrings.xml 的内容解析为共享字符串数组, 每一项是 `<is>...</is>` 片段
+/// 数组下标即 `t="s"` 单元格 `<v>` 中的索引
+pub fn extract_shared_strings(shared_strings_xml: &str) -> Vec<String> {
+    utils::extract_shared_strings(shared_strings_xml)
+}
+
+/// 把 sheet XML 中 `t="s"` 的单元格替换为 `t="inlineStr"`, 内容取自 [`extract_shared_strings`] 的结果
+/// 模板中原有的 `t="inlineStr"` 单元格做相同的处理
+pub fn replace_shared_strings_in_sheet(sheet_xml: &str, shared_strings: &[String]) -> Result<String, XlsxError> {
+    utils::replace_shared_strings_in_sheet(sheet_xml, shared_strings)
+        .map_err(|e| XlsxError::XmlProcessingError(e.to_string()))
+}
+
+/// 合并被 XML 标签分割的 handlebars 语法, 并为 `{{#each}}` 循环插入行列偏移 helper
+/// 
+/// 例如 `<t>{</t><t>{name</t><t>}}</t>` 会被合并为 `{{name}}`
+pub fn merge_handlebars_in_xml(xml: &str) -> Result<String, XlsxError> {
+    utils::merge_handlebars_in_xml(xml.to_string())
+        .map_err(|e| XlsxError::XmlProcessingError(e.to_string()))
+}
@@ -1,8 +1,8 @@
 use serde_json::Value;
-use std::{io::{Cursor, Read, Write}, sync::{Arc, Mutex}};
+use std::{borrow::Cow, io::{Cursor, Read, Seek, Write}, sync::{Arc, Mutex}};
 use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
-use std::collections::HashMap;
-use crate::{utils::{to_column_name, merge_handlebars_in_xml, register_basic_helpers, post_process_xml, replace_shared_strings_in_sheet, validate_xlsx_format}, XlsxError};
+use std::collections::{HashMap, HashSet};
+use crate::{utils::{to_column_name, merge_handlebars_in_xml, register_basic_helpers, post_process_xml, extract_shared_strings, collect_handlebars_names, CellMarkerKeys, RowMarkerKeys, EXCEL_ERROR_LITERALS, replace_shared_strings_in_sheet, validate_xlsx_reader, insert_drawing_into_sheet, sheet_drawing_rid, insert_anchors_into_drawing, max_drawing_object_id, insert_page_breaks_into_sheet, insert_defined_names_into_workbook, DefinedName, insert_conditional_formatting_into_sheet, count_dxfs, append_dxfs_to_styles, merge_json_values, set_sheet_tab_color, set_sheet_view_attributes, set_sheet_format_attribute, auto_fit_columns, cell_xfs, wrap_text_xf, append_cell_xfs_to_styles, wrap_text_cells, num_fmt_xf, next_num_fmt_id, append_num_fmts_to_styles, restyle_cells, value_to_number, set_xml_declaration, XML_DECLARATION, is_external_link, set_cell_texts, parse_cell_ref, normalize_cell_range}, XlsxError};
 use crate::imagesize::get_image_dimensions;
 use uuid::Uuid;
 
@@ -12,6 +12,14 @@ use handlebars::{Handlebars, RenderErrorReason};
 /// 配合 {{removeRow}} helper 使用
 const REMOVE_ROW_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-remove-row|";
 
+/// 用于标记需要隐藏的行的 UUID
+/// 配合 {{hideRow}} helper 使用
+const HIDE_ROW_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-hide-row|";
+
+/// 用于标记行分组级别的 UUID, 级别写在两个标记之间
+/// 配合 {{rowOutlineLevel 1}} helper 使用
+const ROW_OUTLINE_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-outline|";
+
 /// 用于标记数字类型的 UUID
 /// 配合 {{num aa}} helper 使用
 const TO_NUMBER_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-num|";
@@ -20,6 +28,21 @@ const TO_NUMBER_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-num|";
 /// 配合 {{formula "=SUM(A1:B1)"}} helper 使用
 const TO_FORMULA_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-formula|";
 
+/// 用于标记布尔类型的 UUID
+/// 配合 {{bool done}} helper 使用
+const TO_BOOLEAN_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-bool|";
+
+/// 用于标记错误类型的 UUID
+/// 配合 {{cellError "#N/A"}} helper 使用
+const TO_ERROR_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-error|";
+
+/// 用于标记单元格样式的 UUID, 样式序号写在两个标记之间
+/// 配合 {{cellStyle 5}} helper 使用
+const TO_STYLE_KEY: &str = "|e5nBk+z4RMKqlyBo+xQ48A-style|";
+
+/// sheet rels 中超链接关系的类型
+const HYPERLINK_REL_TYPE: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink";
+
 /// 图片信息结构
 #[derive(Debug, Clone)]
 struct ImageInfo {
@@ -28,24 +51,289 @@ struct ImageInfo {
     base64_data: String,  // base64 图片数据
     width: Option<u32>,   // 用户指定宽度（像素）
     height: Option<u32>,  // 用户指定高度（像素）
-    rid: String,          // 唯一的关系 ID（使用 UUID 避免冲突）
+    media_name: String,   // 唯一的图片文件名（使用 UUID 避免冲突）
+    extension: String,    // 图片扩展名，如 "png"、"jpeg"
+    fit_to: Option<(u32, u32)>, // 缩放填充到的结束单元格 (列号, 行号)，使用 twoCellAnchor
+}
+
+/// 条件格式规则, 对应 sheet.xml 中 `<conditionalFormatting>` 下的一个 cellIs 类型的 `<cfRule>`
+#[derive(Debug, Clone)]
+struct ConditionalFormatRule {
+  sqref: String,     // 应用范围，如 "A2:A100"
+  operator: String,  // 比较运算符，如 "lessThan"
+  formula: String,   // 比较值，字符串需要带双引号，如 0 或 "\"done\""
+  dxf: String,       // styles.xml 中的差异格式 <dxf>
+}
+
+/// 输出文件后处理钩子, 参数为 ZIP 路径到文件内容的 map
+pub type PostProcessHook = Box<dyn FnMut(&mut HashMap<String, Vec<u8>>)>;
+
+/// 渲染警告回调, 参数为警告信息
+pub type WarningHook = Box<dyn FnMut(&str)>;
+
+/// 渲染选项
+#[derive(Default)]
+pub struct RenderOptions {
+  /// 渲染完成后、重新压缩前调用, 可以修改、新增或删除输出文件中的任意部件
+  /// 例如添加自定义的 `docProps/custom.xml`
+  /// 
+  /// map 的 key 是 ZIP 内的路径, 如 `xl/worksheets/sheet1.xml`、`[Content_Types].xml` (没有开头的 `/`)
+  /// 新增部件需要自行在 `[Content_Types].xml` 和对应的 rels 中注册
+  pub post_process: Option<PostProcessHook>,
+  /// `{{deleteCurrentSheet}}` 要删除所有工作表时保留最后一个被标记删除的工作表, 而不是返回
+  /// [`XlsxError::CannotDeleteLastSheet`]
+  pub keep_last_sheet: bool,
+  /// `{{img}}` 的图片数据无法解码时跳过该图片并输出警告, 而不是返回 [`XlsxError::ImageDecode`]
+  pub skip_bad_images: bool,
+  /// 图片显示尺寸的最大宽高 (像素), 超过时按原始宽高比等比例缩小显示尺寸
+  /// 只修改 drawing 中的显示尺寸, 不修改图片数据
+  pub max_image_dimension: Option<u32>,
+  /// 渲染后按每列最长的文本估算列宽并写入 `<cols>`, 模板中已经设置过宽度的列保持不变
+  /// 
+  /// 这是近似值: Excel 真正的自动调整列宽依赖字体度量, 这里只按字符数估算 (中日韩文字按 2 个字符计算)
+  pub auto_fit_columns: bool,
+  /// `auto_fit_columns` 估算的最大列宽 (字符数), 默认为 60
+  pub auto_fit_max_width: Option<f64>,
+  /// 重新压缩后再打开生成的文件做一致性检查, 发现问题时返回 [`XlsxError::InvalidOutput`]:
+  /// 每个部件都有内容类型, 工作簿和工作表中引用的每个 `r:id` 在对应的 rels 中都存在
+  pub validate_output: bool,
+  /// 接收渲染过程中的警告, 例如 `skip_bad_images` 跳过的图片、单元格 XML 解析失败后改用简单方法提取
+  /// 
+  /// 默认为 None, 不输出任何警告
+  pub on_warning: Option<WarningHook>,
+  /// 最先渲染的工作表名称 (workbook 中显示的名称, 如 `"Config"`), 其余工作表仍按文件名顺序渲染
+  /// 
+  /// 该工作表中 `{{set_data}}` 写入的数据对之后渲染的所有工作表可见, 不依赖工作表文件的排序;
+  /// 找不到该名称的工作表时输出警告并按原顺序渲染
+  pub first_sheet: Option<String>,
+  /// 调试模式: `{{log value}}` 的输出作为警告交给 `on_warning`, 格式为 `"<sheet 路径>: log: <value>"`
+  /// 
+  /// 默认为 false, `{{log}}` 不输出任何内容
+  pub debug: bool,
+  /// 输出部件的 XML 声明省略 `standalone="yes"`, 即 `<?xml version="1.0" encoding="UTF-8"?>`
+  /// 
+  /// 无论是否设置, 渲染生成或修改的 XML 部件 (包括 rels) 都使用相同的声明, 其中缺少声明的部件也会补上;
+  /// 模板中未被改动的部件原样输出
+  pub omit_xml_standalone: bool,
 }
 
 pub fn render_template(
   zip_bytes: Vec<u8>,
   data: &Value,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  render_template_from_reader(Cursor::new(zip_bytes), data)
+}
+
+/// 使用渲染选项渲染模板, 参见 [`RenderOptions`]
+pub fn render_template_with_options(
+  zip_bytes: Vec<u8>,
+  data: &Value,
+  options: RenderOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let mut output = Vec::new();
+  render_reader_to_writer(Cursor::new(zip_bytes), data, &HashMap::new(), Cursor::new(&mut output), options)?;
+  Ok(output)
+}
+
+/// 从任意可 Seek 的读取器渲染模板, 例如文件、内存映射或 `Cursor<&[u8]>`
+/// 调用方已经持有模板字节时可以避免复制一份 `Vec<u8>`
+pub fn render_template_from_reader<R: Read + Seek>(
+  reader: R,
+  data: &Value,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let mut output = Vec::new();
+  render_reader_to_writer(reader, data, &HashMap::new(), Cursor::new(&mut output), RenderOptions::default())?;
+  Ok(output)
+}
+
+/// 使用默认数据和覆盖数据渲染模板, 两份数据先经过 [`merge_json_values`] 深度合并
+/// 
+/// 对象按 key 递归合并, overrides 中的值优先; 数组整体替换, 不逐项合并
+pub fn render_template_merged(
+  zip_bytes: Vec<u8>,
+  base: &Value,
+  overrides: &Value,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  render_template(zip_bytes, &merge_json_values(base, overrides))
+}
+
+/// 按工作表分别提供数据的渲染器
+/// 
+/// 渲染每个工作表时, 该工作表的数据与全局数据经过 [`merge_json_values`] 深度合并, 工作表的数据优先;
+/// 没有单独提供数据的工作表只使用全局数据
+/// 
+/// ```rust,no_run
+/// use serde_json::json;
+/// use xlsx_handlebars::WorkbookRenderer;
+/// 
+/// let template = std::fs::read("template.xlsx").unwrap();
+/// let output = WorkbookRenderer::new()
+///   .global_data(json!({ "company": "ACME" }))
+///   .data_for_sheet("Summary", json!({ "total": 100 }))
+///   .data_for_sheet("Detail", json!({ "items": [{ "name": "a" }] }))
+///   .render(template)
+///   .unwrap();
+/// ```
+pub struct WorkbookRenderer {
+  global_data: Value,
+  sheet_data: HashMap<String, Value>,
+  options: RenderOptions,
+}
+
+impl Default for WorkbookRenderer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl WorkbookRenderer {
+  pub fn new() -> Self {
+    Self {
+      global_data: Value::Object(serde_json::Map::new()),
+      sheet_data: HashMap::new(),
+      options: RenderOptions::default(),
+    }
+  }
+  
+  /// 所有工作表共用的数据
+  pub fn global_data(mut self, data: Value) -> Self {
+    self.global_data = data;
+    self
+  }
+  
+  /// 指定工作表 (workbook 中显示的名称, 如 `"Summary"`) 的数据, 覆盖全局数据中的同名字段
+  /// 
+  /// 找不到该名称的工作表时输出警告, 参见 [`RenderOptions::on_warning`]
+  pub fn data_for_sheet(mut self, sheet_name: impl Into<String>, data: Value) -> Self {
+    self.sheet_data.insert(sheet_name.into(), data);
+    self
+  }
+  
+  /// 渲染选项, 参见 [`RenderOptions`]
+  pub fn options(mut self, options: RenderOptions) -> Self {
+    self.options = options;
+    self
+  }
+  
+  pub fn render(self, zip_bytes: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut output = Vec::new();
+    render_reader_to_writer(Cursor::new(zip_bytes), &self.global_data, &self.sheet_data, Cursor::new(&mut output), self.options)?;
+    Ok(output)
+  }
+}
+
+/// 渲染模板并直接写入 writer, 不在内存中保存整个输出文件, 例如直接写入 `File`
+/// ZIP 格式在写完每个文件后需要回写文件头, 因此 writer 必须实现 `Seek`
+pub fn render_template_to_writer<W: Write + Seek>(
+  zip_bytes: Vec<u8>,
+  data: &Value,
+  writer: W,
+) -> Result<(), Box<dyn std::error::Error>> {
+  render_reader_to_writer(Cursor::new(zip_bytes), data, &HashMap::new(), writer, RenderOptions::default())
+}
+
+/// 列出模板中引用的所有 handlebars 变量名和 helper 名, 不需要提供数据, 可用于生成数据录入界面
+/// 
+/// 与渲染时一样先替换共享字符串 (同时合并 sharedStrings 中被富文本分割的 handlebars 语法), 再扫描每个 sheet 中的 `{{...}}` 表达式。
+/// 不执行 merge_handlebars_in_xml, 因为它会插入 `{{removeRow}}` 等模板中没有写出的 helper
+/// 按首次出现的顺序返回去重后的名称, 包括 `{{#each}}` / `{{#if}}` 块内引用的名称;
+/// 循环内的名称按模板中的写法返回, 例如 `{{#each items}}{{name}}{{/each}}` 返回 `items` 和 `name`
+pub fn extract_template_variables(zip_bytes: Vec<u8>) -> Result<Vec<String>, XlsxError> {
+  let mut reader = Cursor::new(zip_bytes);
+  validate_xlsx_reader(&mut reader)?;
+  let mut archive = ZipArchive::new(reader).map_err(|_| XlsxError::InvalidZipFormat)?;
+  
+  let read_entry = |archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str| -> Result<String, XlsxError> {
+    let mut file = archive.by_name(name).map_err(|_| XlsxError::InvalidZipFormat)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(|e| XlsxError::XmlProcessingError(format!("{name}: {e}")))?;
+    Ok(contents)
+  };
+  
+  let shared_strings = match archive.index_for_name("xl/sharedStrings.xml") {
+    Some(_) => extract_shared_strings(&read_entry(&mut archive, "xl/sharedStrings.xml")?),
+    None => Vec::new(),
+  };
+  
+  let mut sheet_names: Vec<String> = archive.file_names()
+    .filter(|name| name.starts_with("xl/worksheets/sheet") && name.ends_with(".xml"))
+    .map(|name| name.to_string())
+    .collect();
+  sheet_names.sort();
+  
+  let mut names = Vec::new();
+  for sheet_name in sheet_names {
+    let xml_content = read_entry(&mut archive, &sheet_name)?;
+    let processing_error = |e: Box<dyn std::error::Error>| XlsxError::XmlProcessingError(format!("{sheet_name}: {e}"));
+    let xml_content = replace_shared_strings_in_sheet(&xml_content, &shared_strings).map_err(processing_error)?;
+    collect_handlebars_names(&xml_content, &mut names).map_err(processing_error)?;
+  }
+  Ok(names)
+}
+
+/// 列出模板中已嵌入的所有图片及其像素尺寸, 不需要渲染模板
+/// 
+/// 返回 `(路径, 宽度, 高度)`, 路径是 ZIP 内的完整路径, 如 `xl/media/image1.png` (没有开头的 `/`), 按路径排序;
+/// 无法识别格式的 media 部件 (如 EMF/WMF) 会被跳过
+pub fn list_images(zip_bytes: Vec<u8>) -> Result<Vec<(String, u32, u32)>, XlsxError> {
+  let mut reader = Cursor::new(zip_bytes);
+  validate_xlsx_reader(&mut reader)?;
+  let mut archive = ZipArchive::new(reader).map_err(|_| XlsxError::InvalidZipFormat)?;
+  
+  let mut media_names: Vec<String> = archive.file_names()
+    .filter(|name| name.starts_with("xl/media/"))
+    .map(|name| name.to_string())
+    .collect();
+  media_names.sort();
+  
+  let mut images = Vec::new();
+  for name in media_names {
+    let mut file = archive.by_name(&name).map_err(|_| XlsxError::InvalidZipFormat)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|e| XlsxError::XmlProcessingError(format!("{name}: {e}")))?;
+    if let Some((width, height)) = get_image_dimensions(&data) {
+      images.push((name, width, height));
+    }
+  }
+  Ok(images)
+}
+
+/// 不包含 handlebars 语法的静态 sheet 内容, 包含时返回 None
+/// 检查单个 `{` 而不是 `{{`, 因为富文本可能把 `{{` 分割到不同的 run 中, 这种情况交给正常的合并和渲染流程
+/// 渲染后不再有 sharedStrings.xml, 所以有 t="s" 单元格时仍然需要内联共享字符串, 否则原样返回
+fn static_sheet_xml<'a>(sheet_xml: &'a str, shared_strings: &[String]) -> Result<Option<Cow<'a, str>>, Box<dyn std::error::Error>> {
+  if sheet_xml.contains('{') {
+    return Ok(None);
+  }
+  if !sheet_xml.contains(r#"t="s""#) {
+    return Ok(Some(Cow::Borrowed(sheet_xml)));
+  }
+  let inlined = replace_shared_strings_in_sheet(sheet_xml, shared_strings)?;
+  Ok(if inlined.contains('{') { None } else { Some(Cow::Owned(inlined)) })
+}
+
+fn render_reader_to_writer<R: Read + Seek, W: Write + Seek>(
+  mut reader: R,
+  data: &Value,
+  sheet_data: &HashMap<String, Value>,
+  mut writer: W,
+  mut options: RenderOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
   
   // 首先验证输入是否为有效的 XLSX 文件
-  validate_xlsx_format(&zip_bytes)?;
+  validate_xlsx_reader(&mut reader)?;
   
-  // 创建一个 Cursor 来读取 zip 字节
-  let cursor = Cursor::new(zip_bytes);
-  let mut archive = ZipArchive::new(cursor)?;
+  let mut archive = ZipArchive::new(reader)?;
   
   // 存储解压缩的文件内容
   let files: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::new(Mutex::new(HashMap::new()));
   
+  // 记录原始文件顺序和压缩方式, 重新压缩时保持一致, 保证相同输入得到相同输出
+  let mut entry_order: Vec<String> = Vec::with_capacity(archive.len());
+  let mut entry_compression: HashMap<String, zip::CompressionMethod> = HashMap::new();
+  // 模板中每个文件的摘要, 输出时用来判断部件是否被渲染修改过
+  let mut template_digests: HashMap<String, (usize, u64)> = HashMap::new();
+  
   // 解压缩所有文件
   for i in 0..archive.len() {
     let mut file = archive.by_index(i)?;
@@ -63,6 +351,9 @@ pub fn render_template(
     
     let mut contents = Vec::new();
     file.read_to_end(&mut contents)?;
+    entry_order.push(file_name.clone());
+    entry_compression.insert(file_name.clone(), file.compression());
+    template_digests.insert(file_name.clone(), part_digest(&contents));
     files.lock().unwrap().insert(file_name, contents);
   }
   
@@ -70,31 +361,15 @@ pub fn render_template(
   
   // 处理 sharedStrings.xml 文件
   // 把 sst 标签中的 si 标签 解析出来放到数组中, 其中的 si 标签换成 is 标签
+  // 渲染后所有 sheet 都使用 inlineStr, 不再需要 sharedStrings.xml, 连同它的引用一起删除
   let mut shared_strings = Vec::new();
   {
     let file_name = "xl/sharedStrings.xml";
     let contents = files.lock().unwrap().remove(file_name);
     if let Some(contents) = contents {
-      let xml_content = String::from_utf8(contents.clone())?;
-      let mut start = 0;
-      while let Some(si_start) = xml_content[start..].find("<si>") {
-        let abs_start = start + si_start;
-        if let Some(si_end) = xml_content[abs_start..].find("</si>") {
-          let abs_end = abs_start + si_end + "</si>".len();
-          let si_xml = &xml_content[abs_start..abs_end];
-          // 将 si 标签替换为 is 标签
-          let is_xml = si_xml
-            .replace("<si>", "<is>")
-            .replace("</si>", "</is>");
-          shared_strings.push(is_xml);
-          start = abs_end;
-        } else {
-          break;
-        }
-      }
-      let xml_content = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="0" uniqueCount="0"></sst>"#.to_string();
-      let contents = xml_content.into_bytes();
-      files.lock().unwrap().insert(file_name.to_string(), contents);
+      let xml_content = String::from_utf8(contents)?;
+      shared_strings = extract_shared_strings(&xml_content);
+      remove_shared_strings_references(&mut files.lock().unwrap())?;
     }
   }
   
@@ -107,28 +382,84 @@ pub fn render_template(
   let data1 = Arc::new(Mutex::new(data.clone()));
   let data2 = Arc::clone(&data1);
   
+  // 注册 set_data helper - 写入全局数据, 之后渲染的 sheet 可以读取
+  // 用法: {{set_data "total" 100}}, 支持点号路径: {{set_data "totals.grand" (sum items "price")}}
   handlebars.register_helper("set_data", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     if let Some(key) = h.param(0).and_then(|v| v.value().as_str())
       && let Some(value) = h.param(1) {
         let mut data2 = data2.lock().unwrap();
-        data2[key] = value.value().clone();
+        crate::utils::set_value_by_path(&mut data2, key, value.value().clone())
+          .map_err(|e| RenderErrorReason::Other(format!("set_data \"{key}\": {e}")))?;
       }
     Ok(())
   }));
   
+  // {{log}} 输出的调试信息, 每个 sheet 渲染后作为警告交给 on_warning
+  let debug_logs: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+  let debug_logs2 = Arc::clone(&debug_logs);
+  let debug = options.debug;
+  
+  // 注册 log helper, 覆盖 handlebars 内置的 log, 不输出任何内容到单元格
+  // 只有 RenderOptions::debug 为 true 时才记录, 多个参数以空格分隔
+  // 用法: {{#each items}}{{log @index name}}{{/each}}
+  handlebars.register_helper("log", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    if debug {
+      let text: Vec<String> = h.params().iter().map(|param| crate::utils::value_to_text(param.value())).collect();
+      debug_logs2.lock().unwrap().push(text.join(" "));
+    }
+    Ok(())
+  }));
+  
   // sheet_name
   let sheet_name = Arc::new(Mutex::new(String::new()));
   let sheet_name2 = Arc::clone(&sheet_name);
+  let sheet_name7 = Arc::clone(&sheet_name);
+  
+  // sheet 文件路径 -> 工作表显示名称, 渲染前从 workbook.xml 读取一次
+  let sheet_display_names = sheet_display_names(&files.lock().unwrap())?;
+  
+  // 输出当前工作表的显示名称 (workbook.xml 中的 name, 不是 xl/worksheets/sheet1.xml 这样的文件路径)
+  // 用法: {{_sheetName}} 或 {{currentSheet}}
+  // 同一次渲染中通过 setCurrentSheetName 修改的名称不会体现在这里
+  let sheet_name_helper = move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let current_sheet = sheet_name7.lock().unwrap();
+    if let Some(name) = sheet_display_names.get(current_sheet.as_str()) {
+      out.write(&quick_xml::escape::partial_escape(name))?;
+    }
+    Ok(())
+  };
+  handlebars.register_helper("_sheetName", Box::new(sheet_name_helper.clone()));
+  handlebars.register_helper("currentSheet", Box::new(sheet_name_helper));
+  
+  // helper 之间共享的状态使用 Arc<Mutex<_>>:
+  // Handlebars::register_helper 要求 helper 为 Send + Sync, 不能使用 Rc<RefCell<_>>
+  // 每次调用 render_template 都会新建状态, 渲染是单线程的, 锁不会发生竞争
+  // 实测 20000 行 x 5 列的行循环渲染约 340ms, 其中约 58 万次无竞争加锁共约 13ms (< 4%)
   
   // 行号偏移量
   let row_offset: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
   let row_offset2 = Arc::clone(&row_offset);
-  let row_offset3 = Arc::clone(&row_offset);
-  let row_offset4 = Arc::clone(&row_offset);
   let row_offset5 = Arc::clone(&row_offset);
   let row_offset6 = Arc::clone(&row_offset);
   let row_offset_for_remove = Arc::clone(&row_offset);  // 用于 removeRow helper
   
+  // 正在渲染的单元格 (列号, 行号), 已包含行列偏移
+  // 在 row / c 标签的 r 属性中由 set_row_inline / set_col_inline 记录, 单元格内的 _c / _r / _cr helper 都读取这里记录的位置
+  // 循环结束的单元格中偏移量会在单元格文本之前累加, 如果单元格内实时读取偏移量会多算一次, 例如按列循环中 (concat (_c) (_r)) 指向下一列
+  let current_cell: Arc<Mutex<(u32, u32)>> = Arc::new(Mutex::new((1, 1)));
+  let current_cell2 = Arc::clone(&current_cell);
+  let current_cell5 = Arc::clone(&current_cell);
+  let current_cell6 = Arc::clone(&current_cell);
+  let current_cell7 = Arc::clone(&current_cell);
+  let current_cell8 = Arc::clone(&current_cell);
+  let current_cell9 = Arc::clone(&current_cell);
+  let current_cell10 = Arc::clone(&current_cell);
+  
+  // 已输出的 (上一行, 当前行) 的最终行号, 用于 lastRow helper
+  let rendered_rows: Arc<Mutex<(u32, u32)>> = Arc::new(Mutex::new((0, 0)));
+  let rendered_rows2 = Arc::clone(&rendered_rows);
+  let rendered_rows3 = Arc::clone(&rendered_rows);
+  
   // row_offset_plus 接收参数, 每次调用加上参数的值
   handlebars.register_helper("row_offset_plus", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     if let Some(value) = h.param(0).and_then(|v| v.value().as_u64()) {
@@ -141,6 +472,7 @@ pub fn render_template(
   handlebars.register_helper("row_offset_reset", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     let mut offset = row_offset.lock().unwrap();
     *offset = 0;
+    *rendered_rows.lock().unwrap() = (0, 0);
     Ok(())
   }));
   
@@ -154,33 +486,40 @@ pub fn render_template(
   // 当前行号
   let row_inline = Arc::new(Mutex::new(1u32));
   let row_inline2 = Arc::clone(&row_inline);
-  let row_inline3 = Arc::clone(&row_inline);
   let row_inline4 = Arc::clone(&row_inline);
-  let row_inline5 = Arc::clone(&row_inline);
   
-  // 设置当前行号
+  // 设置当前行号, 同时记录最终行号 = 当前行号 row_inline + 行号偏移量 row_offset
   handlebars.register_helper("set_row_inline", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     if let Some(value) = h.param(0).and_then(|v| v.value().as_u64()) {
       let mut row_inline = row_inline2.lock().unwrap();
       *row_inline = u32::try_from(value).expect("set_row_inline too large for u32");
+      current_cell5.lock().unwrap().1 = *row_inline + *row_offset5.lock().unwrap();
     }
     Ok(())
   }));
   
-  // 获取计算后的 最终当前行号 _r = 当前行号 row_inline + 行号偏移量 row_offset
+  // 获取计算后的 最终当前行号 _r, 即 set_row_inline 记录的行号
   handlebars.register_helper("_r", Box::new(move |_h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
-    let row_inline = row_inline3.lock().unwrap();
-    let row_offset = row_offset3.lock().unwrap();
-    let r = *row_inline + *row_offset;
+    let r = current_cell6.lock().unwrap().1;
+    let mut rendered_rows = rendered_rows2.lock().unwrap();
+    if rendered_rows.1 != r {
+      *rendered_rows = (rendered_rows.1, r);
+    }
     out.write(&r.to_string())?;
     Ok(())
   }));
   
+  // 注册 lastRow helper - 获取当前行之前最后输出的一行的行号 (已包含循环产生的偏移)
+  // 必须放在循环之后的行中使用, 例如合计行: =SUM(B2:B{{lastRow}})
+  handlebars.register_helper("lastRow", Box::new(move |_h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let rendered_rows = rendered_rows3.lock().unwrap();
+    out.write(&rendered_rows.0.to_string())?;
+    Ok(())
+  }));
+  
   // 列号偏移量
   let col_offset: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
   let col_offset2 = Arc::clone(&col_offset);
-  let col_offset3 = Arc::clone(&col_offset);
-  let col_offset4 = Arc::clone(&col_offset);
   let col_offset5 = Arc::clone(&col_offset);
   let col_offset6 = Arc::clone(&col_offset);
   
@@ -209,24 +548,21 @@ pub fn render_template(
   // 当前列号
   let col_inline = Arc::new(Mutex::new(1u32));
   let col_inline2 = Arc::clone(&col_inline);
-  let col_inline3 = Arc::clone(&col_inline);
   let col_inline4 = Arc::clone(&col_inline);
-  let col_inline5 = Arc::clone(&col_inline);
   
-  // 设置当前列号
+  // 设置当前列号, 同时记录最终列号 = 当前列号 col_inline + 列号偏移量 col_offset
   handlebars.register_helper("set_col_inline", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     if let Some(value) = h.param(0).and_then(|v| v.value().as_u64()) {
       let mut col_inline = col_inline2.lock().unwrap();
       *col_inline = u32::try_from(value).expect("set_col_inline too large for u32");
+      current_cell7.lock().unwrap().0 = *col_inline + *col_offset5.lock().unwrap();
     }
     Ok(())
   }));
   
-  // 获取计算后的 最终当前列号 _c = 当前列号 col_inline + 列号偏移量 col_offset
+  // 获取计算后的 最终当前列号 _c, 即 set_col_inline 记录的列号
   handlebars.register_helper("_c", Box::new(move |_h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
-    let col_inline = col_inline3.lock().unwrap();
-    let col_offset = col_offset3.lock().unwrap();
-    let c_num = *col_inline + *col_offset;
+    let c_num = current_cell8.lock().unwrap().0;
     let c_str = to_column_name("A", c_num - 1); // 列号从 1 开始, 需要减 1
     out.write(&c_str)?;
     Ok(())
@@ -248,10 +584,10 @@ pub fn render_template(
   // 如果这两个参数都存在, 则使用这两个参数计算最终的列号和行号
   // 如果参数不存在, 则使用当前的列号和行号, 保持之前的逻辑
   handlebars.register_helper("_cr", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
-    let col_inline = col_inline4.lock().unwrap();
-    let col_offset = col_offset4.lock().unwrap();
-    let row_inline = row_inline4.lock().unwrap();
-    let row_offset = row_offset4.lock().unwrap();
+    let (c_num, r_num) = *current_cell2.lock().unwrap();
+    // 当前单元格开始时的偏移量, 与 c_num / r_num 使用同一时刻的值
+    let col_offset = c_num.saturating_sub(*col_inline4.lock().unwrap());
+    let row_offset = r_num.saturating_sub(*row_inline4.lock().unwrap());
     
     // 处理第一个参数: 初始列号
     let c_str = if let Some(param) = h.param(0) {
@@ -260,40 +596,62 @@ pub fn render_template(
         // 将列名转换为列索引
         let col_index = crate::utils::to_column_index(col_name);
         // 计算最终列索引
-        let final_col_index = col_index + *col_offset;
+        let final_col_index = col_index + col_offset;
         // 转换回列名
         to_column_name("A", final_col_index.saturating_sub(1)) // 列号从 1 开始, 需要减 1
       } else if param.value().is_number() {
         let col_num = param.value().as_u64().unwrap_or(1) as u32;
-        let final_col_num = col_num + *col_offset;
+        let final_col_num = col_num + col_offset;
         to_column_name("A", final_col_num.saturating_sub(1)) // 列号从 1 开始, 需要减 1
       } else {
         // 非法类型则使用当前列号
-        let c_num = *col_inline + *col_offset;
         to_column_name("A", c_num.saturating_sub(1)) // 列号从 1 开始, 需要减 1
       }
     } else {
       // 没有参数则使用当前列号
-      let c_num = *col_inline + *col_offset;
       to_column_name("A", c_num.saturating_sub(1)) // 列号从 1 开始, 需要减 1
     };
     
     // 处理第二个参数: 行号
-    let r_num = if let Some(param) = h.param(1).and_then(|v| v.value().as_u64()) {
-      let r = param as u32 + *row_offset; // 行号需要加上偏移量
-      r
-    } else {
-      *row_inline + *row_offset // 使用当前行号
+    let r_num = match h.param(1).and_then(|v| v.value().as_u64()) {
+      Some(param) => param as u32 + row_offset, // 行号需要加上偏移量
+      None => r_num, // 使用当前行号
     };
     
-    out.write(&format!("{c_str}{r_num}"))?;
+    // abs=true 时输出绝对引用, 如 $B$2
+    if h.hash_get("abs").is_some_and(|v| v.value().as_bool() == Some(true)) {
+      out.write(&format!("${c_str}${r_num}"))?;
+    } else {
+      out.write(&format!("{c_str}{r_num}"))?;
+    }
     
     Ok(())
   }));
   
+  // 注册 cellRef helper - 输出相对当前单元格偏移 dcol 列、drow 行的单元格引用, 只读取位置, 不修改任何状态
+  // 用法: {{cellRef 2 0}} -> 同一行右边第 2 列, {{formula (concat "=" (cellRef -1 0) "*2")}}
+  // 绝对引用: {{cellRef 0 -1 abs=true}} -> $B$1, {{_cr abs=true}} 同样支持
+  // 偏移超出第 1 行或 A 列时取第 1 行或 A 列
+  handlebars.register_helper("cellRef", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let dcol = h.param(0).and_then(|v| v.value().as_i64()).unwrap_or(0);
+    let drow = h.param(1).and_then(|v| v.value().as_i64()).unwrap_or(0);
+    let (c_num, r_num) = *current_cell10.lock().unwrap();
+    let col = (c_num as i64 + dcol).clamp(1, u32::MAX as i64) as u32;
+    let row = (r_num as i64 + drow).clamp(1, u32::MAX as i64) as u32;
+    let col_name = to_column_name("A", col - 1);
+    if h.hash_get("abs").is_some_and(|v| v.value().as_bool() == Some(true)) {
+      out.write(&format!("${col_name}${row}"))?;
+    } else {
+      out.write(&format!("{col_name}{row}"))?;
+    }
+    Ok(())
+  }));
+  
   // 标记删除行的 helper
   // 用法: {{#each items}}...{{else}}<row><c><v>{{removeRow}}</v></c></row>{{/each}}
   // 重要: 会减少 row_offset，确保后续行号正确
+  // 按行循环如果没有 {{else}} 且 {{#each 所在行没有其他内容, merge 阶段会自动补上 {{else}}{{removeRow}}
+  // 已经显式写了 {{removeRow}} 的行不会再自动补充, 避免重复减少 row_offset
   handlebars.register_helper("removeRow", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     // 减少行偏移量，因为这一行会被删除
     // 这样后续行号会自动减1，避免出现空白行
@@ -305,6 +663,42 @@ pub fn render_template(
     Ok(())
   }));
   
+  // 标记隐藏行的 helper, 与 removeRow 不同, 行会保留在 sheet 中, 只是设置 hidden="1"
+  // 用法: {{#each items}}...{{#if detail}}{{hideRow}}{{/if}}{{/each}}
+  handlebars.register_helper("hideRow", Box::new(|_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    out.write(HIDE_ROW_KEY)?;
+    Ok(())
+  }));
+  
+  // 当前 sheet 中最大的行分组级别, 每个 sheet 渲染后取出, 写入 sheetFormatPr 的 outlineLevelRow
+  let row_outline_level: Arc<Mutex<u8>> = Arc::new(Mutex::new(0));
+  let row_outline_level2 = Arc::clone(&row_outline_level);
+  
+  // 设置当前行的分组级别 (1 到 7), 折叠的分组中明细行加上 hidden=true, 分组的汇总行加上 collapsed=true
+  // 用法: {{rowOutlineLevel 1}} 或 {{rowOutlineLevel 2 hidden=true}} 或 {{rowOutlineLevel 1 collapsed=true}}
+  handlebars.register_helper("rowOutlineLevel", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let level = h.param(0).and_then(|v| match v.value() {
+      Value::Number(n) => n.as_u64(),
+      Value::String(s) => s.trim().parse().ok(),
+      _ => None,
+    });
+    let Some(level) = level.filter(|level| (1..=7).contains(level)) else {
+      return Err(RenderErrorReason::Other("rowOutlineLevel helper requires an outline level from 1 to 7".to_string()).into());
+    };
+    let mut max_level = row_outline_level2.lock().unwrap();
+    *max_level = (*max_level).max(level as u8);
+    // 标记之间是 "级别 [collapsed] [hidden]", 后处理时写入 row 标签并删除标记
+    let mut value = level.to_string();
+    for flag in ["collapsed", "hidden"] {
+      if h.hash_get(flag).is_some_and(|v| v.value().as_bool() == Some(true)) {
+        value.push(' ');
+        value.push_str(flag);
+      }
+    }
+    out.write(&format!("{ROW_OUTLINE_KEY}{value}{ROW_OUTLINE_KEY}"))?;
+    Ok(())
+  }));
+  
   // 标记数字类型的 helper
   // 用法: <c r="{{_cr}}"><v>{{num some_value}}</v></c>
   handlebars.register_helper("num", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
@@ -328,20 +722,114 @@ pub fn render_template(
     Ok(())
   }));
   
+  // 标记布尔类型的 helper, 输出为 Excel 的 TRUE / FALSE 单元格
+  // 用法: <c r="{{_cr}}"><v>{{bool item.done}}</v></c>
+  // true、非 0 数字、字符串 "true" / "yes" / "1" (不区分大小写)、非空数组和对象为 TRUE
+  handlebars.register_helper("bool", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    out.write(TO_BOOLEAN_KEY)?; // 先写入标记，后续处理时替换
+    let is_true = match h.param(0).map(|v| v.value()) {
+      Some(Value::Bool(b)) => *b,
+      Some(Value::Number(n)) => n.as_f64().is_some_and(|n| n != 0.0),
+      Some(Value::String(s)) => matches!(s.trim().to_lowercase().as_str(), "true" | "yes" | "1"),
+      Some(Value::Array(arr)) => !arr.is_empty(),
+      Some(Value::Object(obj)) => !obj.is_empty(),
+      Some(Value::Null) | None => false,
+    };
+    out.write(if is_true { "1" } else { "0" })?;
+    Ok(())
+  }));
+  
+  // 标记错误类型的 helper, 输出为 Excel 的错误值单元格
+  // 用法: <c r="{{_cr}}"><v>{{cellError "#N/A"}}</v></c>
+  // 只支持 #N/A、#VALUE!、#REF!、#DIV/0!、#NUM!、#NAME?、#NULL!
+  handlebars.register_helper("cellError", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let error = h.param(0)
+      .and_then(|v| v.value().as_str())
+      .ok_or_else(|| RenderErrorReason::Other("cellError helper requires an error literal such as \"#N/A\"".to_string()))?
+      .trim();
+    if !EXCEL_ERROR_LITERALS.contains(&error) {
+      return Err(RenderErrorReason::Other(format!(
+        "cellError helper: unknown Excel error {error:?}, expected one of {}",
+        EXCEL_ERROR_LITERALS.join(", "),
+      )).into());
+    }
+    out.write(TO_ERROR_KEY)?; // 先写入标记，后续处理时替换
+    out.write(error)?;
+    Ok(())
+  }));
+  
+  // 设置单元格样式的 helper, 样式序号是 styles.xml 中 <cellXfs> 下 <xf> 的序号 (从 0 开始)
+  // 用法: <c r="{{_cr}}">{{#if @last}}{{cellStyle 5}}{{/if}}{{num total}}</c>
+  // 查找序号: 在 Excel 中给一个单元格设置好样式, 保存后在 sheet.xml 中查看该单元格的 s 属性
+  handlebars.register_helper("cellStyle", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let index = h.param(0).and_then(|v| match v.value() {
+      Value::Number(n) => n.as_u64(),
+      Value::String(s) => s.trim().parse().ok(),
+      _ => None,
+    });
+    let Some(index) = index else {
+      return Err(RenderErrorReason::Other("cellStyle helper requires a non-negative style index such as 5".to_string()).into());
+    };
+    // 标记之间是样式序号, 后处理时写入单元格的 s 属性并删除标记
+    out.write(&format!("{TO_STYLE_KEY}{index}{TO_STYLE_KEY}"))?;
+    Ok(())
+  }));
+  
+  // 需要自动换行的单元格 (列号, 行号), 每个 sheet 渲染后取出
+  let wrap_cells: Arc<Mutex<HashSet<(u32, u32)>>> = Arc::new(Mutex::new(HashSet::new()));
+  let wrap_cells2 = Arc::clone(&wrap_cells);
+  let current_cell11 = Arc::clone(&current_cell);
+  
+  // 在单元格中换行, 输出换行符并给单元格加上自动换行的样式
+  // 用法: {{address1}}{{br}}{{address2}}
+  handlebars.register_helper("br", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    wrap_cells2.lock().unwrap().insert(*current_cell11.lock().unwrap());
+    out.write("\n")?;
+    Ok(())
+  }));
+  
+  // 使用百分比数字格式的单元格 (列号, 行号) -> 小数位数, 每个 sheet 渲染后取出
+  let percent_cells: Arc<Mutex<HashMap<(u32, u32), u32>>> = Arc::new(Mutex::new(HashMap::new()));
+  let percent_cells2 = Arc::clone(&percent_cells);
+  let current_cell12 = Arc::clone(&current_cell);
+  
+  // 百分比数字单元格, 单元格的值保持为 0.92, 使用百分比数字格式让 Excel 显示 92%
+  // 用法: {{percentNum score}} 或 {{percentNum score 1}} (保留 1 位小数, 默认 0, 最多 20); 只需要文本时使用 percent
+  handlebars.register_helper("percentNum", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let value = h.param(0).map_or_else(|| 0.into(), |v| value_to_number(v.value()));
+    let decimals = h.param(1).and_then(|v| v.value().as_u64()).unwrap_or(0).min(20) as u32;
+    percent_cells2.lock().unwrap().insert(*current_cell12.lock().unwrap(), decimals);
+    out.write(TO_NUMBER_KEY)?;
+    out.write(&value.to_string())?;
+    Ok(())
+  }));
+  
   // 标记公式类型的 helper
   // 用法: <c r="{{_cr}}"><f>{{formula "=SUM(A1:B1)"}}</f></c>
+  // 带缓存值: {{formula "=A1+B1" 42}} 输出 <f>=A1+B1</f><v>42</v>, 便于不重新计算公式的工具直接读取结果
   handlebars.register_helper("formula", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     out.write(TO_FORMULA_KEY)?; // 先写入标记，后续处理时替换
     if let Some(param) = h.param(0) {
       if param.value().is_string() {
         let formula = param.value().as_str().unwrap();
-        out.write(formula)?;
+        // 公式中的 < > & 需要转义, 否则输出的 XML 无效, 例如 =IF(A1<B1,1,0)
+        out.write(&quick_xml::escape::partial_escape(formula))?;
       } else {
         out.write("")?; // 非字符串则输出空
       }
     } else {
       out.write("")?; // 没有参数则输出空
     }
+    // 可选的缓存值, 用第二个标记与公式分隔, 后续处理时输出为 <v>
+    if let Some(cached) = h.param(1).map(|v| v.value())
+      && !cached.is_null() {
+        let cached = match cached {
+          Value::String(s) => s.clone(),
+          v => v.to_string(),
+        };
+        out.write(TO_FORMULA_KEY)?;
+        out.write(&quick_xml::escape::partial_escape(&cached))?;
+      }
     Ok(())
   }));
   
@@ -349,6 +837,7 @@ pub fn render_template(
   // 用法: {{concat "=SUM(" (_c) "1:" (_c) "10)"}}
   // 或者: {{formula (concat "=SUM(" (_c) "1:" (_c) "10)")}}
   // 可以接受任意数量的参数，将它们全部拼接成一个字符串
+  // 参数中的子表达式在调用前按从左到右的顺序求值; (_c) / (_r) / (_cr) 读取单元格开始时记录的位置, 不受求值顺序影响
   handlebars.register_helper("concat", Box::new(|h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     let mut result = String::new();
     
@@ -359,9 +848,7 @@ pub fn render_template(
       // 根据类型转换为字符串
       if value.is_string() {
         result.push_str(value.as_str().unwrap());
-      } else if value.is_number() {
-        result.push_str(&value.to_string());
-      } else if value.is_boolean() {
+      } else if value.is_number() || value.is_boolean() {
         result.push_str(&value.to_string());
       } else if value.is_null() {
         // null 不添加任何内容
@@ -441,18 +928,204 @@ pub fn render_template(
   // 注册 mergeCell helper - 用于收集需要合并的单元格范围
   // 用法: {{mergeCell "C4:D5"}} 或 {{mergeCell (concat (_c) (_r) ":" (toColumnName (_c) 3) (_r))}}
   handlebars.register_helper("mergeCell", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
-    if let Some(ref_value) = h.param(0) {
-      if let Some(ref_str) = ref_value.value().as_str() {
-        // 简单验证格式：应该包含冒号分隔符
-        if ref_str.contains(':') {
-          let mut cells = merge_cells2.lock().unwrap();
-          cells.push(ref_str.to_string());
+    if let Some(ref_value) = h.param(0)
+      && let Some(ref_str) = ref_value.value().as_str() {
+        // 反向的范围 (如 "B2:A1") 按左上角到右下角写入, 单个单元格和格式不正确的范围忽略
+        if let Some(((start_col, start_row), (end_col, end_row))) = normalize_cell_range(ref_str)
+          && (start_col, start_row) != (end_col, end_row) {
+            let mut cells = merge_cells2.lock().unwrap();
+            cells.push(format!("{}{start_row}:{}{end_row}", to_column_name("A", start_col - 1), to_column_name("A", end_col - 1)));
+          }
+      }
+    Ok(())
+  }));
+  
+  // 从当前单元格开始向下 / 向右合并 count 个单元格, count 小于 2 时不合并
+  // 用法: 循环第一行的标签单元格 {{#if @first}}{{mergeDown (len ../items)}}{{/if}}, 表头 {{mergeAcross (len months)}}
+  for (name, down) in [("mergeDown", true), ("mergeAcross", false)] {
+    let merge_cells3 = Arc::clone(&merge_cells);
+    let current_cell13 = Arc::clone(&current_cell);
+    handlebars.register_helper(name, Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+      let count = h.param(0).and_then(|v| match v.value() {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+      });
+      let Some(count) = count.and_then(|count| u32::try_from(count).ok()) else {
+        return Err(RenderErrorReason::Other(format!("{name} helper requires a non-negative cell count such as {{{{{name} 3}}}}")).into());
+      };
+      if count < 2 {
+        return Ok(());
+      }
+      let (c, r) = *current_cell13.lock().unwrap();
+      // 结束单元格不能超出工作表范围: 列不超过 XFD (16384), 行不超过 1048576
+      let end = if down {
+        r.checked_add(count - 1).filter(|end_r| *end_r <= 1048576).map(|end_r| (c, end_r))
+      } else {
+        c.checked_add(count - 1).filter(|end_c| *end_c <= 16384).map(|end_c| (end_c, r))
+      };
+      let Some((end_c, end_r)) = end else {
+        return Err(RenderErrorReason::Other(format!("{name} {count} from {}{r} goes beyond the last {} of the sheet", to_column_name("A", c - 1), if down { "row" } else { "column" })).into());
+      };
+      merge_cells3.lock().unwrap().push(format!("{}{r}:{}{end_r}", to_column_name("A", c - 1), to_column_name("A", end_c - 1)));
+      Ok(())
+    }));
+  }
+  
+  // 手动分页符 (行分页位置, 列分页位置), 每个 sheet 渲染后取出
+  let page_breaks: Arc<Mutex<(Vec<u32>, Vec<u32>)>> = Arc::new(Mutex::new((Vec::new(), Vec::new())));
+  let page_breaks2 = Arc::clone(&page_breaks);
+  let page_breaks3 = Arc::clone(&page_breaks);
+  let current_cell3 = Arc::clone(&current_cell);
+  let current_cell4 = Arc::clone(&current_cell);
+  
+  // 在当前行之前插入分页符
+  // 用法: 在每个分节的第一行写 {{pageBreakBefore}}, 例如 {{#each sections}}{{#unless @first}}{{pageBreakBefore}}{{/unless}}{{name}}...{{/each}}
+  handlebars.register_helper("pageBreakBefore", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let r = current_cell3.lock().unwrap().1;
+    // 分页符记录在上一行之后, 第 1 行之前不能分页
+    if r > 1 {
+      page_breaks2.lock().unwrap().0.push(r - 1);
+    }
+    Ok(())
+  }));
+  
+  // 在当前列之前插入分页符
+  // 用法: {{colPageBreak}}
+  handlebars.register_helper("colPageBreak", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let c = current_cell4.lock().unwrap().0;
+    // 分页符记录在上一列之后, 第 A 列之前不能分页
+    if c > 1 {
+      page_breaks3.lock().unwrap().1.push(c - 1);
+    }
+    Ok(())
+  }));
+  
+  // 打印区域 (sheet 文件路径 -> 单元格范围), 渲染结束后写入 workbook.xml 的 definedNames
+  let print_areas: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+  let print_areas2 = Arc::clone(&print_areas);
+  let sheet_name8 = Arc::clone(&sheet_name);
+  
+  // 设置当前工作表的打印区域
+  // 用法: {{printArea "A1:G50"}} 或在最后一行写 {{printArea (concat "A1:G" (_r))}}
+  // 同一个工作表多次调用时以最后一次为准
+  handlebars.register_helper("printArea", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    if let Some(range) = h.param(0).and_then(|v| v.value().as_str())
+      && !range.is_empty() {
+        let current_sheet = sheet_name8.lock().unwrap().clone();
+        if !current_sheet.is_empty() {
+          print_areas2.lock().unwrap().insert(current_sheet, range.to_string());
         }
       }
+    Ok(())
+  }));
+  
+  // 全局定义名称 (名称, 引用), 渲染结束后写入 workbook.xml 的 definedNames
+  let global_names: Arc<Mutex<Vec<(String, String)>>> = Arc::new(Mutex::new(Vec::new()));
+  let global_names2 = Arc::clone(&global_names);
+  
+  // 创建工作簿级别的定义名称（命名区域）, 可以在公式中直接引用
+  // 用法: {{defineName "TaxRate" "Config!$B$2"}} 或 {{defineName "Items" (concat "Sheet1!$A$2:$A$" (_r))}}
+  // 名称必须以字母、下划线或反斜杠开头, 不能包含空格, 不能与单元格引用（如 A1、R1C1）相同
+  handlebars.register_helper("defineName", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let (Some(name), Some(reference)) = (
+      h.param(0).and_then(|v| v.value().as_str()),
+      h.param(1).and_then(|v| v.value().as_str()),
+    ) else {
+      return Err(RenderErrorReason::Other("defineName helper requires a name and a reference such as \"Config!$B$2\"".to_string()).into());
+    };
+    if !is_valid_defined_name(name) {
+      return Err(RenderErrorReason::Other(format!("defineName helper: invalid defined name {name:?}")).into());
+    }
+    let mut names = global_names2.lock().unwrap();
+    // 同名时以最后一次为准, Excel 中名称不区分大小写
+    names.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+    names.push((name.to_string(), reference.to_string()));
+    Ok(())
+  }));
+  
+  // 输出引用其他工作表单元格的引用, 工作表名称按 Excel 规则只在需要时加引号
+  // 用法: {{sheetRef "Summary" "A1"}} -> Summary!A1, {{sheetRef "Q1 Sales" "B2"}} -> 'Q1 Sales'!B2
+  // 配合公式: {{formula (concat "=" (sheetRef "Q1 Sales" "B2") "*2")}}
+  handlebars.register_helper("sheetRef", Box::new(|h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let sheet = h.param(0).and_then(|v| v.value().as_str()).unwrap_or_default();
+    let cell = h.param(1).map(|v| crate::utils::value_to_text(v.value())).unwrap_or_default();
+    out.write(&quick_xml::escape::partial_escape(format!("{}!{}", quote_sheet_name(sheet), cell)))?;
+    Ok(())
+  }));
+  
+  // 条件格式规则, 每个 sheet 渲染后取出
+  let conditional_formats: Arc<Mutex<Vec<ConditionalFormatRule>>> = Arc::new(Mutex::new(Vec::new()));
+  let conditional_formats2 = Arc::clone(&conditional_formats);
+  
+  // 按比较规则给单元格设置颜色
+  // 用法: {{conditionalFormat "A2:A100" "lessThan" 0 "red"}} 或 {{conditionalFormat (concat "B2:B" (_r)) "equal" "done" "green"}}
+  // 运算符: lessThan、lessThanOrEqual、greaterThan、greaterThanOrEqual、equal、notEqual
+  // 颜色: red、green、yellow、blue、gray, 或者填充色 "#FFC7CE"
+  handlebars.register_helper("conditionalFormat", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let usage = || RenderErrorReason::Other("conditionalFormat helper usage: {{conditionalFormat \"A2:A100\" \"lessThan\" 0 \"red\"}}".to_string());
+    let sqref = h.param(0).and_then(|v| v.value().as_str()).filter(|s| !s.is_empty()).ok_or_else(usage)?;
+    let operator = h.param(1).and_then(|v| v.value().as_str()).ok_or_else(usage)?;
+    let value = h.param(2).map(|v| v.value()).ok_or_else(usage)?;
+    let color = h.param(3).and_then(|v| v.value().as_str()).unwrap_or("red");
+    
+    if !matches!(operator, "lessThan" | "lessThanOrEqual" | "greaterThan" | "greaterThanOrEqual" | "equal" | "notEqual") {
+      return Err(RenderErrorReason::Other(format!("conditionalFormat helper: unsupported operator {operator:?}")).into());
     }
+    let Some(dxf) = conditional_format_dxf(color) else {
+      return Err(RenderErrorReason::Other(format!("conditionalFormat helper: unsupported color {color:?}")).into());
+    };
+    // 数字原样比较, 其他文本作为字符串比较
+    let formula = match value {
+      Value::Number(n) => n.to_string(),
+      Value::Bool(b) => if *b { "TRUE".to_string() } else { "FALSE".to_string() },
+      Value::String(s) if s.trim().parse::<f64>().is_ok_and(|n| n.is_finite()) => s.trim().to_string(),
+      other => format!("\"{}\"", crate::utils::value_to_text(other).replace('"', "\"\"")),
+    };
+    
+    conditional_formats2.lock().unwrap().push(ConditionalFormatRule {
+      sqref: sqref.to_string(),
+      operator: operator.to_string(),
+      formula,
+      dxf,
+    });
+    Ok(())
+  }));
+  
+  // 工作表标签颜色 (ARGB), 每个 sheet 渲染后取出
+  let sheet_tab_color: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+  let sheet_tab_color2 = Arc::clone(&sheet_tab_color);
+  
+  // 设置当前工作表的标签颜色
+  // 用法: {{setSheetTabColor "FF0000"}} 或 {{setSheetTabColor "#FF0000"}}, 也可以传入 8 位 ARGB 例如 "80FF0000"
+  handlebars.register_helper("setSheetTabColor", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let color = h.param(0).and_then(|v| v.value().as_str()).ok_or_else(|| {
+      RenderErrorReason::Other("setSheetTabColor helper usage: {{setSheetTabColor \"FF0000\"}}".to_string())
+    })?;
+    let Some(argb) = tab_color_argb(color) else {
+      return Err(RenderErrorReason::Other(format!("setSheetTabColor helper: invalid color {color:?}, expected RRGGBB or AARRGGBB hex")).into());
+    };
+    *sheet_tab_color2.lock().unwrap() = Some(argb);
     Ok(())
   }));
   
+  // 工作表视图 <sheetView> 上要设置的属性, 每个 sheet 渲染后取出
+  let sheet_view_attributes: Arc<Mutex<Vec<(&'static str, &'static str)>>> = Arc::new(Mutex::new(Vec::new()));
+  for (name, attribute, value) in [
+    // 隐藏网格线, 用法: {{hideGridlines}}
+    ("hideGridlines", "showGridLines", "0"),
+    // 隐藏行号列标, 用法: {{hideHeaders}}
+    ("hideHeaders", "showRowColHeaders", "0"),
+    // 从右到左显示工作表, 用于阿拉伯语、希伯来语报表, 用法: {{setSheetRTL}}
+    ("setSheetRTL", "rightToLeft", "1"),
+  ] {
+    let sheet_view_attributes2 = Arc::clone(&sheet_view_attributes);
+    handlebars.register_helper(name, Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+      sheet_view_attributes2.lock().unwrap().push((attribute, value));
+      Ok(())
+    }));
+  }
+  
   // 注册 hyperlink helper - 用于在 Excel 中添加超链接
   // 新用法: {{hyperlink (_cr) "Sheet2!A1" "链接文本"}}
   // 参数1: ref - 单元格引用（如 "A26"），通常使用 (_cr) 自动计算
@@ -487,11 +1160,12 @@ pub fn render_template(
       hyperlinks_by_sheet2
         .lock().unwrap()
         .entry(current_sheet)
-        .or_insert_with(Vec::new)
+        .or_default()
         .push(crate::utils::HyperlinkInfo {
           ref_cell,
           location: location.to_string(),
           display,
+          rel_id: None,
         });
     }
     
@@ -500,41 +1174,91 @@ pub fn render_template(
   
   // 注册 img helper - 用于在 Excel 中插入图片
   // 用法: {{img "base64数据" 100 200}} 或 {{img image.data image.width image.height}}
+  // 指定锚点单元格: {{img image.data 100 200 anchor="G3"}} 或 {{img image.data anchor=(_cr "G" 3)}}
   handlebars.register_helper("img", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     // 获取参数
     let base64_data = h.param(0).and_then(|v| v.value().as_str());
     if base64_data.is_none() || base64_data.unwrap().is_empty() {
       return Ok(()); // 没有图片数据，直接返回
     }
-    let base64_data = base64_data.unwrap();
+    // 兼容 data URI: "data:image/jpeg;base64,/9j/..."
+    let (extension, base64_data) = split_image_data_uri(base64_data.unwrap());
     
     let width = h.param(1).and_then(|v| v.value().as_u64()).map(|w| w as u32);
     let height = h.param(2).and_then(|v| v.value().as_u64()).map(|h| h as u32);
     
-    // 获取当前单元格位置
-    let col = *col_inline5.lock().unwrap() + *col_offset5.lock().unwrap();
-    let row = *row_inline5.lock().unwrap() + *row_offset5.lock().unwrap();
+    // 获取锚点单元格位置: 优先使用 anchor="G3" 参数, 否则使用当前单元格
+    let anchor = h.hash_get("anchor")
+      .and_then(|v| v.value().as_str())
+      .and_then(crate::utils::parse_cell_ref);
+    let (col, row) = match anchor {
+      Some((col, row)) => (col, row),
+      None => *current_cell9.lock().unwrap(),
+    };
     
     // 获取当前 sheet 名称
     let current_sheet = sheet_name3.lock().unwrap().clone();
     
     if !current_sheet.is_empty() {
-      // 生成唯一的 rid（使用 UUID 避免与模板中现有 ID 冲突）
-      let rid = Uuid::new_v4().to_string().replace("-", "");
-      let rid = format!("rId{}", &rid[..16]); // 例如: rId1234567890abcdef
+      // 生成唯一的图片文件名（使用 UUID 避免与模板中现有图片冲突）
+      let uuid = Uuid::new_v4().to_string().replace("-", "");
+      let media_name = format!("image{}", &uuid[..16]); // 例如: image1234567890abcdef
       
       // 添加图片信息
       images_by_sheet2
         .lock().unwrap()
         .entry(current_sheet)
-        .or_insert_with(Vec::new)
+        .or_default()
         .push(ImageInfo {
           col,
           row,
           base64_data: base64_data.to_string(),
           width,
           height,
-          rid,
+          media_name,
+          extension: extension.to_string(),
+          fit_to: None,
+        });
+    }
+    
+    Ok(()) // 不输出任何内容
+  }));
+  
+  // 注册 imgFit helper - 插入图片并缩放填满指定的单元格范围 (twoCellAnchor)
+  // 用法: {{imgFit logo "B2:D4"}}
+  // 图片随单元格移动和缩放, 会拉伸到整个范围, 不保持原始宽高比
+  let images_by_sheet3 = Arc::clone(&images_by_sheet);
+  let sheet_name10 = Arc::clone(&sheet_name);
+  handlebars.register_helper("imgFit", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let base64_data = match h.param(0).and_then(|v| v.value().as_str()) {
+      Some(data) if !data.is_empty() => data,
+      _ => return Ok(()), // 没有图片数据，直接返回
+    };
+    let (extension, base64_data) = split_image_data_uri(base64_data);
+    
+    let range = h.param(1).and_then(|v| v.value().as_str()).unwrap_or_default();
+    let cells = range.split_once(':')
+      .and_then(|(start, end)| Some((crate::utils::parse_cell_ref(start)?, crate::utils::parse_cell_ref(end)?)));
+    let Some(((start_col, start_row), (end_col, end_row))) = cells else {
+      return Err(RenderErrorReason::Other(format!("imgFit: invalid cell range \"{}\", expected e.g. \"B2:D4\"", range)).into());
+    };
+    
+    let current_sheet = sheet_name10.lock().unwrap().clone();
+    if !current_sheet.is_empty() {
+      let uuid = Uuid::new_v4().to_string().replace("-", "");
+      images_by_sheet3
+        .lock().unwrap()
+        .entry(current_sheet)
+        .or_default()
+        .push(ImageInfo {
+          col: start_col.min(end_col),
+          row: start_row.min(end_row),
+          base64_data: base64_data.to_string(),
+          width: None,
+          height: None,
+          media_name: format!("image{}", &uuid[..16]),
+          extension: extension.to_string(),
+          fit_to: Some((start_col.max(end_col), start_row.max(end_row))),
         });
     }
     
@@ -569,7 +1293,7 @@ pub fn render_template(
   // 用法: {{setCurrentSheetName "新名称"}} 或 {{setCurrentSheetName (concat department.name " - " year)}}
   // 注意:
   // 1. 工作表名称不能包含：\ / ? * [ ]
-  // 2. 名称长度不能超过 31 个字符
+  // 2. 名称长度不能超过 31 个字符 (按 UTF-16 计数, 与 Excel 一致, 中文每个字算 1 个, emoji 算 2 个), 超出部分截掉
   // 3. 不能与现有工作表重名（会自动处理）
   handlebars.register_helper("setCurrentSheetName", Box::new(move |h: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
     if let Some(new_name) = h.param(0).and_then(|v| v.value().as_str()) {
@@ -579,8 +1303,8 @@ pub fn render_template(
         let clean_name: String = new_name
           .chars()
           .filter(|c| !matches!(c, '\\' | '/' | '?' | '*' | '[' | ']'))
-          .take(31)
           .collect();
+        let clean_name = truncate_sheet_name(&clean_name, SHEET_NAME_MAX_LEN);
         
         if !clean_name.is_empty() {
           sheets_to_rename2.lock().unwrap().insert(current_sheet, clean_name);
@@ -590,7 +1314,7 @@ pub fn render_template(
     Ok(())
   }));
   
-  // 用于收集需要隐藏的工作表（sheet_path -> hide_type）
+  // 用于收集需要隐藏或显示的工作表（sheet_path -> hide_type 或 "visible"）
   let sheets_to_hide: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
   let sheets_to_hide2 = Arc::clone(&sheets_to_hide);
   let sheet_name6 = Arc::clone(&sheet_name);
@@ -618,6 +1342,23 @@ pub fn render_template(
     Ok(())
   }));
   
+  let sheets_to_hide3 = Arc::clone(&sheets_to_hide);
+  let sheet_name9 = Arc::clone(&sheet_name);
+  
+  // 显示当前工作表的 helper, 用于模板中默认隐藏、按条件显示的工作表
+  // 用法: {{#if isAdmin}}{{showCurrentSheet}}{{/if}}
+  // 与 hideCurrentSheet 作用于同一个工作表时, 以最后一次调用为准
+  handlebars.register_helper("showCurrentSheet", Box::new(move |_: &handlebars::Helper, _: &Handlebars, _: &handlebars::Context, _: &mut handlebars::RenderContext, _out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
+    let current_sheet = sheet_name9.lock().unwrap().clone();
+    if !current_sheet.is_empty() {
+      sheets_to_hide3.lock().unwrap().insert(current_sheet, "visible".to_string());
+    }
+    Ok(())
+  }));
+  
+  // 渲染过程中的警告, 渲染结束后交给 on_warning
+  let mut warnings: Vec<String> = Vec::new();
+  
   // 遍历 sheet.xml 找到所有 t="s" 的 c 标签, 把 v 标签中的数字替换成对应的字符串
   // 例如: <c r="A1" t="s"><v>0</v></c> 替换成 <c r="A1" t="inlineStr"><is><t>字符串内容</t></is></c>
   {
@@ -628,13 +1369,77 @@ pub fn render_template(
       .cloned()
       .collect();
     sheet_names.sort();
+    // data_for_sheet 按显示名称提供数据, sheet 文件路径 -> 该工作表的数据
+    let mut data_by_path: HashMap<String, &Value> = HashMap::new();
+    if !sheet_data.is_empty() {
+      let display_names = workbook_sheets(&files)?;
+      for (name, value) in sheet_data {
+        match display_names.iter().find(|(_, display_name)| display_name == name) {
+          Some((path, _)) => {
+            data_by_path.insert(path.clone(), value);
+          }
+          None => warnings.push(format!("data_for_sheet: sheet {name:?} not found")),
+        }
+      }
+    }
+    if let Some(first_sheet) = options.first_sheet.as_deref() {
+      let path = workbook_sheets(&files)?.into_iter().find(|(_, name)| name == first_sheet).map(|(path, _)| path);
+      match path.and_then(|path| sheet_names.iter().position(|name| *name == path)) {
+        Some(index) => {
+          let name = sheet_names.remove(index);
+          sheet_names.insert(0, name);
+        }
+        None => warnings.push(format!("first_sheet: sheet {first_sheet:?} not found")),
+      }
+    }
+    
+    // 条件格式新增的差异格式, 序号接在 styles.xml 中已有的 dxf 之后
+    let existing_dxf_count = match files.get("xl/styles.xml") {
+      Some(styles) => count_dxfs(std::str::from_utf8(styles)?),
+      None => 0,
+    };
+    // 处理后的 sheet 内容 -> 注册的模板名称, 内容相同的 sheet (例如按月份复制的工作表) 共用编译后的模板
+    let mut compiled_templates: HashMap<String, String> = HashMap::new();
+    let mut new_dxfs: Vec<String> = Vec::new();
+    // {{br}} 自动换行新增的单元格格式, 原来的样式序号 -> 新的样式序号
+    let existing_xfs = match files.get("xl/styles.xml") {
+      Some(styles) => cell_xfs(std::str::from_utf8(styles)?),
+      None => Vec::new(),
+    };
+    let mut new_xfs: Vec<String> = Vec::new();
+    let mut wrap_styles: HashMap<u32, u32> = HashMap::new();
+    // {{percentNum}} 新增的自定义数字格式 (id, formatCode), (原来的样式序号, 小数位数) -> 新的样式序号
+    let first_num_fmt_id = match files.get("xl/styles.xml") {
+      Some(styles) => next_num_fmt_id(std::str::from_utf8(styles)?),
+      None => 164,
+    };
+    let mut new_num_fmts: Vec<(u32, String)> = Vec::new();
+    let mut percent_styles: HashMap<(u32, u32), u32> = HashMap::new();
 
     for sheet_name in sheet_names {
+      // 外部超链接需要写入 sheet rels, 例如 xl/worksheets/_rels/sheet1.xml.rels
+      let rels_path = sheet_name.replacen("xl/worksheets/", "xl/worksheets/_rels/", 1) + ".rels";
+      let mut sheet_rels: Option<String> = files.get(&rels_path).map(|rels| String::from_utf8_lossy(rels).into_owned());
+      let mut sheet_rels_changed = false;
+      
       if let Some(contents) = files.get_mut(&sheet_name) {
         // 设置全变量 sheet_name
         *sheet_name2.lock().unwrap() = sheet_name.clone();
         
         let xml_content = std::str::from_utf8(contents)?;
+        
+        // 没有 handlebars 语法的静态 sheet 跳过合并和渲染
+        if let Some(static_xml) = static_sheet_xml(xml_content, &shared_strings)? {
+          let static_xml = match options.auto_fit_columns {
+            true => Cow::Owned(auto_fit_columns(&static_xml, options.auto_fit_max_width.unwrap_or(60.0))?),
+            false => static_xml,
+          };
+          if let Cow::Owned(xml) = static_xml {
+            *contents = xml.into_bytes();
+          }
+          continue;
+        }
+        
         let xml_content = "{{row_offset_reset}}".to_string() + xml_content;
         
         // 提取并移除模板中已有的 mergeCells 和 hyperlinks 标签
@@ -658,51 +1463,214 @@ pub fn render_template(
         let xml_content = merge_handlebars_in_xml(xml_content)?;
         
         // 渲染模板
-        let mut xml_content = handlebars.render_template(
-          &xml_content,
-          &*data1.lock().map_err(|e| Box::new(std::io::Error::other(format!("Failed to lock data: {e}"))))?,
-        ).map_err(|e| {
+        // 使用数据快照渲染, 渲染过程中 set_data 需要再次获取 data 的锁
+        let mut data_snapshot = data1.lock().map_err(|e| Box::new(std::io::Error::other(format!("Failed to lock data: {e}"))))?.clone();
+        if let Some(sheet_value) = data_by_path.get(&sheet_name) {
+          data_snapshot = merge_json_values(&data_snapshot, sheet_value);
+        }
+        let render_error = |e: handlebars::RenderError| {
           let reason: &RenderErrorReason = e.reason();
           XlsxError::TemplateRenderError(reason.to_string())
-        })?;
+        };
+        let template_name = match compiled_templates.get(&xml_content) {
+          Some(name) => name.clone(),
+          None => {
+            // 使用 sheet 路径作为模板名称, 语法错误信息中会显示出错的 sheet
+            handlebars.register_template_string(&sheet_name, &xml_content).map_err(|e| render_error(e.into()))?;
+            compiled_templates.insert(xml_content, sheet_name.clone());
+            sheet_name.clone()
+          }
+        };
+        let mut xml_content = handlebars.render(&template_name, &data_snapshot).map_err(render_error)?;
+        // {{log}} 记录的调试信息
+        warnings.extend(std::mem::take(&mut *debug_logs.lock().unwrap()).into_iter().map(|log| format!("{sheet_name}: log: {log}")));
         
-        // 后处理：删除标记行、转换数字类型、转换公式类型等
-        if xml_content.contains(REMOVE_ROW_KEY) || xml_content.contains(TO_NUMBER_KEY) || xml_content.contains(TO_FORMULA_KEY) {
-          let remove_key = if xml_content.contains(REMOVE_ROW_KEY) { Some(REMOVE_ROW_KEY) } else { None };
-          let number_key = if xml_content.contains(TO_NUMBER_KEY) { Some(TO_NUMBER_KEY) } else { None };
-          let formula_key = if xml_content.contains(TO_FORMULA_KEY) { Some(TO_FORMULA_KEY) } else { None };
+        // 后处理：删除标记行、转换数字类型、转换公式类型、写入合并单元格和超链接、更新 dimension 等
+        // dimension 需要按展开后的行列重新计算, 所以每个 sheet 都要后处理
+        {
+          let find_key = |key: &'static str| if xml_content.contains(key) { Some(key) } else { None };
+          let row_keys = RowMarkerKeys {
+            remove: find_key(REMOVE_ROW_KEY),
+            hide: find_key(HIDE_ROW_KEY),
+            outline: find_key(ROW_OUTLINE_KEY),
+          };
+          let cell_keys = CellMarkerKeys {
+            number: find_key(TO_NUMBER_KEY),
+            formula: find_key(TO_FORMULA_KEY),
+            boolean: find_key(TO_BOOLEAN_KEY),
+            error: find_key(TO_ERROR_KEY),
+            style: find_key(TO_STYLE_KEY),
+          };
           
-          // 获取合并单元格信息
-          let merge_refs = merge_cells.lock().unwrap().clone();
+          // 获取合并单元格信息, 取出后清空, 避免带到下一个 sheet
+          let merge_refs = std::mem::take(&mut *merge_cells.lock().unwrap());
+          
+          let mut sheet_warnings = Vec::new();
           
           // 获取超链接信息
-          let hyperlinks_map = hyperlinks_by_sheet.lock().unwrap();
-          let sheet_hyperlinks = hyperlinks_map.get(&sheet_name);
+          let mut sheet_hyperlinks = hyperlinks_by_sheet.lock().unwrap().remove(&sheet_name);
+          // 外部链接 (http://、https://、mailto:) 在 sheet rels 中添加 TargetMode="External" 的关系, hyperlink 通过 r:id 引用
+          for link in sheet_hyperlinks.iter_mut().flatten().filter(|link| is_external_link(&link.location)) {
+            let (rels, rid) = append_relationship(sheet_rels.as_deref(), HYPERLINK_REL_TYPE, &link.location, true);
+            sheet_rels = Some(rels);
+            sheet_rels_changed = true;
+            link.rel_id = Some(rid);
+          }
           
           xml_content = post_process_xml(
             &xml_content,
-            remove_key,
-            number_key,
-            formula_key,
+            &row_keys,
+            &cell_keys,
             if merge_refs.is_empty() { None } else { Some(&merge_refs) },
-            sheet_hyperlinks.map(|v| v.as_slice()),
+            sheet_hyperlinks.as_deref(),
+            &mut sheet_warnings,
           )?;
+          warnings.extend(sheet_warnings.drain(..).map(|warning| format!("{sheet_name}: {warning}")));
+          
+          // 有显示文本的超链接, 单元格中显示的也是该文本, 而不是模板单元格原来的内容
+          let display_texts: HashMap<(u32, u32), String> = sheet_hyperlinks.iter().flatten()
+            .filter(|link| !link.display.is_empty())
+            .filter_map(|link| Some((parse_cell_ref(&link.ref_cell)?, link.display.clone())))
+            .collect();
+          xml_content = set_cell_texts(&xml_content, &display_texts)?;
+          
+          // 写入手动分页符
+          let (row_breaks, col_breaks) = std::mem::take(&mut *page_breaks.lock().unwrap());
+          xml_content = insert_page_breaks_into_sheet(&xml_content, &row_breaks, &col_breaks)?;
+          
+          // 写入条件格式, 相同的格式共用一个 dxf
+          let rules = std::mem::take(&mut *conditional_formats.lock().unwrap());
+          if !rules.is_empty() {
+            let mut priority = max_cf_priority(&xml_content);
+            let mut cf_xml = String::new();
+            for rule in rules {
+              let dxf_index = match new_dxfs.iter().position(|dxf| *dxf == rule.dxf) {
+                Some(index) => index,
+                None => {
+                  new_dxfs.push(rule.dxf);
+                  new_dxfs.len() - 1
+                }
+              };
+              priority += 1;
+              cf_xml.push_str(&format!(
+                r#"<conditionalFormatting sqref="{}"><cfRule type="cellIs" dxfId="{}" priority="{priority}" operator="{}"><formula>{}</formula></cfRule></conditionalFormatting>"#,
+                quick_xml::escape::escape(rule.sqref.as_str()),
+                existing_dxf_count + dxf_index,
+                rule.operator,
+                quick_xml::escape::partial_escape(&rule.formula),
+              ));
+            }
+            xml_content = insert_conditional_formatting_into_sheet(&xml_content, &cf_xml)?;
+          }
+          
+          // 写入工作表标签颜色
+          if let Some(argb) = sheet_tab_color.lock().unwrap().take() {
+            xml_content = set_sheet_tab_color(&xml_content, &argb)?;
+          }
+          
+          // 有行分组时写入最大分组级别, Excel 据此显示分组按钮
+          let outline_level = std::mem::take(&mut *row_outline_level.lock().unwrap());
+          if outline_level > 0 {
+            xml_content = set_sheet_format_attribute(&xml_content, "outlineLevelRow", &outline_level.to_string())?;
+          }
+          
+          // 写入工作表视图属性 (网格线、行号列标等)
+          let attributes = std::mem::take(&mut *sheet_view_attributes.lock().unwrap());
+          xml_content = set_sheet_view_attributes(&xml_content, &attributes)?;
+          
+          // {{br}} 所在的单元格使用自动换行的样式, 模板没有 styles.xml 时只输出换行符
+          let cells = std::mem::take(&mut *wrap_cells.lock().unwrap());
+          if !existing_xfs.is_empty() {
+            xml_content = wrap_text_cells(&xml_content, &cells, |style| {
+              *wrap_styles.entry(style).or_insert_with(|| {
+                let xf = existing_xfs.get(style as usize).map_or("<xf/>", |xf| xf.as_str());
+                match wrap_text_xf(xf) {
+                  Some(wrapped) => {
+                    new_xfs.push(wrapped);
+                    (existing_xfs.len() + new_xfs.len() - 1) as u32
+                  }
+                  None => style,
+                }
+              })
+            })?;
+          }
+          
+          // {{percentNum}} 所在的单元格使用百分比数字格式, 模板没有 styles.xml 时只输出数字
+          let cells = std::mem::take(&mut *percent_cells.lock().unwrap());
+          if !existing_xfs.is_empty() && !cells.is_empty() {
+            xml_content = restyle_cells(&xml_content, |cell, style| {
+              let decimals = *cells.get(&cell)?;
+              Some(*percent_styles.entry((style, decimals)).or_insert_with(|| {
+                // 内置格式 9 是 0%, 10 是 0.00%, 其他小数位数使用自定义格式
+                let num_fmt_id = match decimals {
+                  0 => 9,
+                  2 => 10,
+                  _ => {
+                    let code = format!("0.{}%", "0".repeat(decimals as usize));
+                    match new_num_fmts.iter().find(|(_, c)| *c == code) {
+                      Some((id, _)) => *id,
+                      None => {
+                        let id = first_num_fmt_id + new_num_fmts.len() as u32;
+                        new_num_fmts.push((id, code));
+                        id
+                      }
+                    }
+                  }
+                };
+                // 已经被 {{br}} 等改为新样式的单元格, 在新增的样式中查找, 保留其对齐方式和字体
+                let xf = existing_xfs.get(style as usize)
+                  .or_else(|| new_xfs.get((style as usize).checked_sub(existing_xfs.len())?))
+                  .map_or("<xf/>", |xf| xf.as_str());
+                let xf = num_fmt_xf(xf, num_fmt_id);
+                new_xfs.push(xf);
+                (existing_xfs.len() + new_xfs.len() - 1) as u32
+              }))
+            })?;
+          }
+          
+          // 按渲染后的文本长度估算列宽
+          if options.auto_fit_columns {
+            xml_content = auto_fit_columns(&xml_content, options.auto_fit_max_width.unwrap_or(60.0))?;
+          }
         }
         
         *contents = xml_content.into_bytes();
       }
+      
+      if sheet_rels_changed && let Some(rels) = sheet_rels {
+        files.insert(rels_path, rels.into_bytes());
+      }
+    }
+    
+    // 写入条件格式使用的差异格式
+    if !new_dxfs.is_empty() {
+      let styles = files.get("xl/styles.xml").ok_or("conditionalFormat requires xl/styles.xml in the template")?;
+      let styles_xml = append_dxfs_to_styles(std::str::from_utf8(styles)?, &new_dxfs);
+      files.insert("xl/styles.xml".to_string(), styles_xml.into_bytes());
+    }
+    
+    // 写入百分比数字单元格使用的自定义数字格式
+    if !new_num_fmts.is_empty() && let Some(styles) = files.get("xl/styles.xml") {
+      let styles_xml = append_num_fmts_to_styles(std::str::from_utf8(styles)?, &new_num_fmts);
+      files.insert("xl/styles.xml".to_string(), styles_xml.into_bytes());
+    }
+    
+    // 写入自动换行、百分比数字格式使用的单元格格式
+    if !new_xfs.is_empty() && let Some(styles) = files.get("xl/styles.xml") {
+      let styles_xml = append_cell_xfs_to_styles(std::str::from_utf8(styles)?, &new_xfs);
+      files.insert("xl/styles.xml".to_string(), styles_xml.into_bytes());
     }
     
     // 处理图片插入
-    let images_map = images_by_sheet.lock().unwrap();
+    let images_map = validate_images(&images_by_sheet.lock().unwrap(), options.skip_bad_images, &mut warnings)?;
     if !images_map.is_empty() {
-      process_images(&mut files, &images_map)?;
+      process_images(&mut files, &images_map, options.max_image_dimension)?;
     }
     
     // 处理工作表删除
     let sheets_to_delete_list = sheets_to_delete.lock().unwrap().clone();
     if !sheets_to_delete_list.is_empty() {
-      delete_sheets(&mut files, &sheets_to_delete_list)?;
+      delete_sheets(&mut files, &sheets_to_delete_list, options.keep_last_sheet)?;
     }
     
     // 处理工作表重命名
@@ -716,41 +1684,240 @@ pub fn render_template(
     if !sheets_to_hide_map.is_empty() {
       hide_sheets(&mut files, &sheets_to_hide_map)?;
     }
+    
+    // 写入打印区域和定义名称, 放在删除和重命名之后, 以便使用最终的工作表序号和名称
+    let print_areas_map = print_areas.lock().unwrap().clone();
+    let global_names_list = global_names.lock().unwrap().clone();
+    if !print_areas_map.is_empty() || !global_names_list.is_empty() {
+      write_defined_names(&mut files, &print_areas_map, &global_names_list)?;
+    }
+  }
+  
+  if let Some(on_warning) = options.on_warning.as_mut() {
+    for warning in &warnings {
+      on_warning(warning);
+    }
+  }
+  
+  // 统一生成或修改过的 XML 部件的声明, 模板中原样保留的部件 (docProps、customXml、静态工作表等) 不改动,
+  // 放在后处理钩子之前, 钩子写入的内容保持原样
+  for (name, contents) in files.lock().unwrap().iter_mut() {
+    if (name.ends_with(".xml") || name.ends_with(".rels"))
+      && template_digests.get(name) != Some(&part_digest(contents))
+      && let Some(xml) = std::str::from_utf8(contents).ok().and_then(|xml| set_xml_declaration(xml, !options.omit_xml_standalone))
+    {
+      *contents = xml.into_bytes();
+    }
+  }
+  
+  // 调用方的后处理钩子
+  if let Some(post_process) = options.post_process.as_mut() {
+    post_process(&mut files.lock().unwrap());
   }
   
   // Extract files from Arc<Mutex<_>>
   let files = Arc::try_unwrap(files).map_err(|_| Box::new(std::io::Error::other("Failed to unwrap Arc")))?.into_inner().map_err(|e| Box::new(std::io::Error::other(format!("Failed to get inner value: {e:?}"))))?;
   
-  // 重新压缩文件
-  let mut output = Vec::new();
-  {
-    let cursor = Cursor::new(&mut output);
-    let mut zip_writer = ZipWriter::new(cursor);
-    
-    for entry in files {
-      let (file_name, contents): (String, Vec<u8>) = entry;
-      let options = SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .compression_level(Some(6)); // 设置压缩级别
-      
-      zip_writer.start_file(file_name, options)?;
-      zip_writer.write_all(&contents)?;
+  // 重新压缩文件, 需要检查时先压缩到内存中, 检查通过后再写入 writer
+  if options.validate_output {
+    let mut output = Cursor::new(Vec::new());
+    write_zip(&mut output, &files, &entry_order, &entry_compression)?;
+    validate_output_package(Cursor::new(output.get_ref()))?;
+    writer.write_all(output.get_ref())?;
+  } else {
+    write_zip(writer, &files, &entry_order, &entry_compression)?;
+  }
+  
+  Ok(())
+}
+
+/// 文件内容的摘要 (长度, 哈希), 用于判断部件在渲染过程中是否被修改
+fn part_digest(contents: &[u8]) -> (usize, u64) {
+  use std::hash::{DefaultHasher, Hash, Hasher};
+  let mut hasher = DefaultHasher::new();
+  contents.hash(&mut hasher);
+  (contents.len(), hasher.finish())
+}
+
+/// 按模板中的顺序和压缩方式把文件压缩写入 writer
+fn write_zip<W: Write + Seek>(
+  writer: W,
+  files: &HashMap<String, Vec<u8>>,
+  entry_order: &[String],
+  entry_compression: &HashMap<String, zip::CompressionMethod>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut zip_writer = ZipWriter::new(writer);
+  
+  for file_name in ordered_entry_names(files, entry_order) {
+    let contents = &files[&file_name];
+    
+    // 原有文件沿用原来的压缩方式; 新增的图片本身已经压缩过, 直接存储
+    let compression = match entry_compression.get(&file_name) {
+      Some(zip::CompressionMethod::Stored) => zip::CompressionMethod::Stored,
+      Some(_) => zip::CompressionMethod::Deflated,
+      None if file_name.starts_with("xl/media/") => zip::CompressionMethod::Stored,
+      None => zip::CompressionMethod::Deflated,
+    };
+    let mut options = SimpleFileOptions::default()
+      .compression_method(compression)
+      .last_modified_time(zip::DateTime::default()); // 固定时间戳, 保证输出稳定
+    if compression == zip::CompressionMethod::Deflated {
+      options = options.compression_level(Some(6)); // 设置压缩级别
     }
     
-    zip_writer.finish()?;
+    zip_writer.start_file(file_name, options)?;
+    zip_writer.write_all(contents)?;
   }
   
-  Ok(output)
+  zip_writer.finish()?;
+  Ok(())
+}
+
+/// 检查生成的 xlsx 文件, 返回发现的第一个不一致:
+/// - 每个部件都要在 `[Content_Types].xml` 中有 Override, 或者扩展名有 Default
+/// - `xl/workbook.xml` 和 `xl/worksheets/*.xml` 中引用的每个 `r:id` 都要在对应的 rels 中有同 Id 的关系
+fn validate_output_package<R: Read + Seek>(reader: R) -> Result<(), Box<dyn std::error::Error>> {
+  let invalid = |message: String| -> Box<dyn std::error::Error> { Box::new(XlsxError::InvalidOutput(message)) };
+  let mut archive = ZipArchive::new(reader)?;
+  let names: Vec<String> = archive.file_names().filter(|name| !name.ends_with('/')).map(str::to_string).collect();
+  let read_entry = |archive: &mut ZipArchive<R>, name: &str| -> Option<String> {
+    let mut contents = String::new();
+    archive.by_name(name).ok()?.read_to_string(&mut contents).ok()?;
+    Some(contents)
+  };
+  
+  // 指定元素上某个属性的所有值
+  let attribute_values = |xml: &str, element: &[u8], attribute: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut values = Vec::new();
+    loop {
+      match reader.read_event()? {
+        quick_xml::events::Event::Start(e) | quick_xml::events::Event::Empty(e) if element.is_empty() || e.local_name().as_ref() == element => {
+          if let Some(attr) = e.try_get_attribute(attribute)? {
+            values.push(attr.unescape_value()?.into_owned());
+          }
+        }
+        quick_xml::events::Event::Eof => break,
+        _ => {}
+      }
+    }
+    Ok(values)
+  };
+  
+  let content_types = read_entry(&mut archive, "[Content_Types].xml").ok_or_else(|| invalid("missing [Content_Types].xml".to_string()))?;
+  let defaults: Vec<String> = attribute_values(&content_types, b"Default", "Extension")?.iter().map(|ext| ext.to_ascii_lowercase()).collect();
+  let overrides = attribute_values(&content_types, b"Override", "PartName")?;
+  for name in names.iter().filter(|name| *name != "[Content_Types].xml") {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    if !overrides.iter().any(|part| part.trim_start_matches('/') == name) && !defaults.contains(&extension) {
+      return Err(invalid(format!("part {name} has no content type in [Content_Types].xml")));
+    }
+  }
+  
+  let parts = names.iter().filter(|name| {
+    *name == "xl/workbook.xml" || (name.starts_with("xl/worksheets/") && !name.contains("/_rels/") && name.ends_with(".xml"))
+  });
+  for name in parts {
+    let xml = read_entry(&mut archive, name).ok_or_else(|| invalid(format!("cannot read {name}")))?;
+    let ids = attribute_values(&xml, b"", "r:id")?;
+    if ids.is_empty() {
+      continue;
+    }
+    let (dir, file) = name.rsplit_once('/').unwrap_or(("", name));
+    let rels_path = format!("{dir}/_rels/{file}.rels");
+    let rel_ids = match read_entry(&mut archive, &rels_path) {
+      Some(rels) => attribute_values(&rels, b"Relationship", "Id")?,
+      None => Vec::new(),
+    };
+    if let Some(id) = ids.iter().find(|id| !rel_ids.contains(id)) {
+      return Err(invalid(format!("{name} references r:id \"{id}\" but {rels_path} has no such relationship")));
+    }
+  }
+  
+  Ok(())
+}
+
+/// 计算重新压缩时的文件顺序
+/// 
+/// 1. `[Content_Types].xml` 始终放在第一位
+/// 2. 其余原有文件保持模板中的顺序（已删除的文件跳过）
+/// 3. 渲染过程中新增的文件（drawing、图片等）按文件名排序追加在最后
+fn ordered_entry_names(
+  files: &HashMap<String, Vec<u8>>,
+  entry_order: &[String],
+) -> Vec<String> {
+  const CONTENT_TYPES: &str = "[Content_Types].xml";
+  
+  let mut names: Vec<String> = Vec::with_capacity(files.len());
+  if files.contains_key(CONTENT_TYPES) {
+    names.push(CONTENT_TYPES.to_string());
+  }
+  
+  for name in entry_order {
+    if name != CONTENT_TYPES && files.contains_key(name) {
+      names.push(name.clone());
+    }
+  }
+  
+  let mut new_names: Vec<&String> = files.keys()
+    .filter(|name| name.as_str() != CONTENT_TYPES && !entry_order.contains(name))
+    .collect();
+  new_names.sort();
+  names.extend(new_names.into_iter().cloned());
+  
+  names
+}
+
+/// 校验所有图片数据能否解码, 返回可以插入的图片
+/// 
+/// 无法解码的图片: skip_bad_images 为 true 时跳过并记录警告, 否则返回 [`XlsxError::ImageDecode`]
+fn validate_images(
+  images_map: &HashMap<String, Vec<ImageInfo>>,
+  skip_bad_images: bool,
+  warnings: &mut Vec<String>,
+) -> Result<HashMap<String, Vec<ImageInfo>>, Box<dyn std::error::Error>> {
+  use base64::Engine;
+  
+  let mut valid_map = HashMap::new();
+  for (sheet_path, images) in images_map {
+    let mut valid_images = Vec::with_capacity(images.len());
+    for img_info in images {
+      let message = match base64::engine::general_purpose::STANDARD.decode(&img_info.base64_data) {
+        Err(e) => Some(format!("invalid base64: {}", e)),
+        Ok(data) if get_image_dimensions(&data).is_none() => Some("unrecognized image format".to_string()),
+        Ok(_) => None,
+      };
+      let Some(message) = message else {
+        valid_images.push(img_info.clone());
+        continue;
+      };
+      let cell = format!("{}{}", to_column_name("", img_info.col), img_info.row);
+      if !skip_bad_images {
+        return Err(Box::new(XlsxError::ImageDecode {
+          sheet: sheet_path.clone(),
+          cell,
+          message,
+        }));
+      }
+      warnings.push(format!("skipped image at {sheet_path}!{cell}: {message}"));
+    }
+    if !valid_images.is_empty() {
+      valid_map.insert(sheet_path.clone(), valid_images);
+    }
+  }
+  Ok(valid_map)
 }
 
 /// 处理图片插入：为每个 sheet 生成 drawing.xml 和 _rels 文件，保存图片到 media
 fn process_images(
   files: &mut HashMap<String, Vec<u8>>,
   images_map: &HashMap<String, Vec<ImageInfo>>,
+  max_image_dimension: Option<u32>,
 ) -> Result<(), Box<dyn std::error::Error>> {
   use base64::Engine;
   
   let mut image_counter = 1; // 全局图片计数器
+  let mut new_drawings = Vec::new(); // 新建的 drawing 文件, 需要在 [Content_Types].xml 中声明
   
   for (sheet_path, images) in images_map {
     if images.is_empty() {
@@ -764,28 +1931,55 @@ fn process_images(
       .parse()
       .unwrap_or(1);
     
-    // 生成 drawing.xml
-    let drawing_path = format!("xl/drawings/drawing{}.xml", sheet_num);
-    let drawing_xml = generate_drawing_xml(images, &mut image_counter)?;
-    files.insert(drawing_path, drawing_xml.into_bytes());
-    
-    // 生成 drawing.xml.rels
-    let drawing_rels_path = format!("xl/drawings/_rels/drawing{}.xml.rels", sheet_num);
-    let drawing_rels = generate_drawing_rels(images);
-    files.insert(drawing_rels_path, drawing_rels.into_bytes());
-    
-    // 生成 sheet.xml.rels（建立 sheet 到 drawing 的关系）
     let sheet_rels_path = format!("xl/worksheets/_rels/sheet{}.xml.rels", sheet_num);
-    let sheet_rels = generate_sheet_rels(sheet_num);
-    files.insert(sheet_rels_path, sheet_rels.into_bytes());
+    let existing_rels = match files.get(&sheet_rels_path) {
+      Some(contents) => Some(String::from_utf8(contents.clone())?),
+      None => None,
+    };
     
-    // 在 sheet.xml 中添加 <drawing r:id="rId1" /> 引用
-    if let Some(sheet_content) = files.get_mut(sheet_path) {
-      let mut xml = String::from_utf8(sheet_content.clone())?;
+    // 已有 drawing (图表、形状等) 的 sheet 只能有一个 drawing, 新图片合并到已有的 drawing 中
+    let existing_drawing_rid = match files.get(sheet_path) {
+      Some(sheet_content) => sheet_drawing_rid(std::str::from_utf8(sheet_content)?)?,
+      None => None,
+    };
+    if let Some(rid) = existing_drawing_rid {
+      let target = existing_rels.as_deref()
+        .map(|rels| relationship_target(rels, &rid))
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| format!("{sheet_path}: drawing relationship {rid} not found in {sheet_rels_path}"))?;
+      let drawing_path = resolve_part_path("xl/worksheets", &target);
+      merge_into_drawing(files, &drawing_path, images, &mut image_counter, max_image_dimension)
+        .map_err(|e| format!("{sheet_path}: {e}"))?;
+    } else {
+      // drawing 文件名优先与 sheet 编号一致, 被模板中其他 drawing 占用时顺延
+      let mut drawing_num = sheet_num;
+      while files.contains_key(&format!("xl/drawings/drawing{}.xml", drawing_num)) {
+        drawing_num += 1;
+      }
       
-      // 在 </worksheet> 之前插入 <drawing> 标签
-      if !xml.contains("<drawing") {
-        xml = xml.replace("</worksheet>", "  <drawing r:id=\"rId1\" />\n</worksheet>");
+      // 生成 drawing.xml
+      let drawing_path = format!("xl/drawings/drawing{}.xml", drawing_num);
+      let rids: Vec<String> = (0..images.len()).map(image_rid).collect();
+      let drawing_xml = generate_drawing_xml(images, &rids, &mut image_counter, max_image_dimension)?;
+      files.insert(drawing_path.clone(), drawing_xml.into_bytes());
+      new_drawings.push(drawing_path);
+      
+      // 生成 drawing.xml.rels
+      let drawing_rels_path = format!("xl/drawings/_rels/drawing{}.xml.rels", drawing_num);
+      let drawing_rels = generate_drawing_rels(images);
+      files.insert(drawing_rels_path, drawing_rels.into_bytes());
+      
+      // 生成或合并 sheet.xml.rels（建立 sheet 到 drawing 的关系）
+      // 模板中已有的关系（如外部超链接）需要保留
+      let drawing_target = format!("../drawings/drawing{}.xml", drawing_num);
+      let (sheet_rels, drawing_rid) = merge_sheet_rels(existing_rels.as_deref(), &drawing_target);
+      files.insert(sheet_rels_path, sheet_rels.into_bytes());
+      
+      // 在 sheet.xml 中添加 <drawing r:id="..." /> 引用，ID 与 rels 中 drawing 关系一致
+      if let Some(sheet_content) = files.get_mut(sheet_path) {
+        let xml = std::str::from_utf8(sheet_content)?;
+        let xml = insert_drawing_into_sheet(xml, &drawing_rid)?;
         *sheet_content = xml.into_bytes();
       }
     }
@@ -796,43 +1990,42 @@ fn process_images(
         .decode(&img_info.base64_data)
         .map_err(|e| format!("Failed to decode base64 image: {}", e))?;
       
-      // 使用 rid 作为文件名，确保唯一性
-      let image_path = format!("xl/media/{}.png", img_info.rid);
+      // 使用 UUID 生成的文件名，确保唯一性
+      let image_path = format!("xl/media/{}.{}", img_info.media_name, img_info.extension);
       files.insert(image_path, image_data);
     }
   }
   
-  // 更新 [Content_Types].xml 添加 PNG 类型和 drawing 类型
+  // 更新 [Content_Types].xml 添加图片类型和 drawing 类型
   if let Some(content_types) = files.get_mut("[Content_Types].xml") {
     let mut xml = String::from_utf8(content_types.clone())?;
     
-    // 添加 PNG 扩展类型
-    if !xml.contains("Extension=\"png\"") {
-      xml = xml.replace(
-        "</Types>",
-        "  <Default Extension=\"png\" ContentType=\"image/png\"/>\n</Types>",
-      );
-    }
-    
-    // 为每个 drawing.xml 添加 Override 声明
-    for (sheet_path, images) in images_map {
-      if !images.is_empty() {
-        let sheet_num: u32 = sheet_path
-          .trim_start_matches("xl/worksheets/sheet")
-          .trim_end_matches(".xml")
-          .parse()
-          .unwrap_or(1);
-        
-        let drawing_part_name = format!("/xl/drawings/drawing{}.xml", sheet_num);
-        if !xml.contains(&drawing_part_name) {
-          xml = xml.replace(
-            "</Types>",
-            &format!(
-              "  <Override PartName=\"{}\" ContentType=\"application/vnd.openxmlformats-officedocument.drawing+xml\"/>\n</Types>",
-              drawing_part_name
-            ),
-          );
-        }
+    // 添加用到的图片扩展类型
+    for img_info in images_map.values().flatten() {
+      let extension = img_info.extension.as_str();
+      if !xml.contains(&format!("Extension=\"{}\"", extension)) {
+        xml = xml.replace(
+          "</Types>",
+          &format!(
+            "  <Default Extension=\"{}\" ContentType=\"{}\"/>\n</Types>",
+            extension,
+            image_content_type(extension),
+          ),
+        );
+      }
+    }
+    
+    // 为每个新建的 drawing.xml 添加 Override 声明
+    for drawing_path in &new_drawings {
+      let drawing_part_name = format!("/{}", drawing_path);
+      if !xml.contains(&drawing_part_name) {
+        xml = xml.replace(
+          "</Types>",
+          &format!(
+            "  <Override PartName=\"{}\" ContentType=\"application/vnd.openxmlformats-officedocument.drawing+xml\"/>\n</Types>",
+            drawing_part_name
+          ),
+        );
       }
     }
     
@@ -842,18 +2035,86 @@ fn process_images(
   Ok(())
 }
 
-/// 生成 drawing.xml 内容
+/// 拆分 img helper 的图片参数, 返回 (扩展名, base64 数据)
+/// 
+/// 支持 `data:image/jpeg;base64,/9j/...` 形式的 data URI, 按声明的 MIME 选择扩展名;
+/// 纯 base64 数据或无法识别的 MIME 按 png 处理
+fn split_image_data_uri(data: &str) -> (&'static str, &str) {
+  let Some(rest) = data.strip_prefix("data:") else {
+    return ("png", data);
+  };
+  let Some((meta, payload)) = rest.split_once(',') else {
+    return ("png", data);
+  };
+  let Some(mime) = meta.strip_suffix(";base64") else {
+    return ("png", data);
+  };
+  let extension = match mime.trim().to_ascii_lowercase().as_str() {
+    "image/jpeg" | "image/jpg" | "image/pjpeg" => "jpeg",
+    "image/gif" => "gif",
+    "image/bmp" => "bmp",
+    "image/webp" => "webp",
+    "image/tiff" => "tiff",
+    _ => "png",
+  };
+  (extension, payload)
+}
+
+/// 图片扩展名对应的 [Content_Types].xml ContentType
+fn image_content_type(extension: &str) -> &'static str {
+  match extension {
+    "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "bmp" => "image/bmp",
+    "webp" => "image/webp",
+    "tiff" => "image/tiff",
+    _ => "image/png",
+  }
+}
+
+/// 宽或高超过 max_dimension 时按宽高比等比例缩小, 使较长的一边等于 max_dimension
+fn fit_image_dimension(width: u32, height: u32, max_dimension: Option<u32>) -> (u32, u32) {
+  let Some(max_dimension) = max_dimension else {
+    return (width, height);
+  };
+  let longest = width.max(height);
+  if longest <= max_dimension {
+    return (width, height);
+  }
+  let scale = max_dimension as f64 / longest as f64;
+  let scaled = |px: u32| ((px as f64 * scale).round() as u32).max(1);
+  (scaled(width), scaled(height))
+}
+
+const XDR_NAMESPACE: &str = "http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing";
+const DRAWINGML_NAMESPACE: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+
+/// 生成 drawing.xml 内容, rids 为每张图片在 drawing.xml.rels 中的关系 ID
 fn generate_drawing_xml(
   images: &[ImageInfo],
+  rids: &[String],
   image_counter: &mut usize,
+  max_image_dimension: Option<u32>,
 ) -> Result<String, Box<dyn std::error::Error>> {
-  let mut xml = String::from(
-    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing" xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main">
-"#,
-  );
+  let anchors = generate_drawing_anchors(images, rids, image_counter, max_image_dimension, "")?;
+  Ok(format!(
+    r#"{XML_DECLARATION}
+<xdr:wsDr xmlns:xdr="{XDR_NAMESPACE}" xmlns:a="{DRAWINGML_NAMESPACE}">
+{anchors}</xdr:wsDr>"#,
+  ))
+}
+
+/// 生成每张图片的 anchor 节点, anchor_attrs 会追加到 anchor 开始标签中 (如命名空间声明)
+fn generate_drawing_anchors(
+  images: &[ImageInfo],
+  rids: &[String],
+  image_counter: &mut usize,
+  max_image_dimension: Option<u32>,
+  anchor_attrs: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+  let mut xml = String::new();
   
-  for img_info in images.iter() {
+  for (img_info, rid) in images.iter().zip(rids) {
     // 解码图片数据以获取实际尺寸
     use base64::Engine;
     let image_data = base64::engine::general_purpose::STANDARD
@@ -866,17 +2127,42 @@ fn generate_drawing_xml(
     // 使用用户指定尺寸或实际尺寸
     let width_px = img_info.width.unwrap_or(actual_width);
     let height_px = img_info.height.unwrap_or(actual_height);
+    let (width_px, height_px) = fit_image_dimension(width_px, height_px, max_image_dimension);
     
     // 转换为 EMU (1 px = 9525 EMU)
     let width_emu = width_px as i64 * 9525;
     let height_emu = height_px as i64 * 9525;
     
-    // 使用 oneCellAnchor 模式：只指定起始位置和绝对尺寸，不受单元格大小限制
     let from_col = img_info.col - 1; // 转换为 0-based
     let from_row = img_info.row - 1;
     
-    xml.push_str(&format!(
-      r#"  <xdr:oneCellAnchor>
+    let anchor_tag = match img_info.fit_to {
+      // 使用 twoCellAnchor 模式：图片填满起止单元格之间的范围，由 Excel 按单元格大小缩放
+      // <xdr:to> 指向结束单元格之后的位置，使结束单元格也被完整覆盖
+      Some((to_col, to_row)) => {
+        xml.push_str(&format!(
+          r#"  <xdr:twoCellAnchor{}>
+    <xdr:from>
+      <xdr:col>{}</xdr:col>
+      <xdr:colOff>0</xdr:colOff>
+      <xdr:row>{}</xdr:row>
+      <xdr:rowOff>0</xdr:rowOff>
+    </xdr:from>
+    <xdr:to>
+      <xdr:col>{}</xdr:col>
+      <xdr:colOff>0</xdr:colOff>
+      <xdr:row>{}</xdr:row>
+      <xdr:rowOff>0</xdr:rowOff>
+    </xdr:to>
+"#,
+          anchor_attrs, from_col, from_row, to_col, to_row,
+        ));
+        "xdr:twoCellAnchor"
+      }
+      // 使用 oneCellAnchor 模式：只指定起始位置和绝对尺寸，不受单元格大小限制
+      None => {
+        xml.push_str(&format!(
+          r#"  <xdr:oneCellAnchor{}>
     <xdr:from>
       <xdr:col>{}</xdr:col>
       <xdr:colOff>0</xdr:colOff>
@@ -884,7 +2170,15 @@ fn generate_drawing_xml(
       <xdr:rowOff>0</xdr:rowOff>
     </xdr:from>
     <xdr:ext cx="{}" cy="{}"/>
-    <xdr:pic>
+"#,
+          anchor_attrs, from_col, from_row, width_emu, height_emu,
+        ));
+        "xdr:oneCellAnchor"
+      }
+    };
+    
+    xml.push_str(&format!(
+      r#"    <xdr:pic>
       <xdr:nvPicPr>
         <xdr:cNvPr id="{}" name="Picture {}"/>
         <xdr:cNvPicPr>
@@ -908,39 +2202,79 @@ fn generate_drawing_xml(
       </xdr:spPr>
     </xdr:pic>
     <xdr:clientData/>
-  </xdr:oneCellAnchor>
+  </{}>
 "#,
-      from_col,      // from col
-      from_row,      // from row
-      width_emu,     // ext cx (绝对宽度)
-      height_emu,    // ext cy (绝对高度)
       *image_counter, // cNvPr id
       *image_counter, // Picture name
-      &img_info.rid, // rId (使用 UUID 生成的唯一 ID)
+      rid,           // rId (drawing.xml.rels 中的关系 ID)
       width_emu,     // xfrm ext cx
       height_emu,    // xfrm ext cy
+      anchor_tag,
     ));
     
     *image_counter += 1;
   }
   
-  xml.push_str("</xdr:wsDr>");
   Ok(xml)
 }
 
+/// 把图片追加到 sheet 已有的 drawing 中, 保留其中原有的图表、形状等对象
+/// 
+/// - 图片关系追加到 drawing.xml.rels, 已有关系 ID 不变
+/// - 新对象的 cNvPr id 从 drawing 中已用的最大 id 之后开始, 避免重复
+/// - anchor 上重新声明 xdr/a 命名空间, 不依赖根节点使用的前缀
+fn merge_into_drawing(
+  files: &mut HashMap<String, Vec<u8>>,
+  drawing_path: &str,
+  images: &[ImageInfo],
+  image_counter: &mut usize,
+  max_image_dimension: Option<u32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let drawing_xml = files.get(drawing_path)
+    .ok_or_else(|| format!("{drawing_path} not found"))?;
+  let drawing_xml = String::from_utf8(drawing_xml.clone())?;
+  
+  let (dir, file_name) = drawing_path.rsplit_once('/').unwrap_or(("", drawing_path));
+  let drawing_rels_path = format!("{dir}/_rels/{file_name}.rels");
+  let mut drawing_rels = match files.get(&drawing_rels_path) {
+    Some(contents) => Some(String::from_utf8(contents.clone())?),
+    None => None,
+  };
+  let mut rids = Vec::with_capacity(images.len());
+  for img_info in images {
+    let target = format!("../media/{}.{}", img_info.media_name, img_info.extension);
+    let (rels, rid) = append_relationship(drawing_rels.as_deref(), IMAGE_REL_TYPE, &target, false);
+    drawing_rels = Some(rels);
+    rids.push(rid);
+  }
+  
+  *image_counter = (*image_counter).max(max_drawing_object_id(&drawing_xml)? + 1);
+  let anchor_attrs = format!(r#" xmlns:xdr="{XDR_NAMESPACE}" xmlns:a="{DRAWINGML_NAMESPACE}""#);
+  let anchors = generate_drawing_anchors(images, &rids, image_counter, max_image_dimension, &anchor_attrs)?;
+  
+  let merged = insert_anchors_into_drawing(&drawing_xml, &anchors)?;
+  files.insert(drawing_path.to_string(), merged.into_bytes());
+  if let Some(drawing_rels) = drawing_rels {
+    files.insert(drawing_rels_path, drawing_rels.into_bytes());
+  }
+  Ok(())
+}
+
+const IMAGE_REL_TYPE: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/image";
+
 /// 生成 drawing.xml.rels 内容
 fn generate_drawing_rels(images: &[ImageInfo]) -> String {
-  let mut xml = String::from(
-    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+  let mut xml = format!(
+    r#"{XML_DECLARATION}
 <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
 "#,
   );
   
-  for img_info in images {
+  for (index, img_info) in images.iter().enumerate() {
     xml.push_str(&format!(
-      r#"  <Relationship Id="{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="../media/{}.png"/>
+      r#"  <Relationship Id="{}" Type="{}" Target="../media/{}.{}"/>
 "#,
-      img_info.rid, img_info.rid
+      image_rid(index), IMAGE_REL_TYPE, img_info.media_name, img_info.extension
     ));
   }
   
@@ -948,15 +2282,62 @@ fn generate_drawing_rels(images: &[ImageInfo]) -> String {
   xml
 }
 
-/// 生成 sheet.xml.rels 内容（建立 sheet 到 drawing 的关系）
-fn generate_sheet_rels(sheet_num: u32) -> String {
-  format!(
-    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+/// drawing.xml.rels 中第 index 张图片的关系 ID
+/// drawing.xml.rels 由我们完整生成，按顺序编号即可: rId1, rId2, ...
+fn image_rid(index: usize) -> String {
+  format!("rId{}", index + 1)
+}
+
+/// 生成或合并 sheet.xml.rels 内容（建立 sheet 到 drawing 的关系）
+/// 
+/// - 没有现有 rels 文件时，生成只包含 drawing 关系的新文件，ID 为 rId1
+/// - 已有 rels 文件时，保留原有关系，选择一个未被占用的 rId 追加 drawing 关系
+/// 
+/// 返回合并后的 rels 内容和 drawing 关系使用的 ID
+fn merge_sheet_rels(existing: Option<&str>, drawing_target: &str) -> (String, String) {
+  const DRAWING_TYPE: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing";
+  append_relationship(existing, DRAWING_TYPE, drawing_target, false)
+}
+
+/// 在 rels 内容中追加一个关系, 规则同 [`merge_sheet_rels`], `external` 为 true 时加上 `TargetMode="External"`
+/// 
+/// 返回合并后的 rels 内容和新关系使用的 ID
+fn append_relationship(existing: Option<&str>, rel_type: &str, target: &str, external: bool) -> (String, String) {
+  let target = quick_xml::escape::escape(target);
+  let target_mode = if external { r#" TargetMode="External""# } else { "" };
+  
+  let Some(existing) = existing.filter(|xml| xml.contains("</Relationships>")) else {
+    let xml = format!(
+      r#"{XML_DECLARATION}
 <Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
-  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing" Target="../drawings/drawing{}.xml"/>
-</Relationships>"#,
-    sheet_num
-  )
+  <Relationship Id="rId1" Type="{rel_type}" Target="{target}"{target_mode}/>
+</Relationships>"#
+    );
+    return (xml, "rId1".to_string());
+  };
+  
+  // 收集已有的 Id，找到第一个未被占用的 rIdN
+  let mut used_ids: Vec<&str> = Vec::new();
+  let mut pos = 0;
+  while let Some(id_pos) = existing[pos..].find(" Id=\"") {
+    let value_start = pos + id_pos + 5; // " Id=\"".len()
+    match existing[value_start..].find('"') {
+      Some(value_end) => {
+        used_ids.push(&existing[value_start..value_start + value_end]);
+        pos = value_start + value_end;
+      }
+      None => break,
+    }
+  }
+  let mut n = 1;
+  while used_ids.contains(&format!("rId{n}").as_str()) {
+    n += 1;
+  }
+  let rid = format!("rId{n}");
+  
+  let relationship = format!(r#"<Relationship Id="{rid}" Type="{rel_type}" Target="{target}"{target_mode}/>"#);
+  let xml = existing.replacen("</Relationships>", &format!("{relationship}</Relationships>"), 1);
+  (xml, rid)
 }
 
 /// 删除指定的工作表及其关键文件
@@ -969,12 +2350,20 @@ fn generate_sheet_rels(sheet_num: u32) -> String {
 /// 5. 从 [Content_Types].xml 中删除 worksheet 的 Override 声明
 /// 
 /// 注意：
-/// - 不能删除最后一个工作表，Excel 工作簿必须至少包含一个工作表
+/// - 不能删除最后一个工作表，Excel 工作簿必须至少包含一个工作表,
+///   keep_last_sheet 为 true 时保留最后一个被标记删除的工作表, 否则返回 [`XlsxError::CannotDeleteLastSheet`]
 /// - 不删除 drawing 文件，避免潜在的引用关系问题，且图片数据不敏感
 fn delete_sheets(
   files: &mut HashMap<String, Vec<u8>>,
   sheets_to_delete: &[String],
+  keep_last_sheet: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
+  // 同一个工作表可能被标记多次, 按首次标记的顺序去重
+  let mut sheets_to_delete: Vec<&String> = sheets_to_delete.iter()
+    .enumerate()
+    .filter(|(index, sheet)| !sheets_to_delete[..*index].contains(sheet))
+    .map(|(_, sheet)| sheet)
+    .collect();
   if sheets_to_delete.is_empty() {
     return Ok(());
   }
@@ -996,11 +2385,22 @@ fn delete_sheets(
   
   // 4. 检查是否会删除所有工作表
   if sheets_to_delete.len() >= total_sheets {
-    return Err(Box::new(std::io::Error::other(
-      "Cannot delete all worksheets. Excel workbook must contain at least one worksheet."
-    )));
+    if !keep_last_sheet {
+      let names = sheet_display_names(files)?;
+      return Err(Box::new(XlsxError::CannotDeleteLastSheet {
+        total: total_sheets,
+        requested: sheets_to_delete.iter().map(|path| names.get(path.as_str()).cloned().unwrap_or_else(|| path.to_string())).collect(),
+      }));
+    }
+    sheets_to_delete.truncate(total_sheets.saturating_sub(1));
   }
   
+  // 被删除的工作表在 <sheets> 中的序号, 用于更新 localSheetId 和 activeTab
+  let sheet_order: Vec<String> = workbook_sheets(files)?.into_iter().map(|(path, _)| path).collect();
+  let deleted_indices: Vec<usize> = sheets_to_delete.iter()
+    .filter_map(|sheet| sheet_order.iter().position(|path| path == *sheet))
+    .collect();
+  
   // 5. 对每个要删除的工作表进行处理
   for sheet_path in sheets_to_delete {
     // 从路径提取 sheet 编号: "xl/worksheets/sheet1.xml" -> "1"
@@ -1028,8 +2428,8 @@ fn delete_sheets(
       }
       
       // 删除整个 Relationship 节点
-      if let Some(node_start) = before.rfind("<Relationship ") {
-        if let Some(node_end) = workbook_rels_xml[rel_start..].find("/>") {
+      if let Some(node_start) = before.rfind("<Relationship ")
+        && let Some(node_end) = workbook_rels_xml[rel_start..].find("/>") {
           let full_end = rel_start + node_end + 2;
           // 删除节点，包括前后的空白
           let mut delete_start = node_start;
@@ -1050,7 +2450,6 @@ fn delete_sheets(
           
           workbook_rels_xml.replace_range(delete_start..delete_end, "");
         }
-      }
     }
     
     // 5.2 从 workbook.xml 中删除对应的 <sheet> 节点
@@ -1099,161 +2498,503 @@ fn delete_sheets(
     // 原因：避免潜在的引用关系问题，且图片形状数据不敏感，保留不影响 Excel 显示
     
     // 5.5 从 [Content_Types].xml 中删除 worksheet 的 Override 声明
+    // 不删除 drawing 的 Override，保留 drawing 文件
     if let Some(content_types) = files.get_mut("[Content_Types].xml") {
-      let mut ct_xml = String::from_utf8(content_types.clone())?;
-      
-      // 只删除 worksheet 的 Override 声明
-      let worksheet_override = format!(
-        "  <Override PartName=\"/xl/worksheets/sheet{}.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>\n",
-        sheet_num
-      );
-      ct_xml = ct_xml.replace(&worksheet_override, "");
-      
-      // 不删除 drawing 的 Override，保留 drawing 文件
-      
-      *content_types = ct_xml.into_bytes();
+      let ct_xml = String::from_utf8(content_types.clone())?;
+      let part_name = format!("PartName=\"/xl/worksheets/sheet{}.xml\"", sheet_num);
+      *content_types = remove_empty_element(&ct_xml, "Override", &part_name).into_bytes();
     }
   }
   
-  // 6. 更新修改后的文件
+  // 6. 剩余工作表的序号前移, 同步更新按序号引用工作表的属性
+  let workbook_xml = reindex_sheet_positions(&workbook_xml, &deleted_indices, sheet_order.len())?;
+  
+  // 7. 更新修改后的文件
+  // 剩余工作表的 sheetId、r:id 和文件名保持不变, 它们不要求连续
   files.insert(workbook_path.to_string(), workbook_xml.into_bytes());
   files.insert(workbook_rels_path.to_string(), workbook_rels_xml.into_bytes());
   
   Ok(())
 }
 
-/// 重命名指定的工作表
-/// 
-/// 重命名工作表包括以下步骤：
-/// 1. 在 workbook.xml 中找到对应的 <sheet> 节点
-/// 2. 修改 name 属性为新名称
+/// 删除工作表后更新 workbook.xml 中按 <sheets> 序号引用工作表的属性
 /// 
-/// 注意事项：
-/// - 工作表名称会自动过滤非法字符：\ / ? * [ ]
-/// - 名称长度会自动限制在 31 个字符以内
-/// - 如果新名称与现有工作表重名，会自动添加数字后缀
-fn rename_sheets(
-  files: &mut HashMap<String, Vec<u8>>,
-  sheets_to_rename: &HashMap<String, String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-  if sheets_to_rename.is_empty() {
-    return Ok(());
-  }
-  
-  // 1. 解析 workbook.xml
-  let workbook_path = "xl/workbook.xml";
-  let workbook_content = files.get(workbook_path)
-    .ok_or("workbook.xml not found")?;
-  let mut workbook_xml = String::from_utf8(workbook_content.clone())?;
+/// - `<definedName localSheetId>`: 作用域为已删除工作表的定义名称一并删除, 其余的序号前移
+/// - `<workbookView activeTab firstSheet>`: 序号前移, 指向已删除的工作表时改为它后面的第一个剩余工作表
+fn reindex_sheet_positions(
+  workbook_xml: &str,
+  deleted_indices: &[usize],
+  total_sheets: usize,
+) -> Result<String, Box<dyn std::error::Error>> {
+  use quick_xml::{events::{BytesStart, Event}, Reader, Writer};
   
-  // 2. 收集所有现有的工作表名称（用于检测重名）
-  let mut existing_names: Vec<String> = Vec::new();
-  let mut start = 0;
-  while let Some(name_pos) = workbook_xml[start..].find("<sheet ") {
-    let abs_pos = start + name_pos;
-    if let Some(name_start) = workbook_xml[abs_pos..].find("name=\"") {
-      let name_abs_start = abs_pos + name_start + 6; // "name=\"".len()
-      if let Some(name_end) = workbook_xml[name_abs_start..].find('"') {
-        let name = workbook_xml[name_abs_start..name_abs_start + name_end].to_string();
-        existing_names.push(name);
-        start = name_abs_start + name_end;
-      } else {
-        break;
+  if deleted_indices.is_empty() {
+    return Ok(workbook_xml.to_string());
+  }
+  let remaining = total_sheets.saturating_sub(deleted_indices.len()).max(1);
+  let new_index = |index: usize| {
+    let shift = deleted_indices.iter().filter(|deleted| **deleted < index).count();
+    (index - shift).min(remaining - 1)
+  };
+  let rewrite_attributes = |e: &BytesStart, names: &[&[u8]]| -> Result<BytesStart<'static>, Box<dyn std::error::Error>> {
+    let mut new_e = BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+    for attr in e.attributes() {
+      let attr = attr?;
+      let index = std::str::from_utf8(&attr.value).ok().and_then(|v| v.parse::<usize>().ok());
+      match index {
+        Some(index) if names.contains(&attr.key.as_ref()) => {
+          new_e.push_attribute((attr.key.as_ref(), new_index(index).to_string().as_bytes()));
+        }
+        _ => new_e.push_attribute(attr),
       }
-    } else {
-      break;
     }
-  }
+    Ok(new_e)
+  };
+  let local_sheet_id = |e: &BytesStart| -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    Ok(e.try_get_attribute("localSheetId")?.and_then(|a| std::str::from_utf8(&a.value).ok()?.parse().ok()))
+  };
   
-  // 3. 对每个要重命名的工作表进行处理
-  for (sheet_path, new_name) in sheets_to_rename {
-    // 从路径提取 sheet 编号
-    let sheet_num: u32 = match sheet_path
-      .trim_start_matches("xl/worksheets/sheet")
-      .trim_end_matches(".xml")
-      .parse() {
-        Ok(num) => num,
-        Err(_) => continue,
-      };
-    
-    // 生成唯一的新名称（如果重名则添加后缀）
-    let mut final_name = new_name.clone();
-    let mut counter = 1;
-    while existing_names.contains(&final_name) {
-      // 限制名称+后缀的总长度不超过 31
-      let suffix = format!(" ({})", counter);
-      let max_base_len = 31 - suffix.len();
-      let base = if new_name.len() > max_base_len {
-        &new_name[..max_base_len]
-      } else {
-        new_name
-      };
-      final_name = format!("{}{}", base, suffix);
-      counter += 1;
-      
-      // 防止无限循环
-      if counter > 100 {
-        final_name = format!("Sheet{}", sheet_num);
-        break;
-      }
+  let mut reader = Reader::from_str(workbook_xml);
+  let mut writer = Writer::new(Cursor::new(Vec::new()));
+  // 正在跳过的已删除工作表的 definedName
+  let mut skipping = false;
+  loop {
+    let event = reader.read_event()?;
+    if skipping {
+      if let Event::End(ref e) = event
+        && e.name().as_ref() == b"definedName" {
+          skipping = false;
+        }
+      continue;
     }
-    
-    // 在 workbook.xml 中查找并替换工作表名称
-    // 需要找到对应 sheet 编号的 <sheet> 节点
-    let sheet_id_pattern = format!("sheetId=\"{}\"", sheet_num);
-    if let Some(sheet_id_pos) = workbook_xml.find(&sheet_id_pattern) {
-      // 向前查找 <sheet 标签的开始
-      let before = &workbook_xml[..sheet_id_pos];
-      if let Some(tag_start) = before.rfind("<sheet ") {
-        // 向后查找 />
-        let after = &workbook_xml[sheet_id_pos..];
-        if let Some(tag_end) = after.find("/>") {
-          let full_end = sheet_id_pos + tag_end + 2;
-          let sheet_tag = &workbook_xml[tag_start..full_end];
-          
-          // 在这个标签中查找并替换 name 属性
-          if let Some(name_start) = sheet_tag.find("name=\"") {
-            let name_abs_start = tag_start + name_start + 6;
-            if let Some(name_end) = workbook_xml[name_abs_start..].find('"') {
-              let name_abs_end = name_abs_start + name_end;
-              let old_name = workbook_xml[name_abs_start..name_abs_end].to_string();
-              
-              // 替换名称
-              workbook_xml.replace_range(name_abs_start..name_abs_end, &final_name);
-              
-              // 更新 existing_names 列表
-              if let Some(pos) = existing_names.iter().position(|n| n == &old_name) {
-                existing_names[pos] = final_name.clone();
-              }
-            }
+    match event {
+      Event::Start(ref e) | Event::Empty(ref e) if e.name().as_ref() == b"definedName" => {
+        let is_start = matches!(event, Event::Start(_));
+        match local_sheet_id(e)? {
+          Some(id) if deleted_indices.contains(&id) => skipping = is_start,
+          Some(_) => {
+            let new_e = rewrite_attributes(e, &[b"localSheetId"])?;
+            writer.write_event(if is_start { Event::Start(new_e) } else { Event::Empty(new_e) })?;
           }
+          None => writer.write_event(event.borrow())?,
         }
       }
+      Event::Empty(ref e) if e.name().as_ref() == b"workbookView" => {
+        writer.write_event(Event::Empty(rewrite_attributes(e, &[b"activeTab", b"firstSheet"])?))?;
+      }
+      Event::Eof => break,
+      event => writer.write_event(event)?,
     }
   }
   
-  // 4. 更新 workbook.xml
-  files.insert(workbook_path.to_string(), workbook_xml.into_bytes());
-  
-  Ok(())
+  Ok(String::from_utf8(writer.into_inner().into_inner())?)
 }
 
-/// 隐藏指定的工作表
-/// 
-/// 隐藏工作表包括以下步骤：
-/// 1. 在 workbook.xml 中找到对应的 <sheet> 节点
-/// 2. 添加或修改 state 属性为 "hidden" 或 "veryHidden"
+/// 从 workbook.xml 和 workbook.xml.rels 读取每个 sheet 文件路径对应的工作表显示名称
+/// 例如 "xl/worksheets/sheet1.xml" -> "Sheet1"
+fn sheet_display_names(
+  files: &HashMap<String, Vec<u8>>,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+  Ok(workbook_sheets(files)?.into_iter().collect())
+}
+
+/// 解析 workbook.xml.rels, 返回 rId -> 目标文件路径, 例如 "rId1" -> "xl/worksheets/sheet1.xml"
+fn workbook_rel_targets(
+  files: &HashMap<String, Vec<u8>>,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+  use quick_xml::{events::Event, Reader};
+  
+  let mut targets = HashMap::new();
+  let Some(workbook_rels) = files.get("xl/_rels/workbook.xml.rels") else {
+    return Ok(targets);
+  };
+  let mut reader = Reader::from_reader(workbook_rels.as_slice());
+  let mut buf = Vec::new();
+  loop {
+    match reader.read_event_into(&mut buf)? {
+      Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"Relationship" => {
+        let id = e.try_get_attribute("Id")?.map(|a| a.unescape_value()).transpose()?;
+        let target = e.try_get_attribute("Target")?.map(|a| a.unescape_value()).transpose()?;
+        if let (Some(id), Some(target)) = (id, target) {
+          // Target 相对于 xl/, 也可能是以 / 开头的绝对路径
+          let path = match target.strip_prefix('/') {
+            Some(absolute) => absolute.to_string(),
+            None => format!("xl/{target}"),
+          };
+          targets.insert(id.into_owned(), path);
+        }
+      }
+      Event::Eof => break,
+      _ => {}
+    }
+    buf.clear();
+  }
+  Ok(targets)
+}
+
+/// 查找 rels 中指定 Id 的关系 Target
+fn relationship_target(rels: &str, id: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+  use quick_xml::{events::Event, Reader};
+  
+  let mut reader = Reader::from_str(rels);
+  loop {
+    match reader.read_event()? {
+      Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"Relationship" => {
+        let rel_id = e.try_get_attribute("Id")?.map(|a| a.unescape_value()).transpose()?;
+        if rel_id.as_deref() == Some(id) {
+          let target = e.try_get_attribute("Target")?.map(|a| a.unescape_value()).transpose()?;
+          return Ok(target.map(|t| t.into_owned()));
+        }
+      }
+      Event::Eof => return Ok(None),
+      _ => {}
+    }
+  }
+}
+
+/// 把 rels 中的 Target 解析为压缩包内的路径, base_dir 为 rels 所属部件所在目录 (如 "xl/worksheets")
 /// 
-/// 隐藏级别：
-/// - "hidden": 普通隐藏，用户可以通过右键菜单 → 取消隐藏
-/// - "veryHidden": 超级隐藏，需要 VBA 代码或属性编辑器才能取消隐藏
+/// Target 以 / 开头时为压缩包内的绝对路径, 否则相对于 base_dir, 支持 ".."
+fn resolve_part_path(base_dir: &str, target: &str) -> String {
+  if let Some(absolute) = target.strip_prefix('/') {
+    return absolute.to_string();
+  }
+  let mut segments: Vec<&str> = base_dir.split('/').filter(|s| !s.is_empty()).collect();
+  for segment in target.split('/') {
+    match segment {
+      "" | "." => {}
+      ".." => {
+        segments.pop();
+      }
+      _ => segments.push(segment),
+    }
+  }
+  segments.join("/")
+}
+
+/// 逐个处理 workbook.xml 中的 <sheet> 节点, 不依赖属性顺序、引号和换行
 /// 
-/// 注意：至少要保留一个可见的工作表，否则 Excel 会报错
-fn hide_sheets(
+/// update 的参数为 sheet 文件路径和 <sheet> 节点, 返回 Some 时用新节点替换原节点
+fn update_workbook_sheets<F>(
   files: &mut HashMap<String, Vec<u8>>,
-  sheets_to_hide: &HashMap<String, String>,
+  mut update: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+  F: FnMut(&str, &quick_xml::events::BytesStart) -> Result<Option<quick_xml::events::BytesStart<'static>>, Box<dyn std::error::Error>>,
+{
+  use quick_xml::{events::Event, Reader, Writer};
+  
+  let targets = workbook_rel_targets(files)?;
+  let workbook_path = "xl/workbook.xml";
+  let workbook = files.get(workbook_path).ok_or("workbook.xml not found")?;
+  let mut reader = Reader::from_reader(workbook.as_slice());
+  let mut writer = Writer::new(Cursor::new(Vec::new()));
+  let mut buf = Vec::new();
+  loop {
+    let event = reader.read_event_into(&mut buf)?;
+    match event {
+      Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"sheet" => {
+        let rid = e.attributes().flatten()
+          .find(|a| a.key.local_name().as_ref() == b"id")
+          .map(|a| a.unescape_value()).transpose()?;
+        let updated = match rid.and_then(|rid| targets.get(rid.as_ref())) {
+          Some(path) => update(path, e)?,
+          None => None,
+        };
+        match (updated, &event) {
+          (Some(new_e), Event::Start(_)) => writer.write_event(Event::Start(new_e))?,
+          (Some(new_e), _) => writer.write_event(Event::Empty(new_e))?,
+          (None, _) => writer.write_event(event.borrow())?,
+        }
+      }
+      Event::Eof => break,
+      _ => writer.write_event(event.borrow())?,
+    }
+    buf.clear();
+  }
+  files.insert(workbook_path.to_string(), writer.into_inner().into_inner());
+  Ok(())
+}
+
+/// 复制节点并设置属性, value 为 None 时删除该属性
+fn with_attribute(
+  e: &quick_xml::events::BytesStart,
+  key: &str,
+  value: Option<&str>,
+) -> Result<quick_xml::events::BytesStart<'static>, Box<dyn std::error::Error>> {
+  let mut new_e = quick_xml::events::BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+  for attr in e.attributes() {
+    let attr = attr?;
+    if attr.key.as_ref() != key.as_bytes() {
+      new_e.push_attribute(attr);
+    }
+  }
+  if let Some(value) = value {
+    new_e.push_attribute((key, value));
+  }
+  Ok(new_e)
+}
+
+/// 按 workbook.xml 中 <sheets> 的顺序列出 (sheet 文件路径, 工作表显示名称)
+/// 
+/// 列表中的序号就是 definedName 的 localSheetId
+fn workbook_sheets(
+  files: &HashMap<String, Vec<u8>>,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+  use quick_xml::{events::Event, Reader};
+  
+  let mut sheets = Vec::new();
+  let Some(workbook) = files.get("xl/workbook.xml") else {
+    return Ok(sheets);
+  };
+  let targets = workbook_rel_targets(files)?;
+  let mut buf = Vec::new();
+  
+  let mut reader = Reader::from_reader(workbook.as_slice());
+  loop {
+    match reader.read_event_into(&mut buf)? {
+      Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"sheet" => {
+        let name = e.try_get_attribute("name")?.map(|a| a.unescape_value()).transpose()?;
+        let rid = e.attributes().flatten()
+          .find(|a| a.key.local_name().as_ref() == b"id")
+          .map(|a| a.unescape_value()).transpose()?;
+        if let (Some(name), Some(rid)) = (name, rid)
+          && let Some(path) = targets.get(rid.as_ref()) {
+            sheets.push((path.clone(), name.into_owned()));
+          }
+      }
+      Event::Eof => break,
+      _ => {}
+    }
+    buf.clear();
+  }
+  
+  Ok(sheets)
+}
+
+/// 把打印区域和全局定义名称写入 workbook.xml 的 definedNames
+/// 
+/// 每个打印区域对应一个 localSheetId 为工作表序号的 `_xlnm.Print_Area`,
+/// 只写了单元格范围（如 "A1:G50"）时自动加上工作表名称并转换为绝对引用;
+/// 全局定义名称没有 localSheetId, 引用原样写入
+fn write_defined_names(
+  files: &mut HashMap<String, Vec<u8>>,
+  print_areas: &HashMap<String, String>,
+  global_names: &[(String, String)],
 ) -> Result<(), Box<dyn std::error::Error>> {
-  if sheets_to_hide.is_empty() {
+  let sheets = workbook_sheets(files)?;
+  let mut defined_names: Vec<DefinedName> = sheets.iter().enumerate()
+    .filter_map(|(index, (path, name))| {
+      let range = print_areas.get(path)?;
+      Some(DefinedName {
+        name: "_xlnm.Print_Area".to_string(),
+        local_sheet_id: Some(index),
+        value: sheet_range_reference(name, range),
+      })
+    })
+    .collect();
+  // 已删除工作表的打印区域不会出现在 sheets 中
+  defined_names.sort_by_key(|d| d.local_sheet_id);
+  defined_names.extend(global_names.iter().map(|(name, reference)| DefinedName {
+    name: name.clone(),
+    local_sheet_id: None,
+    value: reference.clone(),
+  }));
+  if defined_names.is_empty() {
+    return Ok(());
+  }
+  
+  let workbook_path = "xl/workbook.xml";
+  let workbook_xml = std::str::from_utf8(files.get(workbook_path).ok_or("workbook.xml not found")?)?;
+  let workbook_xml = insert_defined_names_into_workbook(workbook_xml, &defined_names)?;
+  files.insert(workbook_path.to_string(), workbook_xml.into_bytes());
+  
+  Ok(())
+}
+
+/// 生成带工作表名称的引用, 例如 ("Sheet 1", "A1:G50") -> "'Sheet 1'!$A$1:$G$50"
+/// 
+/// 范围中已经包含工作表名称（带 !）时原样返回
+fn sheet_range_reference(sheet_name: &str, range: &str) -> String {
+  if range.contains('!') {
+    return range.to_string();
+  }
+  let absolute: Vec<String> = range.split(':')
+    .map(|part| {
+      let part = part.replace('$', "");
+      match part.find(|c: char| c.is_ascii_digit()) {
+        Some(0) => format!("${part}"),
+        Some(index) => format!("${}${}", &part[..index], &part[index..]),
+        None => format!("${part}"),
+      }
+    })
+    .collect();
+  format!("'{}'!{}", sheet_name.replace('\'', "''"), absolute.join(":"))
+}
+
+/// 公式中引用工作表时的名称, 例如 "Summary" -> "Summary", "Q1 Sales" -> "'Q1 Sales'"
+/// 
+/// 名称可以作为普通标识符时 (以字母或下划线开头, 只含字母、数字、下划线和点号, 且不像单元格引用) 不加引号,
+/// 否则加单引号, 名称中的单引号写成两个
+fn quote_sheet_name(sheet_name: &str) -> String {
+  if is_valid_defined_name(sheet_name) && !sheet_name.contains('\\') {
+    sheet_name.to_string()
+  } else {
+    format!("'{}'", sheet_name.replace('\'', "''"))
+  }
+}
+
+/// 检查定义名称是否符合 Excel 的命名规则
+/// 
+/// - 长度 1 ~ 255, 以字母、下划线或反斜杠开头
+/// - 其余字符只能是字母、数字、下划线、点号或反斜杠（不能有空格）
+/// - 不能是单元格引用，如 "A1"、"TAX2024"、"R1C1"、"R"、"C"
+fn is_valid_defined_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  let Some(first) = chars.next() else {
+    return false;
+  };
+  if name.chars().count() > 255
+    || !(first.is_alphabetic() || first == '_' || first == '\\')
+    || !chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '\\')) {
+    return false;
+  }
+  
+  // A1 样式: 列不超过 XFD (16384), 行不超过 1048576
+  if let Some((col, row)) = crate::utils::parse_cell_ref(name)
+    && col <= 16384 && row <= 1048576 {
+      return false;
+    }
+  
+  // R1C1 样式: R、C、R1、C1、R1C1、RC 等
+  let upper = name.to_ascii_uppercase();
+  let is_r1c1 = |s: &str| {
+    let s = s.strip_prefix('R').map(|rest| rest.trim_start_matches(|c: char| c.is_ascii_digit())).unwrap_or(s);
+    match s.strip_prefix('C') {
+      Some(rest) => rest.chars().all(|c| c.is_ascii_digit()),
+      None => s.is_empty(),
+    }
+  };
+  !is_r1c1(&upper)
+}
+
+/// 条件格式颜色对应的差异格式 <dxf>, 预设颜色与 Excel 内置的 "浅红填充色深红色文本" 等样式一致
+/// 
+/// 也可以传入 "#RRGGBB" 只设置填充色, 不支持的颜色返回 None
+fn conditional_format_dxf(color: &str) -> Option<String> {
+  let (font, fill) = match color.to_ascii_lowercase().as_str() {
+    "red" => (Some("FF9C0006"), "FFFFC7CE"),
+    "green" => (Some("FF006100"), "FFC6EFCE"),
+    "yellow" => (Some("FF9C5700"), "FFFFEB9C"),
+    "blue" => (Some("FF1F4E78"), "FFDDEBF7"),
+    "gray" | "grey" => (Some("FF3A3A3A"), "FFD9D9D9"),
+    hex => {
+      let hex = hex.strip_prefix('#')?;
+      if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+      }
+      return Some(format!(r#"<dxf><fill><patternFill><bgColor rgb="FF{}"/></patternFill></fill></dxf>"#, hex.to_ascii_uppercase()));
+    }
+  };
+  let font = font.map(|rgb| format!(r#"<font><color rgb="{rgb}"/></font>"#)).unwrap_or_default();
+  Some(format!(r#"<dxf>{font}<fill><patternFill><bgColor rgb="{fill}"/></patternFill></fill></dxf>"#))
+}
+
+/// 工作表标签颜色转换为 ARGB, 6 位 RGB 补上不透明的 "FF", 可以带 "#" 前缀
+fn tab_color_argb(color: &str) -> Option<String> {
+  let hex = color.trim().strip_prefix('#').unwrap_or(color.trim());
+  if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+  match hex.len() {
+    6 => Some(format!("FF{}", hex.to_ascii_uppercase())),
+    8 => Some(hex.to_ascii_uppercase()),
+    _ => None,
+  }
+}
+
+/// sheet 中已有 cfRule 的最大 priority, 新增的规则排在它们之后
+fn max_cf_priority(sheet_xml: &str) -> u32 {
+  sheet_xml.match_indices("<cfRule ")
+    .filter_map(|(start, _)| {
+      let tag = &sheet_xml[start..start + sheet_xml[start..].find('>')?];
+      let value = tag.split("priority=\"").nth(1)?;
+      value[..value.find('"')?].parse().ok()
+    })
+    .max()
+    .unwrap_or(0)
+}
+
+/// 删除 [Content_Types].xml 和 workbook.xml.rels 中对 sharedStrings.xml 的引用
+/// 
+/// sharedStrings.xml 本身在提取共享字符串时已经从文件列表中移除,
+/// 留下引用会让严格的 OOXML 校验器报告缺失的部件
+fn remove_shared_strings_references(
+  files: &mut HashMap<String, Vec<u8>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  if let Some(content_types) = files.get_mut("[Content_Types].xml") {
+    let ct_xml = String::from_utf8(content_types.clone())?;
+    *content_types = remove_empty_element(&ct_xml, "Override", "PartName=\"/xl/sharedStrings.xml\"").into_bytes();
+  }
+  if let Some(workbook_rels) = files.get_mut("xl/_rels/workbook.xml.rels") {
+    let rels_xml = String::from_utf8(workbook_rels.clone())?;
+    *workbook_rels = remove_empty_element(&rels_xml, "Relationship", "/relationships/sharedStrings\"").into_bytes();
+  }
+  Ok(())
+}
+
+/// 删除所有包含 needle 的自闭合元素 <tag .../>, 连同前面的空白
+fn remove_empty_element(xml: &str, tag: &str, needle: &str) -> String {
+  let open = format!("<{} ", tag);
+  let mut result = String::with_capacity(xml.len());
+  let mut rest = xml;
+  while let Some(start) = rest.find(&open) {
+    let Some(len) = rest[start..].find("/>").map(|end| end + 2) else {
+      break;
+    };
+    let element = &rest[start..start + len];
+    if element.contains(needle) {
+      result.push_str(rest[..start].trim_end_matches([' ', '\t', '\r', '\n']));
+    } else {
+      result.push_str(&rest[..start + len]);
+    }
+    rest = &rest[start + len..];
+  }
+  result.push_str(rest);
+  result
+}
+
+/// Excel 工作表名称的最大长度
+const SHEET_NAME_MAX_LEN: usize = 31;
+
+/// 把工作表名称截断到 max_len 以内, 只在字符边界截断
+/// 
+/// 与 Excel 一样按 UTF-16 代码单元计数: 中文等 BMP 字符算 1 个, emoji 等补充平面字符算 2 个
+fn truncate_sheet_name(name: &str, max_len: usize) -> String {
+  let mut len = 0;
+  name.chars()
+    .take_while(|c| {
+      len += c.len_utf16();
+      len <= max_len
+    })
+    .collect()
+}
+
+/// 重命名指定的工作表
+/// 
+/// 重命名工作表包括以下步骤：
+/// 1. 在 workbook.xml 中找到对应的 <sheet> 节点
+/// 2. 修改 name 属性为新名称
+/// 
+/// 注意事项：
+/// - 工作表名称会自动过滤非法字符：\ / ? * [ ]
+/// - 名称长度会自动限制在 31 个字符以内（按 UTF-16 计数，见 [`truncate_sheet_name`]）
+/// - 如果新名称与现有工作表重名，会自动添加数字后缀, 加上后缀后同样不超过 31 个字符
+fn rename_sheets(
+  files: &mut HashMap<String, Vec<u8>>,
+  sheets_to_rename: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  if sheets_to_rename.is_empty() {
     return Ok(());
   }
   
@@ -1263,18 +3004,27 @@ fn hide_sheets(
     .ok_or("workbook.xml not found")?;
   let mut workbook_xml = String::from_utf8(workbook_content.clone())?;
   
-  // 2. 统计总工作表数量和已隐藏的数量
-  let total_sheets = workbook_xml.matches("<sheet ").count();
-  
-  // 3. 检查是否会隐藏所有工作表
-  if sheets_to_hide.len() >= total_sheets {
-    return Err(Box::new(std::io::Error::other(
-      "Cannot hide all worksheets. Excel workbook must have at least one visible worksheet."
-    )));
+  // 2. 收集所有现有的工作表名称（用于检测重名）
+  let mut existing_names: Vec<String> = Vec::new();
+  let mut start = 0;
+  while let Some(name_pos) = workbook_xml[start..].find("<sheet ") {
+    let abs_pos = start + name_pos;
+    if let Some(name_start) = workbook_xml[abs_pos..].find("name=\"") {
+      let name_abs_start = abs_pos + name_start + 6; // "name=\"".len()
+      if let Some(name_end) = workbook_xml[name_abs_start..].find('"') {
+        let name = workbook_xml[name_abs_start..name_abs_start + name_end].to_string();
+        existing_names.push(name);
+        start = name_abs_start + name_end;
+      } else {
+        break;
+      }
+    } else {
+      break;
+    }
   }
   
-  // 4. 对每个要隐藏的工作表进行处理
-  for (sheet_path, hide_type) in sheets_to_hide {
+  // 3. 对每个要重命名的工作表进行处理
+  for (sheet_path, new_name) in sheets_to_rename {
     // 从路径提取 sheet 编号
     let sheet_num: u32 = match sheet_path
       .trim_start_matches("xl/worksheets/sheet")
@@ -1284,7 +3034,26 @@ fn hide_sheets(
         Err(_) => continue,
       };
     
-    // 在 workbook.xml 中查找对应的 <sheet> 节点
+    // 生成唯一的新名称（如果重名则添加后缀）
+    let new_name = &truncate_sheet_name(new_name, SHEET_NAME_MAX_LEN);
+    let mut final_name = new_name.clone();
+    let mut counter = 1;
+    while existing_names.contains(&final_name) {
+      // 限制名称+后缀的总长度不超过 31, 后缀只有 ASCII 字符
+      let suffix = format!(" ({})", counter);
+      let base = truncate_sheet_name(new_name, SHEET_NAME_MAX_LEN - suffix.len());
+      final_name = format!("{}{}", base, suffix);
+      counter += 1;
+      
+      // 防止无限循环
+      if counter > 100 {
+        final_name = format!("Sheet{}", sheet_num);
+        break;
+      }
+    }
+    
+    // 在 workbook.xml 中查找并替换工作表名称
+    // 需要找到对应 sheet 编号的 <sheet> 节点
     let sheet_id_pattern = format!("sheetId=\"{}\"", sheet_num);
     if let Some(sheet_id_pos) = workbook_xml.find(&sheet_id_pattern) {
       // 向前查找 <sheet 标签的开始
@@ -1296,28 +3065,1777 @@ fn hide_sheets(
           let full_end = sheet_id_pos + tag_end + 2;
           let sheet_tag = &workbook_xml[tag_start..full_end];
           
-          // 检查是否已经有 state 属性
-          if sheet_tag.contains("state=") {
-            // 已有 state 属性，替换它
-            if let Some(state_start) = sheet_tag.find("state=\"") {
-              let state_abs_start = tag_start + state_start + 7; // "state=\"".len()
-              if let Some(state_end) = workbook_xml[state_abs_start..].find('"') {
-                let state_abs_end = state_abs_start + state_end;
-                workbook_xml.replace_range(state_abs_start..state_abs_end, hide_type);
+          // 在这个标签中查找并替换 name 属性
+          if let Some(name_start) = sheet_tag.find("name=\"") {
+            let name_abs_start = tag_start + name_start + 6;
+            if let Some(name_end) = workbook_xml[name_abs_start..].find('"') {
+              let name_abs_end = name_abs_start + name_end;
+              let old_name = workbook_xml[name_abs_start..name_abs_end].to_string();
+              
+              // 替换名称
+              workbook_xml.replace_range(name_abs_start..name_abs_end, &final_name);
+              
+              // 更新 existing_names 列表
+              if let Some(pos) = existing_names.iter().position(|n| n == &old_name) {
+                existing_names[pos] = final_name.clone();
               }
             }
-          } else {
-            // 没有 state 属性，在 /> 之前添加
-            let insert_pos = full_end - 2; // 在 /> 的 / 之前
-            workbook_xml.insert_str(insert_pos, &format!(" state=\"{}\"", hide_type));
           }
         }
       }
     }
   }
   
-  // 5. 更新 workbook.xml
+  // 4. 更新 workbook.xml
   files.insert(workbook_path.to_string(), workbook_xml.into_bytes());
   
   Ok(())
 }
+
+/// 隐藏指定的工作表
+/// 
+/// 隐藏工作表包括以下步骤：
+/// 1. 通过 workbook.xml.rels 中的 r:id 在 workbook.xml 中找到对应的 <sheet> 节点
+/// 2. 添加或修改 state 属性为 "hidden" 或 "veryHidden"
+/// 
+/// 隐藏级别：
+/// - "hidden": 普通隐藏，用户可以通过右键菜单 → 取消隐藏
+/// - "veryHidden": 超级隐藏，需要 VBA 代码或属性编辑器才能取消隐藏
+/// - "visible": 由 showCurrentSheet 设置, 删除 state 属性, 使模板中隐藏的工作表重新显示
+/// 
+/// 注意：至少要保留一个可见的工作表，否则 Excel 会报错
+fn hide_sheets(
+  files: &mut HashMap<String, Vec<u8>>,
+  sheets_to_hide: &HashMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  if sheets_to_hide.is_empty() {
+    return Ok(());
+  }
+  
+  // 1. 统计总工作表数量
+  let sheets = workbook_sheets(files)?;
+  let total_sheets = sheets.len();
+  
+  // 2. 检查是否会隐藏所有工作表
+  let hidden: Vec<String> = sheets.into_iter()
+    .filter(|(path, _)| sheets_to_hide.get(path).is_some_and(|hide_type| hide_type != "visible"))
+    .map(|(_, name)| name)
+    .collect();
+  if hidden.len() >= total_sheets {
+    return Err(Box::new(XlsxError::CannotHideAllSheets { total: total_sheets, requested: hidden }));
+  }
+  
+  // 3. 通过 r:id 找到每个要隐藏的 <sheet> 节点, 添加或替换 state 属性, 显示时删除 state 属性
+  update_workbook_sheets(files, |sheet_path, e| {
+    sheets_to_hide.get(sheet_path)
+      .map(|hide_type| with_attribute(e, "state", Some(hide_type.as_str()).filter(|state| *state != "visible")))
+      .transpose()
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  
+  /// 1x1 像素的 PNG 图片
+  const TINY_PNG_BASE64: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+  
+  /// 构造一个只有一个工作表的最小 xlsx 文件
+  fn build_xlsx(sheet_data: &str, shared_strings: &[&str]) -> Vec<u8> {
+    let entries = xlsx_entries(sheet_data, shared_strings);
+    build_zip(&entries)
+  }
+  
+  /// 最小 xlsx 文件的所有文件内容，方便测试中追加或替换文件
+  fn xlsx_entries(sheet_data: &str, shared_strings: &[&str]) -> Vec<(String, String)> {
+    xlsx_entries_around("", sheet_data, "", shared_strings)
+  }
+  
+  /// 与 build_xlsx 相同, before / after 分别写在 sheetData 的前面和后面, 例如 dimension、sheetViews、mergeCells
+  fn build_xlsx_around(before: &str, sheet_data: &str, after: &str, shared_strings: &[&str]) -> Vec<u8> {
+    build_zip(&xlsx_entries_around(before, sheet_data, after, shared_strings))
+  }
+  
+  /// 与 xlsx_entries 相同, before / after 分别写在 sheetData 的前面和后面
+  fn xlsx_entries_around(before: &str, sheet_data: &str, after: &str, shared_strings: &[&str]) -> Vec<(String, String)> {
+    let sheet_xml = format!(
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">{before}<sheetData>{sheet_data}</sheetData>{after}<pageMargins left="0.7" right="0.7" top="0.75" bottom="0.75" header="0.3" footer="0.3"/></worksheet>"#
+    );
+    let sst_xml = format!(
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="{0}" uniqueCount="{0}">{1}</sst>"#,
+      shared_strings.len(),
+      shared_strings.iter().map(|s| format!("<si><t>{s}</t></si>")).collect::<String>(),
+    );
+    [
+      ("[Content_Types].xml", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/></Types>"#),
+      ("_rels/.rels", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#),
+      ("xl/workbook.xml", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets></workbook>"#),
+      ("xl/_rels/workbook.xml.rels", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/></Relationships>"#),
+      ("xl/worksheets/sheet1.xml", &sheet_xml),
+      ("xl/sharedStrings.xml", &sst_xml),
+    ].iter().map(|(n, c)| (n.to_string(), c.to_string())).collect()
+  }
+  
+  /// 包含多个工作表的 xlsx 文件内容, 每个工作表只有 A1 一个单元格, 内容为 sheet_texts 中对应的共享字符串
+  fn multi_sheet_entries(sheet_texts: &[&str]) -> Vec<(String, String)> {
+    let mut entries = xlsx_entries("", sheet_texts);
+    let sheet_template = entries.remove(entries.iter().position(|(name, _)| name == "xl/worksheets/sheet1.xml").unwrap()).1;
+    let sheets: String = (1..=sheet_texts.len()).map(|n| format!(r#"<sheet name="Sheet{n}" sheetId="{n}" r:id="rId{}"/>"#, n + 1)).collect();
+    let rels: String = (1..=sheet_texts.len()).map(|n| format!(r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{n}.xml"/>"#, n + 1)).collect();
+    let overrides: String = (1..=sheet_texts.len()).map(|n| format!(r#"<Override PartName="/xl/worksheets/sheet{n}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#)).collect();
+    for (name, contents) in entries.iter_mut() {
+      *contents = match name.as_str() {
+        "[Content_Types].xml" => contents.replace(r#"<Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#, &overrides),
+        "xl/workbook.xml" => contents.replace(r#"<sheet name="Sheet1" sheetId="1" r:id="rId1"/>"#, &sheets),
+        "xl/_rels/workbook.xml.rels" => contents.replace(r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>"#, &rels),
+        _ => contents.clone(),
+      };
+    }
+    for n in 1..=sheet_texts.len() {
+      let sheet_data = format!(r#"<row r="1"><c r="A1" t="s"><v>{}</v></c></row>"#, n - 1);
+      entries.push((format!("xl/worksheets/sheet{n}.xml"), sheet_template.replace("<sheetData></sheetData>", &format!("<sheetData>{sheet_data}</sheetData>"))));
+    }
+    entries
+  }
+  
+  /// 按文件名取出 entries 中的文件内容, 用于修改 xlsx_entries 生成的文件
+  fn entry_mut<'a>(entries: &'a mut [(String, String)], name: &str) -> &'a mut String {
+    &mut entries.iter_mut().find(|(n, _)| n == name).unwrap_or_else(|| panic!("{name} not found")).1
+  }
+  
+  /// 按顺序把文件写入 zip
+  fn build_zip<N: AsRef<str>, C: AsRef<str>>(entries: &[(N, C)]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut zip_writer = ZipWriter::new(Cursor::new(&mut output));
+    for (name, contents) in entries {
+      zip_writer.start_file(name.as_ref(), SimpleFileOptions::default()).unwrap();
+      zip_writer.write_all(contents.as_ref().as_bytes()).unwrap();
+    }
+    zip_writer.finish().unwrap();
+    output
+  }
+  
+  /// 读取 zip 中指定文件的文本内容
+  fn read_entry(zip_bytes: &[u8], name: &str) -> Option<String> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes)).unwrap();
+    let mut file = archive.by_name(name).ok()?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    Some(contents)
+  }
+  
+  /// 按 zip 中的顺序列出所有文件名
+  fn entry_names(zip_bytes: &[u8]) -> Vec<String> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes)).unwrap();
+    (0..archive.len()).map(|i| archive.by_index(i).unwrap().name().to_string()).collect()
+  }
+  
+  #[test]
+  fn test_empty_each_removes_its_own_row() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row><row r="4"><c r="A4" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["Header", "{{#each items}}", "{{name}}{{/each}}", "Footer"]);
+    
+    // 空数组: {{#each 所在的第 2 行被自动删除
+    let result = render_template(template.clone(), &json!({ "items": [] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet.contains(r#"<row r="2""#), "{sheet}");
+    assert!(!sheet.contains(REMOVE_ROW_KEY));
+    assert!(sheet.contains("Header") && sheet.contains("Footer"));
+    
+    // 非空数组: 正常渲染
+    let result = render_template(template, &json!({ "items": [{ "name": "a" }, { "name": "b" }] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("<t>a</t>") && sheet.contains("<t>b</t>"), "{sheet}");
+    
+    // {{#each 所在行有其他内容时不自动删除
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row><row r="2"><c r="A2" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["Label", "{{#each items}}", "{{name}}{{/each}}"]);
+    let result = render_template(template, &json!({ "items": [] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("Label"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_output_is_deterministic_and_keeps_entry_order() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{name}}"]);
+    let data = json!({ "name": "test" });
+    
+    let first = render_template(template.clone(), &data).unwrap();
+    let second = render_template(template.clone(), &data).unwrap();
+    assert_eq!(first, second);
+    
+    // 输出顺序与模板一致 (sharedStrings.xml 已被移除), [Content_Types].xml 在最前面
+    let ordered = entry_names(&first);
+    let mut expected = entry_names(&template);
+    expected.retain(|name| name != "xl/sharedStrings.xml");
+    assert_eq!(ordered, expected);
+    assert_eq!(ordered[0], "[Content_Types].xml");
+  }
+  
+  #[test]
+  fn test_merge_sheet_rels() {
+    // 没有现有 rels 文件
+    let (xml, rid) = merge_sheet_rels(None, "../drawings/drawing1.xml");
+    assert_eq!(rid, "rId1");
+    assert!(xml.contains(r#"Id="rId1""#) && xml.contains(r#"Target="../drawings/drawing1.xml""#));
+    
+    // 已有 rels 文件: 保留原有关系并选择未被占用的 ID
+    let existing = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com" TargetMode="External"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.org" TargetMode="External"/></Relationships>"#;
+    let (xml, rid) = merge_sheet_rels(Some(existing), "../drawings/drawing1.xml");
+    assert_eq!(rid, "rId2");
+    assert!(xml.contains("https://example.com") && xml.contains("https://example.org"));
+    assert!(xml.contains(r#"<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing" Target="../drawings/drawing1.xml"/></Relationships>"#));
+    assert_eq!(xml.matches("<Relationship ").count(), 3);
+  }
+  
+  #[test]
+  fn test_image_keeps_existing_sheet_rels() {
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    entries.push((
+      "xl/worksheets/_rels/sheet1.xml.rels".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com" TargetMode="External"/></Relationships>"#.to_string(),
+    ));
+    let template = build_zip(&entries);
+    
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    let rels = read_entry(&result, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+    assert!(rels.contains(r#"Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink""#), "{rels}");
+    assert!(rels.contains(r#"Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing" Target="../drawings/drawing1.xml""#), "{rels}");
+    
+    // sheet.xml 中的 <drawing> 引用的正是 drawing 关系的 ID
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<drawing r:id="rId2"/>"#), "{sheet}");
+    assert!(!sheet.contains(r#"<drawing r:id="rId1""#), "{sheet}");
+    
+    // 图片关系 ID 是合法的顺序编号
+    let drawing_rels = read_entry(&result, "xl/drawings/_rels/drawing1.xml.rels").unwrap();
+    assert!(drawing_rels.contains(r#"Id="rId1""#), "{drawing_rels}");
+  }
+  
+  #[test]
+  fn test_image_anchor_override() {
+    let template = build_xlsx(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#,
+      &["{{img logo 10 10 anchor=\"G3\"}}", "{{img logo 10 10}}"],
+    );
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    // G3 -> 0-based col 6, row 2
+    assert!(drawing.contains("<xdr:col>6</xdr:col>\n      <xdr:colOff>0</xdr:colOff>\n      <xdr:row>2</xdr:row>"), "{drawing}");
+    // 没有 anchor 时使用当前单元格 B1
+    assert!(drawing.contains("<xdr:col>1</xdr:col>\n      <xdr:colOff>0</xdr:colOff>\n      <xdr:row>0</xdr:row>"), "{drawing}");
+  }
+  
+  #[test]
+  fn test_split_feeds_row_each() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[r#"{{#each (split tags ",")}}"#, "{{trim this}}{{/each}}", "Footer"]);
+    let result = render_template(template, &json!({ "tags": "x, y ,z" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // split 的每一项占一行, 行号依次递增
+    for (r, tag) in [(2, "x"), (3, "y"), (4, "z")] {
+      let row = format!(r#"<row r="{r}"><c r="A{r}" t="inlineStr"><is><r><t>{tag}</t></r></is></c></row>"#);
+      assert!(sheet.contains(&row), "{row} not in {sheet}");
+    }
+    // 循环后的行被整体下移, 排在最后一项之后
+    assert!(sheet.find("<t>z</t>").unwrap() < sheet.find("<t>Footer</t>").unwrap());
+  }
+  
+  #[test]
+  fn test_set_data_nested_path() {
+    let mut entries = xlsx_entries(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#,
+      &[r#"{{set_data "totals.grand" 42}}{{set_data "totals.count" 3}}"#, "{{totals.grand}}/{{totals.count}}/{{totals.other}}"],
+    );
+    let sheet2 = entry_mut(&mut entries, "xl/worksheets/sheet1.xml").replace("<v>0</v>", "<v>1</v>");
+    entries.push(("xl/worksheets/sheet2.xml".to_string(), sheet2));
+    let result = render_template(build_zip(&entries), &json!({ "totals": { "other": "x" } })).unwrap();
+    
+    // sheet1 中写入的嵌套字段在 sheet2 中可以读取, 已有的兄弟字段保留
+    let sheet2 = read_entry(&result, "xl/worksheets/sheet2.xml").unwrap();
+    assert!(sheet2.contains("<t>42/3/x</t>"), "{sheet2}");
+    
+    // 不允许在非对象值下写入子路径
+    let entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[r#"{{set_data "name.first" "a"}}"#]);
+    let err = render_template(build_zip(&entries), &json!({ "name": "bob" })).unwrap_err();
+    assert!(err.to_string().contains("set_data"), "{err}");
+  }
+  
+  #[test]
+  fn test_render_template_from_reader() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{name}}"]);
+    let data = json!({ "name": "reader" });
+    
+    // 借用的字节切片与 Vec<u8> 版本结果一致
+    let from_reader = render_template_from_reader(Cursor::new(template.as_slice()), &data).unwrap();
+    assert_eq!(from_reader, render_template(template.clone(), &data).unwrap());
+    assert!(read_entry(&from_reader, "xl/worksheets/sheet1.xml").unwrap().contains("<t>reader</t>"));
+    
+    assert!(render_template_from_reader(Cursor::new(&b"not a zip file at all...."[..]), &data).is_err());
+  }
+  
+  #[test]
+  fn test_render_template_to_writer() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{name}}"]);
+    let data = json!({ "name": "writer" });
+    
+    let mut output = Cursor::new(Vec::new());
+    render_template_to_writer(template.clone(), &data, &mut output).unwrap();
+    assert_eq!(output.into_inner(), render_template(template, &data).unwrap());
+  }
+  
+  #[test]
+  fn test_str_cells_with_handlebars() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="str"><v>{{name}}</v></c><c r="B1" t="str"><v>plain</v></c><c r="C1" t="str"><f>A1&amp;"!"</f><v>old</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[]);
+    let result = render_template(template, &json!({ "name": "Bob & Co" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 包含 handlebars 的 t="str" 单元格转换为字符串单元格并渲染
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>Bob &amp; Co</t></is></c>"#), "{sheet}");
+    // 不含 handlebars 的保持原样, 公式单元格仍然去掉缓存值
+    assert!(sheet.contains(r#"<c r="B1" t="str"><v>plain</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1" t="str"><f>A1&amp;"!"</f></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_existing_inline_str_cells_expand_like_shared_strings() {
+    let inline = |text: &str| format!(r#"<is>{text}</is>"#);
+    let row = |r: u32, text: &str| format!(r#"<row r="{r}"><c r="A{r}" t="inlineStr">{text}</c></row>"#);
+    let sheet_data = [
+      row(1, &inline("<t>{{#each items}}</t>")),
+      row(2, &inline("<r><t>{{na</t></r><r><t>me}}{{/each}}</t></r>")),
+      row(3, &inline("<t>Footer</t>")),
+    ].concat();
+    let data = json!({ "items": [{ "name": "a" }, { "name": "b" }] });
+    let result = render_template(build_xlsx(&sheet_data, &[]), &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<row r="2"><c r="A2" t="inlineStr"><is><r><t>a</t></r></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="3"><c r="A3" t="inlineStr"><is><r><t>b</t></r></is></c></row>"#), "{sheet}");
+    
+    // 与同样内容的 sharedStrings 模板结果一致
+    let sst_sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row>"#;
+    let sst_template = build_xlsx(sst_sheet_data, &["{{#each items}}", "{{name}}{{/each}}", "Footer"]);
+    let sst_sheet = read_entry(&render_template(sst_template, &data).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert_eq!(sheet, sst_sheet);
+  }
+  
+  #[test]
+  fn test_nested_row_and_col_loops_get_distinct_cells() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each rows}}", "{{#each this}}", "{{this}}{{/each}}{{/each}}", "Footer"]);
+    let result = render_template(template, &json!({ "rows": [["a", "b"], ["c", "d"]] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 外层按行循环, 内层按列循环: 每一行的列偏移重新开始, 行偏移只在外层每次迭代时累加
+    for (cell, value) in [("B2", "a"), ("C2", "b"), ("B3", "c"), ("C3", "d")] {
+      let expected = format!(r#"<c r="{cell}" t="inlineStr"><is><r><t>{value}</t></r></is></c>"#);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+  }
+  
+  #[test]
+  fn test_last_row_after_loop() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{this}}{{/each}}", "=SUM(A2:A{{lastRow}})"]);
+    let result = render_template(template, &json!({ "items": [1, 2, 3] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 数据行为 2..4, 循环之后的合计行引用最后一个数据行
+    assert!(sheet.contains(r#"<c r="A4" t="inlineStr"><is><r><t>3</t></r></is></c>"#), "{sheet}");
+    assert!(sheet.contains("=SUM(A2:A4)"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_formula_with_cached_value() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[r#"{{formula "=1+2"}}"#, r#"{{formula "=1+2" total}}"#, r#"{{formula "=UPPER(A2)" "A&amp;B"}}"#]);
+    let result = render_template(template, &json!({ "total": 3 })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1"><f>=1+2</f></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1"><f>=1+2</f><v>3</v></c>"#), "{sheet}");
+    // 文本缓存值使用 t="str", 并进行 XML 转义
+    assert!(sheet.contains(r#"<c r="C1" t="str"><f>=UPPER(A2)</f><v>A&amp;B</v></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_formula_is_xml_escaped() {
+    let template = build_xlsx(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#,
+      &[r#"{{formula "=IF(A1&lt;B1,\"yes\",\"no\")"}}"#, r#"{{formula (concat "=A1&amp;" "\"&gt;\"")}}"#],
+    );
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1"><f>=IF(A1&lt;B1,"yes","no")</f></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1"><f>=A1&amp;"&gt;"</f></c>"#), "{sheet}");
+    // 输出的 sheet 是合法的 XML
+    let mut reader = quick_xml::Reader::from_str(&sheet);
+    while !matches!(reader.read_event().unwrap(), quick_xml::events::Event::Eof) {}
+  }
+  
+  #[test]
+  fn test_number_cells_are_valid_numbers() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{num price}}", "{{num price}} &lt;kg&gt;", "{{num bad}}", "A&amp;B"]);
+    let result = render_template(template, &json!({ "price": 12.5, "bad": "abc" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1"><v>12.5</v></c>"#), "{sheet}");
+    // 数字标记后还有其他文本时不是合法数值, 输出 0, 特殊字符不会写入 <v>
+    assert!(sheet.contains(r#"<c r="B1"><v>0</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1"><v>0</v></c>"#), "{sheet}");
+    // 同一行的其他单元格保留实体
+    assert!(sheet.contains("<t>A&amp;B</t>"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_bool_cells() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{bool done}}", "{{bool pending}}", "{{bool flag}}"]);
+    let result = render_template(template, &json!({ "done": true, "pending": false, "flag": "Yes" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1" t="b"><v>1</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="b"><v>0</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1" t="b"><v>1</v></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_cell_error_cells() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{cellError \"#N/A\"}}", "{{cellError err}}"]);
+    let result = render_template(template.clone(), &json!({ "err": "#DIV/0!" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1" t="e"><v>#N/A</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="e"><v>#DIV/0!</v></c>"#), "{sheet}");
+    
+    let err = render_template(template, &json!({ "err": "#OOPS" })).unwrap_err();
+    assert!(err.to_string().contains("#OOPS"), "{err}");
+  }
+  
+  #[test]
+  fn test_shared_strings_part_is_removed_consistently() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{name}}"]);
+    let result = render_template(template, &json!({ "name": "Alice" })).unwrap();
+    let names = entry_names(&result);
+    
+    assert!(!names.iter().any(|n| n == "xl/sharedStrings.xml"), "{names:?}");
+    
+    // 严格校验: [Content_Types].xml 的每个 Override 和 workbook.xml.rels 的每个内部关系都必须指向存在的部件
+    let content_types = read_entry(&result, "[Content_Types].xml").unwrap();
+    for part in content_types.split("PartName=\"/").skip(1) {
+      let part = &part[..part.find('"').unwrap()];
+      assert!(names.iter().any(|n| n == part), "Override points to missing part {part}");
+    }
+    let rels = read_entry(&result, "xl/_rels/workbook.xml.rels").unwrap();
+    assert!(!rels.contains("sharedStrings"), "{rels}");
+    for target in rels.split("Target=\"").skip(1) {
+      let target = format!("xl/{}", &target[..target.find('"').unwrap()]);
+      assert!(names.contains(&target), "Relationship points to missing part {target}");
+    }
+    
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet.contains(r#"t="s""#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_post_process_hook_adds_custom_part() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{name}}"]);
+    let options = RenderOptions {
+      post_process: Some(Box::new(|files: &mut HashMap<String, Vec<u8>>| {
+        assert!(files.contains_key("xl/worksheets/sheet1.xml"));
+        files.insert("docProps/custom.xml".to_string(), b"<Properties/>".to_vec());
+      })),
+      ..Default::default()
+    };
+    let result = render_template_with_options(template, &json!({ "name": "Alice" }), options).unwrap();
+    
+    assert_eq!(read_entry(&result, "docProps/custom.xml").unwrap(), "<Properties/>");
+    assert!(read_entry(&result, "xl/worksheets/sheet1.xml").unwrap().contains("Alice"));
+  }
+  
+  #[test]
+  fn test_extract_template_variables() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[
+      "{{title}}",
+      "{{#each items}}{{#if ../showIndex}}{{@index}}{{/if}}",
+      "{{num price}} {{upper (concat name \"!\")}}{{/each}}",
+      "{{!-- comment --}}{{formatNumber total 2}}",
+    ]);
+    let names = extract_template_variables(template).unwrap();
+    
+    assert_eq!(names, vec!["title", "items", "showIndex", "num", "price", "upper", "concat", "name", "formatNumber", "total"]);
+    
+    // 渲染时自动补上的 {{else}}{{removeRow}} 不属于模板引用的名称
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{name}}{{/each}}"]);
+    assert_eq!(extract_template_variables(template).unwrap(), vec!["items", "name"]);
+  }
+  
+  #[test]
+  fn test_each_with_block_params_across_rows() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[
+      "{{#each orders as |order|}}",
+      "{{#each order.lines as |line idx|}}",
+      "{{order.id}}-{{idx}}:{{line}}{{/each}}{{/each}}",
+      "Footer",
+    ]);
+    let data = json!({ "orders": [{ "id": "A", "lines": ["x", "y"] }, { "id": "B", "lines": ["z"] }] });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    for (cell, value) in [("B2", "A-0:x"), ("C2", "A-1:y"), ("B3", "B-0:z")] {
+      let expected = format!(r#"<c r="{cell}" t="inlineStr"><is><r><t>{value}</t></r></is></c>"#);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+  }
+  
+  #[test]
+  fn test_template_without_shared_strings() {
+    // 全部使用 inlineStr 编写的模板没有 sharedStrings.xml, 多余的 t="s" 单元格也不应导致崩溃
+    let sheet_data = r#"<row r="1"><c r="A1" t="inlineStr"><is><t>{{title}}</t></is></c><c r="B1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="inlineStr"><is><t>{{#each items}}</t></is></c></row><row r="3"><c r="A3" t="inlineStr"><is><r><t>{{na</t></r><r><t>me}}{{/each}}</t></r></is></c></row>"#;
+    let entries: Vec<(String, String)> = xlsx_entries(sheet_data, &[])
+      .into_iter()
+      .filter(|(name, _)| name != "xl/sharedStrings.xml")
+      .map(|(name, contents)| (name, remove_empty_element(&remove_empty_element(&contents, "Override", "sharedStrings"), "Relationship", "sharedStrings")))
+      .collect();
+    let template = build_zip(&entries);
+    
+    let result = render_template(template, &json!({ "title": "Report", "items": [{ "name": "a" }, { "name": "b" }] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(!entry_names(&result).iter().any(|n| n == "xl/sharedStrings.xml"));
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>Report</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t/></is></c>"#), "{sheet}");
+    assert!(sheet.contains(">a</t>") && sheet.contains(">b</t>"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_sheet_name_helper_outputs_display_name() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let entries: Vec<(String, String)> = xlsx_entries(sheet_data, &["{{_sheetName}}", "{{currentSheet}}"])
+      .into_iter()
+      .map(|(name, contents)| (name, contents.replace(r#"name="Sheet1""#, r#"name="R&amp;D Report""#)))
+      .collect();
+    let result = render_template(build_zip(&entries), &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>R&amp;D Report</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t>R&amp;D Report</t></is></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_loop_rows_keep_template_cell_styles() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2" s="4" customFormat="1"><c r="A2" s="3" t="s"><v>1</v></c><c r="B2" s="5"/><c r="C2" s="6" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{name}}", "{{num qty}}{{/each}}", "Footer"]);
+    let data = json!({ "items": [{ "name": "a", "qty": 1 }, { "name": "b", "qty": 2 }, { "name": "c", "qty": 3 }] });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 每个展开的行都保留模板行和模板单元格的 s 样式, 只有样式的空单元格也有各自的单元格引用
+    for row in 2..=4 {
+      assert!(sheet.contains(&format!(r#"<row r="{row}" s="4" customFormat="1">"#)), "row {row}: {sheet}");
+      assert!(sheet.contains(&format!(r#"<c r="A{row}" s="3" t="inlineStr">"#)), "A{row}: {sheet}");
+      assert!(sheet.contains(&format!(r#"<c r="B{row}" s="5"/>"#)), "B{row}: {sheet}");
+      assert!(sheet.contains(&format!(r#"<c r="C{row}" s="6"><v>{}</v></c>"#, row - 1)), "C{row}: {sheet}");
+    }
+  }
+  
+  #[test]
+  fn test_dimension_grows_after_row_loop() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx_around(r#"<dimension ref="A1:B3"/>"#, sheet_data, "", &["Header", "{{#each items}}", "{{this}}{{/each}}"]);
+    let result = render_template(template, &json!({ "items": [1, 2, 3, 4, 5] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 5 项展开到第 3~7 行, 最宽的是第 1 行的 B 列
+    assert!(sheet.contains(r#"<row r="7"><c r="A7" t="inlineStr"><is><r><t>5</t></r></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<dimension ref="A1:B7"/>"#), "{sheet}");
+    
+    // 模板中没有 dimension 时不添加
+    let template = build_xlsx(sheet_data, &["Header", "{{#each items}}", "{{this}}{{/each}}"]);
+    let result = render_template(template, &json!({ "items": [1] })).unwrap();
+    assert!(!read_entry(&result, "xl/worksheets/sheet1.xml").unwrap().contains("<dimension"));
+  }
+  
+  #[test]
+  fn test_static_merge_cells_kept_without_markers() {
+    // 没有 num / formula 等标记的 sheet 同样需要写回合并单元格
+    let template = build_xlsx_around("", r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, r#"<mergeCells count="1"><mergeCell ref="A1:C1"/></mergeCells>"#, &["{{title}}"]);
+    let result = render_template(template, &json!({ "title": "Report" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<mergeCells count="1"><mergeCell ref="A1:C1"/></mergeCells>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_page_breaks() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row><row r="4"><c r="A4" t="s"><v>3</v></c><c r="B4" t="s"><v>4</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["Title", "{{#each sections}}", "{{#unless @first}}{{pageBreakBefore}}{{/unless}}{{this}}{{/each}}", "Total", "{{colPageBreak}}Notes"]);
+    let result = render_template(template, &json!({ "sections": ["a", "b", "c"] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 分节在第 3、4、5 行, 第 4、5 行之前分页; B 列之前分页
+    assert!(sheet.contains(r#"<c r="A4" t="inlineStr"><is><r><t>b</t></r></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<rowBreaks count="2" manualBreakCount="2"><brk id="3" max="16383" man="1"/><brk id="4" max="16383" man="1"/></rowBreaks>"#), "{sheet}");
+    assert!(sheet.contains(r#"<colBreaks count="1" manualBreakCount="1"><brk id="1" max="1048575" man="1"/></colBreaks></worksheet>"#), "{sheet}");
+    assert!(sheet.find("<pageMargins").unwrap() < sheet.find("<rowBreaks").unwrap());
+  }
+  
+  #[test]
+  fn test_print_area() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row><row r="4"><c r="A4" t="s"><v>3</v></c></row>"#;
+    let entries: Vec<(String, String)> = xlsx_entries(sheet_data, &["Title", "{{#each items}}", "{{this}}{{/each}}", "{{printArea (concat \"A1:G\" (_r))}}Total"])
+      .into_iter()
+      .map(|(name, contents)| (name, contents.replace(r#"name="Sheet1""#, r#"name="Q1 Report""#)))
+      .collect();
+    let result = render_template(build_zip(&entries), &json!({ "items": [1, 2, 3] })).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    
+    // 3 行数据展开后合计行在第 7 行
+    assert!(read_entry(&result, "xl/worksheets/sheet1.xml").unwrap().contains(r#"<c r="A7" t="inlineStr"><is><t>Total</t></is></c>"#));
+    assert!(workbook.contains(r#"</sheets><definedNames><definedName name="_xlnm.Print_Area" localSheetId="0">'Q1 Report'!$A$1:$G$7</definedName></definedNames></workbook>"#), "{workbook}");
+  }
+  
+  #[test]
+  fn test_define_name() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{defineName \"TaxRate\" \"Config!$B$2\"}}{{defineName \"Sales.Total\" (concat \"Sheet1!$A$1:$A$\" (_r))}}Report"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    
+    assert!(workbook.contains(r#"<definedNames><definedName name="TaxRate">Config!$B$2</definedName><definedName name="Sales.Total">Sheet1!$A$1:$A$1</definedName></definedNames>"#), "{workbook}");
+    
+    for name in ["Tax Rate", "A1", "tax2024", "R1C1", "r", "C", "1Rate", ""] {
+      let template = build_xlsx(sheet_data, &[&format!("{{{{defineName \"{name}\" \"Config!$B$2\"}}}}")]);
+      assert!(render_template(template, &json!({})).is_err(), "{name}");
+    }
+    assert!(is_valid_defined_name("XFE1") && is_valid_defined_name("RC_Total") && is_valid_defined_name("_Rate"));
+  }
+  
+  #[test]
+  fn test_delete_all_sheets_policy() {
+    let template = build_zip(&multi_sheet_entries(&["{{deleteCurrentSheet}}", "{{deleteCurrentSheet}}", "Keep"]));
+    let result = render_template(template, &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    assert!(workbook.contains(r#"<sheets><sheet name="Sheet3" sheetId="3" r:id="rId4"/></sheets>"#), "{workbook}");
+    
+    // 默认返回 CannotDeleteLastSheet
+    let template = build_zip(&multi_sheet_entries(&["{{deleteCurrentSheet}}", "{{deleteCurrentSheet}}{{deleteCurrentSheet}}", "{{deleteCurrentSheet}}"]));
+    let err = render_template(template.clone(), &json!({})).unwrap_err();
+    assert!(matches!(err.downcast_ref::<XlsxError>(), Some(XlsxError::CannotDeleteLastSheet { .. })), "{err}");
+    
+    // keep_last_sheet 时保留最后一个被标记删除的工作表
+    let options = RenderOptions { keep_last_sheet: true, ..Default::default() };
+    let result = render_template_with_options(template, &json!({}), options).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    assert!(workbook.contains(r#"<sheets><sheet name="Sheet3" sheetId="3" r:id="rId4"/></sheets>"#), "{workbook}");
+    assert_eq!(entry_names(&result).iter().filter(|n| n.starts_with("xl/worksheets/")).collect::<Vec<_>>(), ["xl/worksheets/sheet3.xml"]);
+  }
+  
+  #[test]
+  fn test_delete_middle_sheet_keeps_remaining_sheets_consistent() {
+    let mut entries = multi_sheet_entries(&["First", "{{deleteCurrentSheet}}", "Third"]);
+    let workbook = entry_mut(&mut entries, "xl/workbook.xml");
+    *workbook = workbook.replace("<sheets>", r#"<bookViews><workbookView activeTab="2"/></bookViews><sheets>"#)
+      .replace("</sheets>", r#"</sheets><definedNames><definedName name="_xlnm.Print_Area" localSheetId="1">Sheet2!$A$1:$B$2</definedName><definedName name="_xlnm.Print_Area" localSheetId="2">Sheet3!$A$1:$B$2</definedName></definedNames>"#);
+    let result = render_template(build_zip(&entries), &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    let rels = read_entry(&result, "xl/_rels/workbook.xml.rels").unwrap();
+    let content_types = read_entry(&result, "[Content_Types].xml").unwrap();
+    
+    // 剩余工作表顺序不变, r:id 都能在 rels 中找到, Override 与剩余的 sheet 文件一一对应
+    assert!(workbook.contains(r#"<sheets><sheet name="Sheet1" sheetId="1" r:id="rId2"/><sheet name="Sheet3" sheetId="3" r:id="rId4"/></sheets>"#), "{workbook}");
+    assert!(rels.contains(r#"Id="rId2""#) && rels.contains(r#"Id="rId4""#) && !rels.contains("sheet2.xml"), "{rels}");
+    let worksheets: Vec<String> = entry_names(&result).into_iter().filter(|n| n.starts_with("xl/worksheets/")).collect();
+    assert_eq!(worksheets, ["xl/worksheets/sheet1.xml", "xl/worksheets/sheet3.xml"]);
+    for n in 1..=3 {
+      assert_eq!(content_types.contains(&format!(r#"PartName="/xl/worksheets/sheet{n}.xml""#)), n != 2, "{content_types}");
+    }
+    assert!(read_entry(&result, "xl/worksheets/sheet3.xml").unwrap().contains("<t>Third</t>"));
+    
+    // 按序号引用工作表的属性前移
+    assert!(workbook.contains(r#"<workbookView activeTab="1"/>"#), "{workbook}");
+    assert!(workbook.contains(r#"<definedNames><definedName name="_xlnm.Print_Area" localSheetId="1">Sheet3!$A$1:$B$2</definedName></definedNames>"#), "{workbook}");
+  }
+  
+  #[test]
+  fn test_rename_duplicate_cjk_sheet_names() {
+    // 31 个字符, 添加 " (1)" 后缀时需要截断, 按字节截断的位置会落在中文字符中间
+    let name = format!("A{}", "销售报表".repeat(8).chars().take(30).collect::<String>());
+    let rename = format!("{{{{setCurrentSheetName \"{name}\"}}}}");
+    let template = build_zip(&multi_sheet_entries(&[&rename, &rename]));
+    let result = render_template(template, &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    
+    let truncated: String = name.chars().take(27).collect();
+    assert!(workbook.contains(&format!(r#"name="{name}""#)), "{workbook}");
+    assert!(workbook.contains(&format!(r#"name="{truncated} (1)""#)), "{workbook}");
+  }
+  
+  #[test]
+  fn test_set_current_sheet_name_truncates_long_cjk_name() {
+    let name = "年度销售报表".repeat(7).chars().take(40).collect::<String>();
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[&format!("{{{{setCurrentSheetName \"{name}\"}}}}")]);
+    let result = render_template(template, &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    
+    let truncated: String = name.chars().take(31).collect();
+    assert!(workbook.contains(&format!(r#"<sheet name="{truncated}" sheetId="1""#)), "{workbook}");
+    
+    // emoji 按 UTF-16 计为 2 个字符
+    assert_eq!(truncate_sheet_name(&"📊".repeat(20), SHEET_NAME_MAX_LEN).chars().count(), 15);
+  }
+  
+  #[test]
+  fn test_hide_sheet_with_reordered_attributes() {
+    let mut entries = multi_sheet_entries(&["{{hideCurrentSheet}}", "Visible"]);
+    let workbook = entry_mut(&mut entries, "xl/workbook.xml");
+    *workbook = workbook.replace(r#"<sheet name="Sheet1" sheetId="1" r:id="rId2"/>"#, "<sheet r:id='rId2' state='visible'\n  sheetId='7' name='Config'/>");
+    let result = render_template(build_zip(&entries), &json!({})).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    
+    assert!(workbook.contains(r#"<sheets><sheet r:id="rId2" sheetId="7" name="Config" state="hidden"/><sheet name="Sheet2" sheetId="2" r:id="rId3"/></sheets>"#), "{workbook}");
+  }
+  
+  #[test]
+  fn test_show_hidden_sheet() {
+    let mut entries = multi_sheet_entries(&["Main", "{{#if reveal}}{{showCurrentSheet}}{{/if}}Config"]);
+    let workbook = entry_mut(&mut entries, "xl/workbook.xml");
+    *workbook = workbook.replace(r#"<sheet name="Sheet2" sheetId="2" r:id="rId3"/>"#, r#"<sheet name="Sheet2" sheetId="2" state="hidden" r:id="rId3"/>"#);
+    let template = build_zip(&entries);
+    
+    let result = render_template(template.clone(), &json!({ "reveal": true })).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    assert!(workbook.contains(r#"<sheet name="Sheet2" sheetId="2" r:id="rId3"/>"#), "{workbook}");
+    
+    let result = render_template(template, &json!({ "reveal": false })).unwrap();
+    let workbook = read_entry(&result, "xl/workbook.xml").unwrap();
+    assert!(workbook.contains(r#"<sheet name="Sheet2" sheetId="2" state="hidden" r:id="rId3"/>"#), "{workbook}");
+  }
+  
+  #[test]
+  fn test_cell_style() {
+    let sheet_data = r#"<row r="1"><c r="A1" s="2" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" s="1" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{cellStyle 5}}Total", "{{cellStyle \"7\"}}{{num 42}}", "{{cellStyle 3}}"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1" s="5" t="inlineStr"><is><t>Total</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" s="7"><v>42</v></c>"#), "{sheet}");
+    // 只有样式没有内容的单元格输出为空单元格
+    assert!(sheet.contains(r#"<c r="C1" s="3"/>"#), "{sheet}");
+    assert!(!sheet.contains("-style|"), "{sheet}");
+    
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{cellStyle \"bold\"}}"]);
+    assert!(render_template(template, &json!({})).is_err());
+  }
+  
+  #[test]
+  fn test_conditional_format() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    let mut entries = xlsx_entries_around("", sheet_data, r#"<mergeCells count="1"><mergeCell ref="A1:B1"/></mergeCells>"#, &[r#"{{conditionalFormat "A2:A100" "lessThan" 0 "red"}}{{conditionalFormat "B2:B100" "equal" "done" "green"}}{{conditionalFormat "C2:C100" "greaterThan" 100 "red"}}Amount"#]);
+    entries.push(("xl/styles.xml".to_string(), r#"<styleSheet><cellXfs count="1"><xf/></cellXfs><cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles><dxfs count="1"><dxf><font><b/></font></dxf></dxfs><tableStyles count="0"/></styleSheet>"#.to_string()));
+    let result = render_template(build_zip(&entries), &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    let styles = read_entry(&result, "xl/styles.xml").unwrap();
+    
+    // 条件格式放在 mergeCells 之后、pageMargins 之前, 相同颜色共用一个 dxf
+    assert!(sheet.contains(r#"</mergeCells><conditionalFormatting sqref="A2:A100"><cfRule type="cellIs" dxfId="1" priority="1" operator="lessThan"><formula>0</formula></cfRule></conditionalFormatting><conditionalFormatting sqref="B2:B100"><cfRule type="cellIs" dxfId="2" priority="2" operator="equal"><formula>"done"</formula></cfRule></conditionalFormatting><conditionalFormatting sqref="C2:C100"><cfRule type="cellIs" dxfId="1" priority="3" operator="greaterThan"><formula>100</formula></cfRule></conditionalFormatting><pageMargins"#), "{sheet}");
+    assert!(styles.contains(r#"<dxfs count="3"><dxf><font><b/></font></dxf><dxf><font><color rgb="FF9C0006"/></font><fill><patternFill><bgColor rgb="FFFFC7CE"/></patternFill></fill></dxf><dxf><font><color rgb="FF006100"/></font>"#), "{styles}");
+    
+    let template = build_xlsx(sheet_data, &[r#"{{conditionalFormat "A2:A100" "between" 0 "red"}}"#]);
+    assert!(render_template(template, &json!({})).is_err());
+  }
+  
+  #[test]
+  fn test_column_loop_with_two_cells_per_item() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}{{name}}", "{{num value}}{{/each}}", "End"]);
+    let data = json!({ "items": [{ "name": "a", "value": 1 }, { "name": "b", "value": 2 }, { "name": "c", "value": 3 }] });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 每项占 2 列, 3 项共 6 列 A~F, 循环之后的单元格在 G 列
+    for (index, (name, value)) in [("a", 1), ("b", 2), ("c", 3)].into_iter().enumerate() {
+      let name_col = to_column_name("A", index as u32 * 2);
+      let value_col = to_column_name("B", index as u32 * 2);
+      assert!(sheet.contains(&format!(r#"<c r="{name_col}1" t="inlineStr"><is><r><t>{name}</t></r></is></c>"#)), "{name_col}: {sheet}");
+      assert!(sheet.contains(&format!(r#"<c r="{value_col}1"><v>{value}</v></c>"#)), "{value_col}: {sheet}");
+    }
+    assert!(sheet.contains(r#"<c r="G1" t="inlineStr"><is><t>End</t></is></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_image_data_uri() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    let logo = format!("data:image/png;base64,{}", TINY_PNG_BASE64);
+    let result = render_template(template, &json!({ "logo": logo })).unwrap();
+    
+    // data URI 前缀被去掉后正常解码, 图片按 png 保存
+    let names = entry_names(&result);
+    assert!(names.iter().any(|name| name.starts_with("xl/media/image") && name.ends_with(".png")), "{names:?}");
+    let drawing_rels = read_entry(&result, "xl/drawings/_rels/drawing1.xml.rels").unwrap();
+    assert!(drawing_rels.contains(".png\"/>"), "{drawing_rels}");
+    let content_types = read_entry(&result, "[Content_Types].xml").unwrap();
+    assert!(content_types.contains(r#"<Default Extension="png" ContentType="image/png"/>"#), "{content_types}");
+    
+    // 按声明的 MIME 选择扩展名
+    assert_eq!(split_image_data_uri("data:image/jpeg;base64,/9j/"), ("jpeg", "/9j/"));
+    assert_eq!(split_image_data_uri("data:image/GIF;base64,R0lG"), ("gif", "R0lG"));
+    assert_eq!(split_image_data_uri(TINY_PNG_BASE64), ("png", TINY_PNG_BASE64));
+    assert_eq!(image_content_type("jpeg"), "image/jpeg");
+  }
+  
+  #[test]
+  fn test_bad_image_data() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let data = json!({ "logo": TINY_PNG_BASE64, "bad": "not base64!" });
+    
+    // 默认返回带工作表和单元格位置的 ImageDecode 错误
+    let template = build_xlsx(sheet_data, &["{{img logo}}", "{{img bad}}"]);
+    let err = render_template(template, &data).unwrap_err();
+    match err.downcast_ref::<XlsxError>() {
+      Some(XlsxError::ImageDecode { sheet, cell, .. }) => {
+        assert_eq!(sheet, "xl/worksheets/sheet1.xml");
+        assert_eq!(cell, "B1");
+      }
+      other => panic!("unexpected error: {other:?}"),
+    }
+    
+    // skip_bad_images 时跳过坏图片, 其它图片正常插入
+    let template = build_xlsx(sheet_data, &["{{img logo}}", "{{img bad}}"]);
+    let options = RenderOptions { skip_bad_images: true, ..Default::default() };
+    let result = render_template_with_options(template, &data, options).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    assert_eq!(drawing.matches("<xdr:oneCellAnchor>").count(), 1, "{drawing}");
+    assert_eq!(entry_names(&result).iter().filter(|name| name.starts_with("xl/media/")).count(), 1);
+    
+    // 只有坏图片时不生成 drawing
+    let template = build_xlsx(sheet_data, &["{{img bad}}", "B"]);
+    let options = RenderOptions { skip_bad_images: true, ..Default::default() };
+    let result = render_template_with_options(template, &data, options).unwrap();
+    assert!(read_entry(&result, "xl/drawings/drawing1.xml").is_none());
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet.contains("<drawing"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_img_fit_range() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[r#"{{imgFit logo "B2:D4"}}"#]);
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    
+    // B2 -> 0-based col 1, row 1; 结束位置是 D4 之后的 col 4, row 4
+    assert!(drawing.contains("<xdr:twoCellAnchor>\n    <xdr:from>\n      <xdr:col>1</xdr:col>\n      <xdr:colOff>0</xdr:colOff>\n      <xdr:row>1</xdr:row>"), "{drawing}");
+    assert!(drawing.contains("<xdr:to>\n      <xdr:col>4</xdr:col>\n      <xdr:colOff>0</xdr:colOff>\n      <xdr:row>4</xdr:row>"), "{drawing}");
+    assert!(drawing.contains("</xdr:twoCellAnchor>"), "{drawing}");
+    assert!(!drawing.contains("oneCellAnchor"), "{drawing}");
+    
+    // 无效范围返回错误
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[r#"{{imgFit logo "B2"}}"#]);
+    assert!(render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).is_err());
+  }
+  
+  #[test]
+  fn test_list_images() {
+    use base64::Engine;
+    let png = base64::engine::general_purpose::STANDARD.decode(TINY_PNG_BASE64).unwrap();
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["A"]);
+    entries.push(("xl/media/image2.emf".to_string(), "not an image".to_string()));
+    let mut template = build_zip(&entries);
+    
+    // 没有图片的模板返回空列表
+    assert!(list_images(template.clone()).unwrap().is_empty());
+    
+    // 添加一张 png 图片, 无法识别的 emf 被跳过
+    let mut archive = ZipArchive::new(Cursor::new(template)).unwrap();
+    let mut output = Vec::new();
+    let mut writer = ZipWriter::new(Cursor::new(&mut output));
+    for i in 0..archive.len() {
+      writer.raw_copy_file(archive.by_index(i).unwrap()).unwrap();
+    }
+    writer.start_file("xl/media/image1.png", SimpleFileOptions::default()).unwrap();
+    writer.write_all(&png).unwrap();
+    writer.finish().unwrap();
+    template = output;
+    
+    assert_eq!(list_images(template).unwrap(), vec![("xl/media/image1.png".to_string(), 1, 1)]);
+  }
+  
+  #[test]
+  fn test_max_image_dimension() {
+    use base64::Engine;
+    // 4000x2000 的 PNG: 尺寸检测只读取 IHDR
+    let mut png = base64::engine::general_purpose::STANDARD.decode(TINY_PNG_BASE64).unwrap();
+    png[16..20].copy_from_slice(&4000u32.to_be_bytes());
+    png[20..24].copy_from_slice(&2000u32.to_be_bytes());
+    let logo = base64::engine::general_purpose::STANDARD.encode(&png);
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    
+    // 超过限制时等比例缩小到 1000x500 像素
+    let options = RenderOptions { max_image_dimension: Some(1000), ..Default::default() };
+    let result = render_template_with_options(build_xlsx(sheet_data, &["{{img logo}}"]), &json!({ "logo": logo }), options).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    assert!(drawing.contains(&format!(r#"<xdr:ext cx="{}" cy="{}"/>"#, 1000 * 9525, 500 * 9525)), "{drawing}");
+    
+    // 未设置时保持原始尺寸
+    let result = render_template(build_xlsx(sheet_data, &["{{img logo}}"]), &json!({ "logo": logo })).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    assert!(drawing.contains(&format!(r#"<xdr:ext cx="{}" cy="{}"/>"#, 4000 * 9525, 2000 * 9525)), "{drawing}");
+    
+    assert_eq!(fit_image_dimension(300, 200, Some(1000)), (300, 200));
+    assert_eq!(fit_image_dimension(1000, 3000, Some(600)), (200, 600));
+  }
+  
+  #[test]
+  fn test_render_template_merged() {
+    let base = json!({
+      "company": { "name": "ACME", "address": { "city": "Shanghai", "zip": "200000" } },
+      "items": [{ "name": "a" }, { "name": "b" }],
+    });
+    let overrides = json!({
+      "company": { "address": { "city": "Beijing" } },
+      "items": [{ "name": "c" }],
+    });
+    
+    // 嵌套对象递归合并, 数组整体替换
+    assert_eq!(merge_json_values(&base, &overrides), json!({
+      "company": { "name": "ACME", "address": { "city": "Beijing", "zip": "200000" } },
+      "items": [{ "name": "c" }],
+    }));
+    // 类型不同时使用覆盖值
+    assert_eq!(merge_json_values(&json!({ "a": { "b": 1 } }), &json!({ "a": 2 })), json!({ "a": 2 }));
+    
+    let template = build_xlsx(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row>"#,
+      &["{{company.name}}", "{{company.address.city}} {{company.address.zip}}", "{{#each items}}{{name}}{{/each}}"],
+    );
+    let result = render_template_merged(template, &base, &overrides).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("ACME") && sheet.contains("Beijing 200000"), "{sheet}");
+    assert!(sheet.contains(">c<") && !sheet.contains(">a<") && !sheet.contains(">b<"), "{sheet}");
+  }
+  
+  #[test]
+  fn test_range_block_rows() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#range 1 12}}", "Month {{this}}{{/range}}", "Footer"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 每个月占一行, 行号依次递增
+    for month in 1..=12 {
+      let r = month + 1;
+      let row = format!(r#"<row r="{r}"><c r="A{r}" t="inlineStr"><is><r><t>Month {month}</t></r></is></c></row>"#);
+      assert!(sheet.contains(&row), "{row} not in {sheet}");
+    }
+    assert_eq!(sheet.matches("Month ").count(), 12, "{sheet}");
+    assert!(sheet.find("<t>Month 12</t>").unwrap() < sheet.find("<t>Footer</t>").unwrap());
+  }
+  
+  #[test]
+  fn test_group_block_rows() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[
+      r#"{{#group items "category"}}"#,
+      "{{@key}}{{#each this}}",
+      "{{name}}{{/each}}{{/group}}",
+      "Footer",
+    ]);
+    let data = json!({ "items": [
+      { "name": "saw", "category": "tools" },
+      { "name": "apple", "category": "food" },
+      { "name": "hammer", "category": "tools" },
+    ] });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 每组占一行: A 列为分组值, 组内各项按列展开
+    for (cell, value) in [("A2", "food"), ("B2", "apple"), ("A3", "tools"), ("B3", "saw"), ("C3", "hammer")] {
+      let expected = format!(r#"<c r="{cell}" t="inlineStr"><is><r><t>{value}</t></r></is></c>"#);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+    assert!(sheet.find("<t>hammer</t>").unwrap() < sheet.find("<t>Footer</t>").unwrap());
+  }
+  
+  #[test]
+  fn test_concat_cell_refs_in_column_loop() {
+    // 循环结束的单元格中, 列偏移会在单元格文本之前累加; _c / _r / _cr 读取单元格开始时记录的位置, 与单元格的 r 属性一致
+    let template = build_xlsx(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row><row r="2"><c r="A2" t="s"><v>3</v></c></row>"#,
+      &["Head", "{{#each items}}", r#"{{concat (_c) (_r)}}|{{_cr}}|{{_cr "A" 5}}{{mergeCell (concat (_c) (_r) ":" (_c) "2")}}{{/each}}"#, "Footer"],
+    );
+    let result = render_template(template, &json!({ "items": [1, 2, 3] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    for (cell, offset_col) in [("C1", "A"), ("D1", "B"), ("E1", "C")] {
+      let expected = format!(r#"<c r="{cell}" t="inlineStr"><is><r><t>{cell}|{cell}|{offset_col}5</t></r></is></c>"#);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+    assert!(sheet.contains(r#"<mergeCell ref="C1:C2"/><mergeCell ref="D1:D2"/><mergeCell ref="E1:E2"/>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_cell_ref_in_row_loop() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{this}}", "{{cellRef 2 0}}|{{cellRef -1 1}}|{{cellRef -5 -5}}{{/each}}", "Footer"]);
+    let result = render_template(template, &json!({ "items": [1, 2, 3] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 每一行的引用都相对该行的 B 列计算
+    for r in 2..=4 {
+      let expected = format!(r#"<c r="B{r}" t="inlineStr"><is><r><t>D{r}|A{}|A1</t></r></is></c>"#, r + 1);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+  }
+  
+  #[test]
+  fn test_absolute_cell_refs() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[
+      "{{#each items}}",
+      "{{this}}",
+      r#"{{_cr abs=true}}|{{_cr "D" 1 abs=true}}|{{cellRef 1 -1 abs=true}}|{{cellRef 1 0}}{{/each}}"#,
+      "Footer",
+    ]);
+    let result = render_template(template, &json!({ "items": [1, 2] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // $ 放在列字母和行号之前, 不带 abs 时仍输出相对引用
+    for r in 2..=3 {
+      let expected = format!(r#"<c r="B{r}" t="inlineStr"><is><r><t>$B${r}|$D${}|$C${}|C{r}</t></r></is></c>"#, r - 1, r - 1);
+      assert!(sheet.contains(&expected), "{expected} not in {sheet}");
+    }
+  }
+  
+  #[test]
+  fn test_sheet_ref() {
+    let template = build_xlsx(
+      r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#,
+      &[r#"{{sheetRef "Summary" "A1"}}|{{sheetRef "汇总_2024" "B2"}}"#, r#"{{formula (concat "=" (sheetRef "Q1 Sales" "$B$2") "*2")}}"#],
+    );
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("<t>Summary!A1|汇总_2024!B2</t>"), "{sheet}");
+    assert!(sheet.contains("<f>='Q1 Sales'!$B$2*2</f>"), "{sheet}");
+    
+    // 包含空格或特殊字符、以数字开头、像单元格引用的名称需要加引号
+    assert_eq!(quote_sheet_name("Sheet1.Data"), "Sheet1.Data");
+    assert_eq!(quote_sheet_name("2024"), "'2024'");
+    assert_eq!(quote_sheet_name("A1"), "'A1'");
+    assert_eq!(quote_sheet_name("R1C1"), "'R1C1'");
+    assert_eq!(quote_sheet_name("Tom's-list"), "'Tom''s-list'");
+  }
+  
+  #[test]
+  fn test_delete_and_hide_all_sheets_error_details() {
+    // 错误中包含工作表总数和被标记的工作表名称
+    let template = build_zip(&multi_sheet_entries(&["{{deleteCurrentSheet}}", "{{deleteCurrentSheet}}"]));
+    let err = render_template(template, &json!({})).unwrap_err();
+    match err.downcast_ref::<XlsxError>() {
+      Some(XlsxError::CannotDeleteLastSheet { total, requested }) => {
+        assert_eq!(*total, 2);
+        assert_eq!(requested, &["Sheet1", "Sheet2"]);
+      }
+      other => panic!("unexpected error: {other:?}"),
+    }
+    assert!(err.to_string().contains("2 of 2 worksheets were marked for deletion (Sheet1, Sheet2)"), "{err}");
+    assert!(err.to_string().contains("keep_last_sheet"), "{err}");
+    
+    let template = build_zip(&multi_sheet_entries(&["{{hideCurrentSheet}}", "{{hideCurrentSheet \"veryHidden\"}}"]));
+    let err = render_template(template, &json!({})).unwrap_err();
+    match err.downcast_ref::<XlsxError>() {
+      Some(XlsxError::CannotHideAllSheets { total, requested }) => {
+        assert_eq!(*total, 2);
+        assert_eq!(requested, &["Sheet1", "Sheet2"]);
+      }
+      other => panic!("unexpected error: {other:?}"),
+    }
+  }
+  
+  #[test]
+  fn test_sheet_tab_color() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[r#"{{setSheetTabColor "ff0000"}}Title"#]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    // sheetPr 是 worksheet 的第一个子元素
+    assert!(sheet.contains(r#"relationships"><sheetPr><tabColor rgb="FFFF0000"/></sheetPr><sheetData>"#), "{sheet}");
+    
+    // 已有 sheetPr 时保留其属性和子元素, 替换原有的 tabColor
+    let template = build_xlsx_around(r#"<sheetPr codeName="Sheet1"><tabColor rgb="FF0000FF"/><pageSetUpPr fitToPage="1"/></sheetPr><dimension ref="A1"/>"#, sheet_data, "", &[r##"{{setSheetTabColor "#00B050"}}Title"##]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<sheetPr codeName="Sheet1"><tabColor rgb="FF00B050"/><pageSetUpPr fitToPage="1"/></sheetPr><dimension ref="A1"/>"#), "{sheet}");
+    
+    let template = build_xlsx_around(r#"<sheetPr filterMode="0"/>"#, sheet_data, "", &[r#"{{setSheetTabColor "00B050"}}Title"#]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<sheetPr filterMode="0"><tabColor rgb="FF00B050"/></sheetPr><sheetData>"#), "{sheet}");
+    
+    let template = build_xlsx(sheet_data, &[r#"{{setSheetTabColor "red"}}Title"#]);
+    assert!(render_template(template, &json!({})).is_err());
+  }
+  
+  #[test]
+  fn test_hide_gridlines_and_headers() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    // 没有 sheetViews 时按 schema 顺序创建在 dimension 之后
+    let template = build_xlsx_around(r#"<dimension ref="A1"/><sheetFormatPr defaultRowHeight="15"/>"#, sheet_data, "", &["{{hideGridlines}}{{hideHeaders}}Title"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<dimension ref="A1"/><sheetViews><sheetView showGridLines="0" showRowColHeaders="0" workbookViewId="0"/></sheetViews><sheetFormatPr"#), "{sheet}");
+    
+    // 已有 sheetView 时保留其属性和子元素
+    let template = build_xlsx_around(r#"<sheetViews><sheetView tabSelected="1" showGridLines="1" workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews>"#, sheet_data, "", &["{{hideGridlines}}Title"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<sheetViews><sheetView tabSelected="1" showGridLines="0" workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews>"#), "{sheet}");
+    
+    // 没有调用 helper 时不改动 sheet
+    let template = build_xlsx(sheet_data, &["Title"]);
+    let result = render_template(template, &json!({})).unwrap();
+    assert!(!read_entry(&result, "xl/worksheets/sheet1.xml").unwrap().contains("sheetView"));
+  }
+  
+  #[test]
+  fn test_sheet_rtl() {
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{setSheetRTL}}{{hideGridlines}}عنوان"]);
+    let result = render_template(template, &json!({})).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"relationships"><sheetViews><sheetView rightToLeft="1" showGridLines="0" workbookViewId="0"/></sheetViews><sheetData>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_with_block_spanning_cells() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row><row r="2"><c r="A2" t="s"><v>4</v></c><c r="B2" t="s"><v>5</v></c></row><row r="3"><c r="A3" t="s"><v>6</v></c><c r="B3" t="s"><v>7</v></c><c r="C3" t="s"><v>8</v></c></row><row r="4"><c r="A4" t="s"><v>9</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#with department}}{{name}}", "{{manager}}", "{{num headcount}}{{/with}}", "{{_cr}}", "{{#with department}}{{_cr}}", "{{name}}{{/with}}", "{{#with department}}{{#each members}}{{this}}", "{{_cr}}{{/each}}", "{{_cr}}{{/with}}", "{{_cr}}"]);
+    let data = json!({ "department": { "name": "R&D", "manager": "Alice", "headcount": 12, "members": ["Bob", "Carol"] } });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>R&amp;D</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t>Alice</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1"><v>12</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="D1" t="inlineStr"><is><t>D1</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="A2" t="inlineStr"><is><t>A2</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B2" t="inlineStr"><is><t>R&amp;D</t></is></c>"#), "{sheet}");
+    // with 中的列循环展开后, 后面的单元格仍然按偏移后的位置计算
+    assert!(sheet.contains(r#"<c r="A3" t="inlineStr"><is><r><t>Bob</t></r></is></c><c r="B3" t="inlineStr"><is><r><t>B3</t></r></is></c><c r="C3" t="inlineStr"><is><r><t>Carol</t></r></is></c><c r="D3" t="inlineStr"><is><r><t>D3</t></r></is></c><c r="E3" t="inlineStr"><is><t>E3</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="A4" t="inlineStr"><is><t>A4</t></is></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_auto_fit_columns() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row><row r="2"><c r="A2" t="s"><v>4</v></c><c r="B2" t="s"><v>5</v></c></row>"#;
+    let template = build_xlsx_around(r#"<cols><col min="3" max="3" width="5" customWidth="1"/></cols>"#, sheet_data, "", &["Name", "{{description}}", "{{long}}", "{{title}}", "Id", "{{note}}"]);
+    let data = json!({ "description": "A much longer description text", "long": "ignored because column C has a width", "title": "报表标题", "note": "short" });
+    let options = RenderOptions { auto_fit_columns: true, ..Default::default() };
+    let result = render_template_with_options(template, &data, options).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // A 列的文本较短保持默认列宽, B 列按最长文本 30 个字符 + 2 估算, C 列保留模板的列宽, 中文按 2 个字符计算
+    assert!(sheet.contains(r#"<cols><col min="2" max="2" width="32" customWidth="1"/><col min="3" max="3" width="5" customWidth="1"/><col min="4" max="4" width="10" customWidth="1"/></cols><sheetData>"#), "{sheet}");
+    
+    // 最大列宽
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{description}}"]);
+    let options = RenderOptions { auto_fit_columns: true, auto_fit_max_width: Some(20.0), ..Default::default() };
+    let result = render_template_with_options(template, &data, options).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<cols><col min="1" max="1" width="20" customWidth="1"/></cols>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_br_line_break() {
+    let sheet_data = r#"<row r="1"><c r="A1" s="1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row><row r="2"><c r="A2" s="1" t="s"><v>2</v></c></row>"#;
+    let mut entries = xlsx_entries(sheet_data, &["{{address1}}{{br}}{{address2}}", "{{name}}", "{{address2}}{{br}}{{address1}}"]);
+    entries.push(("xl/styles.xml".to_string(), r#"<styleSheet><cellXfs count="2"><xf numFmtId="0" fontId="0"/><xf numFmtId="0" fontId="1" applyFont="1"><protection locked="0"/></xf></cellXfs></styleSheet>"#.to_string()));
+    let data = json!({ "address1": "1 Main St", "address2": "Springfield", "name": "Alice" });
+    let result = render_template(build_zip(&entries), &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    let styles = read_entry(&result, "xl/styles.xml").unwrap();
+    
+    // 换行符保留在文本中, 同一样式的单元格共用一个新增的自动换行格式, 没有换行的单元格样式不变
+    assert!(sheet.contains("<c r=\"A1\" t=\"inlineStr\" s=\"2\"><is><t xml:space=\"preserve\">1 Main St\nSpringfield</t></is></c>"), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t>Alice</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains("<c r=\"A2\" t=\"inlineStr\" s=\"2\"><is><t xml:space=\"preserve\">Springfield\n1 Main St</t></is></c>"), "{sheet}");
+    assert!(styles.contains(r#"<cellXfs count="3"><xf numFmtId="0" fontId="0"/><xf numFmtId="0" fontId="1" applyFont="1"><protection locked="0"/></xf><xf numFmtId="0" fontId="1" applyFont="1" applyAlignment="1"><alignment wrapText="1"/><protection locked="0"/></xf></cellXfs>"#), "{styles}");
+  }
+  
+  #[test]
+  fn test_external_hyperlink() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c></row>"#;
+    let mut entries = xlsx_entries(sheet_data, &[r#"{{hyperlink (_cr) url "Docs"}}Docs"#, r#"{{hyperlink (_cr) "Sheet1!A1" "Top"}}Top"#, r#"{{hyperlink (_cr) "mailto:a@example.com"}}Mail"#]);
+    entries.push((
+      "xl/worksheets/_rels/sheet1.xml.rels".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/printerSettings" Target="../printerSettings/printerSettings1.bin"/></Relationships>"#.to_string(),
+    ));
+    let result = render_template(build_zip(&entries), &json!({ "url": "https://example.com/docs?a=1&b=2" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    let rels = read_entry(&result, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+    
+    // 外部链接使用 r:id 引用 rels 中的关系, 工作表内部的链接仍然使用 location
+    assert!(sheet.contains(r#"<hyperlink ref="A1" r:id="rId2" display="Docs""#), "{sheet}");
+    assert!(sheet.contains(r#"<hyperlink ref="B1" location="Sheet1!A1" display="Top""#), "{sheet}");
+    assert!(sheet.contains(r#"<hyperlink ref="C1" r:id="rId3" xr:uid="#), "{sheet}");
+    assert!(rels.contains(r#"<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com/docs?a=1&amp;b=2" TargetMode="External"/>"#), "{rels}");
+    assert!(rels.contains(r#"<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="mailto:a@example.com" TargetMode="External"/>"#), "{rels}");
+    assert!(rels.contains("printerSettings1.bin"), "{rels}");
+    
+    // 没有 rels 文件时新建
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[r#"{{hyperlink (_cr) "https://example.com"}}Site"#]);
+    let result = render_template(template, &json!({})).unwrap();
+    let rels = read_entry(&result, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+    assert!(rels.contains(r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com" TargetMode="External"/>"#), "{rels}");
+  }
+  
+  #[test]
+  fn test_hyperlink_display_text_in_cell() {
+    let sheet_data = r#"<row r="1"><c r="A1" s="3" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &[r#"{{hyperlink (_cr) "Sheet2!A1" "View details"}}Sheet2!A1"#, r#"{{hyperlink (_cr) url}}{{url}}"#]);
+    let result = render_template(template, &json!({ "url": "https://example.com" })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 有显示文本时单元格显示该文本并保留样式, 没有显示文本时保留模板单元格的内容
+    assert!(sheet.contains(r#"<c r="A1" s="3" t="inlineStr"><is><t>View details</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t>https://example.com</t></is></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_one_index_in_loop() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}{{num (oneIndex)}}", "{{name}}{{/each}}"]);
+    let result = render_template(template, &json!({ "items": [{ "name": "a" }, { "name": "b" }] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    // 子表达式中同样可用, 第一项的序号为 1
+    assert!(sheet.contains(r#"<c r="A1"><v>1</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1"><v>2</v></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_validate_output() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#;
+    let validated = || RenderOptions { validate_output: true, ..Default::default() };
+    
+    // 正常渲染 (包括新增的 drawing 和图片) 可以通过检查
+    let template = build_xlsx(sheet_data, &["{{img logo}}{{hyperlink (_cr) \"https://example.com\"}}"]);
+    render_template_with_options(template.clone(), &json!({ "logo": TINY_PNG_BASE64 }), validated()).unwrap();
+    
+    // 新增的部件缺少内容类型
+    let options = RenderOptions {
+      post_process: Some(Box::new(|files| {
+        files.insert("xl/printerSettings/printerSettings1.bin".to_string(), vec![0]);
+      })),
+      ..validated()
+    };
+    let err = render_template_with_options(template.clone(), &json!({ "logo": TINY_PNG_BASE64 }), options).unwrap_err();
+    assert!(matches!(err.downcast_ref::<XlsxError>(), Some(XlsxError::InvalidOutput(message)) if message.contains("xl/printerSettings/printerSettings1.bin")), "{err}");
+    
+    // r:id 在 rels 中找不到
+    let options = RenderOptions {
+      post_process: Some(Box::new(|files| {
+        files.remove("xl/worksheets/_rels/sheet1.xml.rels");
+      })),
+      ..validated()
+    };
+    let err = render_template_with_options(template, &json!({ "logo": TINY_PNG_BASE64 }), options).unwrap_err();
+    assert!(err.to_string().contains(r#"xl/worksheets/sheet1.xml references r:id "rId"#), "{err}");
+  }
+  
+  #[test]
+  fn test_on_warning() {
+    use std::{cell::RefCell, rc::Rc};
+    
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&warnings);
+    let options = RenderOptions {
+      skip_bad_images: true,
+      on_warning: Some(Box::new(move |warning| sink.borrow_mut().push(warning.to_string()))),
+      ..Default::default()
+    };
+    render_template_with_options(template.clone(), &json!({ "logo": "not an image" }), options).unwrap();
+    let warnings = warnings.borrow();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].contains("xl/worksheets/sheet1.xml!A1"), "{warnings:?}");
+    
+    // 默认不输出警告
+    let options = RenderOptions { skip_bad_images: true, ..Default::default() };
+    render_template_with_options(template, &json!({ "logo": "not an image" }), options).unwrap();
+  }
+  
+  #[test]
+  fn test_cells_without_r() {
+    let template = build_xlsx(r#"<row r="1"><c t="s"><v>0</v></c><c t="s"><v>1</v></c><c t="s"><v>2</v></c></row><row r="2"><c t="s"><v>3</v></c><c s="1"/></row>"#, &["{{title}}", "{{#each items}}{{name}}", "{{qty}}{{/each}}", "{{(_c)}}"]);
+    let data = json!({ "title": "T", "items": [{ "name": "a", "qty": 1 }, { "name": "b", "qty": 2 }] });
+    let result = render_template(template, &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 没有 r 属性的单元格按行内位置计算列号, 循环展开后的单元格依次后移
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>T</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><r><t>a</t></r></is></c><c r="C1" t="inlineStr"><is><r><t>1</t></r></is></c><c r="D1" t="inlineStr"><is><r><t>b</t></r></is></c><c r="E1" t="inlineStr"><is><r><t>2</t></r></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="A2" t="inlineStr"><is><t>A</t></is></c><c r="B2" s="1"/>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_rows_without_r() {
+    let strings = ["{{title}}", "{{#each items}}", "{{this}}{{/each}}", "{{(_r)}}"];
+    let data = json!({ "title": "T", "items": ["a", "b"] });
+    let template = build_xlsx(r#"<row><c t="s"><v>0</v></c></row><row><c t="s"><v>1</v></c></row><row><c t="s"><v>2</v></c></row><row/><row><c t="s"><v>3</v></c></row>"#, &strings);
+    let sheet = read_entry(&render_template(template, &data).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 没有 r 属性的行依次编号, 循环展开后的行号依次后移
+    assert!(sheet.contains(r#"<row r="1"><c r="A1" t="inlineStr"><is><t>T</t></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="3"><c r="A3" t="inlineStr"><is><r><t>a</t></r></is></c></row><row r="4"><c r="A4" t="inlineStr"><is><r><t>b</t></r></is></c></row>"#), "{sheet}");
+    
+    // 与带 r 属性的相同模板渲染结果一致
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row><row r="4"/><row r="5"><c r="A5" t="s"><v>3</v></c></row>"#, &strings);
+    let expected = read_entry(&render_template(template, &data).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert_eq!(sheet, expected);
+  }
+  
+  #[test]
+  fn test_hide_row() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2" ht="20" customHeight="1"><c r="A2" t="s"><v>1</v></c><c r="B2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{name}}", "{{#if detail}}{{hideRow}}{{/if}}{{/each}}", "Footer"]);
+    let data = json!({ "items": [{ "name": "a", "detail": false }, { "name": "b", "detail": true }] });
+    let sheet = read_entry(&render_template(template, &data).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 隐藏的行保留原有属性, 后续行号不受影响, 标记从单元格中删除
+    assert!(sheet.contains(r#"<row r="2" ht="20" customHeight="1"><c r="A2" t="inlineStr"><is><t>a</t></is></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="3" ht="20" customHeight="1" hidden="1"><c r="A3" t="inlineStr"><is><t>b</t></is></c>"#), "{sheet}");
+    assert_eq!(sheet.matches("hidden=").count(), 1, "{sheet}");
+    assert!(!sheet.contains(HIDE_ROW_KEY), "{sheet}");
+  }
+  
+  #[test]
+  fn test_row_outline_level() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c></row><row r="4"><c r="A4" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["Total{{rowOutlineLevel 1 collapsed=true}}", "Group{{rowOutlineLevel 1}}", "Detail{{rowOutlineLevel 2 hidden=true}}", "Plain"]);
+    let sheet = read_entry(&render_template(template, &json!({})).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    
+    // 两级分组, 折叠的明细行隐藏, 最大级别写入 sheetFormatPr
+    assert!(sheet.contains(r#"<row r="1" outlineLevel="1" collapsed="1"><c r="A1" t="inlineStr"><is><t>Total</t></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="2" outlineLevel="1"><c r="A2" t="inlineStr"><is><t>Group</t></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="3" outlineLevel="2" hidden="1"><c r="A3" t="inlineStr"><is><t>Detail</t></is></c></row>"#), "{sheet}");
+    assert!(sheet.contains(r#"<row r="4"><c r="A4""#), "{sheet}");
+    assert!(sheet.contains(r#"<sheetFormatPr defaultRowHeight="15" outlineLevelRow="2"/><sheetData>"#), "{sheet}");
+    assert!(!sheet.contains(ROW_OUTLINE_KEY), "{sheet}");
+    
+    // 超出 1 到 7 的级别报错
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{rowOutlineLevel 8}}"]);
+    assert!(render_template(template, &json!({})).is_err());
+  }
+  
+  #[test]
+  fn test_first_sheet() {
+    use std::{cell::RefCell, rc::Rc};
+    
+    let mut entries = multi_sheet_entries(&["{{total}}", r#"{{set_data "total" 42}}Config"#]);
+    let workbook = entry_mut(&mut entries, "xl/workbook.xml");
+    *workbook = workbook.replace(r#"name="Sheet2""#, r#"name="Config""#);
+    let template = build_zip(&entries);
+    
+    // Config 是 sheet2.xml, 默认在 sheet1 之后渲染, sheet1 读不到 total
+    let result = render_template(template.clone(), &json!({})).unwrap();
+    let sheet1 = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet1.contains("42"), "{sheet1}");
+    
+    // 指定 first_sheet 后 Config 先渲染, 其中 set_data 的值对 sheet1 可见
+    let options = RenderOptions { first_sheet: Some("Config".to_string()), ..Default::default() };
+    let result = render_template_with_options(template.clone(), &json!({}), options).unwrap();
+    let sheet1 = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet1.contains("<t>42</t>"), "{sheet1}");
+    
+    // 找不到的工作表名称输出警告
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&warnings);
+    let options = RenderOptions {
+      first_sheet: Some("Missing".to_string()),
+      on_warning: Some(Box::new(move |warning| sink.borrow_mut().push(warning.to_string()))),
+      ..Default::default()
+    };
+    render_template_with_options(template, &json!({}), options).unwrap();
+    assert_eq!(*warnings.borrow(), vec![r#"first_sheet: sheet "Missing" not found"#.to_string()]);
+  }
+  
+  #[test]
+  fn test_percent_num() {
+    let sheet_data = r#"<row r="1"><c r="A1" s="1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c></row>"#;
+    let mut entries = xlsx_entries(sheet_data, &["{{percentNum score}}", "{{percentNum rate 1}}", "{{percentNum \"0.5\" 2}}", "{{percent score}}"]);
+    entries.push(("xl/styles.xml".to_string(), r#"<styleSheet><fonts count="1"><font/></fonts><cellXfs count="2"><xf numFmtId="0" fontId="0"/><xf numFmtId="0" fontId="1" applyFont="1"/></cellXfs></styleSheet>"#.to_string()));
+    let data = json!({ "score": 0.92, "rate": 0.125 });
+    let result = render_template(build_zip(&entries), &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    let styles = read_entry(&result, "xl/styles.xml").unwrap();
+    
+    // 数字单元格保留原始值, 使用百分比数字格式: 0 位小数为内置格式 9, 2 位为内置格式 10, 其他为自定义格式
+    assert!(sheet.contains(r#"<c r="A1" s="2"><v>0.92</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B1" s="3"><v>0.125</v></c>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="C1" s="4"><v>0.5</v></c>"#), "{sheet}");
+    assert!(styles.contains(r#"<numFmts count="1"><numFmt numFmtId="164" formatCode="0.0%"/></numFmts><fonts"#), "{styles}");
+    assert!(styles.contains(r#"<xf numFmtId="9" fontId="1" applyFont="1" applyNumberFormat="1"/><xf numFmtId="164" fontId="0" applyNumberFormat="1"/><xf numFmtId="10" fontId="0" applyNumberFormat="1"/></cellXfs>"#), "{styles}");
+    
+    // 文本版本输出 92%
+    assert!(sheet.contains(r#"<c r="D1" t="inlineStr"><is><t>92%</t></is></c>"#), "{sheet}");
+    
+    // 同一单元格中的 {{br}} 先把样式改为自动换行, 百分比格式在此基础上修改, 保留换行和字体
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" s="1" t="s"><v>0</v></c></row>"#, &["{{br}}{{percentNum score}}"]);
+    entries.push(("xl/styles.xml".to_string(), r#"<styleSheet><fonts count="1"><font/></fonts><cellXfs count="2"><xf numFmtId="0" fontId="0"/><xf numFmtId="0" fontId="1" applyFont="1"/></cellXfs></styleSheet>"#.to_string()));
+    let result = render_template(build_zip(&entries), &data).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    let styles = read_entry(&result, "xl/styles.xml").unwrap();
+    assert!(sheet.contains(r#"<c r="A1" s="3"><v>0.92</v></c>"#), "{sheet}");
+    assert!(styles.contains(r#"<xf numFmtId="0" fontId="1" applyFont="1" applyAlignment="1"><alignment wrapText="1"/></xf><xf numFmtId="9" fontId="1" applyFont="1" applyAlignment="1" applyNumberFormat="1"><alignment wrapText="1"/></xf></cellXfs>"#), "{styles}");
+  }
+  
+  #[test]
+  fn test_workbook_renderer_sheet_data() {
+    use std::{cell::RefCell, rc::Rc};
+    
+    let mut entries = multi_sheet_entries(&["{{company}}-{{title}}", "{{company}}-{{title}}", "{{company}}-{{title}}"]);
+    let workbook = entry_mut(&mut entries, "xl/workbook.xml");
+    *workbook = workbook.replace(r#"name="Sheet1""#, r#"name="Summary""#).replace(r#"name="Sheet2""#, r#"name="Detail""#);
+    let template = build_zip(&entries);
+    
+    // 工作表的数据覆盖全局数据, 没有单独数据的 Sheet3 只使用全局数据
+    let result = WorkbookRenderer::new()
+      .global_data(json!({ "company": "ACME", "title": "Report" }))
+      .data_for_sheet("Summary", json!({ "title": "Summary" }))
+      .data_for_sheet("Detail", json!({ "company": "Sub" }))
+      .render(template.clone())
+      .unwrap();
+    let sheet = |n: u32| read_entry(&result, &format!("xl/worksheets/sheet{n}.xml")).unwrap();
+    assert!(sheet(1).contains("<t>ACME-Summary</t>"), "{}", sheet(1));
+    assert!(sheet(2).contains("<t>Sub-Report</t>"), "{}", sheet(2));
+    assert!(sheet(3).contains("<t>ACME-Report</t>"), "{}", sheet(3));
+    
+    // 找不到的工作表名称输出警告
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&warnings);
+    WorkbookRenderer::new()
+      .data_for_sheet("Missing", json!({}))
+      .options(RenderOptions { on_warning: Some(Box::new(move |warning| sink.borrow_mut().push(warning.to_string()))), ..Default::default() })
+      .render(template)
+      .unwrap();
+    assert_eq!(*warnings.borrow(), vec![r#"data_for_sheet: sheet "Missing" not found"#.to_string()]);
+  }
+  
+  #[test]
+  fn test_log_helper() {
+    use std::{cell::RefCell, rc::Rc};
+    
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{#each items}}{{log @index name}}{{name}}{{/each}}"]);
+    let data = json!({ "items": [{ "name": "a" }, { "name": "b" }] });
+    
+    // debug 模式下记录日志, 单元格中不输出日志内容
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&warnings);
+    let options = RenderOptions {
+      debug: true,
+      on_warning: Some(Box::new(move |warning| sink.borrow_mut().push(warning.to_string()))),
+      ..Default::default()
+    };
+    let result = render_template_with_options(template.clone(), &data, options).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("<t>ab</t>"), "{sheet}");
+    assert_eq!(*warnings.borrow(), vec!["xl/worksheets/sheet1.xml: log: 0 a".to_string(), "xl/worksheets/sheet1.xml: log: 1 b".to_string()]);
+    
+    // 默认不记录
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&warnings);
+    let options = RenderOptions {
+      on_warning: Some(Box::new(move |warning| sink.borrow_mut().push(warning.to_string()))),
+      ..Default::default()
+    };
+    let result = render_template_with_options(template, &data, options).unwrap();
+    assert!(read_entry(&result, "xl/worksheets/sheet1.xml").unwrap().contains("<t>ab</t>"));
+    assert!(warnings.borrow().is_empty());
+  }
+  
+  #[test]
+  fn test_reversed_and_single_cell_merge_ranges() {
+    // 模板中反向的合并范围按左上角 A1 计算偏移, 单个单元格的 "合并范围" 被删除
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c><c r="C3" t="s"><v>3</v></c></row>"#;
+    let template = build_xlsx_around("", sheet_data, r#"<mergeCells count="2"><mergeCell ref="B3:A3"/><mergeCell ref="C3"/></mergeCells>"#, &["{{#each items}}", "{{this}}{{/each}}", "Title", "Note"]);
+    let result = render_template(template, &json!({ "items": ["a", "b"] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    // 合并范围跟随 Title 所在的行 (循环展开后下移)
+    let title_row = sheet.split("<row ").find(|row| row.contains("<t>Title</t>")).unwrap();
+    let row: u32 = title_row.trim_start_matches("r=\"").split('"').next().unwrap().parse().unwrap();
+    assert!(row > 3, "{sheet}");
+    assert!(sheet.contains(&format!(r#"<mergeCells count="1"><mergeCell ref="A{row}:B{row}"/></mergeCells>"#)), "{sheet}");
+    
+    // mergeCell helper 同样规范化
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &[r#"{{mergeCell "D5:B2"}}{{mergeCell "A1"}}Title"#]);
+    let sheet = read_entry(&render_template(template, &json!({})).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<mergeCells count="1"><mergeCell ref="B2:D5"/></mergeCells>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_merge_down_and_across() {
+    // 分组标签在循环的第一行向下合并到循环展开的最后一行
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row><row r="2"><c r="A2" t="s"><v>2</v></c></row><row r="3"><c r="A3" t="s"><v>3</v></c><c r="B3" t="s"><v>4</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{mergeAcross 2}}Report", "", "{{#each items}}", "{{#if @first}}{{mergeDown (len ../items)}}Group{{/if}}", "{{name}}{{/each}}"]);
+    let data = json!({ "items": [{ "name": "a" }, { "name": "b" }, { "name": "c" }] });
+    let sheet = read_entry(&render_template(template, &data).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<mergeCells count="2"><mergeCell ref="A1:B1"/><mergeCell ref="A3:A5"/></mergeCells>"#), "{sheet}");
+    assert!(sheet.contains(r#"<c r="B5" t="inlineStr"><is><r><t>c</t></r></is></c>"#), "{sheet}");
+    
+    // 数量小于 2 时不合并
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{mergeDown 1}}{{mergeAcross 0}}Title"]);
+    let sheet = read_entry(&render_template(template, &json!({})).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet.contains("<mergeCells"), "{sheet}");
+    
+    // 结束单元格超出工作表范围时报错, 不生成错误的合并范围
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{mergeDown 1048576}}Title"]);
+    assert!(render_template(template, &json!({})).is_ok());
+    let template = build_xlsx(r#"<row r="2"><c r="B2" t="s"><v>0</v></c></row>"#, &["{{mergeDown 1048576}}Title"]);
+    let err = render_template(template, &json!({})).unwrap_err();
+    assert!(err.to_string().contains("mergeDown 1048576 from B2 goes beyond the last row of the sheet"), "{err}");
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{mergeAcross 4294967295}}Title"]);
+    let err = render_template(template, &json!({})).unwrap_err();
+    assert!(err.to_string().contains("mergeAcross 4294967295 from A1 goes beyond the last column of the sheet"), "{err}");
+  }
+  
+  #[test]
+  fn test_static_sheets_skip_rendering() {
+    let mut entries = multi_sheet_entries(&["{{name}}", "Static", "Numbers"]);
+    // sheet3 没有共享字符串单元格, 原样输出
+    let numbers_sheet = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><dimension ref="A1:B1"/><sheetData><row r="1"><c r="A1"><v>1</v></c><c r="B1"><f>A1*2</f><v>2</v></c></row></sheetData><mergeCells count="1"><mergeCell ref="A2:B2"/></mergeCells></worksheet>"#;
+    *entry_mut(&mut entries, "xl/worksheets/sheet3.xml") = numbers_sheet.to_string();
+    let result = render_template(build_zip(&entries), &json!({ "name": "Alice" })).unwrap();
+    
+    let sheet1 = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet1.contains(r#"<c r="A1" t="inlineStr"><is><t>Alice</t></is></c>"#), "{sheet1}");
+    // 静态 sheet 的共享字符串仍然内联, 与渲染流程的结果一致
+    let sheet2 = read_entry(&result, "xl/worksheets/sheet2.xml").unwrap();
+    assert!(sheet2.contains(r#"<c r="A1" t="inlineStr"><is><t>Static</t></is></c>"#), "{sheet2}");
+    assert!(!sheet2.contains(r#"t="s""#), "{sheet2}");
+    assert_eq!(read_entry(&result, "xl/worksheets/sheet3.xml").unwrap(), numbers_sheet);
+    // 省略 standalone 时静态 sheet 同样原样输出
+    let options = RenderOptions { omit_xml_standalone: true, ..Default::default() };
+    let result = render_template_with_options(build_zip(&entries), &json!({ "name": "Alice" }), options).unwrap();
+    assert_eq!(read_entry(&result, "xl/worksheets/sheet3.xml").unwrap(), numbers_sheet);
+    
+    // 共享字符串中有 "{" 的 sheet 按正常流程渲染
+    let template = build_xlsx(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{#if true}}yes{{/if}}"]);
+    let sheet = read_entry(&render_template(template, &json!({})).unwrap(), "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains(r#"<c r="A1" t="inlineStr"><is><t>yes</t></is></c>"#), "{sheet}");
+  }
+  
+  #[test]
+  fn test_identical_sheets_share_compiled_template() {
+    // 12 个相同的月份工作表共用一个编译后的模板, 每个工作表仍然使用各自的数据和合并单元格
+    let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let template = build_zip(&multi_sheet_entries(&["{{mergeAcross 3}}{{month}}"; 12]));
+    let mut renderer = WorkbookRenderer::new();
+    for (index, month) in months.iter().enumerate() {
+      renderer = renderer.data_for_sheet(format!("Sheet{}", index + 1), json!({ "month": month }));
+    }
+    let result = renderer.render(template).unwrap();
+    for (index, month) in months.iter().enumerate() {
+      let sheet = read_entry(&result, &format!("xl/worksheets/sheet{}.xml", index + 1)).unwrap();
+      assert!(sheet.contains(&format!("<t>{month}</t>")), "{month}: {sheet}");
+      assert!(sheet.contains(r#"<mergeCells count="1"><mergeCell ref="A1:C1"/></mergeCells>"#), "{month}: {sheet}");
+    }
+    
+    // 模板语法错误仍然作为渲染错误返回, 错误信息中包含出错的 sheet
+    let template = build_zip(&multi_sheet_entries(&["{{#if a}}", "{{#if a}}"]));
+    let err = render_template(template, &json!({})).unwrap_err().to_string();
+    assert!(err.contains(r#"Template error in "xl/worksheets/sheet1.xml""#), "{err}");
+  }
+  
+  #[test]
+  fn test_consistent_xml_declarations() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>"#;
+    let mut entries = xlsx_entries(sheet_data, &["{{img logo}}{{mergeCell \"A1:A2\"}}", r#"{{hyperlink (_cr) "https://example.com"}}Site"#]);
+    // 模板中缺少声明和使用其他写法的部件, 渲染时都会被修改
+    for (name, contents) in entries.iter_mut() {
+      if name == "xl/_rels/workbook.xml.rels" {
+        *contents = contents.split_once("?>").unwrap().1.trim_start().to_string();
+      } else if name == "[Content_Types].xml" {
+        *contents = contents.replacen(XML_DECLARATION, "<?xml version='1.0' encoding='utf-8'?>", 1);
+      }
+    }
+    // 渲染不会改动的部件原样输出, 包括声明
+    entries.push((
+      "docProps/core.xml".to_string(),
+      r#"<?xml version='1.0' encoding='utf-8'?><cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties"><cp:keywords>report</cp:keywords></cp:coreProperties>"#.to_string(),
+    ));
+    let untouched = ["docProps/core.xml", "_rels/.rels", "xl/workbook.xml"];
+    let template = build_zip(&entries);
+    let template_entry = |name: &str| entries.iter().find(|(n, _)| n == name).unwrap().1.clone();
+    
+    let xml_parts = |result: &[u8]| -> Vec<(String, String)> {
+      entry_names(result).into_iter()
+        .filter(|name| name.ends_with(".xml") || name.ends_with(".rels"))
+        .map(|name| { let contents = read_entry(result, &name).unwrap(); (name, contents) })
+        .collect()
+    };
+    let result = render_template(template.clone(), &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    let parts = xml_parts(&result);
+    // 生成的 drawing、drawing rels 和 sheet rels 都在其中
+    for name in ["xl/drawings/drawing1.xml", "xl/drawings/_rels/drawing1.xml.rels", "xl/worksheets/_rels/sheet1.xml.rels"] {
+      assert!(parts.iter().any(|(n, _)| n == name), "{name}: {parts:?}");
+    }
+    for (name, contents) in &parts {
+      if untouched.contains(&name.as_str()) {
+        assert_eq!(*contents, template_entry(name), "{name}");
+        continue;
+      }
+      assert!(contents.starts_with(&format!("{XML_DECLARATION}\n")) || contents.starts_with(&format!("{XML_DECLARATION}<")), "{name}: {contents}");
+      assert_eq!(contents.matches("<?xml").count(), 1, "{name}: {contents}");
+    }
+    
+    // 省略 standalone 属性
+    let options = RenderOptions { omit_xml_standalone: true, ..Default::default() };
+    let result = render_template_with_options(template, &json!({ "logo": TINY_PNG_BASE64 }), options).unwrap();
+    for (name, contents) in xml_parts(&result) {
+      if untouched.contains(&name.as_str()) {
+        assert_eq!(contents, template_entry(&name), "{name}");
+        continue;
+      }
+      assert!(contents.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#), "{name}: {contents}");
+    }
+  }
+  
+  #[test]
+  fn test_image_merges_into_existing_drawing() {
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    let sheet = entry_mut(&mut entries, "xl/worksheets/sheet1.xml");
+    *sheet = sheet.replace("</worksheet>", r#"<drawing r:id="rId1"/></worksheet>"#);
+    let content_types = entry_mut(&mut entries, "[Content_Types].xml");
+    *content_types = content_types.replace("</Types>", r#"<Override PartName="/xl/drawings/drawing1.xml" ContentType="application/vnd.openxmlformats-officedocument.drawing+xml"/></Types>"#);
+    entries.push((
+      "xl/worksheets/_rels/sheet1.xml.rels".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing" Target="../drawings/drawing1.xml"/></Relationships>"#.to_string(),
+    ));
+    entries.push((
+      "xl/drawings/drawing1.xml".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing" xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main"><xdr:twoCellAnchor><xdr:from><xdr:col>3</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>0</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:from><xdr:to><xdr:col>5</xdr:col><xdr:colOff>0</xdr:colOff><xdr:row>2</xdr:row><xdr:rowOff>0</xdr:rowOff></xdr:to><xdr:sp macro="" textlink=""><xdr:nvSpPr><xdr:cNvPr id="2" name="Rectangle 1"/><xdr:cNvSpPr/></xdr:nvSpPr><xdr:spPr><a:prstGeom prst="rect"><a:avLst/></a:prstGeom></xdr:spPr></xdr:sp><xdr:clientData/></xdr:twoCellAnchor></xdr:wsDr>"#.to_string(),
+    ));
+    entries.push((
+      "xl/drawings/_rels/drawing1.xml.rels".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/chart" Target="../charts/chart1.xml"/></Relationships>"#.to_string(),
+    ));
+    let template = build_zip(&entries);
+    
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    
+    // sheet 仍然只有原来的一个 drawing 和一个 drawing 关系
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert_eq!(sheet.matches("<drawing ").count(), 1, "{sheet}");
+    assert!(sheet.contains(r#"<drawing r:id="rId1"/>"#), "{sheet}");
+    let rels = read_entry(&result, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+    assert_eq!(rels.matches("<Relationship ").count(), 1, "{rels}");
+    assert!(read_entry(&result, "xl/drawings/drawing2.xml").is_none());
+    
+    // 原有的形状保留, 图片追加在同一个 drawing 中, cNvPr id 不重复
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    assert!(drawing.contains(r#"<xdr:cNvPr id="2" name="Rectangle 1"/>"#), "{drawing}");
+    assert!(drawing.contains(r#"<xdr:cNvPr id="3" name="Picture 3"/>"#), "{drawing}");
+    assert!(drawing.contains(r#"r:embed="rId2""#), "{drawing}");
+    assert!(drawing.trim_end().ends_with("</xdr:wsDr>"), "{drawing}");
+    
+    // 图片关系追加在原有的图表关系之后
+    let drawing_rels = read_entry(&result, "xl/drawings/_rels/drawing1.xml.rels").unwrap();
+    assert!(drawing_rels.contains(r#"Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/chart""#), "{drawing_rels}");
+    assert!(drawing_rels.contains(r#"Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="../media/"#), "{drawing_rels}");
+    
+    let content_types = read_entry(&result, "[Content_Types].xml").unwrap();
+    assert_eq!(content_types.matches("/xl/drawings/drawing1.xml").count(), 1, "{content_types}");
+  }
+  
+  #[test]
+  fn test_image_skips_unrelated_drawing_name() {
+    // drawing1.xml 已被其他部件占用 (未被 sheet1 引用), 新 drawing 使用下一个空闲文件名
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    let other_drawing = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing"/>"#;
+    entries.push(("xl/drawings/drawing1.xml".to_string(), other_drawing.to_string()));
+    let template = build_zip(&entries);
+    
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    assert_eq!(read_entry(&result, "xl/drawings/drawing1.xml").unwrap(), other_drawing);
+    let drawing = read_entry(&result, "xl/drawings/drawing2.xml").unwrap();
+    assert!(drawing.contains("<xdr:pic>"), "{drawing}");
+    assert!(read_entry(&result, "xl/drawings/_rels/drawing2.xml.rels").is_some());
+    
+    let rels = read_entry(&result, "xl/worksheets/_rels/sheet1.xml.rels").unwrap();
+    assert!(rels.contains(r#"Target="../drawings/drawing2.xml""#), "{rels}");
+    let content_types = read_entry(&result, "[Content_Types].xml").unwrap();
+    assert!(content_types.contains(r#"<Override PartName="/xl/drawings/drawing2.xml""#), "{content_types}");
+  }
+  
+  #[test]
+  fn test_image_merges_into_self_closing_drawing() {
+    let mut entries = xlsx_entries(r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row>"#, &["{{img logo}}"]);
+    let sheet = entry_mut(&mut entries, "xl/worksheets/sheet1.xml");
+    *sheet = sheet.replace("</worksheet>", r#"<drawing r:id="rId1"/></worksheet>"#);
+    entries.push((
+      "xl/worksheets/_rels/sheet1.xml.rels".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/drawing" Target="/xl/drawings/drawing1.xml"/></Relationships>"#.to_string(),
+    ));
+    entries.push((
+      "xl/drawings/drawing1.xml".to_string(),
+      r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing"/><!-- generated -->"#.to_string(),
+    ));
+    let template = build_zip(&entries);
+    
+    let result = render_template(template, &json!({ "logo": TINY_PNG_BASE64 })).unwrap();
+    let drawing = read_entry(&result, "xl/drawings/drawing1.xml").unwrap();
+    assert!(drawing.contains(r#"<xdr:wsDr xmlns:xdr="http://schemas.openxmlformats.org/drawingml/2006/spreadsheetDrawing">  <xdr:oneCellAnchor"#), "{drawing}");
+    assert!(drawing.contains(r#"<xdr:cNvPr id="1" name="Picture 1"/>"#), "{drawing}");
+    assert!(drawing.ends_with("</xdr:wsDr><!-- generated -->"), "{drawing}");
+    
+    // drawing 没有 rels 文件时新建
+    let drawing_rels = read_entry(&result, "xl/drawings/_rels/drawing1.xml.rels").unwrap();
+    assert!(drawing_rels.contains(r#"Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image""#), "{drawing_rels}");
+  }
+  
+  #[test]
+  fn test_empty_each_with_nested_else_removes_its_row() {
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row><row r="3"><c r="A3" t="s"><v>2</v></c><c r="B3" t="s"><v>3</v></c></row><row r="4"><c r="A4" t="s"><v>4</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["Header", "{{#each items}}", "{{name}}", "{{#if paid}}Paid{{else}}Due{{/if}}{{/each}}", "Footer"]);
+    
+    // 循环体中 {{#if}} 的 {{else}} 不属于 each, 空数组时仍然自动删除 {{#each 所在行
+    let result = render_template(template.clone(), &json!({ "items": [] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(!sheet.contains(r#"<row r="2""#), "{sheet}");
+    assert!(sheet.contains("Header") && sheet.contains("Footer"), "{sheet}");
+    
+    let result = render_template(template, &json!({ "items": [{ "name": "a", "paid": true }, { "name": "b", "paid": false }] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("<t>Paid</t>") && sheet.contains("<t>Due</t>"), "{sheet}");
+    
+    // each 自己的 {{else}} 仍然保留, 不再自动补上 removeRow
+    let sheet_data = r#"<row r="1"><c r="A1" t="s"><v>0</v></c></row><row r="2"><c r="A2" t="s"><v>1</v></c></row>"#;
+    let template = build_xlsx(sheet_data, &["{{#each items}}", "{{#if paid}}Paid{{/if}}{{else}}None{{/each}}"]);
+    let result = render_template(template, &json!({ "items": [] })).unwrap();
+    let sheet = read_entry(&result, "xl/worksheets/sheet1.xml").unwrap();
+    assert!(sheet.contains("<t>None</t>"), "{sheet}");
+  }
+}
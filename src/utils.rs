@@ -1,4 +1,4 @@
-use quick_xml::{Reader, Writer, events::Event};
+use quick_xml::{Reader, Writer, events::{BytesEnd, BytesStart, BytesText, Event}};
 
 use std::io::{Cursor, Write};
 use crate::errors::XlsxError;
@@ -28,14 +28,57 @@ pub(crate) fn validate_xlsx_format(file_data: &[u8]) -> Result<(), XlsxError> {
     Ok(())
 }
 
+/// 从读取器开头读取文件头并验证 XLSX 格式, 验证后读取位置回到开头
+pub(crate) fn validate_xlsx_reader<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<(), XlsxError> {
+    use std::io::Read;
+    
+    let mut header = Vec::with_capacity(22);
+    reader.rewind()
+        .and_then(|_| reader.by_ref().take(22).read_to_end(&mut header))
+        .map_err(|_| XlsxError::InvalidZipFormat)?;
+    validate_xlsx_format(&header)?;
+    reader.rewind().map_err(|_| XlsxError::InvalidZipFormat)
+}
+
 /// 超链接信息结构
 #[derive(Debug, Clone)]
 pub(crate) struct HyperlinkInfo {
     pub ref_cell: String,     // 单元格引用，如 "A26"
     pub location: String,     // 链接目标，如 "被链接的工作表!A1"
     pub display: String,      // 显示文本（可选）
+    pub rel_id: Option<String>, // 外部链接在 sheet rels 中的关系 ID, 有值时输出 r:id 而不是 location
+}
+
+/// 是否为外部链接 (http://、https://、mailto:), 外部链接需要通过 sheet rels 中的关系引用
+pub(crate) fn is_external_link(location: &str) -> bool {
+    let location = location.trim_start().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"].iter().any(|scheme| location.starts_with(scheme))
+}
+
+/// 单元格类型标记, 每个字段在 sheet 中出现对应 helper 的输出时为 Some
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CellMarkerKeys<'a> {
+    pub number: Option<&'a str>,   // {{num}} 数字类型标记
+    pub formula: Option<&'a str>,  // {{formula}} 公式类型标记
+    pub boolean: Option<&'a str>,  // {{bool}} 布尔类型标记
+    pub error: Option<&'a str>,    // {{cellError}} 错误类型标记
+    pub style: Option<&'a str>,    // {{cellStyle}} 单元格样式标记
+}
+
+/// 行标记, 每个字段在 sheet 中出现对应 helper 的输出时为 Some
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RowMarkerKeys<'a> {
+    pub remove: Option<&'a str>,   // {{removeRow}} 删除行标记
+    pub hide: Option<&'a str>,     // {{hideRow}} 隐藏行标记
+    pub outline: Option<&'a str>,  // {{rowOutlineLevel}} 行分组级别标记
 }
 
+/// Excel 支持的错误值
+pub(crate) const EXCEL_ERROR_LITERALS: [&str; 7] = ["#N/A", "#VALUE!", "#REF!", "#DIV/0!", "#NUM!", "#NAME?", "#NULL!"];
+
+/// 提取结果：去除标签后的 XML、合并范围列表、超链接列表
+type ExtractedSheetParts = (String, Vec<String>, Vec<HyperlinkInfo>);
+
 /// 从 sheet XML 中提取并移除 mergeCells 和 hyperlinks 标签
 /// 
 /// 这个函数会：
@@ -47,7 +90,7 @@ pub(crate) struct HyperlinkInfo {
 /// 需要在渲染过程中通过 helper 动态添加偏移后的范围
 pub(crate) fn extract_and_remove_merge_cells_and_hyperlinks(
     sheet_xml: &str
-) -> Result<(String, Vec<String>, Vec<HyperlinkInfo>), Box<dyn std::error::Error>> {
+) -> Result<ExtractedSheetParts, Box<dyn std::error::Error>> {
     let mut merge_refs = Vec::new();
     let mut hyperlinks = Vec::new();
     let mut result_xml = sheet_xml.to_string();
@@ -153,6 +196,7 @@ pub(crate) fn extract_and_remove_merge_cells_and_hyperlinks(
                             ref_cell,
                             location,
                             display,
+                            rel_id: None,
                         });
                     }
                     
@@ -178,19 +222,17 @@ pub(crate) fn extract_and_remove_merge_cells_and_hyperlinks(
 /// 通过查找单元格的 sharedString 索引，然后在对应的 shared_strings[index] 前面插入 helper
 pub(crate) fn inject_helpers_into_shared_strings(
     xml_content: &str,
-    shared_strings: &mut Vec<String>,
+    shared_strings: &mut [String],
     merge_refs: &[String],
     hyperlinks: &[HyperlinkInfo],
 ) -> Result<(), Box<dyn std::error::Error>> {
     // 处理 mergeCells
     for merge_ref in merge_refs {
-        if let Some(colon_pos) = merge_ref.find(':') {
-            let start_cell = &merge_ref[..colon_pos];
-            let end_cell = &merge_ref[colon_pos + 1..];
-            
-            // 解析结束单元格的列号和行号
-            let end_col = end_cell.chars().take_while(|c| c.is_alphabetic()).collect::<String>();
-            let end_row = end_cell.chars().skip_while(|c| c.is_alphabetic()).collect::<String>();
+        // 反向的范围 (如 B2:A1) 按左上角到右下角处理, 单个单元格不需要合并 (Excel 也不接受只有一个单元格的合并范围)
+        if let Some(((start_col, start_row), (end_col, end_row))) = normalize_cell_range(merge_ref)
+            && (start_col, start_row) != (end_col, end_row) {
+            let start_cell = format!("{}{}", to_column_name("A", start_col - 1), start_row);
+            let end_col = to_column_name("A", end_col - 1);
             
             // 查找起始单元格并获取其 sharedString 索引
             let cell_pattern = format!("<c r=\"{}\"", start_cell);
@@ -198,11 +240,11 @@ pub(crate) fn inject_helpers_into_shared_strings(
                 let cell_section = &xml_content[cell_start..];
                 
                 // 查找 <v> 标签中的索引值
-                if let Some(v_start) = cell_section.find("<v>") {
-                    if let Some(v_end) = cell_section[v_start + 3..].find("</v>") {
+                if let Some(v_start) = cell_section.find("<v>")
+                    && let Some(v_end) = cell_section[v_start + 3..].find("</v>") {
                         let index_str = &cell_section[v_start + 3..v_start + 3 + v_end];
-                        if let Ok(index) = index_str.parse::<usize>() {
-                            if index < shared_strings.len() {
+                        if let Ok(index) = index_str.parse::<usize>()
+                            && index < shared_strings.len() {
                                 // 构造 helper 调用
                                 let helper_call = format!(
                                     "{{{{mergeCell (concat (_cr) \":\" (_cr \"{}\" {}))}}}}",
@@ -218,9 +260,7 @@ pub(crate) fn inject_helpers_into_shared_strings(
                                     shared_strings[index] = modified;
                                 }
                             }
-                        }
                     }
-                }
             }
         }
     }
@@ -233,11 +273,11 @@ pub(crate) fn inject_helpers_into_shared_strings(
             let cell_section = &xml_content[cell_start..];
             
             // 查找 <v> 标签中的索引值
-            if let Some(v_start) = cell_section.find("<v>") {
-                if let Some(v_end) = cell_section[v_start + 3..].find("</v>") {
+            if let Some(v_start) = cell_section.find("<v>")
+                && let Some(v_end) = cell_section[v_start + 3..].find("</v>") {
                     let index_str = &cell_section[v_start + 3..v_start + 3 + v_end];
-                    if let Ok(index) = index_str.parse::<usize>() {
-                        if index < shared_strings.len() {
+                    if let Ok(index) = index_str.parse::<usize>()
+                        && index < shared_strings.len() {
                             // 构造 helper 调用
                             let helper_call = if link.display.is_empty() {
                                 format!("{{{{hyperlink (_cr) \"{}\" \"\"}}}}", link.location)
@@ -254,9 +294,7 @@ pub(crate) fn inject_helpers_into_shared_strings(
                                 shared_strings[index] = modified;
                             }
                         }
-                    }
                 }
-            }
         }
     }
     
@@ -268,6 +306,8 @@ pub(crate) fn inject_helpers_into_shared_strings(
  * 2. 寻找所有的 {{#each 循环, 看看第1个each是否跨行, 是否跨列, 如果跨行了, 那全局map存储第1个each是row类型的, 就跨行的,  否则只有跨列的, 那就是 c 类型的
  *
  * 3. 区分好所有each之后, 再次遍历标签, 这个时候, 我就能区分不同的循环类型, 就可以对行号和列号的累加做累加逻辑了
+ *
+ * 使用 {{#eachRow items}} / {{#eachCol items}} 时直接指定循环类型, 不再自动推断, 渲染时按普通的 {{#each}} 处理
  */
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) enum EachType {
@@ -285,6 +325,10 @@ pub(crate) struct EachBlockInfo {
     end_row: Option<u32>,   // {{/each}} 时的行号
     start_col: Option<u32>, // {{#each 时的列号
     end_col: Option<u32>,   // {{/each}} 时的列号
+    has_else: bool,         // 循环体内是否出现过 {{else}}
+    auto_remove_row: bool,  // {{#each 所在行除循环外没有其他内容, 空循环时自动删除该行
+    starts_mid_cell: bool,  // 同一个单元格中 {{#each ...}} 之后还有内容, 即循环体从单元格中间开始
+    forced_type: bool,      // 通过 {{#eachRow / {{#eachCol 显式指定了循环类型
 }
 
 /// 合并被XML标签分割的Handlebars语法
@@ -299,6 +343,8 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
     }
     
     let mut each_block_stack = Vec::<EachBlockInfo>::new();
+    // 所有未闭合的块 ({{#if}}、{{#each}} 等), each 块记录其在 each_block_stack 中的下标, 用于判断 {{else}} 属于哪一层
+    let mut open_blocks = Vec::<Option<usize>>::new();
     
     // 创建XML阅读器和写入器
     let mut reader = Reader::from_str(&xml_content);
@@ -318,6 +364,9 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
     // 当前列号(用于跟踪 each 块的列范围)
     let mut current_col: u32 = 0;
     
+    // 当前行是否已经输出过非空白文本(用于判断空循环时能否自动删除 {{#each 所在行)
+    let mut current_row_has_text = false;
+    
     loop {
         match reader.read_event_into(&mut buf) {
             // 处理文本节点
@@ -359,45 +408,92 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
                 // 如果不在 handlebars 表达式中且大括号已平衡，输出缓冲的文本
                 // 否则继续累积文本，等待 handlebars 表达式完整
                 if !in_handlebars && brace_count == 0 && !text_buffer.is_empty() {
+                    // {{#range 1 12}} / {{#group items "category"}} 改写为 {{#each (...)}}, 与 each 一样处理行列偏移
+                    if HELPER_BLOCKS.iter().any(|helper| text_buffer.contains(&format!("{{{{#{helper} ")) || text_buffer.contains(&format!("{{{{/{helper}}}}}"))) {
+                        text_buffer = rewrite_helper_blocks(&text_buffer);
+                    }
+                    let first_new_each = each_block_stack.len();
                     // 如果 text_buffer 中包含 {{#each 或 {{/each}}
                     if text_buffer.contains("{{#each") {
                         // 可能包含多个 {{#each，需要逐个处理
                         let mut remaining = text_buffer.as_str();
-                        while let Some(start_idx) = remaining.find("{{#each ") {
+                        // 只有第一个 {{#each 之前既没有本行的其他文本, 也没有同一文本中的前缀时, 才允许自动删除
+                        let mut auto_remove_row = !current_row_has_text;
+                        while let Some(start_idx) = remaining.find("{{#each") {
+                            // {{#each / {{#eachRow / {{#eachCol, 其他以 each 开头的 helper 不处理
+                            let after_hash = &remaining[start_idx + 3..]; // 跳过 "{{#"
+                            let keyword_len = after_hash.find(|c: char| c.is_whitespace() || c == '}').unwrap_or(after_hash.len());
+                            let each_type = match &after_hash[..keyword_len] {
+                                "each" => EachType::None, // 先占位，后续根据遇到的 row / c 标签推断
+                                "eachRow" => EachType::Row,
+                                "eachCol" => EachType::Col,
+                                _ => {
+                                    remaining = &after_hash[keyword_len..];
+                                    continue;
+                                }
+                            };
+                            let row_is_blank = auto_remove_row && remaining[..start_idx].trim().is_empty();
+                            auto_remove_row = false;
                             // 提取 {{#each 后面的变量名
                             // 例如: "{{#each projects}}" -> "projects"
-                            // 或者: "{{#each items}}" -> "items"
-                            let after_each = &remaining[start_idx + 8..]; // 跳过 "{{#each "
-                            let var_name = after_each
-                                .split('}')
-                                .next()
-                                .unwrap_or("")
+                            // 或者: "{{#eachRow items}}" -> "items"
+                            // 块参数: "{{#each items as |item idx|}}" -> "items"
+                            let after_each = &after_hash[keyword_len..];
+                            let expression = after_each.split("}}").next().unwrap_or("");
+                            let var_name = expression
+                                .split_once(" as |")
+                                .map_or(expression, |(head, _)| head)
                                 .trim()
                                 .to_string();
+                            let starts_mid_cell = after_each
+                                .split_once("}}")
+                                .is_some_and(|(_, rest)| !rest.trim().is_empty());
                             
                             // 将 EachBlockInfo 压入栈，记录开始行号和列号
                             each_block_stack.push(EachBlockInfo {
-                                each_type: EachType::None, // 先占位，后续可以根据需要更新
+                                forced_type: each_type != EachType::None,
+                                each_type,
                                 var_name,
                                 start_row: Some(current_row), // 记录当前行号
                                 end_row: None,
                                 start_col: Some(current_col), // 记录当前列号
                                 end_col: None,
+                                has_else: false,
+                                auto_remove_row: row_is_blank,
+                                starts_mid_cell,
                             });
                             
                             // 继续查找下一个 {{#each
-                            remaining = &after_each[1..];
+                            remaining = after_each;
+                        }
+                        // eachRow / eachCol 只用于指定循环类型, 渲染时使用内置的 each
+                        if text_buffer.contains("{{#eachRow") || text_buffer.contains("{{#eachCol") {
+                            text_buffer = text_buffer.replace("{{#eachRow ", "{{#each ").replace("{{#eachCol ", "{{#each ");
                         }
                     }
+                    if text_buffer.contains("{{/eachRow}}") || text_buffer.contains("{{/eachCol}}") {
+                        text_buffer = text_buffer.replace("{{/eachRow}}", "{{/each}}").replace("{{/eachCol}}", "{{/each}}");
+                    }
+                    if text_buffer.contains("{{") {
+                        mark_each_else(&text_buffer, &mut open_blocks, &mut each_block_stack, first_new_each);
+                    }
                     if text_buffer.contains("{{/each}}") {
                         let count = text_buffer.matches("{{/each}}").count();
                         // 弹出对应的 each_block_stack
-                        for _ in 0..count {
+                        for nth in 0..count {
                             if let Some(mut block_info) = each_block_stack.pop() {
                                 // 记录结束行号和列号
                                 block_info.end_row = Some(current_row);
                                 block_info.end_col = Some(current_col);
                                 
+                                // 强制按行循环但 {{/eachRow}} 与 {{#eachRow 在同一行时, 循环体中没有行, 每一项都会写进同一行的相同单元格
+                                if block_info.forced_type && block_info.each_type == EachType::Row && block_info.start_row == block_info.end_row {
+                                    return Err(format!(
+                                        "{{{{#eachRow {}}}}} in row {current_row} must end in a later row; use {{{{#each}}}} or {{{{#eachCol}}}} to repeat columns within one row",
+                                        block_info.var_name,
+                                    ).into());
+                                }
+                                
                                 // 使用提取的变量名
                                 let _var_name = block_info.var_name;
                                 
@@ -417,18 +513,42 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
                                 
                                 // 每个 block_info 对应一个 {{/each}} 标签, 每个 {{/each}} 标签前面
                                 // 加上偏移量（循环结束后多出来的行数或列数）
+                                // 按行循环且没有 {{else}} 时, 自动补上 {{else}}{{removeRow}}
+                                // 循环 0 次时只会输出 {{#each 所在行, 由 removeRow 标记后在 post_process_xml 中删除
+                                // 如果该行已经有显式的 {{removeRow}} 或其他内容, auto_remove_row 为 false, 不会重复减少 row_offset
+                                if block_info.each_type == EachType::Row && block_info.auto_remove_row && !block_info.has_else
+                                  && let Some((pos, _)) = text_buffer.match_indices("{{/each}}").nth(nth) {
+                                    text_buffer.insert_str(pos, "{{else}}{{removeRow}}");
+                                }
+                                
+                                // 偏移量插入到内层循环的 {{/each}} 之后 (最内层插入到 text_buffer 开头)
+                                // 否则外层的偏移量会在内层循环的每次迭代中重复累加, 例如按行循环中嵌套按列循环
+                                let insert_pos = match nth.checked_sub(1) {
+                                    Some(prev) => text_buffer.match_indices("{{/each}}").nth(prev).map_or(0, |(pos, m)| pos + m.len()),
+                                    None => 0,
+                                };
                                 if block_info.each_type == EachType::Row {
-                                    // 如果是 Row 类型的 each, 则在 text_buffer 前面加上 row_offset_plus
-                                    text_buffer = format!("{{{{row_offset_plus {row_offset_per_item}}}}}{text_buffer}");
+                                    // 如果是 Row 类型的 each, 则加上 row_offset_plus
+                                    text_buffer.insert_str(insert_pos, &format!("{{{{row_offset_plus {row_offset_per_item}}}}}"));
+                                } else if block_info.each_type == EachType::Col
+                                  && block_info.starts_mid_cell && col_offset_per_item > 0 && !block_info.has_else
+                                  && block_info.start_row == block_info.end_row
+                                  && let Some((pos, _)) = text_buffer.match_indices("{{/each}}").nth(nth) {
+                                    // 循环体从单元格中间开始并跨越多个单元格, 例如 {{#each items}}{{name}} | {{value}}{{/each}}
+                                    // 第二次迭代的内容会写进上一次迭代的最后一个单元格, 先做标记, 最后由 align_col_each_cells 把循环体扩展为完整的单元格
+                                    text_buffer.insert_str(pos, &format!("{{{{!{COL_CELLS_MARKER} {}}}}}", col_offset_per_item + 1));
                                 } else if block_info.each_type == EachType::Col {
-                                    // 如果是 Col 类型的 each, 则在 text_buffer 前面加上 col_offset_plus
-                                    text_buffer = format!("{{{{col_offset_plus {col_offset_per_item}}}}}{text_buffer}");
+                                    // 如果是 Col 类型的 each, 则加上 col_offset_plus
+                                    text_buffer.insert_str(insert_pos, &format!("{{{{col_offset_plus {col_offset_per_item}}}}}"));
                                 }
                             } else {
                                 break;
                             }
                         }
                     }
+                    if !text_buffer.trim().is_empty() {
+                        current_row_has_text = true;
+                    }
                     // 使用 from_escaped 避免 Writer 重复转义 (例如 " 变成 &quot;)
                     writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(&text_buffer)))?;
                     text_buffer.clear();
@@ -447,80 +567,30 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
                     }
                     let tag_name = e.name().as_ref().to_vec();
                     if tag_name == b"row" {
-                      // 从 row 标签的 r 属性中提取行号
-                      for attr in e.attributes().flatten() {
-                        let key = std::str::from_utf8(attr.key.as_ref()).unwrap_or("");
-                        if key == "r" {
-                          let value = std::str::from_utf8(&attr.value).unwrap_or("0");
-                          current_row = value.parse::<u32>().unwrap_or(0);
-                          break;
-                        }
-                      }
+                      current_row_has_text = false;
+                      let (new_start, row_num) = row_start_with_offset(e, current_row + 1)?;
+                      current_row = row_num;
+                      // 没有 r 属性的单元格按行内位置计算列号
+                      current_col = 0;
                       
                       // 修改 each_block_stack 的最后一个元素的类型为 Row
                       if let Some(last) = each_block_stack.last_mut()
+                        && !last.forced_type
                         && (last.each_type == EachType::None || last.each_type == EachType::Col) {
                           last.each_type = EachType::Row;
                         }
-                      // 创建新的开始标签，用于修改属性
-                      let mut new_start = e.borrow();
-                      new_start.clear_attributes(); // 清除现有属性
-                      
-                      for attr in e.attributes() {
-                        let attr = attr?;
-                        let key = std::str::from_utf8(attr.key.as_ref())?;
-                        if key == "r" {
-                          // 如果在 each 块内 的 c 标签，更新 r 属性的行号
-                          let value = std::str::from_utf8(&attr.value)?;
-                          let row_num = value.parse::<u32>().unwrap_or(0);
-                          let value = format!("{{{{col_offset_reset}}}}{{{{set_row_inline {row_num}}}}}{{{{_r}}}}");
-                          new_start.push_attribute((key.as_bytes(), value.as_bytes()));
-                        } else {
-                          // 保持其他属性不变
-                          new_start.push_attribute((attr.key.as_ref(), attr.value.as_ref()));
-                        }
-                      }
                       // 输出修改后的开始标签
                       writer.write_event(Event::Start(new_start))?;
                     } else if tag_name == b"c" {
-                      // 从 c 标签的 r 属性中提取列号
-                      for attr in e.attributes().flatten() {
-                        let key = std::str::from_utf8(attr.key.as_ref()).unwrap_or("");
-                        if key == "r" {
-                          let value = std::str::from_utf8(&attr.value).unwrap_or("");
-                          // 从 E7 中提取列字母部分
-                          let r_char: String = value.chars().take_while(|c| c.is_alphabetic()).collect();
-                          current_col = to_column_index(&r_char);
-                          break;
-                        }
-                      }
+                      let (new_start, col) = cell_start_with_offset(e, current_col + 1)?;
+                      current_col = col;
                       
                       // 修改 each_block_stack 的最后一个元素的类型为 Col
                       if let Some(last) = each_block_stack.last_mut()
+                        && !last.forced_type
                         && last.each_type == EachType::None {
                           last.each_type = EachType::Col;
                         }
-                      // 创建新的开始标签，用于修改属性
-                      let mut new_start = e.borrow();
-                      new_start.clear_attributes(); // 清除现有属性
-                      
-                      for attr in e.attributes() {
-                        let attr = attr?;
-                        let key = std::str::from_utf8(attr.key.as_ref())?;
-                        if key == "r" {
-                          // 如果在 each 块内 的 c 标签，更新 r 属性的列号
-                          let value = std::str::from_utf8(&attr.value)?;
-                          // 从 E7 中提取列字母部分
-                          let r_char: String = value.chars().take_while(|c| c.is_alphabetic()).collect();
-                          let col_inline = to_column_index(&r_char);
-                          // println!("{r_char} -> {col_inline}");
-                          let value = format!("{{{{set_col_inline {col_inline}}}}}{{{{_cr}}}}");
-                          new_start.push_attribute((key.as_bytes(), value.as_bytes()));
-                        } else {
-                          // 保持其他属性不变
-                          new_start.push_attribute((attr.key.as_ref(), attr.value.as_ref()));
-                        }
-                      }
                       // 输出修改后的开始标签
                       writer.write_event(Event::Start(new_start))?;
                     } else {
@@ -555,7 +625,34 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
                         writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(&text_buffer)))?;
                         text_buffer.clear();
                     }
-                    writer.write_event(Event::Empty(e.clone()))?;
+                    let tag_name = e.name().as_ref().to_vec();
+                    if tag_name == b"row" {
+                        // 空行 <row r="5" s="2"/> 同样需要按偏移更新行号, 否则循环中会出现重复的行号
+                        let (new_empty, row_num) = row_start_with_offset(e, current_row + 1)?;
+                        current_row = row_num;
+                        current_col = 0;
+                        current_row_has_text = false;
+                        writer.write_event(Event::Empty(new_empty))?;
+                    } else if tag_name == b"c" {
+                        // 只有样式的空单元格 <c r="B2" s="5"/> 同样需要按偏移更新 r, 循环中每个单元格保留模板单元格的 s 样式
+                        let (new_empty, col) = cell_start_with_offset(e, current_col + 1)?;
+                        current_col = col;
+                        writer.write_event(Event::Empty(new_empty))?;
+                    } else {
+                        writer.write_event(Event::Empty(e.clone()))?;
+                    }
+                }
+            }
+            
+            // 处理实体引用 (如 &amp;), handlebars 表达式内部需要还原为普通字符
+            Ok(Event::GeneralRef(ref e)) if in_handlebars || brace_count > 0 => {
+                let name = std::str::from_utf8(e)?;
+                match quick_xml::escape::resolve_predefined_entity(name) {
+                    Some(ch) => text_buffer.push_str(ch),
+                    None => match e.resolve_char_ref()? {
+                        Some(ch) => text_buffer.push(ch),
+                        None => text_buffer.push_str(&format!("&{name};")),
+                    },
                 }
             }
             
@@ -586,11 +683,251 @@ pub(crate) fn merge_handlebars_in_xml(xml_content: String) -> Result<String, Box
     
     // 将结果转换为字符串返回
     let result = writer.into_inner().into_inner();
-    Ok(String::from_utf8(result)?)
+    Ok(align_col_each_cells(String::from_utf8(result)?))
+}
+
+/// 循环体跨越多个完整单元格的按列循环标记, 写在 handlebars 注释中, 后面是每次迭代占用的列数
+const COL_CELLS_MARKER: &str = "col-cells";
+
+/// 把带 COL_CELLS_MARKER 标记的按列循环扩展为完整的单元格
+/// 
+/// {{#each}} 移到起始单元格的 <c 之前, {{/each}} 移到结束单元格的 </c> 之后,
+/// 每次迭代输出完整的若干个单元格, 除最后一次外每次迭代后列偏移增加循环体的列数
+fn align_col_each_cells(mut xml: String) -> String {
+    let marker_prefix = format!("{{{{!{COL_CELLS_MARKER} ");
+    while let Some(marker_start) = xml.find(&marker_prefix) {
+        let Some(marker_len) = xml[marker_start..].find("}}").map(|pos| pos + 2) else {
+            break;
+        };
+        let span = xml[marker_start + marker_prefix.len()..marker_start + marker_len - 2].to_string();
+        let close_start = marker_start + marker_len;
+        let close_end = close_start + "{{/each}}".len();
+        
+        // 向前找到对应的 {{#each, 跳过内层已经闭合的循环
+        let mut depth = 0;
+        let mut open = None;
+        let mut search_end = marker_start;
+        while let Some(pos) = [xml[..search_end].rfind("{{#each"), xml[..search_end].rfind("{{/each}}")].into_iter().flatten().max() {
+            if xml[pos..].starts_with("{{/each}}") {
+                depth += 1;
+            } else if depth == 0 {
+                open = Some(pos);
+                break;
+            } else {
+                depth -= 1;
+            }
+            search_end = pos;
+        }
+        
+        let cell_start = open.and_then(|open| xml[..open].rfind("<c ").filter(|start| !xml[*start..open].contains("</c>")));
+        let cell_end = xml[close_end..].find("</c>").map(|pos| close_end + pos + "</c>".len());
+        let (Some(open), Some(cell_start), Some(cell_end)) = (open, cell_start, cell_end) else {
+            // 找不到完整的单元格时删除标记, 保持原样
+            xml.replace_range(marker_start..close_start, "");
+            continue;
+        };
+        let open_end = open + xml[open..].find("}}").map_or(0, |pos| pos + 2);
+        // 同一单元格中 {{#each 之前打开的块 (例如 {{#with department}}{{#each members}}) 要跟着移到 <c 之前,
+        // {{/each}} 之后关闭的块要跟着移到 </c> 之后, 否则会被包进循环体, 与循环外的结束标签错位
+        let (outer_opens, cell_prefix) = take_unpaired_block_tags(&xml[cell_start..open], false);
+        let (outer_closes, cell_suffix) = take_unpaired_block_tags(&xml[close_end..cell_end], true);
+        
+        xml = format!(
+            "{}{outer_opens}{}{cell_prefix}{}{cell_suffix}{{{{#unless @last}}}}{{{{col_offset_plus {span}}}}}{{{{/unless}}}}{{{{/each}}}}{outer_closes}{}",
+            &xml[..cell_start],
+            &xml[open..open_end],
+            &xml[open_end..marker_start],
+            &xml[cell_end..],
+        );
+    }
+    xml
+}
+
+/// 取出片段中没有配对的块开始标签 (`closing` 为 false) 或块结束标签 (`closing` 为 true)
+/// 
+/// 返回 (按原顺序拼接的这些标签, 去掉这些标签后的片段)
+fn take_unpaired_block_tags(segment: &str, closing: bool) -> (String, String) {
+    let mut opens: Vec<(usize, usize)> = Vec::new();
+    let mut closes: Vec<(usize, usize)> = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = segment[pos..].find("{{").map(|p| pos + p) {
+        let Some(end) = segment[start..].find("}}").map(|p| start + p + 2) else {
+            break;
+        };
+        let tag = segment[start + 2..].trim_start_matches('~');
+        if tag.starts_with('#') {
+            opens.push((start, end));
+        } else if tag.starts_with('/') && opens.pop().is_none() {
+            closes.push((start, end));
+        }
+        pos = end;
+    }
+    
+    let ranges = if closing { closes } else { opens };
+    let mut tags = String::new();
+    let mut rest = String::new();
+    let mut last = 0;
+    for (start, end) in ranges {
+        tags.push_str(&segment[start..end]);
+        rest.push_str(&segment[last..start]);
+        last = end;
+    }
+    rest.push_str(&segment[last..]);
+    (tags, rest)
+}
+
+/// 可以写成块形式的 helper, 渲染前由 rewrite_helper_blocks 改写为 {{#each (helper ...)}}
+const HELPER_BLOCKS: &[&str] = &["range", "group"];
+
+/// 把值生成类的块 helper 改写为 `{{#each}}`, 与 each 一样处理行列偏移:
+/// - `{{#range 1 12}}...{{/range}}` -> `{{#each (range 1 12)}}...{{/each}}`
+/// - `{{#group items "category" as |lines cat|}}...{{/group}}` -> `{{#each (group items "category") as |lines cat|}}...{{/each}}`
+fn rewrite_helper_blocks(text: &str) -> String {
+    let mut result = text.to_string();
+    for helper in HELPER_BLOCKS {
+        let open = format!("{{{{#{helper} ");
+        if !result.contains(&open) && !result.contains(&format!("{{{{/{helper}}}}}")) {
+            continue;
+        }
+        let mut rewritten = String::with_capacity(result.len() + 16);
+        let mut remaining = result.as_str();
+        while let Some(start) = remaining.find(&open) {
+            let args_start = start + open.len();
+            let Some(args_len) = remaining[args_start..].find("}}") else {
+                break;
+            };
+            let expression = &remaining[args_start..args_start + args_len];
+            // 块参数留在括号外面
+            let (args, block_params) = match expression.split_once(" as |") {
+                Some((args, params)) => (args, format!(" as |{params}")),
+                None => (expression, String::new()),
+            };
+            rewritten.push_str(&remaining[..start]);
+            rewritten.push_str(&format!("{{{{#each ({helper} {}){block_params}}}}}", args.trim()));
+            remaining = &remaining[args_start + args_len + 2..];
+        }
+        rewritten.push_str(remaining);
+        result = rewritten.replace(&format!("{{{{/{helper}}}}}"), "{{/each}}");
+    }
+    result
+}
+
+/// 按出现顺序跟踪 text 中的块标签, 把 `{{else}}` / `{{^}}` 记到直接包含它的块上
+/// 
+/// 只有直接属于 each 的 `{{else}}` 才会标记 has_else, 循环体中嵌套的 `{{#if}}...{{else}}...{{/if}}` 不影响外层的 each。
+/// first_new_each 为 text 中第一个 `{{#each` 在 each_block_stack 中的下标
+fn mark_each_else(
+    text: &str,
+    open_blocks: &mut Vec<Option<usize>>,
+    each_block_stack: &mut [EachBlockInfo],
+    first_new_each: usize,
+) {
+    let mut next_each = first_new_each;
+    let mut remaining = text;
+    while let Some(start) = remaining.find("{{") {
+        remaining = &remaining[start + 2..];
+        let tag = remaining.trim_start_matches('~');
+        let is_else = tag.strip_prefix("else").is_some_and(|rest| rest.starts_with(|c: char| c == '}' || c == '~' || c.is_whitespace()))
+            || tag.strip_prefix('^').is_some_and(|rest| rest.trim_start_matches('~').starts_with('}'));
+        if is_else {
+            if let Some(Some(index)) = open_blocks.last()
+                && let Some(block) = each_block_stack.get_mut(*index) {
+                    block.has_else = true;
+                }
+        } else if let Some(block) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+            // 与 merge_handlebars_in_xml 一样只把 {{#each / {{#eachRow / {{#eachCol 当作 each 块
+            let keyword_len = block.find(|c: char| c.is_whitespace() || c == '}' || c == '~').unwrap_or(block.len());
+            let is_each = remaining.starts_with('#') && matches!(&block[..keyword_len], "each" | "eachRow" | "eachCol");
+            if is_each {
+                open_blocks.push(Some(next_each));
+                next_each += 1;
+            } else {
+                open_blocks.push(None);
+            }
+        } else if tag.starts_with('/') {
+            open_blocks.pop();
+        }
+    }
+}
+
+/// 把 sharedStrings.xml 中 sst 标签下的 si 标签解析出来放到数组中, 其中的 si 标签换成 is 标签
+/// 数组下标即 t="s" 单元格 v 标签中的索引
+pub(crate) fn extract_shared_strings(xml_content: &str) -> Vec<String> {
+  let mut shared_strings = Vec::new();
+  let mut start = 0;
+  while let Some(si_start) = xml_content[start..].find("<si>") {
+    let abs_start = start + si_start;
+    if let Some(si_end) = xml_content[abs_start..].find("</si>") {
+      let abs_end = abs_start + si_end + "</si>".len();
+      let si_xml = &xml_content[abs_start..abs_end];
+      // 将 si 标签替换为 is 标签
+      let is_xml = si_xml
+        .replace("<si>", "<is>")
+        .replace("</si>", "</is>");
+      shared_strings.push(is_xml);
+      start = abs_end;
+    } else {
+      break;
+    }
+  }
+  shared_strings
+}
+
+/// 把 row 标签的 r 属性替换为按行偏移计算的 helper, 其他属性 (如 s 样式) 保持不变
+/// 没有 r 属性时按行的位置 position 生成 r
+/// 返回新的标签和模板中的行号
+fn row_start_with_offset(e: &BytesStart, position: u32) -> Result<(BytesStart<'static>, u32), Box<dyn std::error::Error>> {
+  let mut new_start = BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+  let mut row = position;
+  if e.try_get_attribute("r")?.is_none() {
+    let value = format!("{{{{col_offset_reset}}}}{{{{set_row_inline {position}}}}}{{{{_r}}}}");
+    new_start.push_attribute(("r".as_bytes(), value.as_bytes()));
+  }
+  for attr in e.attributes() {
+    let attr = attr?;
+    if attr.key.as_ref() == b"r" {
+      let row_num = std::str::from_utf8(&attr.value)?.parse::<u32>().unwrap_or(0);
+      row = row_num;
+      let value = format!("{{{{col_offset_reset}}}}{{{{set_row_inline {row_num}}}}}{{{{_r}}}}");
+      new_start.push_attribute(("r".as_bytes(), value.as_bytes()));
+    } else {
+      new_start.push_attribute((attr.key.as_ref(), attr.value.as_ref()));
+    }
+  }
+  Ok((new_start, row))
+}
+
+/// 把 c 标签的 r 属性替换为按行列偏移计算的 helper, 其他属性 (如 s 样式) 保持不变
+/// 没有 r 属性时按单元格在行内的位置 position 生成 r
+/// 返回新的标签和模板中的列号
+fn cell_start_with_offset(e: &BytesStart, position: u32) -> Result<(BytesStart<'static>, u32), Box<dyn std::error::Error>> {
+  let mut new_start = BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+  let mut col = position;
+  if e.try_get_attribute("r")?.is_none() {
+    let value = format!("{{{{set_col_inline {position}}}}}{{{{_cr}}}}");
+    new_start.push_attribute(("r".as_bytes(), value.as_bytes()));
+  }
+  for attr in e.attributes() {
+    let attr = attr?;
+    if attr.key.as_ref() == b"r" {
+      // 从 E7 中提取列字母部分
+      let value = std::str::from_utf8(&attr.value)?;
+      let r_char: String = value.chars().take_while(|c| c.is_alphabetic()).collect();
+      let col_inline = to_column_index(&r_char);
+      col = col_inline;
+      let value = format!("{{{{set_col_inline {col_inline}}}}}{{{{_cr}}}}");
+      new_start.push_attribute(("r".as_bytes(), value.as_bytes()));
+    } else {
+      new_start.push_attribute((attr.key.as_ref(), attr.value.as_ref()));
+    }
+  }
+  Ok((new_start, col))
 }
 
 /// 找到所有 t="s" 的 c 标签, 把 v 标签中的数字替换成对应的字符串
 /// 例如: <c r="A1" t="s"><v>0</v></c> 替换成 <c r="A1" t="inlineStr"><is><t>字符串内容</t></is></c>
+/// 没有公式且 v 中包含 {{ 的 t="str" 单元格同样转换为 inlineStr, 以便按字符串单元格渲染
+/// 模板中原有的 t="inlineStr" 单元格, 其 is 标签与 sharedStrings 中的 si 做相同的处理
 pub(crate) fn replace_shared_strings_in_sheet(
   sheet_xml: &str,
   shared_strings: &[String]
@@ -610,16 +947,117 @@ pub(crate) fn replace_shared_strings_in_sheet(
   let mut found_f_tag = false;           // 在当前 c 标签内是否找到了 f 标签
   let mut in_v_tag = false;          // 是否在任意 v 标签内
   
+  // t="str" 的 c 标签: 读到 </c> 确认需要转换之前暂不输出
+  let mut pending_str_cell: Option<quick_xml::events::BytesStart<'static>> = None;
+  let mut str_v_content = String::new();
+  
+  // 模板中原有的 t="inlineStr" 单元格: 收集 is 标签的完整内容后统一处理
+  let mut in_inline_str_cell = false;
+  let mut inline_is_writer: Option<Writer<Cursor<Vec<u8>>>> = None;
+  
   loop {
-    match reader.read_event_into(&mut buf) {
+    let event = reader.read_event_into(&mut buf);
+    
+    if let Some(is_writer) = inline_is_writer.as_mut() {
+      let event = event.map_err(|e| format!("XML解析错误 at position {}: {:?}", reader.buffer_position(), e))?;
+      let is_end = matches!(&event, Event::End(e) if e.name().as_ref() == b"is");
+      is_writer.write_event(event)?;
+      if is_end {
+        // 与 sharedStrings 中的 si 一样合并被分割的 handlebars, 再作为 XML 事件写回
+        let is_xml = String::from_utf8(inline_is_writer.take().unwrap().into_inner().into_inner())?;
+        let is_xml = replace_shared_string_si_with_handlebars(&is_xml)?;
+        let mut is_reader = Reader::from_str(&is_xml);
+        loop {
+          match is_reader.read_event() {
+            Ok(Event::Eof) => break,
+            Ok(ev) => writer.write_event(ev)?,
+            Err(e) => return Err(format!("inline string parse error: {:?}", e).into()),
+          }
+        }
+      }
+      buf.clear();
+      continue;
+    }
+    
+    if let Some(start) = pending_str_cell.take() {
+      match &event {
+        Ok(Event::Start(e)) if e.name().as_ref() == b"v" => {
+          // 暂存 v 标签, 不输出
+          in_v_tag = true;
+          pending_str_cell = Some(start);
+        }
+        Ok(Event::End(e)) if e.name().as_ref() == b"v" => {
+          in_v_tag = false;
+          pending_str_cell = Some(start);
+        }
+        Ok(Event::Text(e)) if in_v_tag => {
+          str_v_content.push_str(std::str::from_utf8(e)?);
+          pending_str_cell = Some(start);
+        }
+        Ok(Event::End(e)) if e.name().as_ref() == b"c" => {
+          if str_v_content.contains("{{") {
+            // <c t="str"><v>{{name}}</v></c> 转换为 <c t="inlineStr"><is><t>{{name}}</t></is></c>
+            let mut new_start = quick_xml::events::BytesStart::new("c");
+            for attr in start.attributes() {
+              let attr = attr?;
+              if attr.key.as_ref() == b"t" {
+                new_start.push_attribute((b"t".as_slice(), b"inlineStr".as_slice()));
+              } else {
+                new_start.push_attribute(attr);
+              }
+            }
+            writer.write_event(Event::Start(new_start))?;
+            writer.write_event(Event::Start(quick_xml::events::BytesStart::new("is")))?;
+            writer.write_event(Event::Start(quick_xml::events::BytesStart::new("t")))?;
+            writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(str_v_content.as_str())))?;
+            writer.write_event(Event::End(quick_xml::events::BytesEnd::new("t")))?;
+            writer.write_event(Event::End(quick_xml::events::BytesEnd::new("is")))?;
+          } else {
+            writer.write_event(Event::Start(start))?;
+            if !str_v_content.is_empty() {
+              writer.write_event(Event::Start(quick_xml::events::BytesStart::new("v")))?;
+              writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(str_v_content.as_str())))?;
+              writer.write_event(Event::End(quick_xml::events::BytesEnd::new("v")))?;
+            }
+          }
+          writer.write_event(Event::End(quick_xml::events::BytesEnd::new("c")))?;
+          str_v_content.clear();
+          in_cell = false;
+          in_v_tag = false;
+          buf.clear();
+          continue;
+        }
+        _ => {
+          // 出现 f 等其他标签, 不是简单的字符串单元格, 按原样输出 c 开始标签后继续正常处理
+          writer.write_event(Event::Start(start))?;
+          str_v_content.clear();
+        }
+      }
+      if pending_str_cell.is_some() {
+        buf.clear();
+        continue;
+      }
+    }
+    
+    match event {
       // 处理开始标签
       Ok(Event::Start(ref e)) => {
         let tag_name = e.name().as_ref().to_vec();
         
-        if tag_name == b"c" {
+        if tag_name == b"c" && e.try_get_attribute("t")?.is_some_and(|t| t.value.as_ref() == b"str") {
+          // t="str" 单元格先暂存, 读到 </c> 时再决定是否转换
+          in_cell = true;
+          found_f_tag = false;
+          pending_str_cell = Some(e.clone().into_owned());
+        } else if tag_name == b"is" && in_inline_str_cell {
+          let mut is_writer = Writer::new(Cursor::new(Vec::new()));
+          is_writer.write_event(Event::Start(e.clone()))?;
+          inline_is_writer = Some(is_writer);
+        } else if tag_name == b"c" {
           // 进入任意 c 标签
           in_cell = true;
           found_f_tag = false; // 重置f标签标志
+          in_inline_str_cell = e.try_get_attribute("t")?.is_some_and(|t| t.value.as_ref() == b"inlineStr");
           
           // 检查是否有 t="s" 属性
           let mut has_shared_string = false;
@@ -691,11 +1129,16 @@ pub(crate) fn replace_shared_strings_in_sheet(
             writer.write_event(Event::Start(new_start))?;
             
             // 如果有 v 标签内容且没有f标签，替换成对应的字符串
-            if !shared_string_v_content.is_empty() && !found_f_tag
-              && let Ok(index) = shared_string_v_content.parse::<usize>()
-                && index < shared_strings.len() {
+            // 索引超出范围 (例如模板没有 sharedStrings.xml) 时输出空字符串, 避免生成没有 is 标签的 inlineStr 单元格
+            let si_content = shared_string_v_content.parse::<usize>().ok().and_then(|index| shared_strings.get(index));
+            if !found_f_tag && si_content.is_none() {
+              writer.write_event(Event::Start(quick_xml::events::BytesStart::new("is")))?;
+              writer.write_event(Event::Empty(quick_xml::events::BytesStart::new("t")))?;
+              writer.write_event(Event::End(quick_xml::events::BytesEnd::new("is")))?;
+            }
+            if !found_f_tag
+              && let Some(si_content) = si_content {
                   // 解析 shared_strings[index] 并作为 XML 事件插入，避免被转义
-                  let si_content = &shared_strings[index];
                   let si_content = replace_shared_string_si_with_handlebars(si_content)?;
                   let mut is_reader = Reader::from_str(&si_content);
                   let mut is_buf = Vec::new();
@@ -723,6 +1166,7 @@ pub(crate) fn replace_shared_strings_in_sheet(
           // 重置 c 标签状态
           in_cell = false;
           found_f_tag = false;
+          in_inline_str_cell = false;
         } else if tag_name == b"f" && in_cell {
           // 输出 f 结束标签
           writer.write_event(Event::End(e.clone()))?;
@@ -834,8 +1278,10 @@ pub(crate) fn replace_shared_string_si_with_handlebars(
           in_t_tag = false;
           
           // 检查收集到的文本内容是否包含 Handlebars 循环语法
-          let contains_each_start = t_text_content.contains("{{#each");
-          let contains_each_end = t_text_content.contains("{{/each");
+          let contains_each_start = t_text_content.contains("{{#each")
+            || HELPER_BLOCKS.iter().any(|helper| t_text_content.contains(&format!("{{{{#{helper} ")));
+          let contains_each_end = t_text_content.contains("{{/each")
+            || HELPER_BLOCKS.iter().any(|helper| t_text_content.contains(&format!("{{{{/{helper}}}}}")));
           
           if (contains_each_start || contains_each_end) && !in_r_tag {
             // 如果包含 each 语法且不在 r 标签内，需要包裹 r 标签
@@ -943,14 +1389,67 @@ pub(crate) fn register_basic_helpers(handlebars: &mut handlebars::Handlebars) ->
     handlebars_helper!(lower: |s: String| s.to_lowercase());
     handlebars.register_helper("lower", Box::new(lower));
     
-    // 注册 add helper (加法)
-    handlebars_helper!(add: |x: i64, y: i64| x + y);
+    // 注册 capitalize helper (首字母大写, 其余小写)
+    handlebars_helper!(capitalize: |s: String| capitalize_word(&s));
+    handlebars.register_helper("capitalize", Box::new(capitalize));
+    
+    // 注册 titleCase helper (每个以空白分隔的单词首字母大写, 其余小写)
+    handlebars_helper!(title_case: |s: String| {
+        let mut result = String::with_capacity(s.len());
+        let mut word_start = true;
+        for ch in s.chars() {
+            if word_start {
+                result.extend(ch.to_uppercase());
+            } else {
+                result.extend(ch.to_lowercase());
+            }
+            word_start = ch.is_whitespace();
+        }
+        result
+    });
+    handlebars.register_helper("titleCase", Box::new(title_case));
+    
+    // 注册 add helper (加法), 数字字符串按数字计算, 无法解析的值按 0 计算
+    // 用法: {{add 3 4}} 或 {{add "3" 4}}
+    handlebars_helper!(add: |x: Value, y: Value| numeric_op(&x, &y, i64::checked_add, |a, b| a + b));
     handlebars.register_helper("add", Box::new(add));
     
-    // 注册 sub helper (减法)
-    handlebars_helper!(sub: |x: i64, y: i64| x - y);
+    // 注册 sub helper (减法), 参数的处理与 add 相同
+    handlebars_helper!(sub: |x: Value, y: Value| numeric_op(&x, &y, i64::checked_sub, |a, b| a - b));
     handlebars.register_helper("sub", Box::new(sub));
     
+    // 注册 percent helper (百分比文本), 乘以 100 后保留指定的小数位数 (默认 0, 最多 20), 不是数字时输出空字符串
+    // 用法: {{percent 0.92}} -> 92%, {{percent 0.925 1}} -> 92.5%; 需要数字单元格时使用 percentNum
+    handlebars_helper!(percent: |x: Value, *args| {
+        let decimals = args.get(1).and_then(|v| v.as_u64()).unwrap_or(0).min(20) as usize;
+        match parse_number(&x).and_then(|n| n.as_f64()) {
+            Some(n) => format!("{:.*}%", decimals, n * 100.0),
+            None => String::new(),
+        }
+    });
+    handlebars.register_helper("percent", Box::new(percent));
+    
+    // 注册 dateDiffDays helper (两个日期相差的天数, end 早于 start 时为负数), 不是数字时输出空字符串
+    // 参数可以是毫秒时间戳或 Excel 日期序列号, 默认按数值大小自动判断, 也可以用 format="serial" / format="timestamp" 指定
+    // 用法: {{dateDiffDays project.start project.end}} 或 {{dateDiffDays 45292 45323 format="serial"}}
+    handlebars_helper!(date_diff_days: |start: Value, end: Value, {format: str = "auto"}| {
+        match (date_value_to_timestamp(&start, format), date_value_to_timestamp(&end, format)) {
+            (Some(start), Some(end)) => Value::from(end.div_euclid(MS_PER_DAY) - start.div_euclid(MS_PER_DAY)),
+            _ => Value::String(String::new()),
+        }
+    });
+    handlebars.register_helper("dateDiffDays", Box::new(date_diff_days));
+    
+    // 注册 abs helper (绝对值), 不是数字时输出空字符串, 可以配合 num 输出数字单元格
+    // 用法: {{num (abs variance)}}
+    handlebars_helper!(abs: |x: Value| numeric_unary_op(&x, i64::checked_abs, f64::abs));
+    handlebars.register_helper("abs", Box::new(abs));
+    
+    // 注册 neg helper (取相反数), 参数的处理与 abs 相同
+    // 用法: {{num (neg amount)}}
+    handlebars_helper!(neg: |x: Value| numeric_unary_op(&x, i64::checked_neg, |n| -n));
+    handlebars.register_helper("neg", Box::new(neg));
+    
     // 注册 len helper (数组/字符串长度)
     handlebars_helper!(len: |x: Value| {
         match x {
@@ -962,9 +1461,425 @@ pub(crate) fn register_basic_helpers(handlebars: &mut handlebars::Handlebars) ->
     });
     handlebars.register_helper("len", Box::new(len));
     
+    // 注册 default helper (返回第一个非 null 且非空字符串的参数)
+    // 用法: {{default user.nickname user.name "Anonymous"}}
+    handlebars_helper!(default: |*args| {
+        match args.iter().find(|v| !v.is_null() && v.as_str() != Some("")) {
+            Some(Value::String(s)) => s.clone(),
+            Some(v) => v.to_string(),
+            None => String::new(),
+        }
+    });
+    handlebars.register_helper("default", Box::new(default));
+    
+    // 注册 keepIfEmpty helper (value 为空时保留模板中原有的占位文本)
+    // 用法: {{keepIfEmpty "N/A" user.phone}}
+    handlebars_helper!(keep_if_empty: |original: Value, value: Value| {
+        let text = value_to_text(&value);
+        if text.is_empty() { value_to_text(&original) } else { text }
+    });
+    handlebars.register_helper("keepIfEmpty", Box::new(keep_if_empty));
+    
+    // 注册 getOr helper (按动态键取值, 缺失或为 null 时返回默认值)
+    // 用法: {{getOr lookupTable item.id "N/A"}}, 数组可以使用数字下标
+    handlebars_helper!(get_or: |map: Value, key: Value, fallback: Value| {
+        let found = match (&map, &key) {
+            (Value::Object(obj), Value::String(k)) => obj.get(k),
+            (Value::Object(obj), k) => obj.get(&k.to_string()),
+            (Value::Array(arr), k) => k.as_u64()
+                .or_else(|| k.as_str().and_then(|s| s.parse().ok()))
+                .and_then(|i| arr.get(i as usize)),
+            _ => None,
+        };
+        match found {
+            Some(v) if !v.is_null() => v.clone(),
+            _ => fallback,
+        }
+    });
+    handlebars.register_helper("getOr", Box::new(get_or));
+    
+    // 注册 json / jsonPretty helper (将任意值序列化为 JSON 文本)
+    // 输出会经过 Handlebars 的转义, 引号等字符在 XML 中以实体形式保存
+    // 用法: {{json someObject}} 或 {{jsonPretty someObject}}
+    handlebars_helper!(json: |x: Value| serde_json::to_string(&x).unwrap_or_default());
+    handlebars.register_helper("json", Box::new(json));
+    handlebars.register_helper("stringify", Box::new(json));
+    
+    handlebars_helper!(json_pretty: |x: Value| serde_json::to_string_pretty(&x).unwrap_or_default());
+    handlebars.register_helper("jsonPretty", Box::new(json_pretty));
+    
+    // 注册 startsWith / endsWith / contains helper (字符串前缀/后缀/包含判断, 非字符串先转为字符串)
+    // 用法: {{#if (contains email "@company.com")}}...{{/if}}
+    handlebars_helper!(starts_with: |s: Value, pat: Value| value_to_text(&s).starts_with(value_to_text(&pat).as_str()));
+    handlebars.register_helper("startsWith", Box::new(starts_with));
+    
+    handlebars_helper!(ends_with: |s: Value, pat: Value| value_to_text(&s).ends_with(value_to_text(&pat).as_str()));
+    handlebars.register_helper("endsWith", Box::new(ends_with));
+    
+    handlebars_helper!(contains: |s: Value, pat: Value| value_to_text(&s).contains(value_to_text(&pat).as_str()));
+    handlebars.register_helper("contains", Box::new(contains));
+    
+    // 注册 trim / replace / split helper (字符串清理, 非字符串先转为字符串)
+    // 用法: {{trim name}}, {{replace phone "-" ""}}, {{#each (split tags ",")}}{{this}}{{/each}}
+    handlebars_helper!(trim: |s: Value| value_to_text(&s).trim().to_string());
+    handlebars.register_helper("trim", Box::new(trim));
+    
+    handlebars_helper!(replace: |s: Value, from: Value, to: Value| {
+        let from = value_to_text(&from);
+        let s = value_to_text(&s);
+        if from.is_empty() { s } else { s.replace(&from, &value_to_text(&to)) }
+    });
+    handlebars.register_helper("replace", Box::new(replace));
+    
+    handlebars_helper!(split: |s: Value, sep: Value| {
+        let s = value_to_text(&s);
+        let sep = value_to_text(&sep);
+        if s.is_empty() {
+            Value::Array(vec![])
+        } else if sep.is_empty() {
+            Value::Array(s.chars().map(|c| Value::String(c.to_string())).collect())
+        } else {
+            Value::Array(s.split(sep.as_str()).map(|part| Value::String(part.to_string())).collect())
+        }
+    });
+    handlebars.register_helper("split", Box::new(split));
+    
+    // 注册 truncate helper (按字符数截断, 被截断时追加 …)
+    // 用法: {{truncate name 20}}
+    handlebars_helper!(truncate: |s: Value, max: u64| {
+        let s = value_to_text(&s);
+        if s.chars().count() > max as usize {
+            let mut truncated: String = s.chars().take(max as usize).collect();
+            truncated.push('…');
+            truncated
+        } else {
+            s
+        }
+    });
+    handlebars.register_helper("truncate", Box::new(truncate));
+    
+    // 注册 substring helper (按字符下标截取 [start, end), 省略 end 时截取到末尾)
+    // 用法: {{substring name 0 3}} 或 {{substring name 2}}
+    handlebars_helper!(substring: |s: Value, start: u64, *args| {
+        let s = value_to_text(&s);
+        let start = start as usize;
+        // *args 包含全部参数, 第 3 个参数为 end
+        let end = args.get(2).and_then(|v| v.as_u64()).map(|e| e as usize);
+        let chars = s.chars().skip(start);
+        match end {
+            Some(end) => chars.take(end.saturating_sub(start)).collect::<String>(),
+            None => chars.collect::<String>(),
+        }
+    });
+    handlebars.register_helper("substring", Box::new(substring));
+    
+    // 注册 padStart / padEnd helper (按字符数补齐到指定长度, 省略填充字符串时使用空格, 长度最多为 MAX_PAD_WIDTH)
+    // 用法: {{padStart id 6 "0"}} -> 000042
+    handlebars_helper!(pad_start: |s: Value, width: u64, *args| {
+        pad_text(&value_to_text(&s), width.min(MAX_PAD_WIDTH) as usize, args.get(2).copied(), true)
+    });
+    handlebars.register_helper("padStart", Box::new(pad_start));
+    
+    handlebars_helper!(pad_end: |s: Value, width: u64, *args| {
+        pad_text(&value_to_text(&s), width.min(MAX_PAD_WIDTH) as usize, args.get(2).copied(), false)
+    });
+    handlebars.register_helper("padEnd", Box::new(pad_end));
+    
+    // 注册 repeatStr helper (重复字符串, 最多重复 MAX_REPEAT_COUNT 次, 避免意外分配巨大的字符串)
+    // 用法: {{repeatStr "█" progress}} -> █████
+    handlebars_helper!(repeat_str: |s: Value, count: i64| {
+        value_to_text(&s).repeat(count.clamp(0, MAX_REPEAT_COUNT) as usize)
+    });
+    handlebars.register_helper("repeatStr", Box::new(repeat_str));
+    
+    // 注册 range helper (生成 start 到 end 的整数数组, 包含 end, 可选步长, 最多 MAX_RANGE_COUNT 项)
+    // 用法: {{#range 1 12}}{{this}}月{{/range}} 或 {{#range 10 1 -3}}{{this}}{{/range}}
+    // 块形式在 merge_handlebars_in_xml 中由 rewrite_helper_blocks 改写为 {{#each (range 1 12)}}, 也可以直接写 {{#each (range 1 12)}}
+    handlebars_helper!(range: |start: i64, end: i64, *args| {
+        let step = args.get(2).and_then(|v| v.as_i64()).unwrap_or(if start <= end { 1 } else { -1 });
+        let mut values = Vec::new();
+        let mut current = start;
+        while step != 0 && values.len() < MAX_RANGE_COUNT
+            && ((step > 0 && current <= end) || (step < 0 && current >= end)) {
+            values.push(Value::from(current));
+            let Some(next) = current.checked_add(step) else { break };
+            current = next;
+        }
+        Value::Array(values)
+    });
+    handlebars.register_helper("range", Box::new(range));
+    
+    // 注册 group helper (按字段值对数组分组, 返回 分组值 -> 该组数组 的对象, 分组按分组值排序)
+    // 用法: {{#group items "category"}}{{@key}}: {{#each this}}{{name}}{{/each}}{{/group}}
+    // 块参数: {{#group items "category" as |lines category|}}, 块形式同样改写为 {{#each (group items "category")}}
+    handlebars_helper!(group: |items: Value, key: str| {
+        let mut groups = serde_json::Map::new();
+        for item in items.as_array().into_iter().flatten() {
+            let group_key = value_to_text(item.get(key).unwrap_or(&Value::Null));
+            if let Value::Array(members) = groups.entry(group_key).or_insert_with(|| Value::Array(Vec::new())) {
+                members.push(item.clone());
+            }
+        }
+        Value::Object(groups)
+    });
+    handlebars.register_helper("group", Box::new(group));
+    
+    // 注册 sortBy helper (按字段排序, 返回新数组, 不修改原数据; 数字按数值比较, 其他按文本比较)
+    // 用法: {{#each (sortBy items "date")}} 或 {{#each (sortBy items "amount" "desc")}}
+    handlebars_helper!(sort_by: |items: Value, key: str, *args| {
+        let desc = args.get(2).is_some_and(|v| v.as_str().is_some_and(|order| order.eq_ignore_ascii_case("desc")));
+        let mut sorted = items.as_array().cloned().unwrap_or_default();
+        sorted.sort_by(|a, b| {
+            let (a, b) = (a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null));
+            if desc { compare_json_values(b, a) } else { compare_json_values(a, b) }
+        });
+        Value::Array(sorted)
+    });
+    handlebars.register_helper("sortBy", Box::new(sort_by));
+    
+    // 注册 filterBy helper (筛选 item[field] 与 value 比较成立的项, 返回新数组)
+    // 用法: {{#each (filterBy items "status" "active")}} 或 {{#each (filterBy items "amount" 100 "gt")}}
+    // 运算符: eq (默认) / ne 与 eq / ne helper 一样比较 JSON 值, gt / lt 按数值比较, 非数字的项不匹配
+    handlebars_helper!(filter_by: |items: Value, key: str, expected: Value, *args| {
+        let operator = args.get(3).and_then(|v| v.as_str()).unwrap_or("eq");
+        let filtered = items.as_array().into_iter().flatten()
+            .filter(|item| {
+                let actual = item.get(key).unwrap_or(&Value::Null);
+                match operator {
+                    "ne" => actual != &expected,
+                    "gt" => actual.as_f64().zip(expected.as_f64()).is_some_and(|(a, e)| a > e),
+                    "lt" => actual.as_f64().zip(expected.as_f64()).is_some_and(|(a, e)| a < e),
+                    _ => actual == &expected,
+                }
+            })
+            .cloned()
+            .collect();
+        Value::Array(filtered)
+    });
+    handlebars.register_helper("filterBy", Box::new(filter_by));
+    
+    // 注册 currency helper (千分位分组 + 固定小数位, 输出为文本单元格)
+    // 用法: {{currency amount "¥" 2}} -> ¥1,234.50, 省略时货币符号为空, 小数位为 2
+    handlebars_helper!(currency: |amount: f64, *args| {
+        let symbol = args.get(1).map(|v| value_to_text(v)).unwrap_or_default();
+        let decimals = args.get(2).and_then(|v| v.as_u64()).unwrap_or(2) as usize;
+        format_currency(amount, &symbol, decimals)
+    });
+    handlebars.register_helper("currency", Box::new(currency));
+    
+    // 注册 if_eq / unless_eq 块 helper (相等时/不等时渲染主块, 否则渲染 {{else}} 块)
+    // 用法: {{#if_eq status "done"}}已完成{{else}}未完成{{/if_eq}}
+    handlebars.register_helper("if_eq", Box::new(if_eq_helper));
+    handlebars.register_helper("unless_eq", Box::new(unless_eq_helper));
+    
+    // 注册 oneIndex helper (从 1 开始的循环序号, 即 @index + 1, 可以在 each / range / group 等循环中使用)
+    // 用法: {{#each items}}{{oneIndex}}. {{name}}{{/each}}, 代替 {{add @index 1}}
+    handlebars.register_helper("oneIndex", Box::new(one_index_helper));
+    
+    Ok(())
+}
+
+/// 比较两个 JSON 值: 都是数字时按数值比较, 否则按 value_to_text 的文本比较
+fn compare_json_values(a: &serde_json::Value, b: &serde_json::Value) -> std::cmp::Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => value_to_text(a).cmp(&value_to_text(b)),
+    }
+}
+
+/// 首字母大写, 其余小写, 按字符处理, 中文等没有大小写的字符保持不变
+fn capitalize_word(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new(),
+    }
+}
+
+/// repeatStr helper 的最大重复次数, 超出时按该次数输出
+const MAX_REPEAT_COUNT: i64 = 10000;
+
+/// padStart / padEnd helper 的最大补齐长度, 即 Excel 单元格最多容纳的字符数, 超出时按该长度补齐
+const MAX_PAD_WIDTH: u64 = 32767;
+
+/// range helper 生成的最大项数, 超出时截断
+const MAX_RANGE_COUNT: usize = 10000;
+
+/// 将数字或数字字符串转为 JSON 数字, 整数保持为整数, 无法解析时返回 None
+fn parse_number(value: &serde_json::Value) -> Option<serde_json::Number> {
+    match value {
+        serde_json::Value::Number(n) => Some(n.clone()),
+        serde_json::Value::String(s) => {
+            let s = s.trim();
+            match s.parse::<i64>() {
+                Ok(n) => Some(n.into()),
+                Err(_) => s.parse::<f64>().ok().and_then(serde_json::Number::from_f64),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// 一天的毫秒数
+const MS_PER_DAY: i64 = 86400000;
+
+/// 小于该值的非负数按 Excel 日期序列号处理 (2958466 是 9999-12-31 之后一天的序列号),
+/// 对应的毫秒时间戳只在 1970-01-01 的前 50 分钟内
+const MAX_EXCEL_SERIAL: f64 = 2958466.0;
+
+/// 把日期参数转为毫秒时间戳, format 为 "serial" (Excel 日期序列号)、"timestamp" (毫秒时间戳) 或 "auto" (按数值大小判断)
+fn date_value_to_timestamp(value: &serde_json::Value, format: &str) -> Option<i64> {
+    let n = parse_number(value)?.as_f64()?;
+    let is_serial = match format {
+        "serial" => true,
+        "timestamp" => false,
+        _ => (0.0..MAX_EXCEL_SERIAL).contains(&n),
+    };
+    if is_serial { excel_date_to_timestamp(n) } else { Some(n as i64) }
+}
+
+/// 将数字或数字字符串转为 JSON 数字, 整数保持为整数, 无法解析的值为 0
+pub(crate) fn value_to_number(value: &serde_json::Value) -> serde_json::Number {
+    parse_number(value).unwrap_or_else(|| 0.into())
+}
+
+/// abs / neg 等单参数算术 helper: 整数按整数计算 (溢出时改用浮点数), 不是数字时输出空字符串
+fn numeric_unary_op(
+    x: &serde_json::Value,
+    int_op: fn(i64) -> Option<i64>,
+    float_op: fn(f64) -> f64,
+) -> serde_json::Value {
+    let Some(x) = parse_number(x) else {
+        return serde_json::Value::String(String::new());
+    };
+    if let Some(n) = x.as_i64().and_then(int_op) {
+        return n.into();
+    }
+    let n = float_op(x.as_f64().unwrap_or(0.0));
+    serde_json::Number::from_f64(n).map_or_else(|| 0.into(), serde_json::Value::Number)
+}
+
+/// add / sub 等算术 helper: 两个参数都是整数时按整数计算 (溢出时改用浮点数), 否则按浮点数计算
+fn numeric_op(
+    x: &serde_json::Value,
+    y: &serde_json::Value,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> serde_json::Value {
+    let (x, y) = (value_to_number(x), value_to_number(y));
+    if let (Some(a), Some(b)) = (x.as_i64(), y.as_i64())
+        && let Some(n) = int_op(a, b) {
+            return n.into();
+        }
+    let n = float_op(x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+    serde_json::Number::from_f64(n).map_or_else(|| 0.into(), serde_json::Value::Number)
+}
+
+/// 将任意值转为文本: 字符串原样返回, null 为空字符串, 其他值使用 JSON 形式
+pub(crate) fn value_to_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// 按字符数将文本补齐到 width, 长度已足够时原样返回, 多字符填充串会被截断到恰好补齐
+fn pad_text(s: &str, width: usize, pad: Option<&serde_json::Value>, at_start: bool) -> String {
+    let len = s.chars().count();
+    let pad = pad.map(value_to_text).unwrap_or_else(|| " ".to_string());
+    if len >= width || pad.is_empty() {
+        return s.to_string();
+    }
+    let fill: String = pad.chars().cycle().take(width - len).collect();
+    if at_start { fill + s } else { s.to_string() + &fill }
+}
+
+/// 格式化金额: 整数部分每 3 位加逗号, 负号放在货币符号之前, 例如 -¥1,234.50
+fn format_currency(amount: f64, symbol: &str, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, amount.abs());
+    let (int_part, frac_part) = match formatted.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+        None => (formatted.as_str(), None),
+    };
+    let mut grouped = String::new();
+    for (i, ch) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(ch);
+    }
+    if let Some(frac_part) = frac_part {
+        grouped.push('.');
+        grouped.push_str(frac_part);
+    }
+    // 四舍五入后为 0 的负数不显示负号
+    let is_negative = amount < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0');
+    format!("{}{symbol}{grouped}", if is_negative { "-" } else { "" })
+}
+
+/// 渲染比较块: 条件为真时渲染主块, 否则渲染 {{else}} 块
+fn render_compare_block<'reg, 'rc>(
+    h: &handlebars::Helper<'rc>,
+    r: &'reg handlebars::Handlebars<'reg>,
+    ctx: &'rc handlebars::Context,
+    rc: &mut handlebars::RenderContext<'reg, 'rc>,
+    out: &mut dyn handlebars::Output,
+    expect_equal: bool,
+) -> handlebars::HelperResult {
+    use handlebars::Renderable;
+    
+    // 与 eq helper 保持一致: 直接比较两个 JSON 值
+    let x = h.param(0).map(|p| p.value().clone()).unwrap_or(serde_json::Value::Null);
+    let y = h.param(1).map(|p| p.value().clone()).unwrap_or(serde_json::Value::Null);
+    
+    let template = if (x == y) == expect_equal {
+        h.template()
+    } else {
+        h.inverse()
+    };
+    
+    if let Some(t) = template {
+        t.render(r, ctx, rc, out)?;
+    }
+    Ok(())
+}
+
+fn if_eq_helper<'reg, 'rc>(
+    h: &handlebars::Helper<'rc>,
+    r: &'reg handlebars::Handlebars<'reg>,
+    ctx: &'rc handlebars::Context,
+    rc: &mut handlebars::RenderContext<'reg, 'rc>,
+    out: &mut dyn handlebars::Output,
+) -> handlebars::HelperResult {
+    render_compare_block(h, r, ctx, rc, out, true)
+}
+
+fn one_index_helper<'reg, 'rc>(
+    _: &handlebars::Helper<'rc>,
+    _: &'reg handlebars::Handlebars<'reg>,
+    _: &'rc handlebars::Context,
+    rc: &mut handlebars::RenderContext<'reg, 'rc>,
+    out: &mut dyn handlebars::Output,
+) -> handlebars::HelperResult {
+    let index = rc.block()
+        .and_then(|block| block.get_local_var("index"))
+        .and_then(|index| index.as_u64())
+        .ok_or_else(|| handlebars::RenderErrorReason::Other("oneIndex helper must be used inside a loop such as {{#each}}".to_string()))?;
+    out.write(&(index + 1).to_string())?;
     Ok(())
 }
 
+fn unless_eq_helper<'reg, 'rc>(
+    h: &handlebars::Helper<'rc>,
+    r: &'reg handlebars::Handlebars<'reg>,
+    ctx: &'rc handlebars::Context,
+    rc: &mut handlebars::RenderContext<'reg, 'rc>,
+    out: &mut dyn handlebars::Output,
+) -> handlebars::HelperResult {
+    render_compare_block(h, r, ctx, rc, out, false)
+}
+
 /// 在 Excel 的 sheet.xml 中列名
 /// 传入当前列名和一个增量，返回新的列名
 /// 用于生成 Excel 列名，如 A, B, ..., Z, AA, AB, ..., ZZ, AAA, ...
@@ -1000,16 +1915,156 @@ pub fn to_column_index(col_name: &str) -> u32 {
   col_index
 }
 
-/// 将时间戳（毫秒）转换为 Excel 日期序列号
-/// 
-/// Excel 使用从 1900年1月1日开始的序列号来表示日期。
-/// 由于 Excel 的历史 bug（将 1900 年视为闰年），需要特殊处理。
-/// 
-/// # 参数
+/// 规范化单元格范围, 返回左上角和右下角的 ((列号, 行号), (列号, 行号))
+/// 反向的范围 (如 "B2:A1") 按左上角到右下角返回, 没有冒号的单个单元格 (如 "C3") 返回起止相同的范围, 格式不正确时返回 None
+pub(crate) fn normalize_cell_range(range: &str) -> Option<((u32, u32), (u32, u32))> {
+    let (start, end) = range.split_once(':').unwrap_or((range, range));
+    let (start_col, start_row) = parse_cell_ref(start)?;
+    let (end_col, end_row) = parse_cell_ref(end)?;
+    Some(((start_col.min(end_col), start_row.min(end_row)), (start_col.max(end_col), start_row.max(end_row))))
+}
+
+/// 解析单元格引用，返回 (列号, 行号)，均为 1-based
+/// 支持 "G3"、"g3" 以及绝对引用 "$G$3"，格式不正确时返回 None
+pub(crate) fn parse_cell_ref(cell_ref: &str) -> Option<(u32, u32)> {
+  let cell_ref = cell_ref.trim().replace('$', "").to_ascii_uppercase();
+  let col_len = cell_ref.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+  if col_len == 0 || col_len > 3 {
+    return None;
+  }
+  let row: u32 = cell_ref[col_len..].parse().ok()?;
+  if row == 0 {
+    return None;
+  }
+  Some((to_column_index(&cell_ref[..col_len]), row))
+}
+
+/// 按点号路径写入值, 例如 "totals.grand", 中间不存在的对象会自动创建
+/// 路径上已有的非对象值不会被覆盖, 返回错误
+pub(crate) fn set_value_by_path(data: &mut serde_json::Value, path: &str, value: serde_json::Value) -> Result<(), String> {
+  let mut current = data;
+  let mut segments = path.split('.').peekable();
+  while let Some(segment) = segments.next() {
+    if current.is_null() {
+      *current = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let Some(obj) = current.as_object_mut() else {
+      return Err(format!("cannot set \"{segment}\" on a non-object value"));
+    };
+    if segments.peek().is_none() {
+      obj.insert(segment.to_string(), value);
+      return Ok(());
+    }
+    current = obj.entry(segment.to_string()).or_insert(serde_json::Value::Null);
+  }
+  Ok(())
+}
+
+/// 收集 sheet XML 文本内容中所有 handlebars 表达式引用的变量名和 helper 名, 按首次出现的顺序追加到 names 中 (去重)
+/// 只扫描文本节点, 属性中的表达式 (如 merge_handlebars_in_xml 插入的 {{_r}} / {{_cr}}) 不会被收集
+/// 
+/// 例如 `{{#each items as |item|}}{{item.name}} {{formatNumber price 2}}{{/each}}`
+/// 收集到 `items`、`item.name`、`formatNumber`、`price`
+pub(crate) fn collect_handlebars_names(xml_content: &str, names: &mut Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+  let mut reader = Reader::from_str(xml_content);
+  let mut text = String::new();
+  loop {
+    match reader.read_event()? {
+      Event::Text(e) => text.push_str(&e.xml_content()?),
+      Event::CData(e) => text.push_str(&String::from_utf8_lossy(&e)),
+      Event::GeneralRef(e) => {
+        if let Some(ch) = e.resolve_char_ref()? {
+          text.push(ch);
+        } else if let Some(entity) = quick_xml::escape::resolve_predefined_entity(&e.decode()?) {
+          text.push_str(entity);
+        }
+      }
+      // 文本节点之间用换行隔开, 避免相邻单元格的文本拼接成新的表达式
+      Event::Start(_) | Event::End(_) | Event::Empty(_) => text.push('\n'),
+      Event::Eof => break,
+      _ => {}
+    }
+  }
+  
+  let mut rest = text.as_str();
+  while let Some(start) = rest.find("{{") {
+    let after = rest[start + 2..].trim_start_matches('{');
+    let Some(end) = after.find("}}") else {
+      break;
+    };
+    for name in handlebars_expression_names(&after[..end]) {
+      if !names.contains(&name) {
+        names.push(name);
+      }
+    }
+    rest = &after[end + 2..];
+  }
+  Ok(())
+}
+
+/// 解析单个 handlebars 表达式 (不含外层大括号) 中的变量名和 helper 名
+/// 跳过注释、结束标签、字符串 / 数字 / 布尔字面量、this、@index 等内置变量、each / if 等内置块 helper 和块参数声明
+fn handlebars_expression_names(expression: &str) -> Vec<String> {
+  let expression = expression.trim().trim_matches('~').trim();
+  if expression.starts_with('!') || expression.starts_with('/') {
+    return Vec::new();
+  }
+  let expression = expression.trim_start_matches(['#', '^', '>', '&']);
+  // {{#each items as |item idx|}} 中的块参数是局部变量, 不是数据中的字段
+  let expression = expression.split_once(" as |").map_or(expression, |(head, _)| head);
+  
+  let mut tokens = Vec::new();
+  let mut token = String::new();
+  let mut quote: Option<char> = None;
+  for ch in expression.chars() {
+    match quote {
+      Some(q) => {
+        if ch == q {
+          quote = None;
+        }
+      }
+      None => match ch {
+        '"' | '\'' => {
+          quote = Some(ch);
+          token.clear();
+        }
+        '(' | ')' => tokens.push(std::mem::take(&mut token)),
+        ch if ch.is_whitespace() => tokens.push(std::mem::take(&mut token)),
+        ch => token.push(ch),
+      },
+    }
+  }
+  tokens.push(token);
+  
+  tokens.into_iter()
+    .filter_map(|token| {
+      // hash 参数 key=value 只关心 value
+      let token = token.rsplit_once('=').map_or(token.as_str(), |(_, value)| value);
+      let mut name = token.trim_start_matches("../");
+      name = name.strip_prefix("this.").unwrap_or(name);
+      let is_literal = name.is_empty()
+        || name.starts_with('@')
+        || name.starts_with('.')
+        || matches!(name, "this" | "else" | "true" | "false" | "null" | "undefined")
+        || matches!(name, "each" | "eachRow" | "eachCol" | "if" | "unless" | "with")
+        || name.parse::<f64>().is_ok();
+      if is_literal { None } else { Some(name.to_string()) }
+    })
+    .collect()
+}
+
+/// 将时间戳（毫秒）转换为 Excel 日期序列号
+/// 
+/// Excel 使用从 1900年1月1日开始的序列号来表示日期，1900-01-01 是 1。
+/// 由于 Excel 的历史 bug（将 1900 年视为闰年），序列号 60 表示不存在的 1900-02-29,
+/// 1900-03-01 之前的日期序列号因此比之后的日期少 1: 1900-02-28 是 59, 1900-03-01 是 61。
+/// 该函数不会返回 60 到 61 之间的序列号, 与 [`excel_date_to_timestamp`] 往返转换结果一致。
+/// 
+/// # 参数
 /// * `timestamp_ms` - Unix 时间戳（毫秒），从 1970-01-01 00:00:00 UTC 开始
 /// 
 /// # 返回
-/// Excel 日期序列号（浮点数）
+/// Excel 日期序列号（浮点数），小数部分为一天中的时间；1899-12-31 之前的日期返回负数
 /// 
 /// # 示例
 /// ```rust
@@ -1017,24 +2072,17 @@ pub fn to_column_index(col_name: &str) -> u32 {
 /// 
 /// // 2024-01-01 00:00:00 UTC
 /// let timestamp = 1704067200000i64;
-/// let excel_date = timestamp_to_excel_date(timestamp);
-/// println!("Excel date number: {}", excel_date);
+/// assert_eq!(timestamp_to_excel_date(timestamp), 45292.0);
 /// ```
 pub fn timestamp_to_excel_date(timestamp_ms: i64) -> f64 {
-    // Excel 的基准日期是 1900-01-01，但实际上从 1899-12-30 开始计数
-    // 25569 是从 1900-01-01 到 1970-01-01 的天数
+    // 1970-01-01 的序列号是 25569, 即从 1899-12-30 开始计数的天数
     const EXCEL_EPOCH_OFFSET: i64 = 25569;
-    const MS_PER_DAY: i64 = 86400000;
     
-    let mut val_tmp = timestamp_ms + EXCEL_EPOCH_OFFSET * MS_PER_DAY;
+    let val_tmp = timestamp_ms + EXCEL_EPOCH_OFFSET * MS_PER_DAY;
     
-    // Excel 错误地将 1900 年视为闰年，需要调整
-    // 60 代表 1900-02-29（不存在的日期）
-    if val_tmp <= 60 * MS_PER_DAY {
-        val_tmp += MS_PER_DAY;
-    } else {
-        val_tmp += 2 * MS_PER_DAY;
-    }
+    // val_tmp 是从 1899-12-30 开始的毫秒数, 与之比较的 61 * MS_PER_DAY 同样是毫秒, 即 1900-03-01 00:00:00
+    // 从 1900-03-01 起序列号就是天数; 之前的日期 (包括 1900-02-28 当天的任意时间) 没有经过不存在的 1900-02-29, 序列号比天数少 1
+    let val_tmp = if val_tmp < 61 * MS_PER_DAY { val_tmp - MS_PER_DAY } else { val_tmp };
     
     val_tmp as f64 / MS_PER_DAY as f64
 }
@@ -1042,419 +2090,1388 @@ pub fn timestamp_to_excel_date(timestamp_ms: i64) -> f64 {
 /// 将 Excel 日期序列号转换为 Unix 时间戳（毫秒）
 /// 
 /// Excel 使用从 1900年1月1日开始的序列号来表示日期。
-/// 此函数将 Excel 序列号转换回 Unix 时间戳。
+/// 此函数将 Excel 序列号转换回 Unix 时间戳, 1900 年闰年 bug 的处理与 [`timestamp_to_excel_date`] 相同。
+/// 序列号 0 是 Excel 显示为 1900-01-00 的 1899-12-31。
 /// 
 /// # 参数
 /// * `excel_date` - Excel 日期序列号
 /// 
 /// # 返回
 /// * `Some(timestamp_ms)` - Unix 时间戳（毫秒），从 1970-01-01 00:00:00 UTC 开始
-/// * `None` - 如果输入的序列号无效（如负数或等于60）
+/// * `None` - 如果输入的序列号无效（负数、非有限数, 或 60 到 61 之间表示不存在的 1900-02-29）
 /// 
 /// # 示例
 /// ```rust
 /// use xlsx_handlebars::excel_date_to_timestamp;
 /// 
-/// // Excel 序列号 45294.0 表示 2024-01-01
-/// let excel_date = 45294.0;
-/// if let Some(timestamp) = excel_date_to_timestamp(excel_date) {
-///     println!("Unix timestamp: {}", timestamp);
-/// }
+/// // Excel 序列号 45292.0 表示 2024-01-01
+/// assert_eq!(excel_date_to_timestamp(45292.0), Some(1704067200000));
+/// assert_eq!(excel_date_to_timestamp(60.0), None);
 /// ```
 pub fn excel_date_to_timestamp(excel_date: f64) -> Option<i64> {
-    const MS_PER_DAY: i64 = 86400000;
     const EXCEL_EPOCH_OFFSET: i64 = 25569;
     
-    let mut val = excel_date;
-    
-    // 处理 Excel 的 1900 年闰年 bug（反向操作）
-    if val < 60.0 {
-        val -= 1.0;
-    } else if val > 60.0 {
-        val -= 2.0;
-    }
-    
-    // 60 代表不存在的日期 1900-02-29
-    if val < 0.0 || excel_date == 60.0 {
+    // 60 到 61 之间代表不存在的日期 1900-02-29
+    if !excel_date.is_finite() || excel_date < 0.0 || (60.0..61.0).contains(&excel_date) {
         return None;
     }
     
+    // 处理 Excel 的 1900 年闰年 bug（反向操作）: 1900-03-01 之前的序列号比从 1899-12-30 开始的天数少 1
+    let val = if excel_date < 60.0 { excel_date + 1.0 } else { excel_date };
+    
     // 转换为时间戳
     let timestamp = (val * MS_PER_DAY as f64).round() as i64 - (EXCEL_EPOCH_OFFSET * MS_PER_DAY);
     
     Some(timestamp)
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  
-  #[test]
-  fn test_excel_column_name() {
-    assert_eq!(to_column_name("A", 0), "A");
-    assert_eq!(to_column_name("A", 1), "B");
-    assert_eq!(to_column_name("Z", 1), "AA");
-    assert_eq!(to_column_name("AA", 1), "AB");
-    assert_eq!(to_column_name("AZ", 1), "BA");
-    assert_eq!(to_column_name("ZZ", 1), "AAA");
-    assert_eq!(to_column_name("AAA", 26), "ABA");
-  }
-  
-  #[test]
-  fn test_replace_shared_string_si_with_handlebars() {
-    // 测试包含 {{#each 且已经在 r 标签内的情况 - 应该保持原样
-    let input_with_each_in_r = r#"<si>
-  <r>
-    <t>a</t>
-  </r>
-  <r>
-    <rPr>
-      <sz val="11" />
-      <color rgb="FFFF0000" />
-    </rPr>
-    <t>{{#each projects}}</t>
-  </r>
-  <phoneticPr fontId="1" type="noConversion" />
-</si>"#;
-    
-    let result = replace_shared_string_si_with_handlebars(input_with_each_in_r).unwrap();
-    println!("输入包含 {{#each 且在 r 标签内的结果:");
-    println!("{}", result);
-    
-    // 因为已经在 r 标签内，所以应该保持原样
-    assert!(result.contains("{{#each projects}}"));
-    assert!(result.contains("<r>"));
-    assert!(result.contains("<t>{{#each projects}}</t>"));
+/// 深度合并两个 JSON 值, 返回合并后的新值
+/// 
+/// - 两边都是对象时按 key 递归合并, overrides 中的值优先
+/// - 其它情况 (包括数组) 直接使用 overrides 中的值, 数组不会逐项合并
+/// 
+/// # 示例
+/// 
+/// ```rust
+/// use serde_json::json;
+/// use xlsx_handlebars::merge_json_values;
+/// 
+/// let base = json!({ "company": { "name": "ACME", "phone": "123" }, "tags": ["a", "b"] });
+/// let overrides = json!({ "company": { "phone": "456" }, "tags": ["c"] });
+/// assert_eq!(
+///     merge_json_values(&base, &overrides),
+///     json!({ "company": { "name": "ACME", "phone": "456" }, "tags": ["c"] }),
+/// );
+/// ```
+pub fn merge_json_values(base: &serde_json::Value, overrides: &serde_json::Value) -> serde_json::Value {
+    match (base, overrides) {
+        (serde_json::Value::Object(base_map), serde_json::Value::Object(override_map)) => {
+            let mut merged = base_map.clone();
+            for (key, value) in override_map {
+                let value = match base_map.get(key) {
+                    Some(base_value) => merge_json_values(base_value, value),
+                    None => value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            serde_json::Value::Object(merged)
+        }
+        _ => overrides.clone(),
+    }
+}
+
+/// worksheet 中已有的 `<drawing r:id="..."/>` 引用的关系 ID, 没有 drawing 元素时返回 None
+pub(crate) fn sheet_drawing_rid(sheet_xml: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut depth = 0;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if depth == 1 && e.name().as_ref() == b"drawing" {
+                    return drawing_rid(&e);
+                }
+                depth += 1;
+            }
+            Ok(Event::Empty(e)) if depth == 1 && e.name().as_ref() == b"drawing" => return drawing_rid(&e),
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Eof) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(format!("XML Error at position {}: {:?}", reader.buffer_position(), e).into()),
+        }
+    }
+}
+
+/// drawing 元素的 r:id 属性, 按本地名称匹配, 不依赖命名空间前缀
+fn drawing_rid(e: &BytesStart) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == b"id" {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn existing_drawing_error(e: &BytesStart) -> Box<dyn std::error::Error> {
+    let rid = drawing_rid(e).ok().flatten().unwrap_or_default();
+    format!("worksheet already has <drawing r:id=\"{rid}\"/>, images must be added to that drawing").into()
+}
+
+/// 在 sheet XML 中插入 `<drawing r:id="..."/>` 标签
+/// 
+/// 使用 XML 解析器定位 worksheet 的直接子元素，而不是字符串替换，
+/// 避免注释或 CDATA 中出现 `</worksheet>` 文本时插入到错误位置。
+/// 按照 schema 顺序，drawing 需要放在 legacyDrawing、tableParts、extLst 等元素之前，
+/// 没有这些元素时放在 `</worksheet>` 之前。
+/// 每个 worksheet 只能有一个 drawing 元素, 已经有 drawing 时返回错误,
+/// 调用方应先用 [`sheet_drawing_rid`] 找到已有的 drawing, 把图片合并进去。
+pub(crate) fn insert_drawing_into_sheet(
+    sheet_xml: &str,
+    drawing_rid: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // schema 中位于 drawing 之后的 worksheet 子元素
+    const AFTER_DRAWING: &[&[u8]] = &[
+        b"legacyDrawing", b"legacyDrawingHF", b"drawingHF", b"picture", b"oleObjects",
+        b"controls", b"webPublishItems", b"tableParts", b"extLst",
+    ];
     
-    // 测试包含 {{#each 但不在 r 标签内的情况 - 应该被包裹
-    let input_with_each_not_in_r = r#"<si>
-  <t>{{#each projects}}</t>
-  <phoneticPr fontId="1" type="noConversion" />
-</si>"#;
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut inserted = false;
     
-    let result2 = replace_shared_string_si_with_handlebars(input_with_each_not_in_r).unwrap();
-    println!("输入包含 {{#each 且不在 r 标签内的结果:");
-    println!("{}", result2);
-    // 应该被包裹在 r 标签内
-    assert!(result2.contains("{{#each projects}}"));
-    assert!(result2.contains("<r><t>{{#each projects}}</t></r>"));
+    let drawing_tag = || {
+        let mut tag = quick_xml::events::BytesStart::new("drawing");
+        tag.push_attribute(("r:id", drawing_rid));
+        Event::Empty(tag)
+    };
     
-    // 测试包含 {{/each}} 且不在 r 标签内的情况
-    let input_with_end_each_not_in_r = r#"<si>
-  <t>{{/each}}</t>
-</si>"#;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if depth == 1 && !inserted {
+                    if e.name().as_ref() == b"drawing" {
+                        return Err(existing_drawing_error(&e));
+                    }
+                    if AFTER_DRAWING.contains(&e.name().as_ref()) {
+                        writer.write_event(drawing_tag())?;
+                        inserted = true;
+                    }
+                }
+                depth += 1;
+                writer.write_event(Event::Start(e))?;
+            }
+            Ok(Event::Empty(e)) => {
+                if depth == 1 && !inserted {
+                    if e.name().as_ref() == b"drawing" {
+                        return Err(existing_drawing_error(&e));
+                    }
+                    if AFTER_DRAWING.contains(&e.name().as_ref()) {
+                        writer.write_event(drawing_tag())?;
+                        inserted = true;
+                    }
+                }
+                writer.write_event(Event::Empty(e))?;
+            }
+            Ok(Event::End(e)) => {
+                depth -= 1;
+                if depth == 0 && e.name().as_ref() == b"worksheet" && !inserted {
+                    writer.write_event(drawing_tag())?;
+                    inserted = true;
+                }
+                writer.write_event(Event::End(e))?;
+            }
+            Ok(Event::Eof) => break,
+            Ok(event) => writer.write_event(event)?,
+            Err(e) => return Err(format!("XML Error at position {}: {:?}", reader.buffer_position(), e).into()),
+        }
+    }
     
-    let result3 = replace_shared_string_si_with_handlebars(input_with_end_each_not_in_r).unwrap();
-    println!("输入包含 {{/each}} 且不在 r 标签内的结果:");
-    println!("{}", result3);
-    // 应该被包裹在 r 标签内
-    assert!(result3.contains("{{/each}}"));
-    assert!(result3.contains("<r><t>{{/each}}</t></r>"));
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 在 drawing XML 的根节点 (`<xdr:wsDr>`) 末尾插入新的 anchor 节点
+/// 
+/// 与 [`insert_drawing_into_sheet`] 一样使用 XML 解析器定位根节点的结束标签,
+/// 自闭合的 `<xdr:wsDr/>` 会展开为开始和结束标签, 根节点之后的注释、处理指令保持在原位置。
+pub(crate) fn insert_anchors_into_drawing(
+    drawing_xml: &str,
+    anchors: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(drawing_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut inserted = false;
     
-    // 测试不包含 each 的情况 - 应该保持原样
-    let input_normal = r#"<si>
-  <r>
-    <t>normal text</t>
-  </r>
-  <r>
-    <rPr>
-      <sz val="11" />
-    </rPr>
-    <t>{{name}}</t>
-  </r>
-</si>"#;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                depth += 1;
+                writer.write_event(Event::Start(e))?;
+            }
+            Ok(Event::Empty(e)) if depth == 0 && e.local_name().as_ref() == b"wsDr" && !inserted => {
+                let end = e.to_end().into_owned();
+                writer.write_event(Event::Start(e))?;
+                writer.write_event(Event::Text(BytesText::from_escaped(anchors)))?;
+                writer.write_event(Event::End(end))?;
+                inserted = true;
+            }
+            Ok(Event::End(e)) => {
+                depth -= 1;
+                if depth == 0 && e.local_name().as_ref() == b"wsDr" && !inserted {
+                    writer.write_event(Event::Text(BytesText::from_escaped(anchors)))?;
+                    inserted = true;
+                }
+                writer.write_event(Event::End(e))?;
+            }
+            Ok(Event::Eof) => break,
+            Ok(event) => writer.write_event(event)?,
+            Err(e) => return Err(format!("XML Error at position {}: {:?}", reader.buffer_position(), e).into()),
+        }
+    }
+    if !inserted {
+        return Err("drawing has no <xdr:wsDr> root element".into());
+    }
     
-    let result4 = replace_shared_string_si_with_handlebars(input_normal).unwrap();
-    println!("输入不包含 each 的结果:");
-    println!("{}", result4);
-    // 应该保持 r 标签结构
-    assert!(result4.contains("<r>"));
-    assert!(result4.contains("{{name}}"));
-  }
-  
-  #[test]
-  fn test_excel_column_index() {
-    assert_eq!(to_column_index("A"), 1);
-    assert_eq!(to_column_index("E"), 5);
-    assert_eq!(to_column_index("Z"), 26);
-    assert_eq!(to_column_index("AA"), 27);
-    assert_eq!(to_column_index("AZ"), 52);
-    assert_eq!(to_column_index("BA"), 53);
-    assert_eq!(to_column_index("ZZ"), 702);
-    assert_eq!(to_column_index("AAA"), 703);
-  }
-  
-  #[test]
-  fn test_excel_column_name_and_index() {
-    let test_cases = vec![
-      ("A", 1),
-      ("Z", 26),
-      ("AA", 27),
-      ("AZ", 52),
-      ("BA", 53),
-      ("ZZ", 702),
-      ("AAA", 703),
-      ("AAB", 704),
-      ("ABC", 731),
-      ("ZZZ", 18278),
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// drawing 中已使用的最大 cNvPr id (图片、形状、图表等对象的编号), 没有对象时为 0
+pub(crate) fn max_drawing_object_id(drawing_xml: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(drawing_xml);
+    let mut max_id = 0;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"cNvPr" => {
+                if let Some(id) = e.try_get_attribute("id")? {
+                    let id = id.unescape_value()?;
+                    if let Ok(id) = id.trim().parse::<usize>() {
+                        max_id = max_id.max(id);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(max_id)
+}
+
+/// 在 sheet XML 中插入 `<conditionalFormatting>` 元素
+/// 
+/// 按照 schema 顺序放在 sheetData、mergeCells 和模板中已有的 conditionalFormatting 之后,
+/// dataValidations、hyperlinks、pageMargins 等元素之前
+pub(crate) fn insert_conditional_formatting_into_sheet(
+    sheet_xml: &str,
+    conditional_formatting_xml: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // schema 中位于 conditionalFormatting 之后的 worksheet 子元素
+    const AFTER_CONDITIONAL_FORMATTING: &[&[u8]] = &[
+        b"dataValidations", b"hyperlinks", b"printOptions", b"pageMargins", b"pageSetup",
+        b"headerFooter", b"rowBreaks", b"colBreaks", b"customProperties", b"cellWatches",
+        b"ignoredErrors", b"smartTags", b"drawing", b"legacyDrawing", b"legacyDrawingHF",
+        b"drawingHF", b"picture", b"oleObjects", b"controls", b"webPublishItems",
+        b"tableParts", b"extLst",
     ];
     
-    for (col_name, expected_index) in test_cases {
-      let index = to_column_index(col_name);
-      assert_eq!(index, expected_index, "Column name to index failed for {}", col_name);
-      
-      let name = to_column_name(col_name, 0);
-      assert_eq!(name, col_name, "Column name identity failed for {}", col_name);
-      
-      let name_plus_one = to_column_name(col_name, 1);
-      let index_plus_one = to_column_index(&name_plus_one);
-      assert_eq!(index_plus_one, expected_index + 1, "Column name to index failed for {} + 1", col_name);
+    if conditional_formatting_xml.is_empty() {
+        return Ok(sheet_xml.to_string());
     }
-  }
-  
-  #[test]
-  fn test_excel_date_conversion() {
-    // 测试 2024-01-01 00:00:00 UTC
-    // Unix timestamp: 1704067200000 ms
-    let timestamp_2024 = 1704067200000i64;
-    let excel_date = timestamp_to_excel_date(timestamp_2024);
     
-    // Excel 中 2024-01-01 的序列号应该是 45294 (包含 Excel 1900 bug 调整)
-    assert!((excel_date - 45294.0).abs() < 0.001, "Excel date for 2024-01-01 should be ~45294, got {}", excel_date);
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut inserted = false;
     
-    // 反向转换
-    if let Some(timestamp) = excel_date_to_timestamp(excel_date) {
-      // 允许一些精度损失（毫秒级别）
-      assert!((timestamp - timestamp_2024).abs() < 1000, "Timestamp mismatch: expected {}, got {}", timestamp_2024, timestamp);
-    } else {
-      panic!("Failed to convert Excel date back to timestamp");
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        let is_after = match &event {
+            Event::Start(e) | Event::Empty(e) => depth == 1 && AFTER_CONDITIONAL_FORMATTING.contains(&e.name().as_ref()),
+            Event::End(e) => depth == 1 && e.name().as_ref() == b"worksheet",
+            _ => false,
+        };
+        if is_after && !inserted {
+            writer.get_mut().write_all(conditional_formatting_xml.as_bytes())?;
+            inserted = true;
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
     }
     
-    // 测试 1970-01-01 00:00:00 UTC (Unix epoch)
-    let timestamp_1970 = 0i64;
-    let excel_date_1970 = timestamp_to_excel_date(timestamp_1970);
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 设置 sheet XML 的工作表标签颜色 `<sheetPr><tabColor rgb="FFFF0000"/></sheetPr>`
+/// 
+/// 按照 schema 顺序, sheetPr 是 worksheet 的第一个子元素, tabColor 是 sheetPr 的第一个子元素;
+/// 已有 sheetPr 时保留其属性和其他子元素, 替换其中已有的 tabColor
+pub(crate) fn set_sheet_tab_color(sheet_xml: &str, argb: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut inserted = false;
+    // 是否位于原有的 sheetPr 内, 以及是否正在跳过其中原有的 tabColor
+    let mut in_sheet_pr = false;
+    let mut skipping_tab_color = false;
     
-    // Excel 中 1970-01-01 的序列号应该是 25571 (25569 + 2 for bug)
-    assert!((excel_date_1970 - 25571.0).abs() < 0.001, "Excel date for 1970-01-01 should be ~25571, got {}", excel_date_1970);
+    let tab_color = || {
+        let mut tag = BytesStart::new("tabColor");
+        tag.push_attribute(("rgb", argb));
+        Event::Empty(tag)
+    };
     
-    // 测试边界情况：1900-02-28 (序列号 59)
-    let excel_date_59 = 59.0;
-    assert!(excel_date_to_timestamp(excel_date_59).is_some());
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        if skipping_tab_color {
+            if let Event::End(e) = &event && e.name().as_ref() == b"tabColor" {
+                skipping_tab_color = false;
+            }
+            continue;
+        }
+        match event {
+            // sheetPr 中原有的 tabColor 被替换
+            Event::Start(ref e) | Event::Empty(ref e) if in_sheet_pr && depth == 2 && e.name().as_ref() == b"tabColor" => {
+                skipping_tab_color = matches!(event, Event::Start(_));
+            }
+            Event::Start(e) if depth == 1 && !inserted && e.name().as_ref() == b"sheetPr" => {
+                inserted = true;
+                in_sheet_pr = true;
+                depth += 1;
+                writer.write_event(Event::Start(e))?;
+                writer.write_event(tab_color())?;
+            }
+            Event::Empty(e) if depth == 1 && !inserted && e.name().as_ref() == b"sheetPr" => {
+                inserted = true;
+                writer.write_event(Event::Start(e))?;
+                writer.write_event(tab_color())?;
+                writer.write_event(Event::End(BytesEnd::new("sheetPr")))?;
+            }
+            // worksheet 的第一个子元素不是 sheetPr (或者 worksheet 没有子元素), 在它之前插入 sheetPr
+            Event::Start(_) | Event::Empty(_) | Event::End(_) if depth == 1 && !inserted => {
+                inserted = true;
+                writer.write_event(Event::Start(BytesStart::new("sheetPr")))?;
+                writer.write_event(tab_color())?;
+                writer.write_event(Event::End(BytesEnd::new("sheetPr")))?;
+                match &event {
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) => depth -= 1,
+                    _ => {}
+                }
+                writer.write_event(event)?;
+            }
+            Event::Start(e) => {
+                depth += 1;
+                writer.write_event(Event::Start(e))?;
+            }
+            Event::End(e) => {
+                depth -= 1;
+                if depth == 1 {
+                    in_sheet_pr = false;
+                }
+                writer.write_event(Event::End(e))?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
     
-    // 测试无效日期：1900-02-29 (序列号 60，不存在的日期)
-    let excel_date_60 = 60.0;
-    assert!(excel_date_to_timestamp(excel_date_60).is_none(), "Excel date 60 (1900-02-29) should be invalid");
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 统计 styles.xml 中已有的差异格式 `<dxf>` 数量, 新增的 dxf 序号从这里开始
+pub(crate) fn count_dxfs(styles_xml: &str) -> usize {
+    let Some(start) = styles_xml.find("<dxfs") else {
+        return 0;
+    };
+    let end = styles_xml[start..].find("</dxfs>").map_or(start, |pos| start + pos);
+    let dxfs = &styles_xml[start..end];
+    dxfs.matches("<dxf>").count() + dxfs.matches("<dxf/>").count() + dxfs.matches("<dxf ").count()
+}
+
+/// 在 styles.xml 的 `<dxfs>` 末尾追加差异格式, 并更新 count
+/// 
+/// 没有 `<dxfs>` 时按 schema 顺序创建在 cellStyles 之后、tableStyles、colors、extLst 之前
+pub(crate) fn append_dxfs_to_styles(styles_xml: &str, dxfs: &[String]) -> String {
+    if dxfs.is_empty() {
+        return styles_xml.to_string();
+    }
     
-    // 测试负数（无效）
-    assert!(excel_date_to_timestamp(-1.0).is_none(), "Negative Excel date should be invalid");
-  }
-  
-  #[test]
-  fn test_extract_and_remove_merge_cells_and_hyperlinks() {
-    // 测试包含完整 mergeCells 标签的情况
-    let input_with_merge = r#"<?xml version="1.0"?>
-<worksheet>
-  <sheetData>
-    <row r="1">
-      <c r="A1"><v>Test</v></c>
-    </row>
-  </sheetData>
-  <mergeCells count="2">
-    <mergeCell ref="A1:B1"/>
-    <mergeCell ref="C2:D3"/>
-  </mergeCells>
-  <pageMargins left="0.7" right="0.7"/>
-</worksheet>"#;
+    let count = count_dxfs(styles_xml) + dxfs.len();
+    let new_dxfs: String = dxfs.concat();
     
-    let (result_xml, merge_refs, hyperlinks) = extract_and_remove_merge_cells_and_hyperlinks(input_with_merge).unwrap();
+    if let Some(start) = styles_xml.find("<dxfs") {
+        let tag_end = styles_xml[start..].find('>').map_or(styles_xml.len(), |pos| start + pos + 1);
+        let self_closing = styles_xml[..tag_end].ends_with("/>");
+        let content_end = if self_closing {
+            tag_end
+        } else {
+            styles_xml[tag_end..].find("</dxfs>").map_or(tag_end, |pos| tag_end + pos)
+        };
+        let close_end = if self_closing { tag_end } else { content_end + "</dxfs>".len() };
+        let existing = if self_closing { "" } else { &styles_xml[tag_end..content_end] };
+        return format!(
+            r#"{}<dxfs count="{count}">{existing}{new_dxfs}</dxfs>{}"#,
+            &styles_xml[..start],
+            &styles_xml[close_end..],
+        );
+    }
     
-    // 验证合并范围被正确提取
-    assert_eq!(merge_refs.len(), 2);
-    assert_eq!(merge_refs[0], "A1:B1");
-    assert_eq!(merge_refs[1], "C2:D3");
-    assert_eq!(hyperlinks.len(), 0);
+    let insert_pos = ["<tableStyles", "<colors", "<extLst", "</styleSheet>"].iter()
+        .find_map(|tag| styles_xml.find(tag))
+        .unwrap_or(styles_xml.len());
+    format!(
+        r#"{}<dxfs count="{count}">{new_dxfs}</dxfs>{}"#,
+        &styles_xml[..insert_pos],
+        &styles_xml[insert_pos..],
+    )
+}
+
+/// styles.xml 中 `<cellXfs>` 下的单元格格式 `<xf>`, 序号即单元格的 s 属性
+pub(crate) fn cell_xfs(styles_xml: &str) -> Vec<String> {
+    let Some(start) = styles_xml.find("<cellXfs") else {
+        return Vec::new();
+    };
+    let end = styles_xml[start..].find("</cellXfs>").map_or(start, |pos| start + pos);
+    let content = &styles_xml[start..end];
+    let mut xfs = Vec::new();
+    let mut pos = content.find('>').map_or(content.len(), |p| p + 1);
+    while let Some(xf_start) = content[pos..].find("<xf").map(|p| pos + p) {
+        let Some(tag_end) = content[xf_start..].find('>').map(|p| xf_start + p + 1) else {
+            break;
+        };
+        let xf_end = if content[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            content[tag_end..].find("</xf>").map_or(content.len(), |p| tag_end + p + "</xf>".len())
+        };
+        xfs.push(content[xf_start..xf_end].to_string());
+        pos = xf_end;
+    }
+    xfs
+}
+
+/// 在单元格格式 `<xf>` 上加上自动换行 `<alignment wrapText="1"/>`, 已经自动换行时返回 None
+pub(crate) fn wrap_text_xf(xf: &str) -> Option<String> {
+    if xf.contains(r#"wrapText="1""#) {
+        return None;
+    }
+    let tag_end = xf.find('>')?;
+    let self_closing = xf[..=tag_end].ends_with("/>");
+    let attrs_end = if self_closing { tag_end - 1 } else { tag_end };
+    // applyAlignment="0" 时 Excel 会忽略 alignment
+    let open_tag = match xf[..attrs_end].find("applyAlignment=\"") {
+        Some(pos) => {
+            let value_start = pos + "applyAlignment=\"".len();
+            let value_end = xf[value_start..].find('"').map_or(value_start, |p| value_start + p);
+            format!("{}1{}", &xf[..value_start], &xf[value_end..attrs_end])
+        }
+        None => format!(r#"{} applyAlignment="1""#, xf[..attrs_end].trim_end()),
+    };
+    if self_closing {
+        return Some(format!(r#"{open_tag}><alignment wrapText="1"/></xf>"#));
+    }
+    let children = &xf[tag_end + 1..];
+    // 已有 alignment 时在上面加上 wrapText, 否则 alignment 作为第一个子元素 (在 protection、extLst 之前)
+    let children = match children.find("<alignment") {
+        Some(pos) => format!(r#"{} wrapText="1"{}"#, &children[..pos + "<alignment".len()], &children[pos + "<alignment".len()..]),
+        None => format!(r#"<alignment wrapText="1"/>{children}"#),
+    };
+    Some(format!("{open_tag}>{children}"))
+}
+
+/// 在 styles.xml 的 `<cellXfs>` 末尾追加单元格格式, 并更新 count
+pub(crate) fn append_cell_xfs_to_styles(styles_xml: &str, xfs: &[String]) -> String {
+    let Some(start) = styles_xml.find("<cellXfs") else {
+        return styles_xml.to_string();
+    };
+    let Some(close) = styles_xml[start..].find("</cellXfs>").map(|pos| start + pos) else {
+        return styles_xml.to_string();
+    };
+    let tag_end = styles_xml[start..].find('>').map_or(close, |pos| start + pos + 1);
+    let count = cell_xfs(styles_xml).len() + xfs.len();
+    format!(
+        r#"{}<cellXfs count="{count}">{}{}{}"#,
+        &styles_xml[..start],
+        &styles_xml[tag_end..close],
+        xfs.concat(),
+        &styles_xml[close..],
+    )
+}
+
+/// 在单元格格式 `<xf>` 上设置数字格式 numFmtId
+pub(crate) fn num_fmt_xf(xf: &str, num_fmt_id: u32) -> String {
+    let xf = set_start_tag_attribute(xf, "numFmtId", &num_fmt_id.to_string());
+    set_start_tag_attribute(&xf, "applyNumberFormat", "1")
+}
+
+/// styles.xml 中自定义数字格式可用的下一个 id, 自定义格式的 id 从 164 开始
+pub(crate) fn next_num_fmt_id(styles_xml: &str) -> u32 {
+    let Some(start) = styles_xml.find("<numFmts") else {
+        return 164;
+    };
+    let end = styles_xml[start..].find("</numFmts>").map_or(styles_xml.len(), |pos| start + pos);
+    styles_xml[start..end].split("numFmtId=\"").skip(1)
+        .filter_map(|rest| rest.split('"').next()?.parse::<u32>().ok())
+        .fold(164, |next, id| next.max(id + 1))
+}
+
+/// 在 styles.xml 的 `<numFmts>` 末尾追加自定义数字格式 (id, formatCode), 并更新 count
+/// 
+/// 没有 `<numFmts>` 时创建为 styleSheet 的第一个子元素
+pub(crate) fn append_num_fmts_to_styles(styles_xml: &str, num_fmts: &[(u32, String)]) -> String {
+    let new_fmts: String = num_fmts.iter()
+        .map(|(id, code)| format!(r#"<numFmt numFmtId="{id}" formatCode="{}"/>"#, quick_xml::escape::escape(code.as_str())))
+        .collect();
+    if let Some(start) = styles_xml.find("<numFmts")
+        && let Some(close) = styles_xml[start..].find("</numFmts>").map(|pos| start + pos) {
+            let tag_end = styles_xml[start..].find('>').map_or(close, |pos| start + pos + 1);
+            let count = styles_xml[tag_end..close].matches("<numFmt ").count() + num_fmts.len();
+            return format!(
+                r#"{}<numFmts count="{count}">{}{new_fmts}{}"#,
+                &styles_xml[..start],
+                &styles_xml[tag_end..close],
+                &styles_xml[close..],
+            );
+        }
+    let Some(tag_end) = styles_xml.find("<styleSheet").and_then(|start| styles_xml[start..].find('>').map(|pos| start + pos + 1)) else {
+        return styles_xml.to_string();
+    };
+    format!(
+        r#"{}<numFmts count="{}">{new_fmts}</numFmts>{}"#,
+        &styles_xml[..tag_end],
+        num_fmts.len(),
+        &styles_xml[tag_end..],
+    )
+}
+
+/// 修改 sheet XML 中单元格的样式序号 s
+/// 
+/// `restyle` 传入单元格 (列号, 行号) 和原来的样式序号 (没有时为 0), 返回新的样式序号, 返回 None 时单元格保持不变
+pub(crate) fn restyle_cells(
+    sheet_xml: &str,
+    mut restyle: impl FnMut((u32, u32), u32) -> Option<u32>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
     
-    // 验证 mergeCells 标签被移除
-    assert!(!result_xml.contains("<mergeCells"));
-    assert!(!result_xml.contains("</mergeCells>"));
-    assert!(!result_xml.contains("mergeCell"));
+    let mut restyled = |e: &BytesStart| -> Result<Option<BytesStart<'static>>, Box<dyn std::error::Error>> {
+        let cell_ref = e.try_get_attribute("r")?.map(|a| a.unescape_value()).transpose()?;
+        let Some(cell) = cell_ref.and_then(|r| parse_cell_ref(&r)) else {
+            return Ok(None);
+        };
+        let style = e.try_get_attribute("s")?.and_then(|a| a.unescape_value().ok()?.parse().ok()).unwrap_or(0);
+        let Some(new_style) = restyle(cell, style) else {
+            return Ok(None);
+        };
+        let mut tag = BytesStart::new("c");
+        for attr in e.attributes() {
+            let attr = attr?;
+            if attr.key.as_ref() != b"s" {
+                tag.push_attribute(attr);
+            }
+        }
+        tag.push_attribute(("s", new_style.to_string().as_str()));
+        Ok(Some(tag))
+    };
     
-    // 验证其他内容保持不变
-    assert!(result_xml.contains("<sheetData>"));
-    assert!(result_xml.contains("<pageMargins"));
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) if e.name().as_ref() == b"c" && let Some(tag) = restyled(e)? => {
+                writer.write_event(Event::Start(tag))?;
+            }
+            Event::Empty(ref e) if e.name().as_ref() == b"c" && let Some(tag) = restyled(e)? => {
+                writer.write_event(Event::Empty(tag))?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
     
-    // 测试包含 hyperlinks 的情况
-    let input_with_hyperlinks = r#"<?xml version="1.0"?>
-<worksheet>
-  <sheetData>
-    <row r="1">
-      <c r="A1"><v>Link</v></c>
-    </row>
-  </sheetData>
-  <hyperlinks>
-    <hyperlink ref="A1" location="Sheet2!A1" display="Go to Sheet2"/>
-    <hyperlink ref="B2" location="https://example.com" display="Example"/>
-  </hyperlinks>
-</worksheet>"#;
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 让 sheet XML 中指定的单元格 (列号, 行号) 自动换行
+/// 
+/// `restyle` 传入原来的样式序号 s (没有时为 0) 返回自动换行的样式序号,
+/// 单元格中的 `<t>` 加上 `xml:space="preserve"`, 保留换行符和首尾空白
+pub(crate) fn wrap_text_cells(
+    sheet_xml: &str,
+    cells: &std::collections::HashSet<(u32, u32)>,
+    mut restyle: impl FnMut(u32) -> u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if cells.is_empty() {
+        return Ok(sheet_xml.to_string());
+    }
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
     
-    let (result_xml2, merge_refs2, hyperlinks2) = extract_and_remove_merge_cells_and_hyperlinks(input_with_hyperlinks).unwrap();
-    assert_eq!(merge_refs2.len(), 0);
-    assert_eq!(hyperlinks2.len(), 2);
-    assert_eq!(hyperlinks2[0].ref_cell, "A1");
-    assert_eq!(hyperlinks2[0].location, "Sheet2!A1");
-    assert_eq!(hyperlinks2[0].display, "Go to Sheet2");
-    assert_eq!(hyperlinks2[1].ref_cell, "B2");
-    assert_eq!(hyperlinks2[1].location, "https://example.com");
-    assert!(!result_xml2.contains("<hyperlinks"));
+    let restyled = |e: &BytesStart, restyle: &mut dyn FnMut(u32) -> u32| -> Result<Option<BytesStart<'static>>, Box<dyn std::error::Error>> {
+        let cell_ref = e.try_get_attribute("r")?.map(|a| a.unescape_value()).transpose()?;
+        let Some(cell) = cell_ref.and_then(|r| parse_cell_ref(&r)) else {
+            return Ok(None);
+        };
+        if !cells.contains(&cell) {
+            return Ok(None);
+        }
+        let style = e.try_get_attribute("s")?.and_then(|a| a.unescape_value().ok()?.parse().ok()).unwrap_or(0);
+        let new_style = restyle(style).to_string();
+        let mut tag = BytesStart::new("c");
+        for attr in e.attributes() {
+            let attr = attr?;
+            if attr.key.as_ref() != b"s" {
+                tag.push_attribute(attr);
+            }
+        }
+        tag.push_attribute(("s", new_style.as_str()));
+        Ok(Some(tag))
+    };
     
-    // 测试同时包含 mergeCells 和 hyperlinks 的情况
-    let input_both = r#"<?xml version="1.0"?>
-<worksheet>
-  <sheetData/>
-  <mergeCells count="1">
-    <mergeCell ref="A1:B2"/>
-  </mergeCells>
-  <hyperlinks>
-    <hyperlink ref="C3" location="Sheet1!A1" display="Link"/>
-  </hyperlinks>
-</worksheet>"#;
+    // 是否位于需要换行的单元格中
+    let mut in_cell = false;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) if e.name().as_ref() == b"c" && let Some(tag) = restyled(e, &mut restyle)? => {
+                in_cell = true;
+                writer.write_event(Event::Start(tag))?;
+            }
+            Event::End(ref e) if e.name().as_ref() == b"c" => {
+                in_cell = false;
+                writer.write_event(Event::End(e.borrow()))?;
+            }
+            Event::Start(ref e) if in_cell && e.name().as_ref() == b"t" && e.try_get_attribute("xml:space")?.is_none() => {
+                let mut tag = e.to_owned();
+                tag.push_attribute(("xml:space", "preserve"));
+                writer.write_event(Event::Start(tag))?;
+            }
+            Event::Empty(ref e) if e.name().as_ref() == b"c" && let Some(tag) = restyled(e, &mut restyle)? => {
+                writer.write_event(Event::Empty(tag))?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
     
-    let (result_xml3, merge_refs3, hyperlinks3) = extract_and_remove_merge_cells_and_hyperlinks(input_both).unwrap();
-    assert_eq!(merge_refs3.len(), 1);
-    assert_eq!(hyperlinks3.len(), 1);
-    assert!(!result_xml3.contains("mergeCells"));
-    assert!(!result_xml3.contains("hyperlinks"));
-  }
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
 
-  
+/// 把 sheet XML 中指定单元格 (列号, 行号) 的内容替换为文本, 单元格变为 inlineStr, 原有的值和公式被删除
+pub(crate) fn set_cell_texts(
+    sheet_xml: &str,
+    texts: &std::collections::HashMap<(u32, u32), String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if texts.is_empty() {
+        return Ok(sheet_xml.to_string());
+    }
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    
+    // 需要替换的单元格的文本, 不是需要替换的单元格时返回 None
+    let cell_text = |e: &BytesStart| -> Result<Option<&String>, Box<dyn std::error::Error>> {
+        let cell_ref = e.try_get_attribute("r")?.map(|a| a.unescape_value()).transpose()?;
+        Ok(cell_ref.and_then(|r| parse_cell_ref(&r)).and_then(|cell| texts.get(&cell)))
+    };
+    let write_cell = |writer: &mut Writer<Cursor<Vec<u8>>>, e: &BytesStart, text: &str| -> Result<(), Box<dyn std::error::Error>> {
+        let mut tag = BytesStart::new("c");
+        for attr in e.attributes() {
+            let attr = attr?;
+            if attr.key.as_ref() != b"t" {
+                tag.push_attribute(attr);
+            }
+        }
+        tag.push_attribute(("t", "inlineStr"));
+        writer.write_event(Event::Start(tag))?;
+        writer.write_event(Event::Start(BytesStart::new("is")))?;
+        writer.write_event(Event::Start(BytesStart::new("t")))?;
+        writer.write_event(Event::Text(BytesText::new(text)))?;
+        writer.write_event(Event::End(BytesEnd::new("t")))?;
+        writer.write_event(Event::End(BytesEnd::new("is")))?;
+        writer.write_event(Event::End(BytesEnd::new("c")))?;
+        Ok(())
+    };
+    
+    // 正在跳过被替换的单元格的原有内容
+    let mut skipping = false;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        if skipping {
+            if let Event::End(e) = &event && e.name().as_ref() == b"c" {
+                skipping = false;
+            }
+            continue;
+        }
+        match event {
+            Event::Start(ref e) if e.name().as_ref() == b"c" && let Some(text) = cell_text(e)? => {
+                write_cell(&mut writer, e, text)?;
+                skipping = true;
+            }
+            Event::Empty(ref e) if e.name().as_ref() == b"c" && let Some(text) = cell_text(e)? => {
+                write_cell(&mut writer, e, text)?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
 }
 
-/// 删除包含指定标记的整个 row 行
-/// 
-/// 这个函数用于删除 XLSX sheet 中包含特定 UUID 标记的整行。
-/// 通常配合 `{{removeRow}}` helper 使用，用于清理 `{{#each}}{{else}}` 产生的空白行。
+/// 在 sheet XML 中插入手动分页符 `<rowBreaks>` / `<colBreaks>`
 /// 
-/// # 参数
-/// * `xml_content` - sheet.xml 的 XML 内容
-/// * `remove_key` - 要查找和删除的行标记
-/// * `to_number_key` - 数字类型转换标记
-/// * `to_formula_key` - 公式类型转换标记
-/// * `merge_cells` - 需要合并的单元格范围列表
-/// ```
-pub(crate) fn post_process_xml(
-    xml_content: &str, 
-    remove_key: Option<&str>,
-    to_number_key: Option<&str>,
-    to_formula_key: Option<&str>,
-    merge_cells: Option<&[String]>,
-    hyperlinks: Option<&[HyperlinkInfo]>,
+/// `row_breaks` 中的 id 表示在该行之后分页, `col_breaks` 中的 id 表示在该列之后分页 (均从 1 开始)。
+/// 按照 schema 顺序放在 headerFooter 之后、drawing、tableParts、extLst 等元素之前,
+/// 模板中已有的分页符会与新增的合并, 不会重复输出两个 rowBreaks / colBreaks 元素。
+pub(crate) fn insert_page_breaks_into_sheet(
+    sheet_xml: &str,
+    row_breaks: &[u32],
+    col_breaks: &[u32],
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let mut reader = Reader::from_str(xml_content);
-    let mut writer = Writer::new(Cursor::new(Vec::new()));
-    let mut buf = Vec::new();
+    // schema 中位于 colBreaks 之后的 worksheet 子元素, rowBreaks 之后还要再加上 colBreaks
+    const AFTER_COL_BREAKS: &[&[u8]] = &[
+        b"customProperties", b"cellWatches", b"ignoredErrors", b"smartTags", b"drawing",
+        b"legacyDrawing", b"legacyDrawingHF", b"drawingHF", b"picture", b"oleObjects",
+        b"controls", b"webPublishItems", b"tableParts", b"extLst",
+    ];
     
-    let mut current_row_content = String::new();
-    let mut in_row = false;
-    let mut row_depth = 0;
-    let mut hyperlinks_inserted = false; // 标记是否已插入 hyperlinks
+    if row_breaks.is_empty() && col_breaks.is_empty() {
+        return Ok(sheet_xml.to_string());
+    }
+    
+    // (元素名, 分页位置, brk 的 max 属性, 是否已输出)
+    // max 为分页线延伸到的最后一列 (rowBreaks) / 最后一行 (colBreaks), 从 0 开始
+    let mut breaks = [
+        (&b"rowBreaks"[..], row_breaks.to_vec(), 16383, row_breaks.is_empty()),
+        (&b"colBreaks"[..], col_breaks.to_vec(), 1048575, col_breaks.is_empty()),
+    ];
+    let breaks_xml = |tag: &[u8], ids: &mut Vec<u32>, max: u32| {
+        ids.sort_unstable();
+        ids.dedup();
+        let tag = String::from_utf8_lossy(tag);
+        let brks: String = ids.iter().map(|id| format!(r#"<brk id="{id}" max="{max}" man="1"/>"#)).collect();
+        format!(r#"<{tag} count="{0}" manualBreakCount="{0}">{brks}</{tag}>"#, ids.len())
+    };
+    
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    // 正在合并的模板中已有的 rowBreaks / colBreaks
+    let mut absorbing: Option<usize> = None;
     
     loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                if e.name().as_ref() == b"row" {
-                    in_row = true;
-                    row_depth += 1;
-                    current_row_content.clear();
-                    current_row_content.push_str(&format!("<{}", String::from_utf8_lossy(e.name().as_ref())));
-                    // 添加属性
-                    for attr in e.attributes().flatten() {
-                        current_row_content.push_str(&format!(" {}=\"{}\"", 
-                            String::from_utf8_lossy(attr.key.as_ref()),
-                            String::from_utf8_lossy(&attr.value)));
-                    }
-                    current_row_content.push('>');
-                } else if in_row {
-                    current_row_content.push_str(&format!("<{}", String::from_utf8_lossy(e.name().as_ref())));
-                    // 添加属性
-                    for attr in e.attributes().flatten() {
-                        current_row_content.push_str(&format!(" {}=\"{}\"", 
-                            String::from_utf8_lossy(attr.key.as_ref()),
-                            String::from_utf8_lossy(&attr.value)));
-                    }
-                    current_row_content.push('>');
-                } else {
-                    // 检查是否是 pageMargins 开始标签，如果是则先插入 hyperlinks
-                    if e.name().as_ref() == b"pageMargins" && !hyperlinks_inserted {
-                        hyperlinks_inserted = true;
-                        
-                        // 先插入 hyperlinks（如果有）
-                        if let Some(links) = hyperlinks {
-                            if !links.is_empty() {
-                                use uuid::Uuid;
-                                
-                                // 生成 hyperlinks XML
-                                let hyperlinks_xml = links.iter()
-                                    .map(|link| {
-                                        let uuid = Uuid::new_v4();
-                                        let uuid_str = format!("{{{}}}", uuid.to_string().to_uppercase());
-                                        
-                                        // 构造超链接标签
-                                        if link.display.is_empty() {
-                                            format!(
-                                                "<hyperlink ref=\"{}\" location=\"{}\" xr:uid=\"{}\"/>",
-                                                link.ref_cell, link.location, uuid_str
-                                            )
-                                        } else {
-                                            format!(
-                                                "<hyperlink ref=\"{}\" location=\"{}\" display=\"{}\" xr:uid=\"{}\"/>",
-                                                link.ref_cell, link.location, link.display, uuid_str
-                                            )
-                                        }
-                                    })
-                                    .collect::<Vec<_>>()
-                                    .join("");
-                                
-                                // 写入 hyperlinks（带命名空间属性）
-                                let hyperlinks_tag = format!(
-                                    "<hyperlinks xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" xmlns:xr=\"http://schemas.microsoft.com/office/spreadsheetml/2014/revision\">{}</hyperlinks>",
-                                    hyperlinks_xml
-                                );
-                                writer.get_mut().write_all(hyperlinks_tag.as_bytes())?;
-                            }
-                        }
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        
+        if let Some(index) = absorbing {
+            match &event {
+                Event::Empty(e) | Event::Start(e) if e.name().as_ref() == b"brk" => {
+                    if let Some(id) = e.try_get_attribute("id")?.and_then(|a| std::str::from_utf8(&a.value).ok()?.parse().ok()) {
+                        breaks[index].1.push(id);
                     }
-                    
-                    writer.write_event(Event::Start(e.clone()))?;
                 }
+                Event::End(e) if e.name().as_ref() == breaks[index].0 => absorbing = None,
+                _ => {}
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"row" && in_row {
-                    row_depth -= 1;
-                    if row_depth == 0 {
-                        current_row_content.push_str(&format!("</{}>", String::from_utf8_lossy(e.name().as_ref())));
-                        
-                        // 检查当前行是否需要删除
-                        let should_remove = if let Some(key) = remove_key {
-                            current_row_content.contains(key)
-                        } else {
-                            false
+            continue;
+        }
+        
+        let child_name = match &event {
+            Event::Start(e) | Event::Empty(e) if depth == 1 => Some(e.name().as_ref().to_vec()),
+            Event::End(e) if depth == 1 && e.name().as_ref() == b"worksheet" => Some(Vec::new()),
+            _ => None,
+        };
+        if let Some(name) = child_name {
+            // 模板中已有且需要合并的分页符, 先收集其中的 brk, 在后面统一输出
+            if let Some(index) = breaks.iter().position(|(tag, _, _, done)| !done && name == *tag) {
+                if matches!(event, Event::Start(_)) {
+                    absorbing = Some(index);
+                }
+                continue;
+            }
+            for (index, (tag, ids, max, done)) in breaks.iter_mut().enumerate() {
+                let is_after = name.is_empty()
+                    || AFTER_COL_BREAKS.contains(&name.as_slice())
+                    || (index == 0 && name == b"colBreaks");
+                if !*done && is_after {
+                    writer.get_mut().write_all(breaks_xml(tag, ids, *max).as_bytes())?;
+                    *done = true;
+                }
+            }
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 设置 sheet XML 中第一个 `<sheetView>` 的属性, 例如 `showGridLines="0"`
+/// 
+/// 已有的同名属性会被替换; 没有 `<sheetViews>` 时按 schema 顺序创建在 sheetPr / dimension 之后
+pub(crate) fn set_sheet_view_attributes(
+    sheet_xml: &str,
+    attributes: &[(&str, &str)],
+) -> Result<String, Box<dyn std::error::Error>> {
+    // schema 中位于 sheetViews 之前的 worksheet 子元素
+    const BEFORE_SHEET_VIEWS: &[&[u8]] = &[b"sheetPr", b"dimension"];
+    
+    if attributes.is_empty() {
+        return Ok(sheet_xml.to_string());
+    }
+    
+    // 在原有的 sheetView 上替换或追加属性, 其余属性保持原来的顺序
+    let with_attributes = |e: &BytesStart| -> Result<BytesStart<'static>, Box<dyn std::error::Error>> {
+        let mut tag = BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+        let mut pending: Vec<(&str, &str)> = attributes.to_vec();
+        for attr in e.attributes() {
+            let attr = attr?;
+            match pending.iter().position(|(key, _)| key.as_bytes() == attr.key.as_ref()) {
+                Some(index) => {
+                    let (key, value) = pending.remove(index);
+                    tag.push_attribute((key, value));
+                }
+                None => tag.push_attribute(attr),
+            }
+        }
+        for (key, value) in pending {
+            tag.push_attribute((key, value));
+        }
+        Ok(tag)
+    };
+    let new_sheet_view = || {
+        let mut tag = BytesStart::new("sheetView");
+        tag.extend_attributes(attributes.iter().copied());
+        tag.push_attribute(("workbookViewId", "0"));
+        tag
+    };
+    
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut done = false;
+    let mut in_sheet_views = false;
+    
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if in_sheet_views && !done && depth == 2 && e.name().as_ref() == b"sheetView" => {
+                done = true;
+                let tag = with_attributes(e)?;
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                    writer.write_event(Event::Start(tag))?;
+                } else {
+                    writer.write_event(Event::Empty(tag))?;
+                }
+                continue;
+            }
+            Event::Start(ref e) if depth == 1 && !done && e.name().as_ref() == b"sheetViews" => {
+                in_sheet_views = true;
+            }
+            Event::End(ref e) if depth == 2 && in_sheet_views && e.name().as_ref() == b"sheetViews" => {
+                in_sheet_views = false;
+                // sheetViews 中没有 sheetView, 补上一个
+                if !done {
+                    done = true;
+                    writer.write_event(Event::Empty(new_sheet_view()))?;
+                }
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if depth == 1 && !done && BEFORE_SHEET_VIEWS.contains(&e.name().as_ref()) => {}
+            // 空的 <sheetViews/> 替换为新建的 sheetViews
+            Event::Empty(ref e) if depth == 1 && !done && e.name().as_ref() == b"sheetViews" => {
+                done = true;
+                writer.write_event(Event::Start(BytesStart::new("sheetViews")))?;
+                writer.write_event(Event::Empty(new_sheet_view()))?;
+                writer.write_event(Event::End(BytesEnd::new("sheetViews")))?;
+                continue;
+            }
+            // 没有 sheetViews, 在第一个位于它之后的元素 (或 worksheet 结束标签) 之前创建
+            Event::Start(_) | Event::Empty(_) | Event::End(_) if depth == 1 && !done => {
+                done = true;
+                writer.write_event(Event::Start(BytesStart::new("sheetViews")))?;
+                writer.write_event(Event::Empty(new_sheet_view()))?;
+                writer.write_event(Event::End(BytesEnd::new("sheetViews")))?;
+            }
+            _ => {}
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 按渲染后的单元格文本长度估算列宽, 写入 `<cols>`
+/// 
+/// 只是近似值: Excel 真正的自动调整列宽依赖字体度量, 这里按字符数估算, 多字节字符 (中日韩文字等) 按 2 个字符宽度计算。
+/// - 列宽 = 最长文本的字符数 + 2, 不超过 `max_width`, 不超过默认列宽 8.43 的列保持默认
+/// - 模板中已经用 `<col>` 设置过的列保持不变
+/// - 跨列合并的单元格不参与计算
+pub(crate) fn auto_fit_columns(sheet_xml: &str, max_width: f64) -> Result<String, Box<dyn std::error::Error>> {
+    const DEFAULT_COL_WIDTH: f64 = 8.43;
+    
+    // 第一遍: 收集每个单元格的文本宽度、已有的 <col> 范围和跨列的合并单元格
+    let mut cells: Vec<(u32, u32, usize)> = Vec::new();
+    let mut existing_cols: Vec<(u32, u32)> = Vec::new();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    let mut reader = Reader::from_str(sheet_xml);
+    // 当前单元格 (列号, 行号, 文本)
+    let mut cell: Option<(u32, u32, String)> = None;
+    let mut in_text = false;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match &event {
+            Event::Start(e) if e.name().as_ref() == b"c" => {
+                let cell_ref = e.try_get_attribute("r")?.map(|a| a.unescape_value()).transpose()?;
+                cell = cell_ref.and_then(|r| parse_cell_ref(&r)).map(|(col, row)| (col, row, String::new()));
+            }
+            Event::Start(e) if cell.is_some() && matches!(e.name().as_ref(), b"t" | b"v") => in_text = true,
+            Event::End(e) if matches!(e.name().as_ref(), b"t" | b"v") => in_text = false,
+            Event::Text(text) if in_text && let Some((_, _, content)) = cell.as_mut() => content.push_str(&text.decode()?),
+            Event::GeneralRef(e) if in_text && let Some((_, _, content)) = cell.as_mut() => {
+                let name = std::str::from_utf8(e)?;
+                match quick_xml::escape::resolve_predefined_entity(name) {
+                    Some(ch) => content.push_str(ch),
+                    None => content.extend(e.resolve_char_ref()?),
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"c" => {
+                if let Some((col, row, content)) = cell.take() {
+                    // 多行文本按最长的一行计算
+                    let width = content.lines()
+                        .map(|line| line.chars().map(|c| if c.len_utf8() > 2 { 2 } else { 1 }).sum::<usize>())
+                        .max()
+                        .unwrap_or(0);
+                    cells.push((col, row, width));
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"col" => {
+                let attr = |name: &str| -> Option<u32> { e.try_get_attribute(name).ok()??.unescape_value().ok()?.parse().ok() };
+                if let (Some(min), Some(max)) = (attr("min"), attr("max")) {
+                    existing_cols.push((min, max));
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"mergeCell" => {
+                let range = e.try_get_attribute("ref")?.map(|a| a.unescape_value()).transpose()?.unwrap_or_default();
+                if let Some((start, end)) = range.split_once(':')
+                  && let (Some(start), Some(end)) = (parse_cell_ref(start), parse_cell_ref(end))
+                  && start.0 != end.0 {
+                    merged.push(start);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    
+    let mut widths: std::collections::BTreeMap<u32, usize> = std::collections::BTreeMap::new();
+    for (col, row, width) in cells {
+        if merged.contains(&(col, row)) || existing_cols.iter().any(|(min, max)| (*min..=*max).contains(&col)) {
+            continue;
+        }
+        let max = widths.entry(col).or_default();
+        *max = (*max).max(width);
+    }
+    let new_cols: Vec<(u32, String)> = widths.into_iter()
+        .map(|(col, chars)| (col, (chars as f64 + 2.0).min(max_width)))
+        .filter(|(_, width)| *width > DEFAULT_COL_WIDTH)
+        .map(|(col, width)| (col, format!(r#"<col min="{col}" max="{col}" width="{}" customWidth="1"/>"#, (width * 100.0).round() / 100.0)))
+        .collect();
+    if new_cols.is_empty() {
+        return Ok(sheet_xml.to_string());
+    }
+    
+    // 第二遍: 新的 <col> 与已有的按 min 排序合并到 <cols> 中, 没有 <cols> 时创建在 sheetData 之前
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut pending = new_cols.into_iter().peekable();
+    let mut in_cols = false;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match &event {
+            Event::Start(e) if depth == 1 && e.name().as_ref() == b"cols" => in_cols = true,
+            Event::Start(e) | Event::Empty(e) if in_cols && e.name().as_ref() == b"col" => {
+                let min = e.try_get_attribute("min")?.and_then(|a| a.unescape_value().ok()?.parse::<u32>().ok()).unwrap_or(0);
+                while let Some((_, col_xml)) = pending.next_if(|(col, _)| *col < min) {
+                    writer.get_mut().write_all(col_xml.as_bytes())?;
+                }
+            }
+            Event::End(e) if in_cols && e.name().as_ref() == b"cols" => {
+                in_cols = false;
+                for (_, col_xml) in pending.by_ref() {
+                    writer.get_mut().write_all(col_xml.as_bytes())?;
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if depth == 1 && e.name().as_ref() == b"sheetData" && pending.peek().is_some() => {
+                writer.get_mut().write_all(b"<cols>")?;
+                for (_, col_xml) in pending.by_ref() {
+                    writer.get_mut().write_all(col_xml.as_bytes())?;
+                }
+                writer.get_mut().write_all(b"</cols>")?;
+            }
+            _ => {}
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 工作簿级别的定义名称, 对应 workbook.xml 中的 `<definedName>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DefinedName {
+    pub name: String,                 // 名称，如 "_xlnm.Print_Area"
+    pub local_sheet_id: Option<usize>, // 作用域工作表在 <sheets> 中的序号（从 0 开始），None 为全局
+    pub value: String,                // 引用，如 "'Sheet1'!$A$1:$G$50"
+}
+
+/// 把定义名称写入 workbook.xml 的 `<definedNames>` 中
+/// 
+/// - 已有同名（且作用域相同）的定义名称会被替换
+/// - 没有 `<definedNames>` 时按 schema 顺序创建在 `<sheets>` 之后
+pub(crate) fn insert_defined_names_into_workbook(
+    workbook_xml: &str,
+    defined_names: &[DefinedName],
+) -> Result<String, Box<dyn std::error::Error>> {
+    // schema 中位于 definedNames 之后的 workbook 子元素
+    const AFTER_DEFINED_NAMES: &[&[u8]] = &[
+        b"calcPr", b"oleSize", b"customWorkbookViews", b"pivotCaches", b"smartTagPr",
+        b"smartTagTypes", b"webPublishing", b"fileRecoveryPr", b"webPublishObjects", b"extLst",
+    ];
+    
+    if defined_names.is_empty() {
+        return Ok(workbook_xml.to_string());
+    }
+    
+    let write_defined_names = |writer: &mut Writer<Cursor<Vec<u8>>>| -> Result<(), Box<dyn std::error::Error>> {
+        for defined_name in defined_names {
+            let mut start = BytesStart::new("definedName");
+            start.push_attribute(("name", defined_name.name.as_str()));
+            if let Some(local_sheet_id) = defined_name.local_sheet_id {
+                start.push_attribute(("localSheetId", local_sheet_id.to_string().as_str()));
+            }
+            writer.write_event(Event::Start(start))?;
+            writer.write_event(Event::Text(BytesText::from_escaped(quick_xml::escape::partial_escape(&defined_name.value))))?;
+            writer.write_event(Event::End(BytesEnd::new("definedName")))?;
+        }
+        Ok(())
+    };
+    let write_defined_names_block = |writer: &mut Writer<Cursor<Vec<u8>>>| -> Result<(), Box<dyn std::error::Error>> {
+        writer.write_event(Event::Start(BytesStart::new("definedNames")))?;
+        write_defined_names(writer)?;
+        writer.write_event(Event::End(BytesEnd::new("definedNames")))?;
+        Ok(())
+    };
+    let is_replaced = |e: &BytesStart| -> Result<bool, Box<dyn std::error::Error>> {
+        let name = e.try_get_attribute("name")?.map(|a| a.unescape_value()).transpose()?;
+        let local_sheet_id = e.try_get_attribute("localSheetId")?
+            .and_then(|a| std::str::from_utf8(&a.value).ok()?.parse::<usize>().ok());
+        Ok(defined_names.iter().any(|d| Some(d.name.as_str()) == name.as_deref() && d.local_sheet_id == local_sheet_id))
+    };
+    
+    let mut reader = Reader::from_str(workbook_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut done = false;
+    // 正在跳过的被替换的 definedName
+    let mut skipping = false;
+    
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        
+        if skipping {
+            if let Event::End(e) = &event
+                && e.name().as_ref() == b"definedName" {
+                    skipping = false;
+                }
+            continue;
+        }
+        
+        match &event {
+            // 模板中已有 definedNames, 新的定义名称追加在末尾
+            Event::Start(e) if depth == 2 && e.name().as_ref() == b"definedName" && is_replaced(e)? => {
+                skipping = true;
+                continue;
+            }
+            Event::Empty(e) if depth == 2 && e.name().as_ref() == b"definedName" && is_replaced(e)? => continue,
+            Event::End(e) if depth == 2 && e.name().as_ref() == b"definedNames" => {
+                write_defined_names(&mut writer)?;
+                done = true;
+            }
+            Event::Empty(e) if depth == 1 && e.name().as_ref() == b"definedNames" => {
+                write_defined_names_block(&mut writer)?;
+                done = true;
+                continue;
+            }
+            Event::Start(e) | Event::Empty(e) if depth == 1 && !done && AFTER_DEFINED_NAMES.contains(&e.name().as_ref()) => {
+                write_defined_names_block(&mut writer)?;
+                done = true;
+            }
+            Event::End(e) if depth == 1 && !done && e.name().as_ref() == b"workbook" => {
+                write_defined_names_block(&mut writer)?;
+                done = true;
+            }
+            _ => {}
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 设置 sheet XML 中 `<sheetFormatPr>` 的属性, 例如行分组的 `outlineLevelRow="2"`
+/// 
+/// 已有的同名属性会被替换; 没有 `<sheetFormatPr>` 时按 schema 顺序创建在 sheetViews 之后
+pub(crate) fn set_sheet_format_attribute(
+    sheet_xml: &str,
+    name: &str,
+    value: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // schema 中位于 sheetFormatPr 之前的 worksheet 子元素
+    const BEFORE_SHEET_FORMAT_PR: &[&[u8]] = &[b"sheetPr", b"dimension", b"sheetViews"];
+    
+    let mut reader = Reader::from_str(sheet_xml);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut depth = 0;
+    let mut done = false;
+    
+    loop {
+        let event = reader.read_event().map_err(|e| format!("XML Error at position {}: {:?}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if depth == 1 && !done && e.name().as_ref() == b"sheetFormatPr" => {
+                done = true;
+                let mut tag = BytesStart::new("sheetFormatPr");
+                let mut replaced = false;
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    if attr.key.as_ref() == name.as_bytes() {
+                        replaced = true;
+                        tag.push_attribute((name, value));
+                    } else {
+                        tag.push_attribute(attr);
+                    }
+                }
+                if !replaced {
+                    tag.push_attribute((name, value));
+                }
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                    writer.write_event(Event::Start(tag))?;
+                } else {
+                    writer.write_event(Event::Empty(tag))?;
+                }
+                continue;
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if depth == 1 && !done && BEFORE_SHEET_FORMAT_PR.contains(&e.name().as_ref()) => {}
+            // 没有 sheetFormatPr, 在第一个位于它之后的元素 (或 worksheet 结束标签) 之前创建, defaultRowHeight 是必需属性
+            Event::Start(_) | Event::Empty(_) | Event::End(_) if depth == 1 && !done => {
+                done = true;
+                let mut tag = BytesStart::new("sheetFormatPr");
+                tag.push_attribute(("defaultRowHeight", "15"));
+                tag.push_attribute((name, value));
+                writer.write_event(Event::Empty(tag))?;
+            }
+            _ => {}
+        }
+        
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    
+    let result = writer.into_inner().into_inner();
+    Ok(String::from_utf8(result)?)
+}
+
+/// 设置 XML 片段中第一个开始标签 (如缓冲行的 row、styles 中的 xf) 的属性, 已有同名属性时替换其值
+fn set_start_tag_attribute(xml: &str, name: &str, value: &str) -> String {
+    let Some(mut tag_end) = xml.find('>') else {
+        return xml.to_string();
+    };
+    if xml[..tag_end].ends_with('/') {
+        tag_end -= 1;
+    }
+    let start_tag = &xml[..tag_end];
+    let attr_prefix = format!(" {name}=\"");
+    let start_tag = match start_tag.find(&attr_prefix) {
+        Some(pos) => {
+            let value_start = pos + attr_prefix.len();
+            let value_end = start_tag[value_start..].find('"').map_or(start_tag.len(), |i| value_start + i);
+            format!("{}{value}{}", &start_tag[..value_start], &start_tag[value_end..])
+        }
+        None => format!("{start_tag}{attr_prefix}{value}\""),
+    };
+    format!("{start_tag}{}", &xml[tag_end..])
+}
+
+/// 删除包含指定标记的整个 row 行
+/// 
+/// 这个函数用于删除 XLSX sheet 中包含特定 UUID 标记的整行。
+/// 通常配合 `{{removeRow}}` helper 使用，用于清理 `{{#each}}{{else}}` 产生的空白行。
+/// 
+/// # 参数
+/// * `xml_content` - sheet.xml 的 XML 内容
+/// * `row_keys` - 删除行、隐藏行、行分组级别标记
+/// * `cell_keys` - 数字、公式、布尔、错误类型转换标记
+/// * `merge_cells` - 需要合并的单元格范围列表
+/// * `warnings` - 收集处理过程中的警告, 例如单元格 XML 解析失败改用简单方法提取
+/// ```
+pub(crate) fn post_process_xml(
+    xml_content: &str, 
+    row_keys: &RowMarkerKeys,
+    cell_keys: &CellMarkerKeys,
+    merge_cells: Option<&[String]>,
+    hyperlinks: Option<&[HyperlinkInfo]>,
+    warnings: &mut Vec<String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_str(xml_content);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut buf = Vec::new();
+    
+    let mut current_row_content = String::new();
+    let mut in_row = false;
+    let mut row_depth = 0;
+    let mut hyperlinks_inserted = false; // 标记是否已插入 hyperlinks
+    let mut used_range: Option<(u32, u32, u32, u32)> = None; // 实际输出的单元格范围 (最小列, 最小行, 最大列, 最大行)
+    
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                if e.name().as_ref() == b"row" {
+                    in_row = true;
+                    row_depth += 1;
+                    current_row_content.clear();
+                    current_row_content.push_str(&format!("<{}", String::from_utf8_lossy(e.name().as_ref())));
+                    // 添加属性
+                    for attr in e.attributes().flatten() {
+                        current_row_content.push_str(&format!(" {}=\"{}\"", 
+                            String::from_utf8_lossy(attr.key.as_ref()),
+                            String::from_utf8_lossy(&attr.value)));
+                    }
+                    current_row_content.push('>');
+                } else if in_row {
+                    current_row_content.push_str(&format!("<{}", String::from_utf8_lossy(e.name().as_ref())));
+                    // 添加属性
+                    for attr in e.attributes().flatten() {
+                        current_row_content.push_str(&format!(" {}=\"{}\"", 
+                            String::from_utf8_lossy(attr.key.as_ref()),
+                            String::from_utf8_lossy(&attr.value)));
+                    }
+                    current_row_content.push('>');
+                } else {
+                    // 检查是否是 pageMargins 开始标签，如果是则先插入 hyperlinks
+                    if e.name().as_ref() == b"pageMargins" && !hyperlinks_inserted {
+                        hyperlinks_inserted = true;
+                        
+                        // 先插入 hyperlinks（如果有）
+                        if let Some(links) = hyperlinks
+                            && !links.is_empty() {
+                                writer.get_mut().write_all(hyperlinks_xml(links).as_bytes())?;
+                            }
+                    }
+                    
+                    writer.write_event(Event::Start(e.clone()))?;
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if e.name().as_ref() == b"row" && in_row {
+                    row_depth -= 1;
+                    if row_depth == 0 {
+                        current_row_content.push_str(&format!("</{}>", String::from_utf8_lossy(e.name().as_ref())));
+                        
+                        // 检查当前行是否需要删除
+                        let should_remove = if let Some(key) = row_keys.remove {
+                            current_row_content.contains(key)
+                        } else {
+                            false
                         };
                         
+                        // 包含隐藏标记的行保留, 删除标记后在 row 标签上设置 hidden="1"
+                        if let Some(key) = row_keys.hide
+                            && current_row_content.contains(key) {
+                                current_row_content = set_start_tag_attribute(&current_row_content.replace(key, ""), "hidden", "1");
+                            }
+                        
+                        // 行分组级别: 标记之间是 "级别 [collapsed] [hidden]", 写入 row 标签后删除标记
+                        if let Some(key) = row_keys.outline {
+                            let mut outline = None;
+                            while let Some(marker_start) = current_row_content.find(key) {
+                                let value_start = marker_start + key.len();
+                                let Some(value_len) = current_row_content[value_start..].find(key) else {
+                                    break;
+                                };
+                                outline = Some(current_row_content[value_start..value_start + value_len].to_string());
+                                current_row_content.replace_range(marker_start..value_start + value_len + key.len(), "");
+                            }
+                            if let Some(outline) = outline {
+                                let mut parts = outline.split_whitespace();
+                                if let Some(level) = parts.next() {
+                                    current_row_content = set_start_tag_attribute(&current_row_content, "outlineLevel", level);
+                                }
+                                for flag in parts {
+                                    current_row_content = set_start_tag_attribute(&current_row_content, flag, "1");
+                                }
+                            }
+                        }
+                        
                         if !should_remove {
-                            // 处理数字类型转换
-                            let mut processed_content = if let Some(num_key) = to_number_key {
-                                process_number_cells(&current_row_content, num_key)?
+                            // 处理单元格样式, 需要在类型转换之前删除样式标记
+                            let mut processed_content = if let Some(style_key) = cell_keys.style {
+                                process_style_cells(&current_row_content, style_key)
                             } else {
                                 current_row_content.clone()
                             };
                             
+                            // 处理数字类型转换
+                            if let Some(num_key) = cell_keys.number {
+                                processed_content = process_number_cells(&processed_content, num_key, warnings)?;
+                            }
+                            
                             // 处理公式类型转换
-                            if let Some(formula_key) = to_formula_key {
-                                processed_content = process_formula_cells(&processed_content, formula_key)?;
+                            if let Some(formula_key) = cell_keys.formula {
+                                processed_content = process_formula_cells(&processed_content, formula_key, warnings)?;
+                            }
+                            
+                            // 处理布尔类型转换
+                            if let Some(boolean_key) = cell_keys.boolean {
+                                processed_content = process_boolean_cells(&processed_content, boolean_key, warnings)?;
+                            }
+                            
+                            // 处理错误类型转换
+                            if let Some(error_key) = cell_keys.error {
+                                processed_content = process_error_cells(&processed_content, error_key, warnings)?;
                             }
                             
                             // 写入处理后的行
                             writer.get_mut().write_all(processed_content.as_bytes())?;
+                            extend_used_range(&processed_content, &mut used_range);
                         }
                         // 如果包含删除标记，则跳过整行
                         
@@ -1472,8 +3489,8 @@ pub(crate) fn post_process_xml(
                         writer.write_event(Event::End(e.clone()))?;
                         
                         // 如果有合并单元格信息，插入 mergeCells 标签
-                        if let Some(refs) = merge_cells {
-                            if !refs.is_empty() {
+                        if let Some(refs) = merge_cells
+                            && !refs.is_empty() {
                                 // 去重处理
                                 let mut unique_refs: Vec<String> = refs.to_vec();
                                 unique_refs.sort();
@@ -1492,7 +3509,6 @@ pub(crate) fn post_process_xml(
                                 // 写入 mergeCells
                                 writer.get_mut().write_all(merge_cells_xml.as_bytes())?;
                             }
-                        }
                     } else {
                         writer.write_event(Event::End(e.clone()))?;
                     }
@@ -1521,40 +3537,10 @@ pub(crate) fn post_process_xml(
                         hyperlinks_inserted = true;
                         
                         // 先插入 hyperlinks（如果有）
-                        if let Some(links) = hyperlinks {
-                            if !links.is_empty() {
-                                use uuid::Uuid;
-                                
-                                // 生成 hyperlinks XML
-                                let hyperlinks_xml = links.iter()
-                                    .map(|link| {
-                                        let uuid = Uuid::new_v4();
-                                        let uuid_str = format!("{{{}}}", uuid.to_string().to_uppercase());
-                                        
-                                        // 构造超链接标签
-                                        if link.display.is_empty() {
-                                            format!(
-                                                "<hyperlink ref=\"{}\" location=\"{}\" xr:uid=\"{}\"/>",
-                                                link.ref_cell, link.location, uuid_str
-                                            )
-                                        } else {
-                                            format!(
-                                                "<hyperlink ref=\"{}\" location=\"{}\" display=\"{}\" xr:uid=\"{}\"/>",
-                                                link.ref_cell, link.location, link.display, uuid_str
-                                            )
-                                        }
-                                    })
-                                    .collect::<Vec<_>>()
-                                    .join("");
-                                
-                                // 写入 hyperlinks（带命名空间属性）
-                                let hyperlinks_tag = format!(
-                                    "<hyperlinks xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" xmlns:xr=\"http://schemas.microsoft.com/office/spreadsheetml/2014/revision\">{}</hyperlinks>",
-                                    hyperlinks_xml
-                                );
-                                writer.get_mut().write_all(hyperlinks_tag.as_bytes())?;
+                        if let Some(links) = hyperlinks
+                            && !links.is_empty() {
+                                writer.get_mut().write_all(hyperlinks_xml(links).as_bytes())?;
                             }
-                        }
                     }
                     
                     writer.write_event(Event::Empty(e.clone()))?;
@@ -1565,6 +3551,14 @@ pub(crate) fn post_process_xml(
                     writer.write_event(Event::Comment(e.clone()))?;
                 }
             }
+            Ok(Event::GeneralRef(ref e)) => {
+                // 实体引用 (如 &amp;) 是单独的事件, 行内的需要原样保留
+                if in_row {
+                    current_row_content.push_str(&format!("&{};", std::str::from_utf8(e)?));
+                } else {
+                    writer.write_event(Event::GeneralRef(e.clone()))?;
+                }
+            }
             Ok(Event::Eof) => break,
             Ok(event) => {
                 if !in_row {
@@ -1576,20 +3570,219 @@ pub(crate) fn post_process_xml(
         buf.clear();
     }
     
-    let result = writer.into_inner().into_inner();
-    Ok(String::from_utf8(result)?)
+    let result = String::from_utf8(writer.into_inner().into_inner())?;
+    Ok(rewrite_dimension(result, used_range))
+}
+
+/// 生成 `<hyperlinks>` 元素, 外部链接通过 r:id 引用 sheet rels 中的关系, 其他链接使用 location
+fn hyperlinks_xml(links: &[HyperlinkInfo]) -> String {
+    use uuid::Uuid;
+    
+    let links_xml: String = links.iter()
+        .map(|link| {
+            let uuid_str = format!("{{{}}}", Uuid::new_v4().to_string().to_uppercase());
+            let target = match &link.rel_id {
+                Some(rel_id) => format!("r:id=\"{rel_id}\""),
+                None => format!("location=\"{}\"", link.location),
+            };
+            if link.display.is_empty() {
+                format!("<hyperlink ref=\"{}\" {target} xr:uid=\"{uuid_str}\"/>", link.ref_cell)
+            } else {
+                format!("<hyperlink ref=\"{}\" {target} display=\"{}\" xr:uid=\"{uuid_str}\"/>", link.ref_cell, link.display)
+            }
+        })
+        .collect();
+    
+    // 带命名空间属性, r:id 和 xr:uid 需要
+    format!(
+        "<hyperlinks xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" xmlns:xr=\"http://schemas.microsoft.com/office/spreadsheetml/2014/revision\">{links_xml}</hyperlinks>"
+    )
+}
+
+/// 用行内容中所有 <c r="..."> 的单元格引用扩展已使用的范围
+fn extend_used_range(row_content: &str, used_range: &mut Option<(u32, u32, u32, u32)>) {
+    let mut rest = row_content;
+    while let Some(start) = rest.find("<c ") {
+        rest = &rest[start + 3..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        let Some(r_start) = tag.find(" r=\"").map(|i| i + 4).or_else(|| tag.starts_with("r=\"").then_some(3)) else {
+            continue;
+        };
+        let Some((col, row)) = tag[r_start..].split('"').next().and_then(parse_cell_ref) else {
+            continue;
+        };
+        *used_range = Some(match *used_range {
+            Some((min_col, min_row, max_col, max_row)) => (min_col.min(col), min_row.min(row), max_col.max(col), max_row.max(row)),
+            None => (col, row, col, row),
+        });
+    }
+}
+
+/// 按实际输出的单元格重写 <dimension ref="..."/>, 没有 dimension 元素时不添加
+/// 没有任何单元格时与 Excel 一致使用 A1
+fn rewrite_dimension(xml_content: String, used_range: Option<(u32, u32, u32, u32)>) -> String {
+    let Some(start) = xml_content.find("<dimension ") else {
+        return xml_content;
+    };
+    let Some(len) = xml_content[start..].find("/>").map(|end| end + 2) else {
+        return xml_content;
+    };
+    let range = match used_range {
+        Some((min_col, min_row, max_col, max_row)) if (min_col, min_row) == (max_col, max_row) => {
+            format!("{}{}", to_column_name("A", min_col - 1), min_row)
+        }
+        Some((min_col, min_row, max_col, max_row)) => format!(
+            "{}{}:{}{}",
+            to_column_name("A", min_col - 1), min_row,
+            to_column_name("A", max_col - 1), max_row,
+        ),
+        None => "A1".to_string(),
+    };
+    let mut result = xml_content;
+    result.replace_range(start..start + len, &format!("<dimension ref=\"{range}\"/>"));
+    result
 }
 
 /// 处理行内容中的数字类型单元格
 /// 将包含 to_number_key 标记的单元格转换为数字格式
 /// 提取 <is> 标签内的文本，转换为 <v>数值</v> 格式
-fn process_number_cells(row_content: &str, to_number_key: &str) -> Result<String, Box<dyn std::error::Error>> {
-    // 如果不包含数字标记，直接返回
-    if !row_content.contains(to_number_key) {
-        return Ok(row_content.to_string());
+fn process_number_cells(row_content: &str, to_number_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
+    process_value_cells(row_content, to_number_key, None, number_cell_value, warnings)
+}
+
+/// 处理行内容中的布尔类型单元格
+/// 将包含 to_boolean_key 标记的单元格转换为 <c t="b"><v>1</v></c> 格式
+fn process_boolean_cells(row_content: &str, to_boolean_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
+    process_value_cells(row_content, to_boolean_key, Some("b"), boolean_cell_value, warnings)
+}
+
+/// 处理行内容中的单元格样式
+/// 包含 `{style_key}5{style_key}` 标记的单元格设置 s="5" 并删除标记, 同一个单元格有多个标记时以最后一个为准
+/// 删除标记后没有内容的单元格输出为只有样式的空单元格 <c r="A1" s="5"/>
+fn process_style_cells(row_content: &str, style_key: &str) -> String {
+    if !row_content.contains(style_key) {
+        return row_content.to_string();
     }
     
-    // 使用 XML 解析器来准确处理
+    let mut output = String::with_capacity(row_content.len());
+    let mut rest = row_content;
+    while let Some(start) = [rest.find("<c "), rest.find("<c>")].into_iter().flatten().min() {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(tag_len) = rest.find('>').map(|pos| pos + 1) else {
+            break;
+        };
+        let start_tag = &rest[..tag_len];
+        if start_tag.ends_with("/>") {
+            output.push_str(start_tag);
+            rest = &rest[tag_len..];
+            continue;
+        }
+        let Some(cell_len) = rest.find("</c>").map(|pos| pos + 4) else {
+            break;
+        };
+        let mut content = rest[tag_len..cell_len - 4].to_string();
+        rest = &rest[cell_len..];
+        
+        // 取出所有样式标记
+        let mut style = None;
+        while let Some(marker_start) = content.find(style_key) {
+            let value_start = marker_start + style_key.len();
+            let Some(value_len) = content[value_start..].find(style_key) else {
+                break;
+            };
+            style = Some(content[value_start..value_start + value_len].to_string());
+            content.replace_range(marker_start..value_start + value_len + style_key.len(), "");
+        }
+        let Some(style) = style else {
+            output.push_str(start_tag);
+            output.push_str(&content);
+            output.push_str("</c>");
+            continue;
+        };
+        
+        // 替换或添加 s 属性
+        let attrs = start_tag[2..].trim_end_matches('>');
+        let mut new_tag = String::from("<c");
+        let mut has_style = false;
+        for (key, value) in parse_tag_attributes(attrs) {
+            let value = if key == "s" { has_style = true; style.as_str() } else { value };
+            new_tag.push_str(&format!(" {key}=\"{value}\""));
+        }
+        if !has_style {
+            // s 放在 r 之后、t 之前, 与 Excel 的属性顺序一致
+            match new_tag.find(" t=\"") {
+                Some(pos) => new_tag.insert_str(pos, &format!(" s=\"{style}\"")),
+                None => new_tag.push_str(&format!(" s=\"{style}\"")),
+            }
+        }
+        
+        // 删除标记后没有任何文本和值的单元格 (例如 <is><t></t></is>) 输出为空单元格
+        let has_text = content.split('<').any(|part| part.split_once('>').is_some_and(|(_, text)| !text.trim().is_empty()));
+        if !has_text && !content.contains("<f") && !content.contains("<v") {
+            let tag_without_type = parse_tag_attributes(new_tag[2..].trim())
+                .into_iter()
+                .filter(|(key, _)| *key != "t")
+                .map(|(key, value)| format!(" {key}=\"{value}\""))
+                .collect::<String>();
+            output.push_str(&format!("<c{tag_without_type}/>"));
+        } else {
+            output.push_str(&new_tag);
+            output.push('>');
+            output.push_str(&content);
+            output.push_str("</c>");
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// 解析开始标签中的属性, 输入为标签名之后的部分, 如 ` r="A1" t="inlineStr"`
+/// 只用于 post_process_xml 重新构建的标签, 这些标签的属性值都使用双引号
+fn parse_tag_attributes(attrs: &str) -> Vec<(&str, &str)> {
+    attrs.split('"').collect::<Vec<_>>().chunks_exact(2)
+        .map(|pair| (pair[0].trim().trim_end_matches('='), pair[1]))
+        .collect()
+}
+
+/// 处理行内容中的错误类型单元格
+/// 将包含 to_error_key 标记的单元格转换为 <c t="e"><v>#N/A</v></c> 格式
+fn process_error_cells(row_content: &str, to_error_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
+    process_value_cells(row_content, to_error_key, Some("e"), error_cell_value, warnings)
+}
+
+/// 数字单元格的值: 单元格中除数字外还有其他文本时 (如 "{{num x}} kg") 不是合法的数值, 输出 0
+fn number_cell_value(text: &str) -> String {
+    let text = text.trim();
+    if text.parse::<f64>().is_ok_and(|n| n.is_finite()) { text.to_string() } else { "0".to_string() }
+}
+
+/// 布尔单元格的值: 只有 1 / 0
+fn boolean_cell_value(text: &str) -> String {
+    if text.trim() == "1" { "1".to_string() } else { "0".to_string() }
+}
+
+/// 错误单元格的值: 单元格中除错误值外还有其他文本时不是合法的错误值, 输出 #VALUE!
+fn error_cell_value(text: &str) -> String {
+    let text = text.trim();
+    if EXCEL_ERROR_LITERALS.contains(&text) { text.to_string() } else { "#VALUE!".to_string() }
+}
+
+/// 将包含 key 标记的单元格转换为 <c t="cell_type"><v>值</v></c>
+/// cell_type 为 None 时移除 t 属性 (数字单元格), value 用于把提取的文本转换为合法的 <v> 内容
+fn process_value_cells(
+    row_content: &str,
+    marker_key: &str,
+    cell_type: Option<&str>,
+    value: fn(&str) -> String,
+    warnings: &mut Vec<String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // 如果不包含标记，直接返回
+    if !row_content.contains(marker_key) {
+        return Ok(row_content.to_string());
+    }
+    
+    // 使用 XML 解析器来准确处理
     let mut reader = Reader::from_str(row_content);
     let mut output = String::new();
     let mut buf = Vec::new();
@@ -1639,21 +3832,24 @@ fn process_number_cells(row_content: &str, to_number_key: &str) -> Result<String
                     cell_content.push_str("</c>");
                     
                     // 检查内容是否包含数字标记
-                    if cell_content.contains(to_number_key) {
+                    if cell_content.contains(marker_key) {
                         // 提取 <is> 标签内的所有 <t> 文本
-                        let text_value = extract_text_from_is(&cell_content, to_number_key)?;
+                        let text_value = extract_text_from_is(&cell_content, marker_key, warnings)?;
                         
-                        // 重新构建单元格，移除 t 属性
+                        // 重新构建单元格，移除原有的 t 属性
                         output.push_str("<c");
                         for (key, value) in &cell_attrs {
                             if key != "t" {  // 移除 t 属性
                                 output.push_str(&format!(" {}=\"{}\"", key, value));
                             }
                         }
+                        if let Some(cell_type) = cell_type {
+                            output.push_str(&format!(" t=\"{}\"", cell_type));
+                        }
                         output.push('>');
                         
-                        // 添加 <v> 标签包含提取的数值
-                        output.push_str(&format!("<v>{}</v>", text_value));
+                        // 添加 <v> 标签包含提取的值
+                        output.push_str(&format!("<v>{}</v>", value(&text_value)));
                         output.push_str("</c>");
                     } else {
                         // 非数字单元格，原样输出
@@ -1710,6 +3906,15 @@ fn process_number_cells(row_content: &str, to_number_key: &str) -> Result<String
                     output.push_str("/>");
                 }
             }
+            Ok(Event::GeneralRef(ref e)) => {
+                // 实体引用 (如 &amp;) 是单独的事件, 原样保留
+                let entity = format!("&{};", std::str::from_utf8(e)?);
+                if in_cell {
+                    cell_content.push_str(&entity);
+                } else {
+                    output.push_str(&entity);
+                }
+            }
             Ok(Event::Eof) => break,
             Ok(_) => {
                 // 其他事件跳过
@@ -1723,13 +3928,13 @@ fn process_number_cells(row_content: &str, to_number_key: &str) -> Result<String
 }
 
 /// 从 <is> 标签内提取所有 <t> 标签的文本内容，并移除数字标记
-fn extract_text_from_is(cell_content: &str, to_number_key: &str) -> Result<String, Box<dyn std::error::Error>> {
+fn extract_text_from_is(cell_content: &str, to_number_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
     // cell_content 包含完整的单元格内容，可能格式不完整
     // 我们需要找到 <is> 标签并提取其中的文本
     
     // 首先尝试找到 <is> 标签的位置
-    if let Some(is_start) = cell_content.find("<is") {
-        if let Some(is_end) = cell_content[is_start..].find("</is>") {
+    if let Some(is_start) = cell_content.find("<is")
+        && let Some(is_end) = cell_content[is_start..].find("</is>") {
             // 提取 <is>...</is> 部分
             let is_content = &cell_content[is_start..is_start + is_end + 5]; // +5 for "</is>"
             
@@ -1758,11 +3963,16 @@ fn extract_text_from_is(cell_content: &str, to_number_key: &str) -> Result<Strin
                             result.push_str(text);
                         }
                     }
+                    Ok(Event::GeneralRef(ref e)) => {
+                        if in_t {
+                            result.push_str(&format!("&{};", std::str::from_utf8(e)?));
+                        }
+                    }
                     Ok(Event::Eof) => break,
                     Ok(_) => {}
                     Err(e) => {
                         // 如果解析失败，尝试简单的字符串搜索
-                        eprintln!("警告: XML 解析失败，使用简单方法提取: {:?}", e);
+                        warnings.push(format!("XML parsing failed, falling back to simple text extraction: {e:?}"));
                         return extract_text_simple(is_content, to_number_key);
                     }
                 }
@@ -1773,7 +3983,6 @@ fn extract_text_from_is(cell_content: &str, to_number_key: &str) -> Result<Strin
             let result = result.replace(to_number_key, "");
             return Ok(result);
         }
-    }
     
     // 如果没有找到 <is> 标签，尝试简单方法
     extract_text_simple(cell_content, to_number_key)
@@ -1804,7 +4013,7 @@ fn extract_text_simple(content: &str, to_number_key: &str) -> Result<String, Box
 /// 处理行内容中的公式类型单元格
 /// 将包含 to_formula_key 标记的单元格转换为公式格式
 /// 提取 <is> 标签内的文本，转换为 <f>公式</f> 格式
-fn process_formula_cells(row_content: &str, to_formula_key: &str) -> Result<String, Box<dyn std::error::Error>> {
+fn process_formula_cells(row_content: &str, to_formula_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
     // 如果不包含公式标记，直接返回
     if !row_content.contains(to_formula_key) {
         return Ok(row_content.to_string());
@@ -1861,8 +4070,12 @@ fn process_formula_cells(row_content: &str, to_formula_key: &str) -> Result<Stri
                     
                     // 检查内容是否包含公式标记
                     if cell_content.contains(to_formula_key) {
-                        // 提取 <is> 或 <f> 标签内的公式文本
-                        let formula_text = extract_formula_from_cell(&cell_content, to_formula_key)?;
+                        // 提取 <is> 或 <f> 标签内的公式文本, 以及可选的缓存值
+                        let formula_text = extract_formula_from_cell(&cell_content, to_formula_key, warnings)?;
+                        let (formula_text, cached_value) = match formula_text.split_once(to_formula_key) {
+                            Some((formula, cached)) => (formula.to_string(), Some(cached.to_string())),
+                            None => (formula_text, None),
+                        };
                         
                         // 重新构建单元格，移除 t 属性
                         output.push_str("<c");
@@ -1871,10 +4084,18 @@ fn process_formula_cells(row_content: &str, to_formula_key: &str) -> Result<Stri
                                 output.push_str(&format!(" {}=\"{}\"", key, value));
                             }
                         }
+                        // 非数字的缓存值是字符串类型的公式结果
+                        if let Some(cached) = &cached_value
+                            && cached.trim().parse::<f64>().is_err() {
+                                output.push_str(" t=\"str\"");
+                            }
                         output.push('>');
                         
-                        // 添加 <f> 标签包含公式
+                        // 添加 <f> 标签包含公式, 有缓存值时再添加 <v>
                         output.push_str(&format!("<f>{}</f>", formula_text));
+                        if let Some(cached) = cached_value {
+                            output.push_str(&format!("<v>{}</v>", cached));
+                        }
                         output.push_str("</c>");
                     } else {
                         // 非公式单元格，原样输出
@@ -1931,6 +4152,15 @@ fn process_formula_cells(row_content: &str, to_formula_key: &str) -> Result<Stri
                     output.push_str("/>");
                 }
             }
+            Ok(Event::GeneralRef(ref e)) => {
+                // 实体引用 (如 &amp;) 是单独的事件, 原样保留
+                let entity = format!("&{};", std::str::from_utf8(e)?);
+                if in_cell {
+                    cell_content.push_str(&entity);
+                } else {
+                    output.push_str(&entity);
+                }
+            }
             Ok(Event::Eof) => break,
             Ok(_) => {
                 // 其他事件跳过
@@ -1945,10 +4175,11 @@ fn process_formula_cells(row_content: &str, to_formula_key: &str) -> Result<Stri
 
 /// 从单元格内容中提取公式文本
 /// 可能来自 <is><t>标记公式</t></is> 或 <f>标记公式</f> 标签
-fn extract_formula_from_cell(cell_content: &str, to_formula_key: &str) -> Result<String, Box<dyn std::error::Error>> {
+/// 带缓存值时格式为 标记公式标记缓存值, 返回的文本中保留第二个标记
+fn extract_formula_from_cell(cell_content: &str, to_formula_key: &str, warnings: &mut Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
     // 首先尝试从 <is> 标签提取（类似数字的处理）
-    if let Some(is_start) = cell_content.find("<is") {
-        if let Some(is_end) = cell_content[is_start..].find("</is>") {
+    if let Some(is_start) = cell_content.find("<is")
+        && let Some(is_end) = cell_content[is_start..].find("</is>") {
             let is_content = &cell_content[is_start..is_start + is_end + 5];
             
             let mut reader = Reader::from_str(is_content);
@@ -1975,29 +4206,33 @@ fn extract_formula_from_cell(cell_content: &str, to_formula_key: &str) -> Result
                             result.push_str(text);
                         }
                     }
+                    Ok(Event::GeneralRef(ref e)) => {
+                        if in_t {
+                            result.push_str(&format!("&{};", std::str::from_utf8(e)?));
+                        }
+                    }
                     Ok(Event::Eof) => break,
                     Ok(_) => {}
                     Err(e) => {
-                        eprintln!("警告: XML 解析失败，使用简单方法提取: {:?}", e);
+                        warnings.push(format!("XML parsing failed, falling back to simple formula extraction: {e:?}"));
                         return extract_formula_simple(cell_content, to_formula_key);
                     }
                 }
                 buf.clear();
             }
             
-            let result = result.replace(to_formula_key, "");
+            // 只移除开头的标记, 第二个标记用于分隔公式和缓存值
+            let result = result.replacen(to_formula_key, "", 1);
             return Ok(result);
         }
-    }
     
     // 尝试从 <f> 标签提取
-    if let Some(f_start) = cell_content.find("<f>") {
-        if let Some(f_end) = cell_content[f_start + 3..].find("</f>") {
+    if let Some(f_start) = cell_content.find("<f>")
+        && let Some(f_end) = cell_content[f_start + 3..].find("</f>") {
             let formula = &cell_content[f_start + 3..f_start + 3 + f_end];
-            let formula = formula.replace(to_formula_key, "");
+            let formula = formula.replacen(to_formula_key, "", 1);
             return Ok(formula);
         }
-    }
     
     // 备用简单方法
     extract_formula_simple(cell_content, to_formula_key)
@@ -2008,11 +4243,10 @@ fn extract_formula_simple(content: &str, to_formula_key: &str) -> Result<String,
     let mut result = String::new();
     
     // 首先尝试从 <f> 标签提取
-    if let Some(f_start) = content.find("<f>") {
-        if let Some(f_end) = content[f_start + 3..].find("</f>") {
+    if let Some(f_start) = content.find("<f>")
+        && let Some(f_end) = content[f_start + 3..].find("</f>") {
             result = content[f_start + 3..f_start + 3 + f_end].to_string();
         }
-    }
     
     // 如果没有找到，从 <t> 标签提取
     if result.is_empty() {
@@ -2030,6 +4264,840 @@ fn extract_formula_simple(content: &str, to_formula_key: &str) -> Result<String,
     }
     
     // 移除公式标记
-    let result = result.replace(to_formula_key, "");
+    let result = result.replacen(to_formula_key, "", 1);
     Ok(result)
 }
+
+/// Excel 生成的部件使用的 XML 声明
+pub(crate) const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+/// 把 XML 部件的声明统一为 [`XML_DECLARATION`], `standalone` 为 false 时省略 standalone 属性
+/// 
+/// 没有声明时在开头添加, 声明了 UTF-8 以外编码的部件保持不变; 不需要修改时返回 None
+pub(crate) fn set_xml_declaration(xml: &str, standalone: bool) -> Option<String> {
+    let declaration = if standalone { XML_DECLARATION } else { r#"<?xml version="1.0" encoding="UTF-8"?>"# };
+    let (bom, content) = match xml.strip_prefix('\u{feff}') {
+        Some(content) => ("\u{feff}", content),
+        None => ("", xml),
+    };
+    let rest = match content.strip_prefix("<?xml") {
+        Some(after) => {
+            let end = after.find("?>")?;
+            let encoding = after[..end].split_once("encoding=").and_then(|(_, value)| {
+                let quote = value.chars().next()?;
+                value[1..].split(quote).next()
+            });
+            if encoding.is_some_and(|encoding| !encoding.eq_ignore_ascii_case("UTF-8")) {
+                return None;
+            }
+            &after[end + 2..]
+        }
+        None => content,
+    };
+    if content.len() - rest.len() == declaration.len() && content.starts_with(declaration) {
+        return None;
+    }
+    // 原来没有声明时另起一行, 与生成的部件一致
+    let separator = if content.len() == rest.len() { "\n" } else { "" };
+    Some(format!("{bom}{declaration}{separator}{rest}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  #[test]
+  fn test_excel_column_name() {
+    assert_eq!(to_column_name("A", 0), "A");
+    assert_eq!(to_column_name("A", 1), "B");
+    assert_eq!(to_column_name("Z", 1), "AA");
+    assert_eq!(to_column_name("AA", 1), "AB");
+    assert_eq!(to_column_name("AZ", 1), "BA");
+    assert_eq!(to_column_name("ZZ", 1), "AAA");
+    assert_eq!(to_column_name("AAA", 26), "ABA");
+  }
+  
+  #[test]
+  fn test_replace_shared_string_si_with_handlebars() {
+    // 测试包含 {{#each 且已经在 r 标签内的情况 - 应该保持原样
+    let input_with_each_in_r = r#"<si>
+  <r>
+    <t>a</t>
+  </r>
+  <r>
+    <rPr>
+      <sz val="11" />
+      <color rgb="FFFF0000" />
+    </rPr>
+    <t>{{#each projects}}</t>
+  </r>
+  <phoneticPr fontId="1" type="noConversion" />
+</si>"#;
+    
+    let result = replace_shared_string_si_with_handlebars(input_with_each_in_r).unwrap();
+    println!("输入包含 {{#each 且在 r 标签内的结果:");
+    println!("{}", result);
+    
+    // 因为已经在 r 标签内，所以应该保持原样
+    assert!(result.contains("{{#each projects}}"));
+    assert!(result.contains("<r>"));
+    assert!(result.contains("<t>{{#each projects}}</t>"));
+    
+    // 测试包含 {{#each 但不在 r 标签内的情况 - 应该被包裹
+    let input_with_each_not_in_r = r#"<si>
+  <t>{{#each projects}}</t>
+  <phoneticPr fontId="1" type="noConversion" />
+</si>"#;
+    
+    let result2 = replace_shared_string_si_with_handlebars(input_with_each_not_in_r).unwrap();
+    println!("输入包含 {{#each 且不在 r 标签内的结果:");
+    println!("{}", result2);
+    // 应该被包裹在 r 标签内
+    assert!(result2.contains("{{#each projects}}"));
+    assert!(result2.contains("<r><t>{{#each projects}}</t></r>"));
+    
+    // 测试包含 {{/each}} 且不在 r 标签内的情况
+    let input_with_end_each_not_in_r = r#"<si>
+  <t>{{/each}}</t>
+</si>"#;
+    
+    let result3 = replace_shared_string_si_with_handlebars(input_with_end_each_not_in_r).unwrap();
+    println!("输入包含 {{/each}} 且不在 r 标签内的结果:");
+    println!("{}", result3);
+    // 应该被包裹在 r 标签内
+    assert!(result3.contains("{{/each}}"));
+    assert!(result3.contains("<r><t>{{/each}}</t></r>"));
+    
+    // 测试不包含 each 的情况 - 应该保持原样
+    let input_normal = r#"<si>
+  <r>
+    <t>normal text</t>
+  </r>
+  <r>
+    <rPr>
+      <sz val="11" />
+    </rPr>
+    <t>{{name}}</t>
+  </r>
+</si>"#;
+    
+    let result4 = replace_shared_string_si_with_handlebars(input_normal).unwrap();
+    println!("输入不包含 each 的结果:");
+    println!("{}", result4);
+    // 应该保持 r 标签结构
+    assert!(result4.contains("<r>"));
+    assert!(result4.contains("{{name}}"));
+  }
+  
+  #[test]
+  fn test_excel_column_index() {
+    assert_eq!(to_column_index("A"), 1);
+    assert_eq!(to_column_index("E"), 5);
+    assert_eq!(to_column_index("Z"), 26);
+    assert_eq!(to_column_index("AA"), 27);
+    assert_eq!(to_column_index("AZ"), 52);
+    assert_eq!(to_column_index("BA"), 53);
+    assert_eq!(to_column_index("ZZ"), 702);
+    assert_eq!(to_column_index("AAA"), 703);
+  }
+  
+  #[test]
+  fn test_excel_column_name_and_index() {
+    let test_cases = vec![
+      ("A", 1),
+      ("Z", 26),
+      ("AA", 27),
+      ("AZ", 52),
+      ("BA", 53),
+      ("ZZ", 702),
+      ("AAA", 703),
+      ("AAB", 704),
+      ("ABC", 731),
+      ("ZZZ", 18278),
+    ];
+    
+    for (col_name, expected_index) in test_cases {
+      let index = to_column_index(col_name);
+      assert_eq!(index, expected_index, "Column name to index failed for {}", col_name);
+      
+      let name = to_column_name(col_name, 0);
+      assert_eq!(name, col_name, "Column name identity failed for {}", col_name);
+      
+      let name_plus_one = to_column_name(col_name, 1);
+      let index_plus_one = to_column_index(&name_plus_one);
+      assert_eq!(index_plus_one, expected_index + 1, "Column name to index failed for {} + 1", col_name);
+    }
+  }
+  
+  #[test]
+  fn test_excel_date_conversion() {
+    // 测试 2024-01-01 00:00:00 UTC
+    // Unix timestamp: 1704067200000 ms
+    let timestamp_2024 = 1704067200000i64;
+    let excel_date = timestamp_to_excel_date(timestamp_2024);
+    
+    // Excel 中 2024-01-01 的序列号应该是 45292 (=DATE(2024,1,1))
+    assert!((excel_date - 45292.0).abs() < 0.001, "Excel date for 2024-01-01 should be ~45292, got {}", excel_date);
+    
+    // 反向转换
+    if let Some(timestamp) = excel_date_to_timestamp(excel_date) {
+      // 允许一些精度损失（毫秒级别）
+      assert!((timestamp - timestamp_2024).abs() < 1000, "Timestamp mismatch: expected {}, got {}", timestamp_2024, timestamp);
+    } else {
+      panic!("Failed to convert Excel date back to timestamp");
+    }
+    
+    // 测试 1970-01-01 00:00:00 UTC (Unix epoch)
+    let timestamp_1970 = 0i64;
+    let excel_date_1970 = timestamp_to_excel_date(timestamp_1970);
+    
+    // Excel 中 1970-01-01 的序列号应该是 25569
+    assert!((excel_date_1970 - 25569.0).abs() < 0.001, "Excel date for 1970-01-01 should be ~25569, got {}", excel_date_1970);
+    
+    // 测试边界情况：1900-02-28 (序列号 59)
+    let excel_date_59 = 59.0;
+    assert!(excel_date_to_timestamp(excel_date_59).is_some());
+    
+    // 测试无效日期：1900-02-29 (序列号 60，不存在的日期)
+    let excel_date_60 = 60.0;
+    assert!(excel_date_to_timestamp(excel_date_60).is_none(), "Excel date 60 (1900-02-29) should be invalid");
+    
+    // 测试负数（无效）
+    assert!(excel_date_to_timestamp(-1.0).is_none(), "Negative Excel date should be invalid");
+  }
+  
+  #[test]
+  fn test_extract_and_remove_merge_cells_and_hyperlinks() {
+    // 测试包含完整 mergeCells 标签的情况
+    let input_with_merge = r#"<?xml version="1.0"?>
+<worksheet>
+  <sheetData>
+    <row r="1">
+      <c r="A1"><v>Test</v></c>
+    </row>
+  </sheetData>
+  <mergeCells count="2">
+    <mergeCell ref="A1:B1"/>
+    <mergeCell ref="C2:D3"/>
+  </mergeCells>
+  <pageMargins left="0.7" right="0.7"/>
+</worksheet>"#;
+    
+    let (result_xml, merge_refs, hyperlinks) = extract_and_remove_merge_cells_and_hyperlinks(input_with_merge).unwrap();
+    
+    // 验证合并范围被正确提取
+    assert_eq!(merge_refs.len(), 2);
+    assert_eq!(merge_refs[0], "A1:B1");
+    assert_eq!(merge_refs[1], "C2:D3");
+    assert_eq!(hyperlinks.len(), 0);
+    
+    // 验证 mergeCells 标签被移除
+    assert!(!result_xml.contains("<mergeCells"));
+    assert!(!result_xml.contains("</mergeCells>"));
+    assert!(!result_xml.contains("mergeCell"));
+    
+    // 验证其他内容保持不变
+    assert!(result_xml.contains("<sheetData>"));
+    assert!(result_xml.contains("<pageMargins"));
+    
+    // 测试包含 hyperlinks 的情况
+    let input_with_hyperlinks = r#"<?xml version="1.0"?>
+<worksheet>
+  <sheetData>
+    <row r="1">
+      <c r="A1"><v>Link</v></c>
+    </row>
+  </sheetData>
+  <hyperlinks>
+    <hyperlink ref="A1" location="Sheet2!A1" display="Go to Sheet2"/>
+    <hyperlink ref="B2" location="https://example.com" display="Example"/>
+  </hyperlinks>
+</worksheet>"#;
+    
+    let (result_xml2, merge_refs2, hyperlinks2) = extract_and_remove_merge_cells_and_hyperlinks(input_with_hyperlinks).unwrap();
+    assert_eq!(merge_refs2.len(), 0);
+    assert_eq!(hyperlinks2.len(), 2);
+    assert_eq!(hyperlinks2[0].ref_cell, "A1");
+    assert_eq!(hyperlinks2[0].location, "Sheet2!A1");
+    assert_eq!(hyperlinks2[0].display, "Go to Sheet2");
+    assert_eq!(hyperlinks2[1].ref_cell, "B2");
+    assert_eq!(hyperlinks2[1].location, "https://example.com");
+    assert!(!result_xml2.contains("<hyperlinks"));
+    
+    // 测试同时包含 mergeCells 和 hyperlinks 的情况
+    let input_both = r#"<?xml version="1.0"?>
+<worksheet>
+  <sheetData/>
+  <mergeCells count="1">
+    <mergeCell ref="A1:B2"/>
+  </mergeCells>
+  <hyperlinks>
+    <hyperlink ref="C3" location="Sheet1!A1" display="Link"/>
+  </hyperlinks>
+</worksheet>"#;
+    
+    let (result_xml3, merge_refs3, hyperlinks3) = extract_and_remove_merge_cells_and_hyperlinks(input_both).unwrap();
+    assert_eq!(merge_refs3.len(), 1);
+    assert_eq!(hyperlinks3.len(), 1);
+    assert!(!result_xml3.contains("mergeCells"));
+    assert!(!result_xml3.contains("hyperlinks"));
+  }
+  
+  #[test]
+  fn test_if_eq_and_unless_eq() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "status": "done", "count": 3 });
+    
+    // 相等: 渲染主块
+    let r = hbs.render_template(r#"{{#if_eq status "done"}}Y{{else}}N{{/if_eq}}"#, &data).unwrap();
+    assert_eq!(r, "Y");
+    // 不等: 渲染 else 块
+    let r = hbs.render_template(r#"{{#if_eq status "todo"}}Y{{else}}N{{/if_eq}}"#, &data).unwrap();
+    assert_eq!(r, "N");
+    // 数字比较与 eq 一致, 类型不同视为不等
+    let r = hbs.render_template(r#"{{#if_eq count 3}}Y{{else}}N{{/if_eq}}"#, &data).unwrap();
+    assert_eq!(r, "Y");
+    let r = hbs.render_template(r#"{{#if_eq count "3"}}Y{{else}}N{{/if_eq}}"#, &data).unwrap();
+    assert_eq!(r, "N");
+    
+    // unless_eq: 不等时渲染主块
+    let r = hbs.render_template(r#"{{#unless_eq status "todo"}}Y{{else}}N{{/unless_eq}}"#, &data).unwrap();
+    assert_eq!(r, "Y");
+    let r = hbs.render_template(r#"{{#unless_eq status "done"}}Y{{else}}N{{/unless_eq}}"#, &data).unwrap();
+    assert_eq!(r, "N");
+    // 没有 else 块时不输出
+    let r = hbs.render_template(r#"{{#unless_eq status "done"}}Y{{/unless_eq}}"#, &data).unwrap();
+    assert_eq!(r, "");
+  }
+  
+  #[test]
+  fn test_default_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "user": { "nickname": "", "name": "Alice", "age": 0 } });
+    
+    assert_eq!(hbs.render_template(r#"{{default user.nickname user.name "Anonymous"}}"#, &data).unwrap(), "Alice");
+    assert_eq!(hbs.render_template(r#"{{default user.missing user.nickname "Anonymous"}}"#, &data).unwrap(), "Anonymous");
+    // 数字 0 不是空值, 按字符串形式输出
+    assert_eq!(hbs.render_template(r#"{{default user.age "N/A"}}"#, &data).unwrap(), "0");
+    // 全部为空时不输出
+    assert_eq!(hbs.render_template(r#"{{default user.missing user.nickname}}"#, &data).unwrap(), "");
+    // 与普通变量一样会被转义
+    assert_eq!(hbs.render_template(r#"{{default user.missing "A&B"}}"#, &data).unwrap(), "A&amp;B");
+  }
+  
+  #[test]
+  fn test_json_helper_round_trip() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({
+      "obj": { "name": "A&B <co>", "tags": ["x", "y"], "nested": { "n": 1, "q": "\"quoted\"" } }
+    });
+    
+    // 经过合并、渲染后, XML 中的文本反转义后应与原 JSON 完全一致
+    let xml = merge_handlebars_in_xml("<t>{{json obj}}</t>".to_string()).unwrap();
+    let rendered = hbs.render_template(&xml, &data).unwrap();
+    let inner = rendered.strip_prefix("<t>").and_then(|s| s.strip_suffix("</t>")).unwrap();
+    let text = quick_xml::escape::unescape(inner).unwrap();
+    assert_eq!(text, serde_json::to_string(&data["obj"]).unwrap());
+    
+    let pretty = hbs.render_template("{{{jsonPretty obj.tags}}}", &data).unwrap();
+    assert_eq!(pretty, "[\n  \"x\",\n  \"y\"\n]");
+    assert_eq!(hbs.render_template("{{{stringify obj.tags}}}", &data).unwrap(), r#"["x","y"]"#);
+  }
+  
+  #[test]
+  fn test_get_or_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({
+      "names": { "1": "Alice", "2": null },
+      "list": ["x", "y"],
+      "id": 1,
+    });
+    
+    assert_eq!(hbs.render_template(r#"{{getOr names "1" "N/A"}}"#, &data).unwrap(), "Alice");
+    // 数字键按字符串查找
+    assert_eq!(hbs.render_template(r#"{{getOr names id "N/A"}}"#, &data).unwrap(), "Alice");
+    // null 和缺失的键都返回默认值
+    assert_eq!(hbs.render_template(r#"{{getOr names "2" "N/A"}}"#, &data).unwrap(), "N/A");
+    assert_eq!(hbs.render_template(r#"{{getOr names "3" "N/A"}}"#, &data).unwrap(), "N/A");
+    assert_eq!(hbs.render_template(r#"{{getOr list 1 "N/A"}}"#, &data).unwrap(), "y");
+    assert_eq!(hbs.render_template(r#"{{getOr list 5 "N/A"}}"#, &data).unwrap(), "N/A");
+    assert_eq!(hbs.render_template(r#"{{getOr missing "a" "N/A"}}"#, &data).unwrap(), "N/A");
+  }
+  
+  #[test]
+  fn test_insert_drawing_into_sheet() {
+    // 注释中的 </worksheet> 不应影响插入位置
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns:r="r"><sheetData/><!-- </worksheet> --><pageMargins left="0.7"/></worksheet>"#;
+    let result = insert_drawing_into_sheet(xml, "rId2").unwrap();
+    assert!(result.contains(r#"<!-- </worksheet> --><pageMargins left="0.7"/><drawing r:id="rId2"/></worksheet>"#), "{result}");
+    assert!(result.starts_with(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#));
+    
+    // drawing 需要放在 tableParts / extLst 之前
+    let xml = r#"<worksheet><sheetData/><tableParts count="0"/><extLst><ext/></extLst></worksheet>"#;
+    let result = insert_drawing_into_sheet(xml, "rId1").unwrap();
+    assert_eq!(result, r#"<worksheet><sheetData/><drawing r:id="rId1"/><tableParts count="0"/><extLst><ext/></extLst></worksheet>"#);
+    
+    // 已有 drawing 时返回错误, 通过 sheet_drawing_rid 取得已有的关系 ID
+    let xml = r#"<worksheet><sheetData/><drawing r:id="rId5"/></worksheet>"#;
+    let err = insert_drawing_into_sheet(xml, "rId1").unwrap_err().to_string();
+    assert_eq!(err, r#"worksheet already has <drawing r:id="rId5"/>, images must be added to that drawing"#);
+    assert_eq!(sheet_drawing_rid(xml).unwrap().as_deref(), Some("rId5"));
+    assert_eq!(sheet_drawing_rid(r#"<worksheet><sheetData><row r="1"/></sheetData><drawing xmlns:r2="r" r2:id="rId3"></drawing></worksheet>"#).unwrap().as_deref(), Some("rId3"));
+    assert_eq!(sheet_drawing_rid(r#"<worksheet><sheetData/></worksheet>"#).unwrap(), None);
+  }
+  
+  #[test]
+  fn test_parse_cell_ref() {
+    assert_eq!(parse_cell_ref("G3"), Some((7, 3)));
+    assert_eq!(parse_cell_ref("aa10"), Some((27, 10)));
+    assert_eq!(parse_cell_ref("$B$2"), Some((2, 2)));
+    assert_eq!(parse_cell_ref("B"), None);
+    assert_eq!(parse_cell_ref("3"), None);
+    assert_eq!(parse_cell_ref("A0"), None);
+    assert_eq!(parse_cell_ref("A1:B2"), None);
+  }
+  
+  #[test]
+  fn test_string_predicate_helpers() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "email": "bob@company.com", "code": 12345 });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render(r#"{{startsWith email "bob"}}"#), "true");
+    assert_eq!(render(r#"{{startsWith email "alice"}}"#), "false");
+    assert_eq!(render(r#"{{endsWith email "@company.com"}}"#), "true");
+    assert_eq!(render(r#"{{endsWith email ".org"}}"#), "false");
+    assert_eq!(render(r#"{{#if (contains email "@company")}}yes{{else}}no{{/if}}"#), "yes");
+    assert_eq!(render(r#"{{#if (contains email "@other")}}yes{{else}}no{{/if}}"#), "no");
+    // 数字先转为字符串再比较
+    assert_eq!(render(r#"{{startsWith code "123"}}"#), "true");
+    assert_eq!(render(r#"{{endsWith code 45}}"#), "true");
+    assert_eq!(render(r#"{{contains code "34"}}"#), "true");
+  }
+  
+  #[test]
+  fn test_trim_replace_split_helpers() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "name": "  张三  ", "phone": "138-0000-1111", "tags": "a,b,,c", "n": 1020 });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render("[{{trim name}}]"), "[张三]");
+    assert_eq!(render(r#"{{replace phone "-" ""}}"#), "13800001111");
+    assert_eq!(render(r#"{{replace n 0 9}}"#), "1929");
+    // 空的查找字符串不做替换
+    assert_eq!(render(r#"{{replace phone "" "x"}}"#), "138-0000-1111");
+    assert_eq!(render(r#"{{#each (split tags ",")}}<{{this}}>{{/each}}"#), "<a><b><><c>");
+    assert_eq!(render(r#"{{len (split tags ",")}}"#), "4");
+    assert_eq!(render(r#"{{len (split missing ",")}}"#), "0");
+  }
+  
+  #[test]
+  fn test_truncate_and_substring_helpers() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "name": "北京市朝阳区建国路", "short": "上海", "en": "hello world" });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render("{{truncate name 3}}"), "北京市…");
+    assert_eq!(render("{{truncate short 3}}"), "上海");
+    assert_eq!(render("{{truncate name 9}}"), "北京市朝阳区建国路");
+    assert_eq!(render("{{truncate en 5}}"), "hello…");
+    
+    assert_eq!(render("{{substring name 3 6}}"), "朝阳区");
+    assert_eq!(render("{{substring name 6}}"), "建国路");
+    // 越界时截取到末尾, start >= end 时为空
+    assert_eq!(render("{{substring short 1 10}}"), "海");
+    assert_eq!(render("[{{substring short 2 1}}]"), "[]");
+  }
+  
+  #[test]
+  fn test_pad_helpers() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "id": 42, "sku": "AB", "long": "1234567", "name": "张三" });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render(r#"{{padStart id 6 "0"}}"#), "000042");
+    assert_eq!(render(r#"{{padEnd sku 5 "-"}}"#), "AB---");
+    // 已经足够长时不补齐
+    assert_eq!(render(r#"{{padStart long 6 "0"}}"#), "1234567");
+    // 多字符填充串循环使用并截断到恰好补齐
+    assert_eq!(render(r#"{{padStart sku 7 "xy"}}"#), "xyxyxAB");
+    assert_eq!(render(r#"{{padEnd sku 5 "xy"}}"#), "ABxyx");
+    // 按字符计数, 默认使用空格填充
+    assert_eq!(render("[{{padEnd name 4}}]"), "[张三  ]");
+    // 超过上限时只补齐到 MAX_PAD_WIDTH 个字符
+    let capped = render(r#"{{padStart id 1000000000000 "0"}}"#);
+    assert_eq!(capped.len(), MAX_PAD_WIDTH as usize);
+    assert!(capped.ends_with("00042"));
+    assert_eq!(render(r#"{{padEnd sku 18446744073709551615}}"#).chars().count(), MAX_PAD_WIDTH as usize);
+  }
+  
+  #[test]
+  fn test_currency_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "a": 1234.5, "big": 1234567.891, "neg": -9876.5, "zero": 0, "tiny": -0.001, "small": 999 });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render(r#"{{currency a "¥" 2}}"#), "¥1,234.50");
+    assert_eq!(render(r#"{{currency big "$" 2}}"#), "$1,234,567.89");
+    assert_eq!(render(r#"{{currency neg "¥" 2}}"#), "-¥9,876.50");
+    assert_eq!(render(r#"{{currency zero "¥" 2}}"#), "¥0.00");
+    assert_eq!(render(r#"{{currency tiny "¥" 2}}"#), "¥0.00");
+    assert_eq!(render(r#"{{currency small "" 0}}"#), "999");
+    assert_eq!(render("{{currency big}}"), "1,234,567.89");
+  }
+  
+  #[test]
+  fn test_keep_if_empty_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "phone": "", "name": "Alice", "count": 0 });
+    let render = |tpl: &str| hbs.render_template(tpl, &data).unwrap();
+    
+    assert_eq!(render(r#"{{keepIfEmpty "N/A" phone}}"#), "N/A");
+    assert_eq!(render(r#"{{keepIfEmpty "N/A" missing}}"#), "N/A");
+    assert_eq!(render(r#"{{keepIfEmpty "N/A" name}}"#), "Alice");
+    assert_eq!(render(r#"{{keepIfEmpty "N/A" count}}"#), "0");
+  }
+  
+  #[test]
+  fn test_each_row_and_each_col_force_loop_type() {
+    // 跨行的循环默认推断为按行循环, eachCol 强制按列循环
+    let xml = r#"<row r="1"><c r="A1"><is><t>{{#eachCol items}}{{name}}</t></is></c></row><row r="2"><c r="B2"><is><t>{{/eachCol}}</t></is></c></row>"#;
+    let result = merge_handlebars_in_xml(xml.to_string()).unwrap();
+    assert!(result.contains("{{col_offset_plus 1}}{{/each}}"), "{result}");
+    assert!(!result.contains("row_offset_plus"), "{result}");
+    assert!(result.contains("{{#each items}}") && !result.contains("eachCol"), "{result}");
+    
+    // 不跨行的循环默认推断为按列循环, 强制按行循环的 eachRow 必须跨行, 否则每一项都会写进同一行
+    let xml = r#"<row r="1"><c r="A1"><is><t>{{#eachRow items}}</t></is></c><c r="B1"><is><t>{{name}}{{/eachRow}}</t></is></c></row>"#;
+    let err = merge_handlebars_in_xml(xml.to_string()).unwrap_err().to_string();
+    assert_eq!(err, "{{#eachRow items}} in row 1 must end in a later row; use {{#each}} or {{#eachCol}} to repeat columns within one row");
+    
+    // 跨行时 eachRow 正常按行循环
+    let xml = r#"<row r="1"><c r="A1"><is><t>{{#eachRow items}}</t></is></c></row><row r="2"><c r="A2"><is><t>{{name}}{{/eachRow}}</t></is></c></row>"#;
+    let result = merge_handlebars_in_xml(xml.to_string()).unwrap();
+    assert!(result.contains("{{row_offset_plus 1}}{{name}}{{else}}{{removeRow}}{{/each}}"), "{result}");
+    assert!(!result.contains("col_offset_plus"), "{result}");
+    
+    // 普通 each 保持原有的推断
+    let xml = r#"<row r="1"><c r="A1"><is><t>{{#each items}}</t></is></c><c r="B1"><is><t>{{name}}{{/each}}</t></is></c></row>"#;
+    let result = merge_handlebars_in_xml(xml.to_string()).unwrap();
+    assert!(result.contains("{{col_offset_plus 1}}{{name}}{{/each}}"), "{result}");
+  }
+  
+  #[test]
+  fn test_insert_defined_names_into_workbook() {
+    let print_area = DefinedName { name: "_xlnm.Print_Area".to_string(), local_sheet_id: Some(0), value: "'Sheet1'!$A$1:$C$9".to_string() };
+    
+    // 没有 definedNames 时创建在 calcPr 之前
+    let workbook = r#"<workbook><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets><calcPr calcId="191029"/></workbook>"#;
+    let result = insert_defined_names_into_workbook(workbook, std::slice::from_ref(&print_area)).unwrap();
+    assert_eq!(result, r#"<workbook><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets><definedNames><definedName name="_xlnm.Print_Area" localSheetId="0">'Sheet1'!$A$1:$C$9</definedName></definedNames><calcPr calcId="191029"/></workbook>"#);
+    
+    // 替换同一工作表已有的打印区域, 保留其他定义名称
+    let workbook = r#"<workbook><sheets/><definedNames><definedName name="_xlnm.Print_Area" localSheetId="0">Sheet1!$A$1:$B$2</definedName><definedName name="Rate">Sheet1!$B$1</definedName></definedNames></workbook>"#;
+    let result = insert_defined_names_into_workbook(workbook, &[print_area]).unwrap();
+    assert_eq!(result, r#"<workbook><sheets/><definedNames><definedName name="Rate">Sheet1!$B$1</definedName><definedName name="_xlnm.Print_Area" localSheetId="0">'Sheet1'!$A$1:$C$9</definedName></definedNames></workbook>"#);
+  }
+  
+  #[test]
+  fn test_capitalize_and_title_case() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "city": "éCOLE de paris", "mixed": "北京 office ÅSA", "empty": "" });
+    
+    assert_eq!(hbs.render_template("{{capitalize city}}", &data).unwrap(), "École de paris");
+    assert_eq!(hbs.render_template("{{titleCase city}}", &data).unwrap(), "École De Paris");
+    assert_eq!(hbs.render_template("{{titleCase mixed}}", &data).unwrap(), "北京 Office Åsa");
+    assert_eq!(hbs.render_template("{{capitalize mixed}}", &data).unwrap(), "北京 office åsa");
+    assert_eq!(hbs.render_template("{{capitalize empty}}{{titleCase empty}}", &data).unwrap(), "");
+  }
+  
+  #[test]
+  fn test_repeat_str_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "progress": 5 });
+    
+    assert_eq!(hbs.render_template(r#"{{repeatStr "█" progress}}"#, &data).unwrap(), "█████");
+    assert_eq!(hbs.render_template(r#"{{repeatStr "-*" 3}}"#, &data).unwrap(), "-*-*-*");
+    assert_eq!(hbs.render_template(r#"{{repeatStr "x" 0}}{{repeatStr "x" -2}}"#, &data).unwrap(), "");
+    // 超过上限时只重复 MAX_REPEAT_COUNT 次
+    let capped = hbs.render_template(r#"{{repeatStr "ab" 1000000000}}"#, &data).unwrap();
+    assert_eq!(capped.len(), 2 * MAX_REPEAT_COUNT as usize);
+  }
+  
+  #[test]
+  fn test_range_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({});
+    assert_eq!(hbs.render_template("{{#each (range 1 5)}}{{this}},{{/each}}", &data).unwrap(), "1,2,3,4,5,");
+    assert_eq!(hbs.render_template("{{#each (range 0 10 5)}}{{@index}}:{{this}} {{/each}}", &data).unwrap(), "0:0 1:5 2:10 ");
+    assert_eq!(hbs.render_template("{{#each (range 3 1)}}{{this}}{{/each}}", &data).unwrap(), "321");
+    assert_eq!(hbs.render_template("{{#each (range 1 3 0)}}{{this}}{{/each}}", &data).unwrap(), "");
+    
+    assert_eq!(rewrite_helper_blocks("A{{#range 1 12 2}}{{this}}{{/range}}B"), "A{{#each (range 1 12 2)}}{{this}}{{/each}}B");
+  }
+  
+  #[test]
+  fn test_group_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "items": [
+      { "name": "saw", "category": "tools" },
+      { "name": "apple", "category": "food" },
+      { "name": "hammer", "category": "tools" },
+    ] });
+    let template = r#"{{#each (group items "category")}}{{@key}}:{{#each this}}{{name}} {{/each}};{{/each}}"#;
+    assert_eq!(hbs.render_template(template, &data).unwrap(), "food:apple ;tools:saw hammer ;");
+    
+    assert_eq!(
+      rewrite_helper_blocks(r#"{{#group items "category" as |lines cat|}}{{cat}}{{/group}}"#),
+      r#"{{#each (group items "category") as |lines cat|}}{{cat}}{{/each}}"#,
+    );
+  }
+  
+  #[test]
+  fn test_sort_by_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "items": [
+      { "name": "b", "amount": 10, "date": "2024-03-01" },
+      { "name": "a", "amount": 9, "date": "2024-01-15" },
+      { "name": "c", "amount": 100, "date": "2024-02-10" },
+    ] });
+    
+    // 数字按数值排序, 不是按文本 ("100" < "9")
+    assert_eq!(hbs.render_template(r#"{{#each (sortBy items "amount")}}{{name}}{{/each}}"#, &data).unwrap(), "abc");
+    assert_eq!(hbs.render_template(r#"{{#each (sortBy items "amount" "desc")}}{{name}}{{/each}}"#, &data).unwrap(), "cba");
+    // 字符串按文本排序
+    assert_eq!(hbs.render_template(r#"{{#each (sortBy items "date")}}{{name}}{{/each}}"#, &data).unwrap(), "acb");
+    assert_eq!(hbs.render_template(r#"{{#each (sortBy items "date" "desc")}}{{name}}{{/each}}"#, &data).unwrap(), "bca");
+    // 不修改原数据
+    assert_eq!(hbs.render_template(r#"{{#each (sortBy items "name")}}{{/each}}{{#each items}}{{name}}{{/each}}"#, &data).unwrap(), "bac");
+  }
+  
+  #[test]
+  fn test_filter_by_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "items": [
+      { "name": "a", "status": "active", "amount": 50 },
+      { "name": "b", "status": "closed", "amount": 150 },
+      { "name": "c", "status": "active", "amount": 100.5 },
+      { "name": "d", "amount": "n/a" },
+    ] });
+    
+    assert_eq!(hbs.render_template(r#"{{#each (filterBy items "status" "active")}}{{name}}{{/each}}"#, &data).unwrap(), "ac");
+    assert_eq!(hbs.render_template(r#"{{#each (filterBy items "status" "active" "ne")}}{{name}}{{/each}}"#, &data).unwrap(), "bd");
+    // 数值比较, 非数字的项不匹配
+    assert_eq!(hbs.render_template(r#"{{#each (filterBy items "amount" 100 "gt")}}{{name}}{{/each}}"#, &data).unwrap(), "bc");
+    assert_eq!(hbs.render_template(r#"{{#each (filterBy items "amount" 100 "lt")}}{{name}}{{/each}}"#, &data).unwrap(), "a");
+    assert_eq!(hbs.render_template(r#"{{len (filterBy items "status" "pending")}}"#, &data).unwrap(), "0");
+  }
+  
+  #[test]
+  fn test_one_index_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "items": ["a", "b", "c"] });
+    assert_eq!(hbs.render_template("{{#each items}}{{oneIndex}}.{{this}} {{/each}}", &data).unwrap(), "1.a 2.b 3.c ");
+    assert_eq!(hbs.render_template("{{#each (range 5 7)}}{{#if true}}{{oneIndex}}{{/if}}{{/each}}", &data).unwrap(), "123");
+    assert!(hbs.render_template("{{oneIndex}}", &data).is_err());
+  }
+  
+  #[test]
+  fn test_extract_fallback_records_warning() {
+    let mut warnings = Vec::new();
+    // 未闭合的注释让 XML 解析失败, 改用简单方法提取并记录警告
+    let cell = "<c r=\"A1\" t=\"inlineStr\"><is><t>KEY42</t><!-- </is></c>";
+    assert_eq!(extract_text_from_is(cell, "KEY", &mut warnings).unwrap(), "42");
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("falling back to simple text extraction"), "{warnings:?}");
+    
+    let cell = "<c r=\"A1\" t=\"inlineStr\"><is><t>KEY=SUM(A1:A2)</t><!-- </is></c>";
+    extract_formula_from_cell(cell, "KEY", &mut warnings).unwrap();
+    assert_eq!(warnings.len(), 2);
+    
+    // 正常的单元格不产生警告
+    extract_text_from_is("<c r=\"A1\"><is><t>KEY1</t></is></c>", "KEY", &mut warnings).unwrap();
+    assert_eq!(warnings.len(), 2);
+  }
+  
+  #[test]
+  fn test_add_sub_coerce_numeric_strings() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "qty": "3", "price": " 4 ", "rate": "1.5", "name": "abc" });
+    assert_eq!(hbs.render_template("{{add 3 4}} {{sub 3 4}}", &data).unwrap(), "7 -1");
+    assert_eq!(hbs.render_template("{{add qty 4}} {{sub qty price}}", &data).unwrap(), "7 -1");
+    assert_eq!(hbs.render_template("{{add rate 1}} {{sub 2 rate}}", &data).unwrap(), "2.5 0.5");
+    // 无法解析的值按 0 计算
+    assert_eq!(hbs.render_template("{{add name 4}} {{sub missing 1}}", &data).unwrap(), "4 -1");
+    // 可以嵌套使用
+    assert_eq!(hbs.render_template("{{add (sub qty 1) \"10\"}}", &data).unwrap(), "12");
+  }
+  
+  #[test]
+  fn test_abs_neg_helpers() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "a": -5, "b": -2.5, "c": "-3", "d": 7, "name": "abc" });
+    assert_eq!(hbs.render_template("{{abs a}} {{abs b}} {{abs c}} {{abs d}}", &data).unwrap(), "5 2.5 3 7");
+    assert_eq!(hbs.render_template("{{neg a}} {{neg b}} {{neg c}} {{neg d}}", &data).unwrap(), "5 2.5 3 -7");
+    // 不是数字时输出空字符串
+    assert_eq!(hbs.render_template("[{{abs name}}][{{neg missing}}][{{abs true}}]", &data).unwrap(), "[][][]");
+    // i64::MIN 的绝对值超出整数范围时改用浮点数
+    assert_eq!(hbs.render_template("{{abs -9223372036854775808}}", &data).unwrap(), "9.223372036854776e18");
+  }
+  
+  #[test]
+  fn test_percent_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    let data = serde_json::json!({ "score": 0.92, "rate": "0.125", "name": "abc" });
+    assert_eq!(hbs.render_template("{{percent score}} {{percent rate 1}} {{percent 1.5 2}} {{percent -0.05}}", &data).unwrap(), "92% 12.5% 150.00% -5%");
+    assert_eq!(hbs.render_template("[{{percent name}}][{{percent missing}}]", &data).unwrap(), "[][]");
+    
+    let styles = r#"<styleSheet><numFmts count="1"><numFmt numFmtId="170" formatCode="0.0"/></numFmts><fonts/></styleSheet>"#;
+    assert_eq!(next_num_fmt_id(styles), 171);
+    assert_eq!(next_num_fmt_id("<styleSheet><fonts/></styleSheet>"), 164);
+    assert_eq!(
+      append_num_fmts_to_styles(styles, &[(171, "0.0%".to_string())]),
+      r#"<styleSheet><numFmts count="2"><numFmt numFmtId="170" formatCode="0.0"/><numFmt numFmtId="171" formatCode="0.0%"/></numFmts><fonts/></styleSheet>"#
+    );
+    assert_eq!(
+      append_num_fmts_to_styles(r#"<styleSheet xmlns="x"><fonts/></styleSheet>"#, &[(164, "0.0%".to_string())]),
+      r#"<styleSheet xmlns="x"><numFmts count="1"><numFmt numFmtId="164" formatCode="0.0%"/></numFmts><fonts/></styleSheet>"#
+    );
+    assert_eq!(num_fmt_xf(r#"<xf numFmtId="0" fontId="1"/>"#, 9), r#"<xf numFmtId="9" fontId="1" applyNumberFormat="1"/>"#);
+  }
+  
+  #[test]
+  fn test_date_diff_days_helper() {
+    let mut hbs = handlebars::Handlebars::new();
+    register_basic_helpers(&mut hbs).unwrap();
+    // 2024-01-01 与 2024-03-01 (闰年) 的毫秒时间戳
+    let data = serde_json::json!({ "start": 1704067200000i64, "end": 1709251200000i64, "name": "abc" });
+    assert_eq!(hbs.render_template("{{dateDiffDays start end}} {{dateDiffDays end start}}", &data).unwrap(), "60 -60");
+    // 同一天内的不同时间相差 0 天
+    assert_eq!(hbs.render_template("{{dateDiffDays start 1704153599999}}", &data).unwrap(), "0");
+    // Excel 序列号跨过不存在的 1900-02-29 (序列号 60): 59 是 1900-02-28, 61 是 1900-03-01, 实际相差 1 天
+    assert_eq!(hbs.render_template("{{dateDiffDays 59 61}} {{dateDiffDays 1 100}}", &data).unwrap(), "1 98");
+    assert_eq!(hbs.render_template(r#"{{dateDiffDays "59" "61" format="serial"}}"#, &data).unwrap(), "1");
+    // 指定 format="timestamp" 时小数值也按毫秒时间戳处理
+    assert_eq!(hbs.render_template(r#"{{dateDiffDays 0 86400000 format="timestamp"}} {{dateDiffDays 0 1 format="timestamp"}}"#, &data).unwrap(), "1 0");
+    // 不是数字时输出空字符串
+    assert_eq!(hbs.render_template("[{{dateDiffDays name end}}][{{dateDiffDays start missing}}]", &data).unwrap(), "[][]");
+  }
+  
+  #[test]
+  fn test_excel_date_1900_window() {
+    // 1900-01-01 00:00:00 UTC 的毫秒时间戳
+    let jan_1: i64 = -2208988800000;
+    let day = MS_PER_DAY;
+    // (从 1900-01-01 开始的天数, 序列号): 1900-01-01, 1900-01-31, 1900-02-01, 1900-02-28, 1900-03-01, 1900-03-02
+    for (days, serial) in [(0, 1.0), (30, 31.0), (31, 32.0), (58, 59.0), (59, 61.0), (60, 62.0)] {
+      let timestamp = jan_1 + days * day;
+      assert_eq!(timestamp_to_excel_date(timestamp), serial, "days {days}");
+      assert_eq!(excel_date_to_timestamp(serial), Some(timestamp), "serial {serial}");
+    }
+    // 序列号 0 是 1899-12-31 (Excel 显示为 1900-01-00)
+    assert_eq!(timestamp_to_excel_date(jan_1 - day), 0.0);
+    assert_eq!(excel_date_to_timestamp(0.0), Some(jan_1 - day));
+    
+    // 不存在的 1900-02-29: 60 到 61 之间的序列号无效, 也不会由真实日期生成
+    assert_eq!(excel_date_to_timestamp(60.0), None);
+    assert_eq!(excel_date_to_timestamp(60.5), None);
+    assert!(timestamp_to_excel_date(jan_1 + 59 * day - 1) < 60.0);
+    assert_eq!(excel_date_to_timestamp(f64::NAN), None);
+    
+    // 1900-03-01 前后逐小时往返转换结果一致
+    for hour in -72..72 {
+      let timestamp = jan_1 + 59 * day + hour * 3600000;
+      assert_eq!(excel_date_to_timestamp(timestamp_to_excel_date(timestamp)), Some(timestamp), "hour {hour}");
+    }
+  }
+  
+  #[test]
+  fn test_excel_date_time_of_day() {
+    let hour: i64 = 3600000;
+    // 1900-02-28 00:00:00 UTC 的毫秒时间戳
+    let feb_28: i64 = -2203977600000;
+    
+    // 小数部分是一天中的时间, 1900-02-28 12:00 是 59.5
+    assert_eq!(timestamp_to_excel_date(feb_28 + 12 * hour), 59.5);
+    assert_eq!(excel_date_to_timestamp(59.5), Some(feb_28 + 12 * hour));
+    assert_eq!(timestamp_to_excel_date(feb_28 + 18 * hour), 59.75);
+    
+    // 1900-02-28 的最后一毫秒仍然小于 60, 1900-03-01 00:00 正好是 61
+    let last_ms = timestamp_to_excel_date(feb_28 + MS_PER_DAY - 1);
+    assert!(last_ms > 59.999 && last_ms < 60.0, "{last_ms}");
+    assert_eq!(timestamp_to_excel_date(feb_28 + MS_PER_DAY), 61.0);
+    
+    // 现代日期: 2024-01-01 06:00 是 45292.25, 1 秒的精度可以往返转换
+    let timestamp = 1704067200000i64 + 6 * hour;
+    assert_eq!(timestamp_to_excel_date(timestamp), 45292.25);
+    assert_eq!(excel_date_to_timestamp(timestamp_to_excel_date(timestamp + 1000)), Some(timestamp + 1000));
+  }
+  
+  #[test]
+  fn test_normalize_cell_range() {
+    assert_eq!(normalize_cell_range("A1:C3"), Some(((1, 1), (3, 3))));
+    assert_eq!(normalize_cell_range("C3:A1"), Some(((1, 1), (3, 3))));
+    // 只有行或列反向时同样取左上角和右下角
+    assert_eq!(normalize_cell_range("A3:C1"), Some(((1, 1), (3, 3))));
+    assert_eq!(normalize_cell_range("$B$2"), Some(((2, 2), (2, 2))));
+    assert_eq!(normalize_cell_range("A1:"), None);
+    assert_eq!(normalize_cell_range("abc"), None);
+  }
+  
+  #[test]
+  fn test_set_xml_declaration() {
+    let standard = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+    assert_eq!(set_xml_declaration(&format!("{standard}\n<a/>"), true), None);
+    assert_eq!(set_xml_declaration(&format!("{standard}\n<a/>"), false).as_deref(), Some("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a/>"));
+    assert_eq!(set_xml_declaration("<?xml version='1.0' encoding='utf-8'?><a/>", true), Some(format!("{standard}<a/>")));
+    assert_eq!(set_xml_declaration("<?xml version=\"1.0\"?>\r\n<a/>", true), Some(format!("{standard}\r\n<a/>")));
+    assert_eq!(set_xml_declaration("<a/>", true), Some(format!("{standard}\n<a/>")));
+    assert_eq!(set_xml_declaration("\u{feff}<a/>", true), Some(format!("\u{feff}{standard}\n<a/>")));
+    // 其他编码的部件保持不变
+    assert_eq!(set_xml_declaration(r#"<?xml version="1.0" encoding="UTF-16"?><a/>"#, true), None);
+  }
+  
+  #[test]
+  fn test_insert_anchors_into_drawing() {
+    let anchor = "<xdr:oneCellAnchor/>";
+    
+    // 插入到根节点的结束标签之前, 根节点之后的注释保持原位
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<xdr:wsDr xmlns:xdr="x"><xdr:sp/></xdr:wsDr><!-- </xdr:wsDr> -->"#;
+    let result = insert_anchors_into_drawing(xml, anchor).unwrap();
+    assert!(result.ends_with(r#"<xdr:wsDr xmlns:xdr="x"><xdr:sp/><xdr:oneCellAnchor/></xdr:wsDr><!-- </xdr:wsDr> -->"#), "{result}");
+    
+    // 自闭合的根节点展开为开始和结束标签
+    let result = insert_anchors_into_drawing(r#"<xdr:wsDr xmlns:xdr="x"/>"#, anchor).unwrap();
+    assert_eq!(result, r#"<xdr:wsDr xmlns:xdr="x"><xdr:oneCellAnchor/></xdr:wsDr>"#);
+    
+    assert!(insert_anchors_into_drawing("<other/>", anchor).is_err());
+  }
+  
+  #[test]
+  fn test_max_drawing_object_id() {
+    // 单引号、属性前后的空白和其他命名空间前缀都能识别
+    let xml = r#"<xdr:wsDr xmlns:xdr="x"><xdr:sp><xdr:nvSpPr><xdr:cNvPr name="Shape" id='7'/></xdr:nvSpPr></xdr:sp><d:pic xmlns:d="x"><d:nvPicPr><d:cNvPr  id = "3" name="Picture"></d:cNvPr></d:nvPicPr></d:pic></xdr:wsDr>"#;
+    assert_eq!(max_drawing_object_id(xml).unwrap(), 7);
+    assert_eq!(max_drawing_object_id(r#"<xdr:wsDr xmlns:xdr="x"/>"#).unwrap(), 0);
+  }
+}